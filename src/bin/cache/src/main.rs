@@ -1,4 +1,4 @@
-use std::time::Instant;
+use std::time::Duration;
 
 use clap::Parser;
 use miette::IntoDiagnostic;
@@ -164,7 +164,7 @@ fn main() -> miette::Result<()> {
                     "{} {:?} {} seconds old",
                     info.id,
                     info.flags,
-                    (Instant::now() - info.start).as_secs_f32()
+                    Duration::from_nanos(info.held_ns).as_secs_f32()
                 );
                 i += 1;
             }
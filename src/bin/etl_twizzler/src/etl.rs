@@ -1,7 +1,8 @@
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
-    path::PathBuf,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc::sync_channel, Mutex},
 };
 
 use serde::{Deserialize, Serialize};
@@ -52,6 +53,7 @@ where
         offset: u64,
     ) -> std::io::Result<()> {
         let mut f = File::open(&path)?;
+        let mode = file_mode(&f.metadata()?);
         let len = f.seek(SeekFrom::End(0))?;
         f.seek(SeekFrom::Start(0))?;
         let mut buf_writer = BufReader::new(f);
@@ -66,6 +68,7 @@ where
             custom_metadata.pad[0..data.len()].copy_from_slice(&data);
         }
         header.set_size(len);
+        header.set_mode(mode);
 
         self.tarchive
             .append_data(&mut header, &path, &mut buf_writer)?;
@@ -132,11 +135,66 @@ pub fn form_twizzler_object<R: std::io::Read>(
     Ok(twzid)
 }
 
-pub fn form_fs_file<R: std::io::Read>(stream: R, name: String, offset: u64) -> std::io::Result<()> {
-    let mut writer = File::create(name)?;
+// Creating a file inserts a name into the namespace, which is shared between unpack workers.
+static NAMING_LOCK: Mutex<()> = Mutex::new(());
+
+fn create_named_file(name: String) -> std::io::Result<File> {
+    let _guard = NAMING_LOCK.lock().unwrap();
+    File::create(name)
+}
+
+// File modes are stored in the tar header's mode field as unix permission bits. Files added with
+// file_add record their source mode (on hosts without unix permissions, 0o444 or 0o644 depending
+// on whether the file is read-only). Entries added with stream_add have no mode, stored as 0.
+//
+// On unpack, a recorded mode is applied to the created file on unix hosts. Twizzler's fs does not
+// have permission bits -- access to a file's object is governed by its protections and the
+// capabilities of the accessor -- so the mode is kept in the archive but not applied there.
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn apply_mode(file: &File, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn apply_mode(_file: &File, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn entry_mode(header: &Header) -> Option<u32> {
+    header.mode().ok().filter(|mode| *mode != 0)
+}
+
+pub fn form_fs_file<R: std::io::Read>(
+    stream: R,
+    name: String,
+    offset: u64,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let mut writer = create_named_file(name)?;
     writer.seek(SeekFrom::Start(offset))?;
     let mut stream = BufReader::new(stream);
     io::copy(&mut stream, &mut writer)?;
+    // Set the mode last, as it may remove write permission.
+    if let Some(mode) = mode {
+        apply_mode(&writer, mode)?;
+    }
+    writer.sync_all()?;
 
     Ok(())
 }
@@ -147,7 +205,7 @@ pub fn form_persistent_vector<R: std::io::Read>(
     name: String,
     offset: u64,
 ) -> std::io::Result<()> {
-    let mut writer = File::create(name)?;
+    let mut writer = create_named_file(name)?;
     writer.seek(SeekFrom::Start(offset))?;
     let _stream: Vec<String> = BufReader::new(stream)
         .split(b'\n')
@@ -158,6 +216,121 @@ pub fn form_persistent_vector<R: std::io::Read>(
     Ok(())
 }
 
+fn entry_info<R: std::io::Read>(entry: &tar::Entry<R>) -> (String, SpecialData, Option<u32>) {
+    let path = entry
+        .path()
+        .unwrap()
+        .to_owned()
+        .into_owned()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let bad_idea: SpecialData = bincode::deserialize(&entry.header().as_old().pad).unwrap();
+    (path, bad_idea, entry_mode(entry.header()))
+}
+
+// Joins the archive entry path `path` onto `dest`, failing with InvalidInput if the path is
+// absolute or has `..` components, since either would place the entry outside of `dest`.
+pub fn path_under(dest: &Path, path: &str) -> std::io::Result<PathBuf> {
+    let escapes = Path::new(path).components().any(|c| {
+        matches!(
+            c,
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir
+        )
+    });
+    if escapes {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("archive entry {} is outside of the destination", path),
+        ));
+    }
+    Ok(dest.join(path))
+}
+
+fn unpack_entry<R: std::io::Read>(
+    stream: R,
+    path: String,
+    bad_idea: &SpecialData,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    println!("unpacked {}", path);
+    match bad_idea.kind {
+        PackType::StdFile => {
+            form_fs_file(stream, path, bad_idea.offset, mode)?;
+        }
+        PackType::TwzObj => {
+            #[cfg(target_os = "twizzler")]
+            form_twizzler_object(stream, path, bad_idea.offset)?;
+            #[cfg(not(target_os = "twizzler"))]
+            form_fs_file(stream, path, bad_idea.offset, mode)?;
+        }
+        PackType::PVec => {
+            form_persistent_vector(stream, path, bad_idea.offset)?;
+        }
+    }
+    Ok(())
+}
+
+// The outcome of unpacking a single archive entry, as reported by Unpack::unpack_into.
+#[derive(Debug)]
+pub struct UnpackedEntry {
+    pub path: String,
+    pub result: std::io::Result<()>,
+}
+
+// An archive entry as reported by Unpack::list, described by its header alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedEntry {
+    pub path: String,
+    pub kind: PackType,
+    pub size: u64,
+    pub is_dir: bool,
+    // Archive offset of the entry's header, which names the entry to Unpack::unpack_entry_at.
+    pub header_pos: u64,
+}
+
+// Progress of a resumable unpack, kept in a small file (an object, on Twizzler) next to the
+// restored data. It holds the archive offset of the header of the last entry that was completely
+// materialized, as 8 little-endian bytes. The record fits in a single block and is rewritten in
+// place, so an interruption leaves either the old or the new offset. An empty journal means no
+// entry has completed yet.
+struct UnpackJournal {
+    file: File,
+    completed: Option<u64>,
+}
+
+impl UnpackJournal {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let mut record = [0; 8];
+        let completed = match file.read_exact(&mut record) {
+            Ok(()) => Some(u64::from_le_bytes(record)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+        Ok(UnpackJournal { file, completed })
+    }
+
+    fn is_completed(&self, header_pos: u64) -> bool {
+        self.completed.is_some_and(|done| header_pos <= done)
+    }
+
+    fn record(&mut self, header_pos: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header_pos.to_le_bytes())?;
+        self.file.sync_all()?;
+        self.completed = Some(header_pos);
+        Ok(())
+    }
+}
+
+// The largest entry Unpack::unpack_parallel reads into memory to hand to a worker thread.
+const PARALLEL_ENTRY_MAX: u64 = 1 << 20;
+
 pub struct Unpack<T: std::io::Read> {
     tarchive: tar::Archive<T>,
 }
@@ -175,32 +348,8 @@ where
     pub fn unpack(mut self) -> std::io::Result<()> {
         for e in self.tarchive.entries().unwrap() {
             if let Ok(entry) = e {
-                let path = entry
-                    .path()
-                    .unwrap()
-                    .to_owned()
-                    .into_owned()
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
-                let bad_idea: SpecialData =
-                    bincode::deserialize(&entry.header().as_old().pad).unwrap();
-
-                println!("unpacked {}", path);
-                match bad_idea.kind {
-                    PackType::StdFile => {
-                        form_fs_file(entry, path, bad_idea.offset)?;
-                    }
-                    PackType::TwzObj => {
-                        #[cfg(target_os = "twizzler")]
-                        form_twizzler_object(entry, path, bad_idea.offset)?;
-                        #[cfg(not(target_os = "twizzler"))]
-                        form_fs_file(entry, path, bad_idea.offset)?;
-                    }
-                    PackType::PVec => {
-                        form_persistent_vector(entry, path, bad_idea.offset)?;
-                    }
-                }
+                let (path, bad_idea, mode) = entry_info(&entry);
+                unpack_entry(entry, path, &bad_idea, mode)?;
             } else if let Err(e) = e {
                 println!("{}", e);
             }
@@ -209,6 +358,212 @@ where
         Ok(())
     }
 
+    // Like unpack, but records progress in the journal at `journal`, so that an unpack that was
+    // interrupted can be restarted with the same archive and journal and pick up where it left
+    // off. Entries the journal records as completed are skipped rather than created again. An
+    // entry that was being written when the unpack was interrupted is not recorded, so it is
+    // redone from the start. The journal is removed once the whole archive has been unpacked.
+    // Returns the number of entries unpacked by this call.
+    pub fn unpack_resumable(mut self, journal: &Path) -> std::io::Result<usize> {
+        let mut progress = UnpackJournal::open(journal)?;
+        let mut unpacked = 0;
+        for e in self.tarchive.entries()? {
+            let entry = e?;
+            let header_pos = entry.raw_header_position();
+            if progress.is_completed(header_pos) {
+                continue;
+            }
+            let (path, bad_idea, mode) = entry_info(&entry);
+            unpack_entry(entry, path, &bad_idea, mode)?;
+            progress.record(header_pos)?;
+            unpacked += 1;
+        }
+        drop(progress);
+        std::fs::remove_file(journal)?;
+
+        Ok(unpacked)
+    }
+
+    // Like unpack, but places every entry under `dest` and keeps going when an entry fails,
+    // returning the result for each entry instead. Directory entries are created as namespaces
+    // and are not reported.
+    pub fn unpack_into(mut self, dest: &Path) -> std::io::Result<Vec<UnpackedEntry>> {
+        let mut report = Vec::new();
+        for e in self.tarchive.entries()? {
+            let entry = match e {
+                Ok(entry) => entry,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            let (path, bad_idea, mode) = entry_info(&entry);
+            let full_path = match path_under(dest, &path) {
+                Ok(full_path) => full_path,
+                Err(e) => {
+                    report.push(UnpackedEntry {
+                        path,
+                        result: Err(e),
+                    });
+                    continue;
+                }
+            };
+            if entry.header().entry_type().is_dir() {
+                if let Err(e) = std::fs::create_dir_all(&full_path) {
+                    println!("failed to create {}: {}", full_path.display(), e);
+                }
+                continue;
+            }
+            let result = full_path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| {
+                    unpack_entry(
+                        entry,
+                        full_path.to_string_lossy().into_owned(),
+                        &bad_idea,
+                        mode,
+                    )
+                });
+            report.push(UnpackedEntry { path, result });
+        }
+
+        Ok(report)
+    }
+
+    // Lists the entries of the archive without unpacking any of them. Entry contents are skipped
+    // over, not read.
+    pub fn list(mut self) -> std::io::Result<Vec<ListedEntry>> {
+        let mut listed = Vec::new();
+        for e in self.tarchive.entries()? {
+            let entry = e?;
+            let (path, bad_idea, _) = entry_info(&entry);
+            listed.push(ListedEntry {
+                path,
+                kind: bad_idea.kind,
+                size: entry.size(),
+                is_dir: entry.header().entry_type().is_dir(),
+                header_pos: entry.raw_header_position(),
+            });
+        }
+
+        Ok(listed)
+    }
+
+    // Unpacks just the entry whose header is at `header_pos`, as reported by list, writing it to
+    // `dest` instead of the path recorded in the archive. Fails with NotFound if no entry starts
+    // there.
+    pub fn unpack_entry_at(mut self, header_pos: u64, dest: &Path) -> std::io::Result<()> {
+        for e in self.tarchive.entries()? {
+            let entry = e?;
+            if entry.raw_header_position() != header_pos {
+                continue;
+            }
+            let (_, bad_idea, mode) = entry_info(&entry);
+            return unpack_entry(entry, dest.to_string_lossy().into_owned(), &bad_idea, mode);
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no archive entry at offset {}", header_pos),
+        ))
+    }
+
+    // Like unpack_entry_at, but copies the entry's contents to `out` rather than creating a file,
+    // preceded by the zeroes the entry's offset calls for. Returns the number of bytes written.
+    pub fn copy_entry_at<W: std::io::Write>(
+        mut self,
+        header_pos: u64,
+        out: &mut W,
+    ) -> std::io::Result<u64> {
+        for e in self.tarchive.entries()? {
+            let mut entry = e?;
+            if entry.raw_header_position() != header_pos {
+                continue;
+            }
+            let (_, bad_idea, _) = entry_info(&entry);
+            let padding = io::copy(&mut io::repeat(0).take(bad_idea.offset), out)?;
+            return Ok(padding + io::copy(&mut entry, out)?);
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no archive entry at offset {}", header_pos),
+        ))
+    }
+
+    // Like unpack, but places every entry under `dest`, and creates and writes the entries on
+    // `threads` worker threads. Reading a tar archive is sequential, so entries are read here and
+    // handed to the workers over a bounded channel. Only entries of up to PARALLEL_ENTRY_MAX bytes
+    // are buffered for the workers; larger ones are streamed to their files here, while the
+    // workers carry on. At most 2 * threads entries wait in the channel and each worker holds one,
+    // so the buffered data is bounded by 3 * threads * PARALLEL_ENTRY_MAX. Returns the first error
+    // hit by any worker, or here.
+    pub fn unpack_parallel(mut self, dest: &Path, threads: usize) -> std::io::Result<()> {
+        let threads = threads.max(1);
+        let (sender, receiver) =
+            sync_channel::<(String, SpecialData, Option<u32>, Vec<u8>)>(threads * 2);
+        let receiver = Mutex::new(receiver);
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| -> std::io::Result<()> {
+                        loop {
+                            let next = receiver.lock().unwrap().recv();
+                            let Ok((path, bad_idea, mode, data)) = next else {
+                                return Ok(());
+                            };
+                            unpack_entry(data.as_slice(), path, &bad_idea, mode)?;
+                        }
+                    })
+                })
+                .collect();
+
+            let mut result = Ok(());
+            for e in self.tarchive.entries()? {
+                match e {
+                    Ok(mut entry) => {
+                        let (path, bad_idea, mode) = entry_info(&entry);
+                        let path = match path_under(dest, &path) {
+                            Ok(path) => path.to_string_lossy().into_owned(),
+                            Err(e) => {
+                                result = Err(e);
+                                break;
+                            }
+                        };
+                        if entry.size() > PARALLEL_ENTRY_MAX {
+                            if let Err(e) = unpack_entry(entry, path, &bad_idea, mode) {
+                                result = Err(e);
+                                break;
+                            }
+                            continue;
+                        }
+                        let mut data = Vec::new();
+                        if let Err(e) = entry.read_to_end(&mut data) {
+                            result = Err(e);
+                            break;
+                        }
+                        // This only fails if every worker has already stopped on an error.
+                        if sender.send((path, bad_idea, mode, data)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            drop(sender);
+
+            for worker in workers {
+                let worker_result = worker.join().unwrap();
+                if result.is_ok() {
+                    result = worker_result;
+                }
+            }
+            result
+        })
+    }
+
     pub fn inspect<W: std::io::Write>(mut self, write_stream: &mut W) -> std::io::Result<()> {
         for e in self.tarchive.entries().unwrap() {
             if let Ok(entry) = e {
@@ -258,3 +613,284 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Tests that change the working directory hold this, since it's shared by all test threads.
+    static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn unpack_parallel_many_entries() {
+        let dir = std::env::temp_dir().join(format!("etl-unpack-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // One entry is too large to buffer, so it's streamed instead of handed to a worker.
+        let big = 63;
+        let contents = |i: usize| {
+            let repeat = if i == big {
+                PARALLEL_ENTRY_MAX as usize / 8 + 1
+            } else {
+                i + 1
+            };
+            format!("entry {} ", i).repeat(repeat).into_bytes()
+        };
+        let offset = |i: usize| (i % 3) as u64 * 16;
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        for i in 0..64 {
+            let kind = if i % 2 == 0 {
+                PackType::StdFile
+            } else {
+                PackType::TwzObj
+            };
+            pack.stream_add(
+                contents(i).as_slice(),
+                format!("entry-{}", i),
+                kind,
+                offset(i),
+            )
+            .unwrap();
+        }
+        pack.build();
+
+        Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_parallel(&dir, 4)
+            .unwrap();
+
+        for i in 0..64 {
+            let data = std::fs::read(dir.join(format!("entry-{}", i))).unwrap();
+            let (pad, data) = data.split_at(offset(i) as usize);
+            assert!(pad.iter().all(|b| *b == 0));
+            assert_eq!(data, contents(i));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unpack_into_reports_each_entry() {
+        let dir = std::env::temp_dir().join(format!("etl-unpack-into-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        pack.stream_add(&b"first"[..], "a".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.stream_add(&b"second"[..], "sub/b".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.build();
+
+        let report = Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_into(&dir)
+            .unwrap();
+        let paths: Vec<_> = report.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a", "sub/b"]);
+        assert!(report.iter().all(|e| e.result.is_ok()));
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"first");
+        assert_eq!(std::fs::read(dir.join("sub/b")).unwrap(), b"second");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unpack_into_rejects_escaping_entries() {
+        let root = std::env::temp_dir().join(format!("etl-escape-{}", std::process::id()));
+        let dir = root.join("dest");
+        std::fs::create_dir_all(&dir).unwrap();
+        let abs = root.join("abs");
+
+        // tar::Builder refuses to write these paths, so set the header names directly.
+        let mut archive = Vec::new();
+        let mut builder = tar::Builder::new(&mut archive);
+        let abs_name = abs.to_str().unwrap().to_owned();
+        for name in ["../escape", abs_name.as_str(), "ok"] {
+            let mut header = Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(4);
+            header.set_cksum();
+            builder.append(&header, &b"data"[..]).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let report = Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_into(&dir)
+            .unwrap();
+        let paths: Vec<_> = report.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["../escape", abs_name.as_str(), "ok"]);
+        for rejected in &report[..2] {
+            let err = rejected.result.as_ref().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(report[2].result.is_ok());
+        assert!(std::fs::metadata(root.join("escape")).is_err());
+        assert!(std::fs::metadata(&abs).is_err());
+        assert_eq!(std::fs::read(dir.join("ok")).unwrap(), b"data");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn list_then_unpack_one_entry() {
+        let dir = std::env::temp_dir().join(format!("etl-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        pack.stream_add(&b"first"[..], "a".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.stream_add(&b"second"[..], "sub/b".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.build();
+
+        let listed = Unpack::new(archive.as_slice()).unwrap().list().unwrap();
+        let paths: Vec<_> = listed.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a", "sub/b"]);
+        assert_eq!(listed[1].size, 6);
+        assert!(listed
+            .iter()
+            .all(|e| !e.is_dir && e.kind == PackType::StdFile));
+
+        let dest = dir.join("b-only");
+        Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_entry_at(listed[1].header_pos, &dest)
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"second");
+        assert!(std::fs::metadata(dir.join("a")).is_err());
+
+        // An entry can also be read from just the part of the archive that starts at its header.
+        let mut copied = Vec::new();
+        let len = Unpack::new(&archive[listed[1].header_pos as usize..])
+            .unwrap()
+            .copy_entry_at(0, &mut copied)
+            .unwrap();
+        assert_eq!(len, 6);
+        assert_eq!(copied, b"second");
+
+        let missing = Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_entry_at(listed[1].header_pos + 1, &dest)
+            .unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Reads from the archive, but fails once `remaining` bytes have been read, as if the unpack
+    // was killed partway.
+    struct Interrupted<'a> {
+        archive: &'a [u8],
+        remaining: usize,
+    }
+
+    impl Read for Interrupted<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "interrupted"));
+            }
+            let len = buf.len().min(self.remaining);
+            let n = self.archive.read(&mut buf[..len])?;
+            self.remaining -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn unpack_resumes_after_interruption() {
+        let _cwd = CWD_LOCK.lock().unwrap();
+        let dir = std::env::temp_dir().join(format!("etl-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        let journal = dir.join("journal");
+
+        // Each entry is a 512 byte header followed by two blocks of data.
+        const ENTRY_LEN: usize = 512 * 3;
+        let contents = |i: usize| vec![b'a' + i as u8; 1024];
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        for i in 0..8 {
+            pack.stream_add(
+                contents(i).as_slice(),
+                format!("entry-{}", i),
+                PackType::StdFile,
+                0,
+            )
+            .unwrap();
+        }
+        pack.build();
+
+        // Interrupt while writing the data of entry 3, leaving it partially written.
+        let interrupted = Interrupted {
+            archive: &archive,
+            remaining: ENTRY_LEN * 3 + 512 + 300,
+        };
+        assert!(Unpack::new(interrupted)
+            .unwrap()
+            .unpack_resumable(&journal)
+            .is_err());
+        assert!(std::fs::read("entry-3").unwrap().len() < 1024);
+        assert!(std::fs::metadata("entry-4").is_err());
+
+        // Entries that were completed before the interruption are not created again.
+        std::fs::write("entry-0", b"kept").unwrap();
+        let unpacked = Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_resumable(&journal)
+            .unwrap();
+        assert_eq!(unpacked, 5);
+        assert_eq!(std::fs::read("entry-0").unwrap(), b"kept");
+        for i in 1..8 {
+            assert_eq!(std::fs::read(format!("entry-{}", i)).unwrap(), contents(i));
+        }
+        assert!(std::fs::metadata(&journal).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_mode_round_trips() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _cwd = CWD_LOCK.lock().unwrap();
+        let src = std::env::temp_dir().join(format!("etl-mode-src-{}", std::process::id()));
+        let dest = std::env::temp_dir().join(format!("etl-mode-dest-{}", std::process::id()));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        // Archive paths must be relative, so add the file from its own directory.
+        std::env::set_current_dir(&src).unwrap();
+        std::fs::write("script", b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions("script", std::fs::Permissions::from_mode(0o751)).unwrap();
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        pack.file_add("script".into(), PackType::StdFile, 0)
+            .unwrap();
+        pack.stream_add(&b"data"[..], "plain".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.build();
+
+        let report = Unpack::new(archive.as_slice())
+            .unwrap()
+            .unpack_into(&dest)
+            .unwrap();
+        assert!(report.iter().all(|e| e.result.is_ok()));
+
+        let unpacked = dest.join("script");
+        let mode = std::fs::metadata(&unpacked).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o751);
+        assert_eq!(std::fs::read(&unpacked).unwrap(), b"#!/bin/sh\n");
+        // Streams carry no mode, so they get the default for new files.
+        assert!(std::fs::metadata(dest.join("plain")).is_ok());
+
+        std::fs::remove_dir_all(&src).unwrap();
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -24,6 +24,11 @@ enum Commands {
         file_list: Vec<String>,
     },
     Unpack {
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Record progress here, and resume from it if a previous unpack was interrupted.
+        #[arg(long, conflicts_with = "threads")]
+        journal: Option<String>,
         archive_path: String,
     },
     Inspect {
@@ -84,10 +89,20 @@ fn main() {
 
             pack.build();
         }
-        Commands::Unpack { archive_path } => {
+        Commands::Unpack {
+            threads,
+            journal,
+            archive_path,
+        } => {
             let archive = std::fs::File::open(archive_path).unwrap();
             let unpack = Unpack::new(archive).unwrap();
-            unpack.unpack().unwrap();
+            if let Some(journal) = journal {
+                unpack.unpack_resumable(journal.as_ref()).unwrap();
+            } else if let Some(threads) = threads {
+                unpack.unpack_parallel(".".as_ref(), threads).unwrap();
+            } else {
+                unpack.unpack().unwrap();
+            }
         }
         Commands::Inspect { archive_path } => {
             let archive = std::fs::File::open(archive_path).unwrap();
@@ -0,0 +1,160 @@
+//! Shell input handling. Pasted text arrives in a single read, possibly several lines at once,
+//! and possibly with lines longer than the line editor's buffer. [LineSplitter] sits between the
+//! terminal and the editor and hands the editor one line at a time, so each pasted line runs as
+//! its own command, in order.
+
+use std::collections::VecDeque;
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const BS: u8 = 0x08;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Escape {
+    None,
+    Start,
+    Csi,
+}
+
+pub struct LineSplitter {
+    pending: VecDeque<u8>,
+    line_max: usize,
+    line_len: usize,
+    escape: Escape,
+    last_was_cr: bool,
+    overflowed: bool,
+}
+
+impl LineSplitter {
+    /// Create a splitter for an editor that can hold at most `line_max` bytes of a line.
+    pub fn new(line_max: usize) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            line_max,
+            line_len: 0,
+            escape: Escape::None,
+            last_was_cr: false,
+            overflowed: false,
+        }
+    }
+
+    /// True if all queued input has been handed out.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queue input read from the terminal. Line endings are turned into a single CR, which is what
+    /// the editor gets from the enter key, so that pasted LF and CRLF text works too.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            match b {
+                LF if self.last_was_cr => {}
+                LF | CR => self.pending.push_back(CR),
+                _ => self.pending.push_back(b),
+            }
+            self.last_was_cr = b == CR;
+        }
+    }
+
+    /// Move queued input into `buf`, stopping after the first line ending, so that the editor
+    /// never sees the next command before it has returned the current one. Once the current line
+    /// is full, the rest of it is dropped and the line is marked as overflowed (see
+    /// [Self::take_overflow]).
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            let Some(b) = self.pending.pop_front() else {
+                break;
+            };
+            if b == CR {
+                self.line_len = 0;
+                self.escape = Escape::None;
+                buf[n] = b;
+                n += 1;
+                break;
+            }
+            if self.keep(b) {
+                buf[n] = b;
+                n += 1;
+            }
+        }
+        n
+    }
+
+    /// Returns true if part of the line the editor last returned was dropped because it didn't
+    /// fit, and clears the mark.
+    pub fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
+
+    // Only printable bytes take up room in the editor's buffer. Control bytes and escape sequences
+    // (arrow keys, terminal replies) are editing commands, and always get through.
+    fn keep(&mut self, b: u8) -> bool {
+        match self.escape {
+            Escape::Start => {
+                self.escape = if b == b'[' { Escape::Csi } else { Escape::None };
+                return true;
+            }
+            Escape::Csi => {
+                if (0x40..=0x7e).contains(&b) {
+                    self.escape = Escape::None;
+                }
+                return true;
+            }
+            Escape::None => {}
+        }
+        match b {
+            ESC => {
+                self.escape = Escape::Start;
+                true
+            }
+            BS | DEL => {
+                self.line_len = self.line_len.saturating_sub(1);
+                true
+            }
+            0..=0x1f => true,
+            _ if self.line_len < self.line_max => {
+                self.line_len += 1;
+                true
+            }
+            _ => {
+                self.overflowed = true;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn next_line(input: &mut LineSplitter) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let mut line = Vec::new();
+        while line.last() != Some(&CR) {
+            let n = input.read(&mut buf);
+            assert!(n > 0);
+            line.extend_from_slice(&buf[..n]);
+        }
+        line
+    }
+
+    #[test]
+    fn overflow_mid_paste() {
+        let mut input = LineSplitter::new(8);
+        input.push(b"ls\r\nwrite 0123456789\nls\x1b[A\n");
+
+        assert_eq!(next_line(&mut input), b"ls\r");
+        assert!(!input.take_overflow());
+
+        // The long line is cut at the limit; the next line is unaffected.
+        assert_eq!(next_line(&mut input), b"write 01\r");
+        assert!(input.take_overflow());
+        assert_eq!(next_line(&mut input), b"ls\x1b[A\r");
+        assert!(!input.take_overflow());
+        assert!(input.is_empty());
+    }
+}
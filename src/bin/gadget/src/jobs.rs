@@ -0,0 +1,137 @@
+//! A small registry of background tasks (the HTTP server, background watches), so the shell can
+//! list them and stop them.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+/// Shared flag telling a background task to stop. Tasks are expected to check it regularly and
+/// return once it is set, finishing any work they are in the middle of first.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+struct Job {
+    name: String,
+    token: CancelToken,
+    thread: JoinHandle<()>,
+}
+
+/// Summary of a registered job, as shown by `jobs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobInfo {
+    pub id: u64,
+    pub name: String,
+    pub running: bool,
+}
+
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+}
+
+impl JobRegistry {
+    /// Run `f` on a new thread as a background job named `name`. The closure gets the job's
+    /// cancellation token. Returns the job's id.
+    pub fn spawn<F>(&self, name: impl ToString, f: F) -> u64
+    where
+        F: FnOnce(CancelToken) + Send + 'static,
+    {
+        let token = CancelToken::new();
+        let thread_token = token.clone();
+        let thread = std::thread::spawn(move || f(thread_token));
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.jobs.lock().unwrap().insert(
+            id,
+            Job {
+                name: name.to_string(),
+                token,
+                thread,
+            },
+        );
+        id
+    }
+
+    /// List registered jobs. Jobs whose threads have exited on their own are reported once as
+    /// not running, and then forgotten.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let list = jobs
+            .iter()
+            .map(|(id, job)| JobInfo {
+                id: *id,
+                name: job.name.clone(),
+                running: !job.thread.is_finished(),
+            })
+            .collect();
+        jobs.retain(|_, job| !job.thread.is_finished());
+        list
+    }
+
+    /// Cancel job `id` and wait for it to exit. Returns false if there is no such job.
+    pub fn kill(&self, id: u64) -> bool {
+        // Don't hold the lock while waiting, so that the job may still use the registry.
+        let Some(job) = self.jobs.lock().unwrap().remove(&id) else {
+            return false;
+        };
+        job.token.cancel();
+        if job.thread.join().is_err() {
+            tracing::warn!("job {} ({}) panicked", id, job.name);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn kill_cancels_job() {
+        let jobs = JobRegistry::default();
+        let (tx, rx) = mpsc::channel();
+        let id = jobs.spawn("spinner", move |token| {
+            while !token.is_cancelled() {
+                std::thread::yield_now();
+            }
+            tx.send(()).unwrap();
+        });
+
+        assert_eq!(
+            jobs.list(),
+            [JobInfo {
+                id,
+                name: "spinner".to_owned(),
+                running: true
+            }]
+        );
+
+        assert!(jobs.kill(id));
+        // The job saw the cancellation and ran to completion before kill returned.
+        assert!(rx.try_recv().is_ok());
+        assert!(jobs.list().is_empty());
+        assert!(!jobs.kill(id));
+    }
+}
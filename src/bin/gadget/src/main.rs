@@ -1,39 +1,104 @@
 use std::{
+    collections::HashSet,
     fs::OpenOptions,
     io::{ErrorKind, Read, Write},
     net::Ipv4Addr,
+    path::Path,
+    sync::atomic::AtomicU64,
     time::{Duration, Instant},
 };
 
 use colored::Colorize;
 use embedded_io::ErrorType;
+use etl_twizzler::etl::Unpack;
+use input::LineSplitter;
+use jobs::{CancelToken, JobRegistry};
 use monitor_api::CompartmentHandle;
 use naming::{static_naming_factory, GetFlags, NsNodeKind, StaticNamingHandle as NamingHandle};
 use pager::adv_lethe;
 use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use tiny_http::Response;
 use tracing::Level;
-use twizzler::{collections::vec::VecObject, marker::Invariant, object::ObjectBuilder};
-use twizzler_abi::syscall::{
-    sys_object_create, BackingType, LifetimeType, ObjectCreate, ObjectCreateFlags,
+use twizzler::{
+    collections::vec::{VecObject, VecObjectAlloc},
+    marker::Invariant,
+    object::ObjectBuilder,
+};
+use twizzler_abi::{
+    object::{ObjID, NULLPAGE_SIZE},
+    syscall::{
+        sys_object_create, sys_object_ctrl, sys_thread_sync, BackingType, DeleteFlags,
+        LifetimeType, ObjectControlCmd, ObjectCreate, ObjectCreateFlags, ObjectSource, ThreadSync,
+        ThreadSyncFlags, ThreadSyncOp, ThreadSyncReference, ThreadSyncSleep, ThreadSyncWake,
+    },
 };
 use twizzler_rt_abi::object::MapFlags;
 
-struct TwzIo;
+mod input;
+mod jobs;
+mod mount;
+
+// Offset of the size word in a file object, following the runtime's file metadata header
+// (magic, then size) that sits right after the null page.
+const FILE_SIZE_WORD_OFFSET: usize = NULLPAGE_SIZE + 8;
+// The header takes up the page after the null page, and file data starts after it. Data past
+// FILE_OBJECT_DATA_MAX bytes spills into other objects that the header points to.
+const FILE_DATA_OFFSET: usize = NULLPAGE_SIZE * 2;
+const FILE_OBJECT_DATA_MAX: u64 = (1 << 26) - FILE_DATA_OFFSET as u64;
+// How long watch sleeps before re-checking a file whose writer didn't signal a wakeup.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const WATCH_DEFAULT_UPDATES: usize = 10;
+// How long the HTTP server waits for a request before checking whether it has been stopped.
+const HTTP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size of the line editor's buffer, and so the longest command the shell accepts.
+const LINE_BUFFER_SIZE: usize = 1024;
+/// Number of pages of the test vector to read in as soon as it is mapped.
+const VEC_PREFETCH_PAGES: usize = 64;
+
+struct TwzIo<R> {
+    source: R,
+    input: LineSplitter,
+}
+
+impl<R: Read> TwzIo<R> {
+    fn new(source: R) -> Self {
+        Self {
+            source,
+            input: LineSplitter::new(LINE_BUFFER_SIZE),
+        }
+    }
+}
 
-impl ErrorType for TwzIo {
+impl<R> ErrorType for TwzIo<R> {
     type Error = std::io::Error;
 }
 
-impl embedded_io::Read for TwzIo {
+impl<R: Read> embedded_io::Read for TwzIo<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        let len = std::io::stdin().read(buf)?;
-
-        Ok(len)
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.input.is_empty() {
+                let mut chunk = [0; 256];
+                let len = self.source.read(&mut chunk)?;
+                if len == 0 {
+                    return Ok(0);
+                }
+                self.input.push(&chunk[..len]);
+            }
+            // Everything queued may have been dropped as overflow, in which case read more.
+            let len = self.input.read(buf);
+            if len > 0 {
+                return Ok(len);
+            }
+        }
     }
 }
 
-impl embedded_io::Write for TwzIo {
+impl<R> embedded_io::Write for TwzIo<R> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         std::io::stdout().write(buf)
     }
@@ -59,40 +124,130 @@ fn lethe_cmd(args: &[&str], _namer: &mut NamingHandle) {
     }
 }
 
+/// A compartment as reported by `show compartments`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CompartmentEntry {
+    name: String,
+    state: String,
+    libs: Vec<LibraryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LibraryEntry {
+    name: String,
+    id: String,
+    gates: Vec<String>,
+}
+
+/// A name in the current namespace as reported by `show files`. The modification time is in
+/// seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileEntry {
+    name: String,
+    kind: String,
+    size: Option<u64>,
+    modified: Option<u64>,
+    id: String,
+}
+
+fn compartment_entries() -> Vec<CompartmentEntry> {
+    ["monitor", "init", "gadget", "naming", "logboi", "pager-srv"]
+        .into_iter()
+        .map(|name| {
+            let ch = monitor_api::CompartmentHandle::lookup(name).unwrap();
+            let info = ch.info();
+            CompartmentEntry {
+                name: info.name,
+                state: format!("{:?}", info.flags),
+                libs: ch
+                    .libs()
+                    .map(|lib| {
+                        let libinfo = lib.info();
+                        LibraryEntry {
+                            name: libinfo.name,
+                            id: format!("{}", libinfo.objid),
+                            gates: lib.gates().into_iter().map(|gate| gate.name).collect(),
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn file_entries(namer: &mut NamingHandle) -> Vec<FileEntry> {
+    let names = namer.enumerate_names().unwrap();
+    names
+        .into_iter()
+        .map(|name| {
+            let name_str = name.name().unwrap();
+            let (kind, size) = match name.kind {
+                NsNodeKind::Namespace => ("ns", None),
+                NsNodeKind::SymLink => ("link", None),
+                NsNodeKind::Object => ("obj", file_size(name.id)),
+            };
+            let modified = std::fs::metadata(&name_str)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            FileEntry {
+                name: name_str.to_owned(),
+                kind: kind.to_owned(),
+                size,
+                modified,
+                id: format!("{:x}", name.id),
+            }
+        })
+        .collect()
+}
+
 fn show(args: &[&str], namer: &mut NamingHandle) {
+    let json = args.iter().any(|a| *a == "-j" || *a == "--json");
+    let args = args
+        .iter()
+        .filter(|a| **a != "-j" && **a != "--json")
+        .collect::<Vec<_>>();
     if args.len() <= 1 {
-        println!("usage: show <item>");
+        println!("usage: show [-j|--json] <item>");
         println!("possible items: compartments, files, lethe");
         return;
     }
-    match args[1] {
+    match *args[1] {
         "c" | "comp" | "compartments" => {
-            fn print_compartment(ch: CompartmentHandle) {
-                let info = ch.info();
-                println!(" -- {} (state: {:?})", info.name, info.flags);
-                for lib in ch.libs() {
-                    let libinfo = lib.info();
-                    println!("     -- {:30} {}", libinfo.name, libinfo.objid,)
+            let entries = compartment_entries();
+            if json {
+                println!("{}", serde_json::to_string(&entries).unwrap());
+                return;
+            }
+            for entry in entries {
+                println!(" -- {} (state: {})", entry.name, entry.state);
+                for lib in entry.libs {
+                    println!("     -- {:30} {}", lib.name, lib.id);
+                    for gate in lib.gates {
+                        println!("         * {}", gate);
+                    }
                 }
             }
-
-            let gadget = monitor_api::CompartmentHandle::lookup("gadget").unwrap();
-            let init = monitor_api::CompartmentHandle::lookup("init").unwrap();
-            let monitor = monitor_api::CompartmentHandle::lookup("monitor").unwrap();
-            let namer = monitor_api::CompartmentHandle::lookup("naming").unwrap();
-            let logger = monitor_api::CompartmentHandle::lookup("logboi").unwrap();
-            let pager = monitor_api::CompartmentHandle::lookup("pager-srv").unwrap();
-            print_compartment(monitor);
-            print_compartment(init);
-            print_compartment(gadget);
-            print_compartment(namer);
-            print_compartment(logger);
-            print_compartment(pager);
         }
         "f" | "fi" | "files" => {
-            let names = namer.enumerate_names().unwrap();
-            for name in names {
-                println!("{:<20} :: {:x}", name.name().unwrap(), name.id);
+            let entries = file_entries(namer);
+            if json {
+                println!("{}", serde_json::to_string(&entries).unwrap());
+                return;
+            }
+            for entry in entries {
+                let size = match entry.kind.as_str() {
+                    "obj" => entry.size.map_or("?".to_string(), human_size),
+                    _ => String::new(),
+                };
+                let modified = entry.modified.map_or("-".to_string(), |secs| {
+                    format_time(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+                });
+                println!(
+                    "{:<20} {:<4} {:>10} {:<19} :: {}",
+                    entry.name, entry.kind, size, modified, entry.id
+                );
             }
         }
         _ => {
@@ -101,6 +256,60 @@ fn show(args: &[&str], namer: &mut NamingHandle) {
     }
 }
 
+/// Read the size of a file object from its metadata header, or None if the object can't be mapped
+/// or isn't a file.
+fn file_size(id: ObjID) -> Option<u64> {
+    const FILE_MAGIC: u64 = 0xBEEFDEAD;
+    let handle = twizzler_rt_abi::object::twz_rt_map_object(id.into(), MapFlags::READ).ok()?;
+    let word = |off: usize| unsafe {
+        (*handle.start().add(off).cast::<AtomicU64>()).load(std::sync::atomic::Ordering::SeqCst)
+    };
+    let (magic, size) = (word(NULLPAGE_SIZE), word(FILE_SIZE_WORD_OFFSET));
+    (magic == FILE_MAGIC).then_some(size)
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Format a time as "YYYY-MM-DD HH:MM:SS" (UTC).
+fn format_time(time: std::time::SystemTime) -> String {
+    let Ok(since_epoch) = time.duration_since(std::time::UNIX_EPOCH) else {
+        return "-".to_string();
+    };
+    let secs = since_epoch.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // Convert days since the epoch to a civil date (Howard Hinnant's days_from_civil, inverted).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
 fn demo(_args: &[&str]) {
     tracing::info!("starting gadget file create demo");
     let file_id = sys_object_create(
@@ -141,10 +350,14 @@ fn read_file(args: &[&str], namer: &mut NamingHandle) {
         println!("usage: read <filename>");
     }
     let filename = args[1];
-    let Ok(_id) = namer.get(filename, GetFlags::FOLLOW_SYMLINK) else {
+    if let Err(e) = mount::materialize(filename, namer) {
+        tracing::warn!("failed to materialize {}: {}", filename, e);
+        return;
+    }
+    if namer.get(filename, GetFlags::FOLLOW_SYMLINK).is_err() {
         tracing::warn!("name {} not found", filename);
         return;
-    };
+    }
 
     //let idname = id.to_string();
     let mut file = std::fs::File::open(&filename).unwrap();
@@ -177,6 +390,154 @@ fn write_file(args: &[&str], namer: &mut NamingHandle) {
     file.sync_all().unwrap();
 }
 
+/// Wake up anyone sleeping on the size word of file `id` (see [watch_file]).
+fn notify_file_change(id: ObjID) {
+    let wake = ThreadSyncWake::new(
+        ThreadSyncReference::ObjectRef(id, FILE_SIZE_WORD_OFFSET),
+        usize::MAX,
+    );
+    let _ = sys_thread_sync(&mut [ThreadSync::new_wake(wake)], None);
+}
+
+fn read_contents(filename: &str) -> Option<Vec<u8>> {
+    let mut file = std::fs::File::open(filename).ok()?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
+
+fn watch_file(args: &[&str], namer: &mut NamingHandle, jobs: &JobRegistry) {
+    // A trailing & runs the watch as a background job.
+    let (args, background) = match args.split_last() {
+        Some((&"&", rest)) => (rest, true),
+        _ => (args, false),
+    };
+    if args.len() < 2 {
+        println!("usage: watch <filename> [updates] [&]");
+        return;
+    }
+    let filename = args[1].to_owned();
+    let updates = match args.get(2).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("usage: watch <filename> [updates] [&]");
+            return;
+        }
+        None => WATCH_DEFAULT_UPDATES,
+    };
+    if background {
+        let id = jobs.spawn(format!("watch {}", filename), move |token| {
+            let mut namer = static_naming_factory().unwrap();
+            watch_until(&filename, updates, &mut namer, &token);
+        });
+        println!("[{}] watching {} in the background", id, args[1]);
+    } else {
+        watch_until(&filename, updates, namer, &CancelToken::new());
+    }
+}
+
+fn watch_until(filename: &str, updates: usize, namer: &mut NamingHandle, token: &CancelToken) {
+    let Ok(node) = namer.get(filename, GetFlags::FOLLOW_SYMLINK) else {
+        tracing::warn!("name {} not found", filename);
+        return;
+    };
+
+    let handle = match twizzler_rt_abi::object::twz_rt_map_object(node.id.into(), MapFlags::READ) {
+        Ok(handle) => handle,
+        Err(e) => {
+            tracing::warn!("failed to map {}: {}", filename, e);
+            return;
+        }
+    };
+    let size_word = unsafe {
+        handle
+            .start()
+            .add(FILE_SIZE_WORD_OFFSET)
+            .cast::<AtomicU64>()
+            .cast_const()
+    };
+
+    let Some(mut contents) = read_contents(filename) else {
+        tracing::warn!("failed to read {}", filename);
+        return;
+    };
+    println!("{}", String::from_utf8_lossy(&contents));
+
+    let mut seen = 0;
+    while seen < updates && !token.is_cancelled() {
+        let size = unsafe { &*size_word }.load(std::sync::atomic::Ordering::SeqCst);
+        let sleep = ThreadSyncSleep::new(
+            ThreadSyncReference::Virtual(size_word),
+            size,
+            ThreadSyncOp::Equal,
+            ThreadSyncFlags::empty(),
+        );
+        // Writers that don't signal the size word are still picked up by the timeout.
+        let _ = sys_thread_sync(
+            &mut [ThreadSync::new_sleep(sleep)],
+            Some(WATCH_POLL_INTERVAL),
+        );
+
+        if namer.get(filename, GetFlags::FOLLOW_SYMLINK).is_err() {
+            println!("  -> {} was deleted, done watching.", filename.italic());
+            return;
+        }
+        let Some(new_contents) = read_contents(filename) else {
+            println!(
+                "  -> {} can no longer be read, done watching.",
+                filename.italic()
+            );
+            return;
+        };
+        if new_contents != contents {
+            seen += 1;
+            println!("  -> {} changed ({}/{}):", filename.italic(), seen, updates);
+            println!("{}", String::from_utf8_lossy(&new_contents));
+            contents = new_contents;
+        }
+    }
+}
+
+fn list_jobs(jobs: &JobRegistry) {
+    for job in jobs.list() {
+        let state = if job.running { "running" } else { "done" };
+        println!("[{}] {:8} {}", job.id, state, job.name);
+    }
+}
+
+fn mount_archive(args: &[&str], namer: &mut NamingHandle) {
+    let Some(archive) = args.get(1) else {
+        println!("usage: mount <archive> [mount-point]");
+        return;
+    };
+    // By default, mount foo.tar at foo.
+    let mount_point = args.get(2).map_or_else(
+        || {
+            Path::new(archive)
+                .with_extension("")
+                .to_string_lossy()
+                .into_owned()
+        },
+        |dir| dir.to_string(),
+    );
+    match mount::mount(archive, &mount_point, namer) {
+        Ok(files) => tracing::info!("mounted {} at {} ({} files)", archive, mount_point, files),
+        Err(e) => println!("mount {}: {}", archive, e),
+    }
+}
+
+fn kill_job(args: &[&str], jobs: &JobRegistry) {
+    let Some(Ok(id)) = args.get(1).map(|id| id.parse::<u64>()) else {
+        println!("usage: kill <id>");
+        return;
+    };
+    if jobs.kill(id) {
+        println!("[{}] stopped", id);
+    } else {
+        println!("no such job {}", id);
+    }
+}
+
 fn new_file(args: &[&str], namer: &mut NamingHandle) {
     if args.len() < 2 {
         println!("usage: new <filename>");
@@ -196,6 +557,139 @@ fn new_file(args: &[&str], namer: &mut NamingHandle) {
     );
 }
 
+fn link_file(args: &[&str], namer: &mut NamingHandle) {
+    if args.len() < 3 {
+        println!("usage: ln <target> <alias>");
+        return;
+    }
+    let (target, alias) = (args[1], args[2]);
+    if namer.get(alias, GetFlags::empty()).is_ok() {
+        tracing::warn!("name {} already exists", alias);
+        return;
+    }
+    if let Err(e) = namer.put_symlink(alias, target) {
+        tracing::warn!("failed to link {} -> {}: {}", alias, target, e);
+        return;
+    }
+    match namer.get(alias, GetFlags::FOLLOW_SYMLINK) {
+        Ok(node) => tracing::info!("{} -> {} (objid: {:x})", alias, target, node.id),
+        Err(_) => tracing::info!("{} -> {} (dangling)", alias, target),
+    }
+}
+
+fn copy_file(args: &[&str], namer: &mut NamingHandle) {
+    let force = args.iter().any(|a| *a == "-f");
+    let args = args.iter().filter(|a| **a != "-f").collect::<Vec<_>>();
+    if args.len() < 3 {
+        println!("usage: cp [-f] <src> <dst>");
+        return;
+    }
+    let (src, dst) = (*args[1], *args[2]);
+    match cp(namer, src, dst, force) {
+        Ok(id) => tracing::info!("copied {} to {} (objid: {:x})", src, dst, id),
+        Err(e) => println!("cp {} {}: {}", src, dst, e),
+    }
+}
+
+/// Copy the file `src` to a new file named `dst`, returning the new file's object ID. If `dst`
+/// already exists, it's replaced when `force` is set, and otherwise it's an error. The copy is
+/// made before `dst` is touched, and the name is then pointed at it, so a failed copy leaves `dst`
+/// as it was. Files that fit in one object are copied by the kernel, object to object. Larger
+/// files (which span several objects), and files the kernel fails to copy, are read and written
+/// back out instead.
+fn cp(namer: &mut NamingHandle, src: &str, dst: &str, force: bool) -> std::io::Result<ObjID> {
+    let src_node = namer
+        .get(src, GetFlags::FOLLOW_SYMLINK)
+        .map_err(naming_io_error)?;
+    if src_node.kind != NsNodeKind::Object {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a file", src),
+        ));
+    }
+    let replaced = match namer.get(dst, GetFlags::empty()) {
+        Ok(node) if force && node.kind != NsNodeKind::Namespace => Some(node),
+        Ok(_) => return Err(ErrorKind::AlreadyExists.into()),
+        Err(_) => None,
+    };
+
+    let id = match copy_file_object(src_node.id) {
+        Some(id) => id,
+        None => {
+            tracing::debug!("falling back to copying {} by reading it", src);
+            copy_file_by_reading(namer, src, dst)?
+        }
+    };
+    let delete = |id| {
+        let _ = sys_object_ctrl(id, ObjectControlCmd::Delete(DeleteFlags::empty()));
+    };
+
+    if replaced.is_some() {
+        if let Err(e) = namer.remove(dst) {
+            delete(id);
+            return Err(naming_io_error(e));
+        }
+    }
+    if let Err(e) = namer.put(dst, id) {
+        // Put back whatever was there.
+        if let Some(old) = &replaced {
+            let _ = match old.readlink() {
+                Ok(target) => namer.put_symlink(dst, target),
+                Err(_) => namer.put(dst, old.id),
+            };
+        }
+        delete(id);
+        return Err(naming_io_error(e));
+    }
+    if let Some(old) = replaced.filter(|old| old.kind == NsNodeKind::Object) {
+        delete(old.id);
+    }
+    Ok(id)
+}
+
+/// Copy the file `src` into a new file object by reading it, and return the object, without a
+/// name. It's written under a temporary name next to `dst`, which is dropped once it's done.
+fn copy_file_by_reading(namer: &mut NamingHandle, src: &str, dst: &str) -> std::io::Result<ObjID> {
+    let tmp = format!("{}.cp-{}", dst, std::process::id());
+    let copied = std::fs::read(src)
+        .and_then(|data| std::fs::write(&tmp, data))
+        .and_then(|_| {
+            namer
+                .get(&tmp, GetFlags::empty())
+                .map(|node| node.id)
+                .map_err(naming_io_error)
+        });
+    match copied {
+        Ok(id) => {
+            namer.remove(&tmp).map_err(naming_io_error)?;
+            Ok(id)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Make a new object holding a copy of the file object `src`, or None if the file doesn't fit in
+/// one object or the kernel can't copy it.
+fn copy_file_object(src: ObjID) -> Option<ObjID> {
+    let size = file_size(src).filter(|size| *size <= FILE_OBJECT_DATA_MAX)?;
+    let len = FILE_DATA_OFFSET - NULLPAGE_SIZE + size as usize;
+    let source = ObjectSource::new_copy(src, NULLPAGE_SIZE as u64, NULLPAGE_SIZE as u64, len);
+    sys_object_create(
+        ObjectCreate::new(
+            BackingType::Normal,
+            LifetimeType::Persistent,
+            None,
+            ObjectCreateFlags::empty(),
+        ),
+        &[source],
+        &[],
+    )
+    .ok()
+}
+
 fn del_file(args: &[&str], namer: &mut NamingHandle) {
     if args.len() < 2 {
         println!("usage: write <filename>");
@@ -214,12 +708,102 @@ fn del_file(args: &[&str], namer: &mut NamingHandle) {
     adv_lethe();
 }
 
-fn setup_http(namer: &mut NamingHandle) {
+fn du_cmd(args: &[&str], namer: &mut NamingHandle) {
+    let path = args.get(1).copied().unwrap_or(".");
+    match du(namer, path) {
+        Ok(total) => println!("{:>10}  {}", human_size(total), path),
+        Err(e) => println!("du {}: {}", path, e),
+    }
+}
+
+/// Total size in bytes of the files under the namespace at `path`, like `du -s`. Namespaces are
+/// walked recursively and symlinks are not followed. An object reachable under several names is
+/// only counted once, and a namespace that is reached again (a cycle) is not walked again.
+fn du(namer: &mut NamingHandle, path: &str) -> std::io::Result<u64> {
+    let root = namer
+        .get(path, GetFlags::FOLLOW_SYMLINK)
+        .map_err(naming_io_error)?;
+    if root.kind != NsNodeKind::Namespace {
+        return Ok(file_size(root.id).unwrap_or(0));
+    }
+    let mut seen = HashSet::from([root.id]);
+    du_namespace(namer, Path::new(path), &mut seen)
+}
+
+fn du_namespace(
+    namer: &mut NamingHandle,
+    path: &Path,
+    seen: &mut HashSet<ObjID>,
+) -> std::io::Result<u64> {
+    let mut total = 0;
+    for node in namer
+        .enumerate_names_relative(path)
+        .map_err(naming_io_error)?
+    {
+        let name = node.name().map_err(naming_io_error)?;
+        if name == "." || name == ".." || !seen.insert(node.id) {
+            continue;
+        }
+        total += match node.kind {
+            NsNodeKind::Namespace => du_namespace(namer, &path.join(name), seen)?,
+            NsNodeKind::Object => file_size(node.id).unwrap_or(0),
+            NsNodeKind::SymLink => 0,
+        };
+    }
+    Ok(total)
+}
+
+fn naming_io_error(e: twizzler_rt_abi::error::TwzError) -> std::io::Error {
+    std::io::Error::other(format!("{:?}", e))
+}
+
+/// Unpack a tar archive uploaded with PUT into the namespace at `path`, returning a plain-text
+/// report with one line per file.
+fn import_archive(path: &str, archive: &[u8], namer: &mut NamingHandle) -> std::io::Result<String> {
+    let report = Unpack::new(archive)?.unpack_into(Path::new(path))?;
+    let mut body = String::new();
+    for entry in report {
+        let full_path = Path::new(path).join(&entry.path);
+        match entry.result {
+            Ok(()) => {
+                println!(
+                    "  -> Imported {}.",
+                    full_path.display().to_string().italic()
+                );
+                if let Ok(node) = namer.get(&full_path.to_string_lossy(), GetFlags::FOLLOW_SYMLINK)
+                {
+                    notify_file_change(node.id.into());
+                }
+                body.push_str(&format!("ok {}\n", entry.path));
+            }
+            Err(e) => {
+                tracing::warn!("failed to import {}: {}", full_path.display(), e);
+                body.push_str(&format!("failed {}: {}\n", entry.path, e));
+            }
+        }
+    }
+    Ok(body)
+}
+
+fn setup_http(namer: &mut NamingHandle, token: &CancelToken) {
     tracing::info!("setting up http");
     let server = tiny_http::Server::http((Ipv4Addr::new(127, 0, 0, 1), 5555)).unwrap();
     tracing::info!("server ready");
-    let mut reqs = server.incoming_requests();
-    while let Some(mut request) = reqs.next() {
+    serve_http(&server, namer, token);
+}
+
+/// Serve requests until `token` is cancelled. A request that has already been received is always
+/// answered in full before the server stops.
+fn serve_http(server: &tiny_http::Server, namer: &mut NamingHandle, token: &CancelToken) {
+    while !token.is_cancelled() {
+        let mut request = match server.recv_timeout(HTTP_POLL_INTERVAL) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!("http server failed: {}", e);
+                return;
+            }
+        };
         if let Some(ra) = request.remote_addr() {
             tracing::info!("connection from: {}", ra);
         }
@@ -227,6 +811,16 @@ fn setup_http(namer: &mut NamingHandle) {
         let path = request.url().to_string();
         tracing::info!("serving {} {}", request.method(), path);
         request.as_reader().read_to_end(&mut buf).unwrap();
+        // A mounted entry can't be opened until it has been materialized.
+        if *request.method() == tiny_http::Method::Get {
+            if let Err(e) = mount::materialize(&path, namer) {
+                let _ = request.respond(
+                    Response::from_string(format!("file {} could not be unpacked: {}", path, e))
+                        .with_status_code(500),
+                );
+                continue;
+            }
+        }
         let _ = match request.method() {
             tiny_http::Method::Get => match namer.change_namespace(&path) {
                 Ok(_) => {
@@ -307,6 +901,9 @@ fn setup_http(namer: &mut NamingHandle) {
                         println!("  -> Note, though, that here we've just written file data to new sectors, already encrypted.");
                         println!("     So little work is done during epoch, this time.");
                         file.sync_all().unwrap();
+                        if let Ok(node) = namer.get(&path, GetFlags::FOLLOW_SYMLINK) {
+                            notify_file_change(node.id.into());
+                        }
                         request.respond(Response::empty(200))
                     }
                     Err(e) => request.respond(
@@ -315,6 +912,19 @@ fn setup_http(namer: &mut NamingHandle) {
                     ),
                 }
             }
+            tiny_http::Method::Put => {
+                println!(
+                    "  -> Importing an archive into {}. Each file is synced as it is written.",
+                    path.italic()
+                );
+                match import_archive(&path, &buf, namer) {
+                    Ok(body) => request.respond(Response::from_string(body)),
+                    Err(e) => request.respond(
+                        Response::from_string(format!("archive could not be unpacked: {}", e))
+                            .with_status_code(400),
+                    ),
+                }
+            }
             tiny_http::Method::Delete => {
                 println!("  -> First we'll remove the file, and then issue another {}.", "Lethe epoch".blue().italic());
                 match std::fs::remove_file(&path) {
@@ -335,6 +945,7 @@ fn setup_http(namer: &mut NamingHandle) {
         }
         .unwrap();
     }
+    tracing::info!("http server stopped");
 }
 
 fn banner() -> &'static str {
@@ -353,21 +964,28 @@ pub struct TestVecItem {
 unsafe impl Invariant for TestVecItem {}
 
 fn gdtest(args: &[&str], namer: &mut NamingHandle) {
-    let id = namer.get("test-vec", GetFlags::FOLLOW_SYMLINK);
-    let mut vo = if let Ok(id) = id {
-        let obj = twizzler::object::Object::map(
-            id.id.into(),
-            MapFlags::READ | MapFlags::WRITE | MapFlags::PERSIST,
+    // Several gdtest instances may start at once, so create and name the vector atomically.
+    let (id, _) = namer
+        .get_or_create(
+            "test-vec",
+            || {
+                let builder = ObjectBuilder::default().persist();
+                let vo = VecObject::<TestVecItem, VecObjectAlloc>::new(builder)?;
+                Ok(vo.object().id())
+            },
+            |id| {
+                let _ = sys_object_ctrl(id, ObjectControlCmd::Delete(DeleteFlags::empty()));
+            },
         )
         .unwrap();
-        VecObject::from(obj)
-    } else {
-        let builder = ObjectBuilder::default().persist();
-        let vo = VecObject::new(builder).unwrap();
-        let _ = namer.remove("test-vec");
-        namer.put("test-vec", vo.object().id()).unwrap();
-        vo
-    };
+    // Start reading the vector in before the first access, rather than faulting in page by page.
+    let obj = twizzler::object::Object::map_prefetch(
+        id,
+        MapFlags::READ | MapFlags::WRITE | MapFlags::PERSIST,
+        VEC_PREFETCH_PAGES,
+    )
+    .unwrap();
+    let mut vo = VecObject::from(obj);
 
     println!("vec object is: {}", vo.object().id());
 
@@ -380,7 +998,9 @@ fn gdtest(args: &[&str], namer: &mut NamingHandle) {
             }
         }
         "a" | "append" => {
-            vo.append((0..10_000_000).into_iter().map(|x| TestVecItem { x }))
+            const APPEND_COUNT: u32 = 10_000_000;
+            vo.reserve(APPEND_COUNT as usize).unwrap();
+            vo.append((0..APPEND_COUNT).into_iter().map(|x| TestVecItem { x }))
                 .unwrap();
         }
         "rs" | "read-all-slices" => {
@@ -445,9 +1065,10 @@ fn main() {
     //let mut logger = LogHandle::new().unwrap();
     //logger.log(b"Hello Logger!\n");
 
-    std::thread::spawn(|| {
+    let jobs = JobRegistry::default();
+    jobs.spawn("http server", |token| {
         let mut namer = static_naming_factory().unwrap();
-        setup_http(&mut namer);
+        setup_http(&mut namer, &token);
     });
 
     //tracing::info!("testing namer: {:?}", namer.get("initrd/gadget"));
@@ -456,72 +1077,389 @@ fn main() {
     println!("{}", banner());
     println!("       TWISTED GADGET DEMO");
 
-    let mut io = TwzIo;
-    let mut buffer = [0; 1024];
+    let mut io = TwzIo::new(std::io::stdin());
+    repl(&mut io, &mut namer, &jobs);
+}
+
+/// Read commands from `io` and run them until `quit`. Each line of pasted input is run as a
+/// separate command; a line too long for the editor is reported and not run at all.
+fn repl<R: Read>(io: &mut TwzIo<R>, namer: &mut NamingHandle, jobs: &JobRegistry) {
+    let mut buffer = [0; LINE_BUFFER_SIZE];
     let mut editor = noline::builder::EditorBuilder::from_slice(&mut buffer)
-        .build_sync(&mut io)
+        .build_sync(io)
         .unwrap();
     loop {
-        let line = editor.readline("gadget> ", &mut io).unwrap();
-        let split = line.split_whitespace().collect::<Vec<_>>();
-        if split.len() == 0 {
+        let line = editor.readline("gadget> ", io).unwrap();
+        if io.input.take_overflow() {
+            println!(
+                "input line too long (max {} bytes), ignored",
+                LINE_BUFFER_SIZE
+            );
             continue;
         }
-        match split[0] {
-            "show" => {
-                show(&split, &mut namer);
-            }
-            "intro" => {
-                println!("Welcome to the {}!", "Twisted Demo".bold());
-                println!();
-                println!("This terminal is a virtual machine demonstrating the Twisted Gadget.");
-                println!("The other terminal is on the host, and will be interacting with the gadget via HTTP.");
-                println!();
-                println!("This demo will show of creation, writing, reading, and deleting files");
-                println!(
-                    "from the Twisted Gadget. Files are stored using {}, the provable-deletion",
-                    "Lethe".bold()
-                );
-                println!(
-                    "filesystem developed as part of the Twisted project. The operating system"
-                );
-                println!("is {}, which enables strong isolation and cabability-based security, written in Rust.", "Twizzler".bold());
-            }
-            "quit" => {
-                break;
-            }
-            "clear" => {
-                print!("\x1b[2J");
-                println!("{}", banner());
-                println!("       TWISTED GADGET DEMO");
-            }
-            "test" => {
-                gdtest(&split, &mut namer);
-            }
-            "demo" => {
-                demo(&split);
-            }
-            "new" => {
-                new_file(&split, &mut namer);
-            }
-            "write" => {
-                write_file(&split, &mut namer);
-            }
-            "read" => {
-                read_file(&split, &mut namer);
-            }
-            "del" => {
-                del_file(&split, &mut namer);
-            }
-            "lethe" => {
-                lethe_cmd(&split, &mut namer);
-            }
-            //"http" => {
-            //    setup_http(&mut namer);
-            //}
-            _ => {
-                println!("unknown command {}", split[0]);
-            }
+        if !run_command(line, namer, jobs) {
+            break;
+        }
+    }
+}
+
+/// Run one command line. Returns false if the shell should exit.
+fn run_command(line: &str, namer: &mut NamingHandle, jobs: &JobRegistry) -> bool {
+    let split = line.split_whitespace().collect::<Vec<_>>();
+    if split.len() == 0 {
+        return true;
+    }
+    match split[0] {
+        "show" => {
+            show(&split, namer);
+        }
+        "intro" => {
+            println!("Welcome to the {}!", "Twisted Demo".bold());
+            println!();
+            println!("This terminal is a virtual machine demonstrating the Twisted Gadget.");
+            println!("The other terminal is on the host, and will be interacting with the gadget via HTTP.");
+            println!();
+            println!("This demo will show of creation, writing, reading, and deleting files");
+            println!(
+                "from the Twisted Gadget. Files are stored using {}, the provable-deletion",
+                "Lethe".bold()
+            );
+            println!("filesystem developed as part of the Twisted project. The operating system");
+            println!("is {}, which enables strong isolation and cabability-based security, written in Rust.", "Twizzler".bold());
+        }
+        "quit" => {
+            return false;
+        }
+        "clear" => {
+            print!("\x1b[2J");
+            println!("{}", banner());
+            println!("       TWISTED GADGET DEMO");
+        }
+        "test" => {
+            gdtest(&split, namer);
+        }
+        "demo" => {
+            demo(&split);
+        }
+        "new" => {
+            new_file(&split, namer);
+        }
+        "write" => {
+            write_file(&split, namer);
+        }
+        "read" => {
+            read_file(&split, namer);
+        }
+        "del" => {
+            del_file(&split, namer);
         }
+        "ln" => {
+            link_file(&split, namer);
+        }
+        "cp" => {
+            copy_file(&split, namer);
+        }
+        "watch" => {
+            watch_file(&split, namer, jobs);
+        }
+        "jobs" => {
+            list_jobs(jobs);
+        }
+        "kill" => {
+            kill_job(&split, jobs);
+        }
+        "lethe" => {
+            lethe_cmd(&split, namer);
+        }
+        "du" => {
+            du_cmd(&split, namer);
+        }
+        "mount" => {
+            mount_archive(&split, namer);
+        }
+        //"http" => {
+        //    setup_http(namer);
+        //}
+        _ => {
+            println!("unknown command {}", split[0]);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use etl_twizzler::etl::{Pack, PackType};
+    use tiny_http::shim::SmolTcpStream as TcpStream;
+
+    use super::*;
+
+    #[test]
+    fn show_files_formatting() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+
+        let t = std::time::UNIX_EPOCH + Duration::from_secs(951_782_400 + 3661);
+        assert_eq!(format_time(t), "2000-02-29 01:01:01");
+        assert_eq!(format_time(std::time::UNIX_EPOCH), "1970-01-01 00:00:00");
+    }
+
+    #[test]
+    fn show_files_json() {
+        let dir = format!("/data/gadget-json-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(format!("{}/hello", dir), b"hello world").unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        namer.change_namespace(dir).unwrap();
+        let entries = file_entries(&mut namer);
+        let json = serde_json::to_string(&entries).unwrap();
+        let parsed: Vec<FileEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, entries);
+
+        let hello = parsed.iter().find(|e| e.name == "hello").unwrap();
+        assert_eq!(hello.kind, "obj");
+        assert_eq!(hello.size, Some(11));
+    }
+
+    #[test]
+    fn du_sums_subtree() {
+        let dir = format!("/data/gadget-du-{}", std::process::id());
+        std::fs::create_dir_all(format!("{}/sub/deeper", dir)).unwrap();
+        std::fs::write(format!("{}/a", dir), [0u8; 100]).unwrap();
+        std::fs::write(format!("{}/sub/b", dir), [0u8; 2000]).unwrap();
+        std::fs::write(format!("{}/sub/deeper/c", dir), [0u8; 30000]).unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        assert_eq!(du(&mut namer, &dir).unwrap(), 32100);
+        assert_eq!(du(&mut namer, &format!("{}/sub", dir)).unwrap(), 32000);
+
+        // A second name for the same object doesn't count it twice, and links aren't followed.
+        let b = namer
+            .get(&format!("{}/sub/b", dir), GetFlags::empty())
+            .unwrap();
+        namer.put(format!("{}/b-again", dir), b.id).unwrap();
+        namer
+            .put_symlink(format!("{}/sub/deeper/up", dir), format!("{}/sub", dir))
+            .unwrap();
+        assert_eq!(du(&mut namer, &dir).unwrap(), 32100);
+    }
+
+    #[test]
+    fn cp_copies_contents() {
+        let dir = format!("/data/gadget-cp-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        let (src, dst) = (format!("{}/src", dir), format!("{}/dst", dir));
+        let data: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        let id = cp(&mut namer, &src, &dst, false).unwrap();
+        assert_ne!(id, namer.get(&src, GetFlags::empty()).unwrap().id);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+
+        // The copy is independent of the original.
+        std::fs::write(&src, b"changed").unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+
+        // An existing destination is only replaced when forced.
+        let err = cp(&mut namer, &src, &dst, false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let old = namer.get(&dst, GetFlags::empty()).unwrap().id;
+        let id = cp(&mut namer, &src, &dst, true).unwrap();
+        assert_ne!(id, old);
+        assert_eq!(std::fs::read(&dst).unwrap(), b"changed");
+
+        // A failed copy leaves the destination alone.
+        let missing = format!("{}/missing", dir);
+        assert!(cp(&mut namer, &missing, &dst, true).is_err());
+        assert_eq!(namer.get(&dst, GetFlags::empty()).unwrap().id, id);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pasted_lines_run_in_order() {
+        let dir = format!("/data/gadget-paste-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        let long = format!("new {}/{}", dir, "x".repeat(LINE_BUFFER_SIZE));
+        // Answer the editor's terminal size query, then paste everything at once. The overlong
+        // command in the middle is dropped without disturbing the ones around it.
+        let input = format!(
+            "\x1b[24;80Rnew {0}/a\nnew {0}/b\r\n{1}\r\nnew {0}/c\nquit\nnew {0}/d\n",
+            dir, long
+        );
+        let mut io = TwzIo::new(input.as_bytes());
+        let mut namer = static_naming_factory().unwrap();
+        repl(&mut io, &mut namer, &JobRegistry::default());
+
+        namer.change_namespace(dir).unwrap();
+        let mut names: Vec<_> = namer
+            .enumerate_names()
+            .unwrap()
+            .iter()
+            .map(|e| e.name().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn mount_materializes_on_read() {
+        let dir = format!("/data/gadget-mount-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive_path = format!("{}/demo.tar", dir);
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        for name in ["one", "sub/two"] {
+            pack.stream_add(name.as_bytes(), name.to_owned(), PackType::StdFile, 0)
+                .unwrap();
+        }
+        pack.build();
+        std::fs::write(&archive_path, &archive).unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        run_command(
+            &format!("mount {}", archive_path),
+            &mut namer,
+            &JobRegistry::default(),
+        );
+        let mounted = format!("{}/demo", dir);
+        let (one, two) = (format!("{}/one", mounted), format!("{}/sub/two", mounted));
+
+        // Both entries are named, but nothing has been unpacked yet, and opening them fails
+        // rather than finding an empty file.
+        assert!(mount::is_pending(&one, &mut namer) && mount::is_pending(&two, &mut namer));
+        assert!(std::fs::read(&two).is_err());
+
+        // Reading an entry materializes it, and only it.
+        run_command(
+            &format!("read {}", two),
+            &mut namer,
+            &JobRegistry::default(),
+        );
+        assert!(!mount::is_pending(&two, &mut namer));
+        assert!(mount::is_pending(&one, &mut namer));
+        assert_eq!(std::fs::read(&two).unwrap(), b"sub/two");
+        assert!(std::fs::read(&one).is_err());
+
+        // Materializing an entry again, or a file that was never mounted, does nothing.
+        mount::materialize(&two, &mut namer).unwrap();
+        mount::materialize(&archive_path, &mut namer).unwrap();
+        assert_eq!(std::fs::read(&two).unwrap(), b"sub/two");
+    }
+
+    #[test]
+    fn mount_failed_materialize_stays_pending() {
+        let dir = format!("/data/gadget-mount-fail-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive_path = format!("{}/demo.tar", dir);
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        pack.stream_add(&b"one"[..], "one".to_owned(), PackType::StdFile, 0)
+            .unwrap();
+        pack.build();
+        std::fs::write(&archive_path, &archive).unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        let mounted = format!("{}/demo", dir);
+        mount::mount(&archive_path, &mounted, &mut namer).unwrap();
+        let one = format!("{}/one", mounted);
+
+        // With nowhere to name the unpacked object, materializing fails, and the entry stays
+        // pending and unreadable.
+        let contents = format!("{}/{}", mounted, mount::CONTENTS_DIR);
+        namer.remove(&contents).unwrap();
+        assert!(mount::materialize(&one, &mut namer).is_err());
+        assert!(mount::is_pending(&one, &mut namer));
+        assert!(std::fs::read(&one).is_err());
+
+        // The next read tries again.
+        namer.put_namespace(&contents, false).unwrap();
+        mount::materialize(&one, &mut namer).unwrap();
+        assert!(!mount::is_pending(&one, &mut namer));
+        assert_eq!(std::fs::read(&one).unwrap(), b"one");
+    }
+
+    #[test]
+    fn mount_rejects_escaping_entries() {
+        let dir = format!("/data/gadget-mount-escape-{}", std::process::id());
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive_path = format!("{}/escape.tar", dir);
+
+        // tar::Builder refuses to write this path, so set the header name directly.
+        let mut archive = Vec::new();
+        let mut builder = tar::Builder::new(&mut archive);
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(4);
+        header.set_cksum();
+        builder.append(&header, &b"data"[..]).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+        std::fs::write(&archive_path, &archive).unwrap();
+
+        let mut namer = static_naming_factory().unwrap();
+        let err = mount::mount(&archive_path, &format!("{}/mnt", dir), &mut namer).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(std::fs::metadata(format!("{}/escape", dir)).is_err());
+    }
+
+    #[test]
+    fn put_archive_creates_files() {
+        // Let the OS pick the port, so this cannot collide with anything else listening.
+        let server = tiny_http::Server::http((Ipv4Addr::new(127, 0, 0, 1), 0)).unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        std::thread::spawn(move || {
+            let mut namer = static_naming_factory().unwrap();
+            serve_http(&server, &mut namer, &CancelToken::new());
+        });
+
+        let dir = std::env::temp_dir().join(format!("gadget-put-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut archive = Vec::new();
+        let mut pack = Pack::new(&mut archive);
+        for name in ["one", "two"] {
+            pack.stream_add(name.as_bytes(), name.to_owned(), PackType::StdFile, 0)
+                .unwrap();
+        }
+        pack.build();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let header = format!(
+            "PUT {} HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            dir,
+            archive.len()
+        );
+        client.write_all(header.as_bytes()).unwrap();
+        client.write_all(&archive).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("ok one\n"));
+        assert!(response.contains("ok two\n"));
+
+        let mut namer = static_naming_factory().unwrap();
+        namer.change_namespace(dir).unwrap();
+        let mut names: Vec<_> = namer
+            .enumerate_names()
+            .unwrap()
+            .iter()
+            .map(|e| e.name().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["one", "two"]);
+        assert_eq!(std::fs::read(format!("{}/two", dir)).unwrap(), b"two");
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
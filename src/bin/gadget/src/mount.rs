@@ -0,0 +1,207 @@
+//! Mounting an etl archive as a namespace without unpacking it up front. The mount point and the
+//! namespaces under it are not persistent, and each file entry is named right away by a symlink
+//! into the mount's [CONTENTS_DIR], which names nothing until the entry is first read. Until then,
+//! opening the entry fails rather than finding an empty file. On its first read, the entry is
+//! unpacked into a volatile object that the symlink's target then names, so nothing from the
+//! archive reaches persistent storage, and the whole mount is gone after a restart.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use etl_twizzler::etl::{path_under, Unpack};
+use naming::{GetFlags, NsNodeKind, StaticNamingHandle as NamingHandle};
+use twizzler_abi::{
+    meta::{MetaExt, MetaInfo, MEXT_SIZED},
+    object::{ObjID, Protections, MAX_SIZE, NULLPAGE_SIZE},
+    syscall::{
+        sys_object_create, sys_object_ctrl, BackingType, DeleteFlags, LifetimeType,
+        ObjectControlCmd, ObjectCreate, ObjectCreateFlags,
+    },
+};
+use twizzler_rt_abi::object::MapFlags;
+
+use crate::naming_io_error;
+
+/// The namespace, under the mount point, that names the objects of the entries that have been
+/// materialized.
+pub const CONTENTS_DIR: &str = ".contents";
+
+/// A mounted file entry.
+struct LazyEntry {
+    archive: Arc<[u8]>,
+    header_pos: u64,
+    /// Whether the entry has been materialized. Held while materializing, so two readers of the
+    /// same entry don't both unpack it.
+    done: Mutex<bool>,
+}
+
+/// Mounted entries still waiting to be materialized, by the path their placeholder links to.
+static PENDING: Mutex<BTreeMap<String, Arc<LazyEntry>>> = Mutex::new(BTreeMap::new());
+
+/// Mount the archive at `archive_path` as the namespace `mount_point`, which must not exist yet,
+/// returning the number of files it holds. The archive is read into memory once and shared by its
+/// entries.
+pub fn mount(
+    archive_path: &str,
+    mount_point: &str,
+    namer: &mut NamingHandle,
+) -> std::io::Result<usize> {
+    let archive: Arc<[u8]> = std::fs::read(archive_path)?.into();
+    let entries = Unpack::new(&archive[..])?.list()?;
+    // Check every path before creating anything, so a crafted archive can't place files outside
+    // the mount point.
+    let full_paths = entries
+        .iter()
+        .map(|entry| path_under(Path::new(mount_point), &entry.path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if let Some(parent) = Path::new(mount_point).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    namer
+        .put_namespace(mount_point, false)
+        .map_err(naming_io_error)?;
+    let contents = Path::new(mount_point).join(CONTENTS_DIR);
+    namer
+        .put_namespace(&contents, false)
+        .map_err(naming_io_error)?;
+
+    let mut files = 0;
+    for (entry, full_path) in entries.into_iter().zip(full_paths) {
+        if entry.is_dir {
+            put_namespaces(namer, Path::new(mount_point), Path::new(&entry.path))?;
+            continue;
+        }
+        if let Some(parent) = Path::new(&entry.path).parent() {
+            put_namespaces(namer, Path::new(mount_point), parent)?;
+        }
+        let target = contents
+            .join(files.to_string())
+            .to_string_lossy()
+            .into_owned();
+        namer
+            .symlink(&full_path, &target)
+            .map_err(naming_io_error)?;
+        PENDING.lock().unwrap().insert(
+            target,
+            Arc::new(LazyEntry {
+                archive: archive.clone(),
+                header_pos: entry.header_pos,
+                done: Mutex::new(false),
+            }),
+        );
+        files += 1;
+    }
+    Ok(files)
+}
+
+/// Create the non-persistent namespaces for `path` under `mount_point`, skipping any that exist.
+fn put_namespaces(
+    namer: &mut NamingHandle,
+    mount_point: &Path,
+    path: &Path,
+) -> std::io::Result<()> {
+    let mut ns = PathBuf::from(mount_point);
+    for component in path.components() {
+        ns.push(component);
+        if namer.get(&ns.to_string_lossy(), GetFlags::empty()).is_err() {
+            namer.put_namespace(&ns, false).map_err(naming_io_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` names a mounted entry that hasn't been materialized yet.
+#[cfg(test)]
+pub fn is_pending(path: &str, namer: &mut NamingHandle) -> bool {
+    pending_target(path, namer).is_some_and(|target| PENDING.lock().unwrap().contains_key(&target))
+}
+
+/// The target of the symlink `path`, if it is one.
+fn pending_target(path: &str, namer: &mut NamingHandle) -> Option<String> {
+    let node = namer.get(path, GetFlags::empty()).ok()?;
+    if node.kind != NsNodeKind::SymLink {
+        return None;
+    }
+    node.readlink().ok().map(str::to_owned)
+}
+
+/// Unpack the contents of the mounted entry named `path`, if that hasn't happened yet. Call this
+/// before opening a file; it does nothing for files that aren't pending mounted entries. If
+/// unpacking fails, the entry stays pending, so opening it keeps failing and the next read tries
+/// again.
+pub fn materialize(path: &str, namer: &mut NamingHandle) -> std::io::Result<()> {
+    let Some(target) = pending_target(path, namer) else {
+        return Ok(());
+    };
+    // Look the entry up, but don't hold the map while unpacking, so that reads of other entries
+    // aren't held up behind this one.
+    let Some(entry) = PENDING.lock().unwrap().get(&target).cloned() else {
+        return Ok(());
+    };
+    let mut done = entry.done.lock().unwrap();
+    if *done {
+        return Ok(());
+    }
+    tracing::info!("materializing {}", path);
+    let id = unpack_volatile(&entry.archive, entry.header_pos)?;
+    if let Err(e) = namer.put(&target, id) {
+        let _ = sys_object_ctrl(id, ObjectControlCmd::Delete(DeleteFlags::empty()));
+        return Err(naming_io_error(e));
+    }
+    *done = true;
+    PENDING.lock().unwrap().remove(&target);
+    Ok(())
+}
+
+/// Unpack the entry whose header is at `header_pos` into a new volatile object. The object is laid
+/// out as the runtime reads objects that aren't files: the data starts right after the null page,
+/// and its length is recorded in a sized meta extension.
+fn unpack_volatile(archive: &[u8], header_pos: u64) -> std::io::Result<ObjID> {
+    let id = sys_object_create(
+        ObjectCreate::new(
+            BackingType::Normal,
+            LifetimeType::Volatile,
+            None,
+            ObjectCreateFlags::empty(),
+            Protections::all(),
+        ),
+        &[],
+        &[],
+    )
+    .map_err(naming_io_error)?;
+    let fill = || -> std::io::Result<()> {
+        let handle = twizzler_rt_abi::object::twz_rt_map_object(
+            id.into(),
+            MapFlags::READ | MapFlags::WRITE | MapFlags::NO_NULLPAGE,
+        )
+        .map_err(naming_io_error)?;
+        // Safety: the object is new, so nothing else refers to its data, and with NO_NULLPAGE the
+        // data runs from the start of the mapping to just before the meta page.
+        let mut data =
+            unsafe { std::slice::from_raw_parts_mut(handle.start(), MAX_SIZE - NULLPAGE_SIZE * 2) };
+        // Start at the entry's header, so the archive isn't scanned from the beginning.
+        let len = Unpack::new(&archive[header_pos as usize..])?.copy_entry_at(0, &mut data)?;
+        let meta = handle.meta().cast::<MetaInfo>();
+        // Safety: the meta page is mapped along with the object, and a new object has room for
+        // another extension.
+        unsafe {
+            let exts = meta
+                .cast::<u8>()
+                .add(size_of::<MetaInfo>())
+                .cast::<MetaExt>();
+            exts.add((*meta).extcount as usize).write(MetaExt {
+                tag: MEXT_SIZED,
+                value: len,
+            });
+            (*meta).extcount += 1;
+        }
+        Ok(())
+    };
+    fill().inspect_err(|_| {
+        let _ = sys_object_ctrl(id, ObjectControlCmd::Delete(DeleteFlags::empty()));
+    })?;
+    Ok(id)
+}
@@ -1,4 +1,8 @@
-use std::io::{Read, Write};
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
 
 use embedded_io::ErrorType;
 use monitor_api::{CompartmentFlags, CompartmentHandle, CompartmentLoader, NewCompartmentFlags};
@@ -244,6 +248,8 @@ fn main() {
 
     println!("To run a program, type its name.");
 
+    let mut cwd = PathBuf::from("/");
+
     let mut io = TwzIo;
     let mut buffer = [0; 1024];
     let mut history = [0; 1024];
@@ -279,9 +285,13 @@ fn main() {
 
         tracing::debug!("got env: {:?}, cmd: {:?}", vars, cmd);
 
+        if run_builtin(&mut cwd, &cmd.iter().map(|s| **s).collect::<Vec<_>>()) {
+            continue;
+        }
+
         let comp = CompartmentLoader::new(cmd[0], cmd[0], NewCompartmentFlags::empty())
             .args(&cmd)
-            .env(vars.into_iter().map(|(k, v)| format!("{}={}", k, v)))
+            .env(program_env(vars, &cwd))
             .load();
         if let Ok(comp) = comp {
             let mut flags = comp.info().flags;
@@ -294,6 +304,75 @@ fn main() {
     }
 }
 
+/// Run cmd if it is a shell builtin, returning whether it was one. Builtins resolve their
+/// arguments against cwd, and cd changes it.
+fn run_builtin(cwd: &mut PathBuf, cmd: &[&str]) -> bool {
+    match cmd[0] {
+        "cd" => {
+            let target = resolve_path(cwd, cmd.get(1).copied().unwrap_or("/"));
+            if std::fs::metadata(&target).is_ok_and(|m| m.is_dir()) {
+                *cwd = target;
+            } else {
+                println!("cd: {}: not a directory", target.display());
+            }
+        }
+        "pwd" => println!("{}", cwd.display()),
+        "mkdir" | "rm" if cmd.len() < 2 => println!("{}: missing operand", cmd[0]),
+        "mkdir" => {
+            for target in cmd[1..].iter().map(|arg| resolve_path(cwd, arg)) {
+                if let Err(e) = std::fs::create_dir(&target) {
+                    println!("mkdir: {}: {}", target.display(), e);
+                }
+            }
+        }
+        "rm" => {
+            for target in cmd[1..].iter().map(|arg| resolve_path(cwd, arg)) {
+                // Like rm without -r, leave directories alone.
+                let res = match std::fs::metadata(&target) {
+                    Ok(m) if m.is_dir() => {
+                        println!("rm: {}: is a directory", target.display());
+                        continue;
+                    }
+                    _ => std::fs::remove_file(&target),
+                };
+                if let Err(e) = res {
+                    println!("rm: {}: {}", target.display(), e);
+                }
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// The environment for a program started from the console: the variables given on the command
+/// line, plus the working directory as PWD, which programs like ls resolve relative paths against.
+fn program_env(
+    vars: impl IntoIterator<Item = (impl Display, impl Display)>,
+    cwd: &Path,
+) -> Vec<String> {
+    vars.into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .chain(std::iter::once(format!("PWD={}", cwd.display())))
+        .collect()
+}
+
+/// Resolve a path given to a shell builtin against the current working directory, handling "."
+/// and ".." lexically.
+fn resolve_path(cwd: &Path, arg: &str) -> PathBuf {
+    let mut path = PathBuf::from("/");
+    for comp in cwd.join(arg).components() {
+        match comp {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(name) => path.push(name),
+            _ => {}
+        }
+    }
+    path
+}
+
 fn as_env<'a>(s: &'a str) -> Result<(&'a str, &'a str), &'a str> {
     let mut split = s.split("=");
     Ok((split.next().ok_or(s)?, split.next().ok_or(s)?))
@@ -335,3 +414,55 @@ fn run_tests() {
     #[allow(deprecated)]
     twizzler_abi::syscall::sys_debug_shutdown(0);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{program_env, resolve_path, run_builtin};
+
+    #[test]
+    fn cd_then_relative() {
+        let cwd = resolve_path(Path::new("/"), "data");
+        assert_eq!(cwd, Path::new("/data"));
+        assert_eq!(resolve_path(&cwd, "foo/bar"), Path::new("/data/foo/bar"));
+        assert_eq!(resolve_path(&cwd, "./foo"), Path::new("/data/foo"));
+        assert_eq!(resolve_path(&cwd, ".."), Path::new("/"));
+        assert_eq!(resolve_path(&cwd, "../.."), Path::new("/"));
+        assert_eq!(resolve_path(&cwd, "/initrd"), Path::new("/initrd"));
+    }
+    #[test]
+    fn cd_changes_where_builtins_and_programs_look() {
+        let root = std::env::temp_dir().join(format!("init-cd-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/file"), b"x").unwrap();
+        std::fs::write(root.join("file"), b"x").unwrap();
+
+        let mut cwd = PathBuf::from("/");
+        assert!(run_builtin(&mut cwd, &["cd", root.to_str().unwrap()]));
+        assert!(run_builtin(&mut cwd, &["cd", "sub"]));
+        assert_eq!(cwd, root.join("sub"));
+
+        // A relative rm removes the file in the new directory, not the one above it.
+        assert!(run_builtin(&mut cwd, &["rm", "file"]));
+        assert!(!root.join("sub/file").exists());
+        assert!(root.join("file").exists());
+
+        // cd to something that is not a directory leaves cwd alone.
+        assert!(run_builtin(&mut cwd, &["cd", "../file"]));
+        assert_eq!(cwd, root.join("sub"));
+
+        // Programs like ls are told the new directory.
+        let env = program_env([("FOO", "bar")], &cwd);
+        assert_eq!(
+            env,
+            [
+                "FOO=bar".to_string(),
+                format!("PWD={}", root.join("sub").display())
+            ]
+        );
+        assert!(!run_builtin(&mut cwd, &["ls", "."]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
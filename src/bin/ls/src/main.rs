@@ -1,4 +1,7 @@
-use std::{cmp::Ordering, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
 
 use clap::Parser;
 use naming::{static_naming_factory, NsNodeKind, StaticNamingHandle};
@@ -42,12 +45,22 @@ fn recurse(handle: &mut StaticNamingHandle, foo: &mut PathBuf) {
     }
 }
 
+/// Resolve the path to list against the shell's working directory, given to us in PWD.
+fn target_path(path: Option<&str>) -> String {
+    let cwd = std::env::var("PWD").unwrap_or_else(|_| "/".to_string());
+    match path {
+        Some(path) => Path::new(&cwd).join(path).display().to_string(),
+        None => cwd,
+    }
+}
+
 fn main() {
     let args = Args::parse();
+    let target = target_path(args.path.as_deref());
 
     use std::fs;
 
-    let paths = fs::read_dir(&args.path.as_ref().unwrap_or(&"/".to_string())).unwrap();
+    let paths = fs::read_dir(&target).unwrap();
     for path in paths {
         println!("Name: {}", path.unwrap().path().display())
     }
@@ -59,9 +72,7 @@ fn main() {
         path.push(".");
         recurse(&mut namer, &mut path);
     } else {
-        let mut names = namer
-            .enumerate_names_relative(&args.path.unwrap_or("/".to_string()))
-            .unwrap();
+        let mut names = namer.enumerate_names_relative(&target).unwrap();
         names.sort_by(|a, b| {
             if a.kind == NsNodeKind::Namespace {
                 Ordering::Greater
@@ -77,3 +88,16 @@ fn main() {
         println!("")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::target_path;
+
+    #[test]
+    fn relative_paths_follow_pwd() {
+        std::env::set_var("PWD", "/data/sub");
+        assert_eq!(target_path(None), "/data/sub");
+        assert_eq!(target_path(Some("dir")), "/data/sub/dir");
+        assert_eq!(target_path(Some("/initrd")), "/initrd");
+    }
+}
@@ -1,14 +1,19 @@
+use std::mem::MaybeUninit;
+
 use colog::default_builder;
 use log::{info, LevelFilter};
 use twizzler::{
     marker::BaseType,
-    object::{Object, ObjectBuilder, TypedObject},
+    object::{Object, TypedObject},
 };
 use twizzler_abi::{
     object::Protections,
-    syscall::{sys_sctx_attach, ObjectCreate},
+    syscall::{sys_object_create, sys_sctx_attach, ObjectCreate},
+};
+use twizzler_rt_abi::{
+    error::{GenericError, TwzError},
+    object::MapFlags,
 };
-use twizzler_rt_abi::object::MapFlags;
 use twizzler_security::{Cap, SecCtx, SecCtxFlags, SigningKey, SigningScheme};
 
 #[derive(Debug)]
@@ -45,31 +50,31 @@ fn main() {
 
     sys_sctx_attach(sec_ctx.id()).unwrap();
 
-    // lets create an object and try to access it
+    // lets create an object and try to access it. Anyone may read it, but writing takes a
+    // capability signed by `s_key`.
     let spec = ObjectCreate::new(
         Default::default(),
         Default::default(),
         Some(v_key.id()),
         Default::default(),
-        // Protections::all(),
-        // Protections::READ | Protections::WRITE,
         Protections::READ,
     );
     info!("creating target object with spec: {:?}", spec);
 
-    let target_obj = ObjectBuilder::new(spec)
-        .build(DumbBase {
-            _payload: 123456789,
-        })
-        .unwrap();
-
-    let target_id = target_obj.id().clone();
-    drop(target_obj);
+    let target_id = sys_object_create(spec, &[], &[]).unwrap();
 
     info!("target_id :{:?}", target_id);
     info!("sec_ctx id:{:?}", sec_ctx.id());
 
-    let prots = Protections::empty();
+    // We don't hold a capability for the target yet, so only its default protections apply.
+    let denied = Object::<DumbBase>::map(target_id, MapFlags::READ | MapFlags::WRITE);
+    assert!(matches!(
+        denied,
+        Err(TwzError::Generic(GenericError::AccessDenied))
+    ));
+    println!("Mapping without a capability was denied");
+
+    let prots = Protections::READ | Protections::WRITE;
 
     let cap = Cap::new(
         target_id,
@@ -84,11 +89,23 @@ fn main() {
 
     sec_ctx.insert_cap(cap).unwrap();
     println!("Inserted Capability!");
-    // attach to this sec_ctx
 
     // time to try accessing this object
+    let target = unsafe {
+        Object::<MaybeUninit<DumbBase>>::map_unchecked(target_id, MapFlags::READ | MapFlags::WRITE)
+    }
+    .unwrap();
+    target
+        .into_tx()
+        .unwrap()
+        .write(DumbBase {
+            _payload: 123456789,
+        })
+        .unwrap()
+        .into_object()
+        .unwrap();
 
-    let target = Object::<DumbBase>::map(target_id, MapFlags::READ | MapFlags::WRITE).unwrap();
+    let target = Object::<DumbBase>::map_checked(target_id, MapFlags::READ).unwrap();
     let base = target.base();
     println!("base: {:?}", base);
 
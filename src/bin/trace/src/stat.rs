@@ -140,6 +140,7 @@ pub fn stat(state: TracingState) {
                                     1 => Some("Delete".to_string()),
                                     2 => Some("Sync".to_string()),
                                     3 => Some("Preload".to_string()),
+                                    4 => Some("PreloadPages".to_string()),
                                     _ => Some("???".to_string()),
                                 },
                                 0,
@@ -20,3 +20,16 @@ pub(super) static mut PHYS_MEM_OFFSET: u64 = 0;
 pub fn phys_to_virt(pa: PhysAddr) -> VirtAddr {
     VirtAddr::new(pa.raw() + unsafe { PHYS_MEM_OFFSET }).unwrap()
 }
+
+/// Wait for this CPU's earlier stores to write-combined (Normal non-cacheable) memory to complete,
+/// so that they are visible to devices. `dsb st` covers the full system, not just the inner
+/// shareable domain, since the observer is a device.
+pub fn write_combine_fence() {
+    unsafe { core::arch::asm!("dsb st", options(nostack, preserves_flags)) };
+}
+
+/// Wait for this CPU's earlier loads and stores to complete before any later ones. Used around
+/// accesses to device memory, so a device sees them in program order.
+pub fn device_fence() {
+    unsafe { core::arch::asm!("dsb sy", options(nostack, preserves_flags)) };
+}
@@ -71,6 +71,20 @@ impl BootInfo for Armv8BootInfo {
             ""
         }
     }
+
+    fn entropy_seed(&self) -> &'static [u8] {
+        // Firmware that has a seed puts it in the rng-seed property of the /chosen node.
+        let Some(resp) = DTB_REQ.get_response() else {
+            return &[];
+        };
+        let Ok(dtb) = (unsafe { fdt::Fdt::from_ptr(resp.dtb_ptr().cast()) }) else {
+            return &[];
+        };
+        dtb.find_node("/chosen")
+            .and_then(|chosen| chosen.property("rng-seed"))
+            .map(|seed| seed.value)
+            .unwrap_or(&[])
+    }
 }
 
 use limine::memory_map::EntryType;
@@ -21,3 +21,16 @@ pub fn phys_to_virt(pa: PhysAddr) -> VirtAddr {
     let raw: u64 = pa.into();
     VirtAddr::new(raw + unsafe { PHYS_MEM_OFFSET }).unwrap()
 }
+
+/// Drain this CPU's write-combining buffers, so that earlier stores to write-combined memory are
+/// visible to devices. Stores to WC memory are weakly ordered and may be held in the buffers
+/// indefinitely; `sfence` orders them before any later store and forces them out.
+pub fn write_combine_fence() {
+    unsafe { core::arch::asm!("sfence", options(nostack, preserves_flags)) };
+}
+
+/// Order this CPU's earlier loads and stores, to any kind of memory, before its later ones. Used
+/// around accesses to device memory, so a device sees them in program order.
+pub fn device_fence() {
+    unsafe { core::arch::asm!("mfence", options(nostack, preserves_flags)) };
+}
@@ -1,5 +1,5 @@
 use p256::ecdsa::{
-    signature::{self, Signer, Verifier},
+    signature::{self, DigestSigner, DigestVerifier, Signer, Verifier},
     Signature, SigningKey, VerifyingKey,
 };
 use sha2::{Digest, Sha256};
@@ -11,10 +11,29 @@ pub fn sha256(input: impl AsRef<[u8]>) -> [u8; 32] {
     res.into()
 }
 
+/// Hash a sequence of slices as if they were one contiguous buffer, without copying them together
+/// first.
+pub fn sha256_parts<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
 pub fn sign(private_key: &SigningKey, message: &[u8]) -> Signature {
     private_key.sign(message)
 }
 
+/// Sign a message, also returning the SHA-256 digest that was signed (e.g. to record it in an audit
+/// log), without hashing the message twice. The signature is the same one [sign] makes.
+pub fn sign_hashed(private_key: &SigningKey, message: &[u8]) -> (Signature, [u8; 32]) {
+    let mut hasher = Sha256::new();
+    hasher.update(message);
+    let digest = hasher.clone().finalize().into();
+    (private_key.sign_digest(hasher), digest)
+}
+
 pub fn verify(
     public_key: &VerifyingKey,
     message: &[u8],
@@ -23,6 +42,32 @@ pub fn verify(
     public_key.verify(message, &signature)
 }
 
+// Hashes the domain, length-prefixed so that (domain, msg) pairs can't be shifted into one
+// another, followed by the message.
+fn domain_digest(domain: &[u8], message: &[u8]) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update((domain.len() as u64).to_le_bytes());
+    hasher.update(domain);
+    hasher.update(message);
+    hasher
+}
+
+/// Sign a message under a domain (context string), so that the signature will not verify under
+/// any other domain.
+pub fn sign_with_domain(private_key: &SigningKey, domain: &[u8], message: &[u8]) -> Signature {
+    private_key.sign_digest(domain_digest(domain, message))
+}
+
+/// Verify a signature made with [sign_with_domain] under the same domain.
+pub fn verify_with_domain(
+    public_key: &VerifyingKey,
+    domain: &[u8],
+    message: &[u8],
+    signature: Signature,
+) -> signature::Result<()> {
+    public_key.verify_digest(domain_digest(domain, message), &signature)
+}
+
 mod test {
 
     use core::hint::black_box;
@@ -40,6 +85,20 @@ mod test {
         assert_eq!(hash[..], expected);
     }
 
+    #[kernel_test]
+    fn test_hashing_parts() {
+        let data: [u8; 300] = core::array::from_fn(|i| i as u8);
+        let expected = sha256(data);
+        assert_eq!(sha256_parts([&data[..]]), expected);
+        assert_eq!(
+            sha256_parts([&data[..1], &data[1..100], &data[100..100], &data[100..]]),
+            expected
+        );
+        assert_eq!(sha256_parts(data.chunks(7)), expected);
+        assert_ne!(sha256_parts(data[..299].chunks(7)), expected);
+        assert_eq!(sha256_parts(core::iter::empty()), sha256([]));
+    }
+
     #[kernel_test]
     fn bench_hashing() {
         benchmark(|| {
@@ -63,6 +122,42 @@ mod test {
         verify(&pub_key, message, signature).expect("should be a valid signature");
     }
 
+    #[kernel_test]
+    fn test_sign_hashed() {
+        let key = [
+            168, 182, 114, 184, 168, 191, 237, 9, 90, 139, 135, 141, 26, 180, 247, 51, 86, 17, 197,
+            11, 229, 2, 25, 252, 9, 84, 135, 246, 235, 97, 11, 60,
+        ];
+        let private_key = SigningKey::from_slice(&key).unwrap();
+        let message = b"capability bytes";
+        let (signature, digest) = sign_hashed(&private_key, message);
+        assert_eq!(digest, sha256(message));
+
+        let pub_key: VerifyingKey = private_key.into();
+        verify(&pub_key, message, signature).expect("should be a valid signature");
+    }
+
+    #[kernel_test]
+    fn test_domain_signature() {
+        let key = [
+            168, 182, 114, 184, 168, 191, 237, 9, 90, 139, 135, 141, 26, 180, 247, 51, 86, 17, 197,
+            11, 229, 2, 25, 252, 9, 84, 135, 246, 235, 97, 11, 60,
+        ];
+        let private_key = SigningKey::from_slice(&key).unwrap();
+        let message = b"capability bytes";
+        let signature = sign_with_domain(&private_key, b"twizzler-cap", message);
+
+        let pub_key: VerifyingKey = private_key.into();
+        verify_with_domain(&pub_key, b"twizzler-cap", message, signature)
+            .expect("should be a valid signature");
+        assert!(verify_with_domain(&pub_key, b"twizzler-del", message, signature).is_err());
+        // shifting bytes between the domain and message must not produce the same digest
+        assert!(
+            verify_with_domain(&pub_key, b"twizzler-", b"capcapability bytes", signature).is_err()
+        );
+        assert!(verify(&pub_key, message, signature).is_err());
+    }
+
     #[kernel_test]
     fn bench_signing() {
         let key = [
@@ -193,6 +193,7 @@ pub fn create_busroot(
     });
     let info = DeviceRepr::new(KsoHdr::new(name), DeviceType::Bus, bt, DeviceId::new(0));
     obj.write_base(&info);
+    obj.sync();
     get_device_map().lock().insert(obj.id(), device.clone());
     let ksom = get_kso_manager();
     ksom.device_roots.lock().push(device.clone());
@@ -221,6 +222,7 @@ pub fn create_device(
     });
     let info = DeviceRepr::new(KsoHdr::new(name), DeviceType::Device, bt, id);
     obj.write_base(&info);
+    obj.sync();
     get_device_map().lock().insert(obj.id(), device.clone());
     parent.inner.lock().children.push(device.clone());
     device
@@ -245,6 +247,7 @@ impl Device {
     pub fn add_info<T>(&self, info: &T) {
         let obj = Arc::new(crate::obj::Object::new_kernel());
         obj.write_base(info);
+        obj.sync();
         crate::obj::register_object(obj.clone());
         self.inner
             .lock()
@@ -261,6 +264,7 @@ impl Device {
             info,
         };
         obj.write_base(&mmio_info);
+        obj.sync();
         crate::obj::register_object(obj.clone());
         self.inner
             .lock()
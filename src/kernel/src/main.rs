@@ -55,12 +55,15 @@ extern crate alloc;
 extern crate bitflags;
 
 use alloc::boxed::Box;
-use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
 
 use ::log::LevelFilter;
 use arch::BootInfoSystemTable;
 use initrd::BootModule;
-use memory::{MemoryRegion, VirtAddr};
+use memory::{MemoryRegion, PhysAddr, VirtAddr};
 use once::Once;
 use random::start_entropy_contribution_thread;
 
@@ -78,6 +81,16 @@ pub trait BootInfo {
     fn get_modules(&self) -> &'static [BootModule];
     /// Get a pointer to the kernel command line.
     fn get_cmd_line(&self) -> &'static str;
+    /// Return the physical ranges whose contents were handed over by a previous kernel (e.g.
+    /// across kexec), and so must not be reused by the frame allocator.
+    fn preserved_regions(&self) -> &'static [Range<PhysAddr>] {
+        &[]
+    }
+    /// Return the random seed provided by the bootloader or firmware, if any, for seeding the
+    /// kernel's random number generator at boot.
+    fn entropy_seed(&self) -> &'static [u8] {
+        &[]
+    }
 }
 
 static TEST_MODE: AtomicBool = AtomicBool::new(false);
@@ -170,6 +183,7 @@ fn kernel_main<B: BootInfo + Send + Sync + 'static>(boot_info: B) -> ! {
     processor::boot_all_secondaries(image::get_tls());
 
     clock::init();
+    random::seed_at_boot(boot_info);
     interrupt::init();
 
     let lock = spinlock::Spinlock::<u32>::new(0);
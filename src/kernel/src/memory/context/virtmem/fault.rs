@@ -17,7 +17,7 @@ use crate::{
         FAULT_STATS,
     },
     obj::PageNumber,
-    security::{AccessInfo, PermsInfo, KERNEL_SCTX},
+    security::{AccessInfo, PermsInfo, SecCtxMgr, KERNEL_SCTX},
     thread::{current_memory_context, current_thread_ref},
 };
 
@@ -106,6 +106,7 @@ fn check_security(
     ctx: &ContextRef,
     user_sctx: ObjID,
     id: ObjID,
+    page_number: PageNumber,
     addr: VirtAddr,
     cause: MemoryAccessKind,
     ip: VirtAddr,
@@ -129,21 +130,10 @@ fn check_security(
         access_kind,
         exec_id: Some(exec_info.object().id()),
         exec_off: ip - exec_info.range.start,
+        target_off: Some(page_number.as_byte_offset()),
     };
     if let Some(ct) = current_thread_ref() {
-        let perms = ct.secctx.check_active_access(&access_info);
-        if (perms.provide | default_prot) & !perms.restrict & access_kind == access_kind {
-            return Ok(perms);
-        }
-        let perms = ct.secctx.search_access(&access_info);
-        if (perms.provide | default_prot) & !perms.restrict & access_kind != access_kind {
-            Err(UpcallInfo::SecurityViolation(SecurityViolationInfo {
-                address: addr.raw(),
-                access_kind: cause,
-            }))
-        } else {
-            Ok(perms)
-        }
+        check_access(&ct.secctx, &access_info, default_prot, addr, cause)
     } else {
         Ok(PermsInfo {
             ctx: KERNEL_SCTX,
@@ -153,6 +143,32 @@ fn check_security(
     }
 }
 
+/// Check a faulting access against the thread's security contexts: the active one first, then all
+/// attached ones. Capabilities scoped to part of the target only count for pages inside their
+/// scope, so an access outside it is a security violation.
+fn check_access(
+    secctx: &SecCtxMgr,
+    access_info: &AccessInfo,
+    default_prot: Protections,
+    addr: VirtAddr,
+    cause: MemoryAccessKind,
+) -> Result<PermsInfo, UpcallInfo> {
+    let access_kind = access_info.access_kind;
+    let perms = secctx.check_active_access(access_info);
+    if (perms.provide | default_prot) & !perms.restrict & access_kind == access_kind {
+        return Ok(perms);
+    }
+    let perms = secctx.search_access(access_info);
+    if (perms.provide | default_prot) & !perms.restrict & access_kind != access_kind {
+        Err(UpcallInfo::SecurityViolation(SecurityViolationInfo {
+            address: addr.raw(),
+            access_kind: cause,
+        }))
+    } else {
+        Ok(perms)
+    }
+}
+
 fn page_fault_to_region(
     addr: VirtAddr,
     cause: MemoryAccessKind,
@@ -192,7 +208,16 @@ fn page_fault_to_region(
         */
     }
 
-    let perms = check_security(&ctx, sctx_id, id.clone(), addr, cause, ip, default_prot)?;
+    let perms = check_security(
+        &ctx,
+        sctx_id,
+        id.clone(),
+        page_number,
+        addr,
+        cause,
+        ip,
+        default_prot,
+    )?;
 
     // Do we need to switch contexts?
     if perms.ctx != sctx_id && !addr.is_kernel() {
@@ -236,6 +261,9 @@ fn page_fault_to_region(
         Ok(())
     };
 
+    // What a scoped capability grants depends on the page, so neighbouring pages may lie outside
+    // its scope. This applies to kernel accesses too, as the mapping is made in the user's context.
+    let one_page = current_thread_ref().is_some_and(|ct| ct.secctx.has_scoped_caps(id));
     info.map(
         addr,
         ip,
@@ -243,6 +271,7 @@ fn page_fault_to_region(
         flags,
         perms,
         default_prot,
+        one_page,
         start_time,
         mapper,
     )
@@ -284,3 +313,124 @@ pub fn page_fault(addr: VirtAddr, cause: MemoryAccessKind, flags: PageFaultFlags
         current_thread_ref().unwrap().send_upcall(upcall);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use twizzler_abi::{
+        object::{Protections, NULLPAGE_SIZE},
+        upcall::{MemoryAccessKind, UpcallInfo},
+    };
+    use twizzler_kernel_macros::kernel_test;
+    use twizzler_security::{Cap, CapScope};
+
+    use super::check_access;
+    use crate::{
+        arch::VirtAddr,
+        obj::PageNumber,
+        security::{
+            test_util::{context_with_caps, signed_object},
+            AccessInfo, SecCtxMgr,
+        },
+    };
+
+    #[kernel_test]
+    fn test_scoped_cap_fault() {
+        let (target, s_key) = signed_object(Protections::empty());
+        let scope = CapScope::new(NULLPAGE_SIZE as u64 * 4, NULLPAGE_SIZE as u64 * 2);
+        let ctx = context_with_caps(|ctx_id| {
+            alloc::vec![Cap::new_scoped(
+                target.id(),
+                ctx_id,
+                Protections::READ | Protections::WRITE,
+                &s_key,
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                scope,
+            )
+            .expect("capability creation shouldnt have errored")]
+        });
+        let secctx = SecCtxMgr::new(Arc::clone(&ctx));
+
+        // Faults as the fault handler sees them, for a write to the given page of the target.
+        let fault = |page: usize| {
+            let access_info = AccessInfo {
+                target_id: target.id(),
+                access_kind: Protections::WRITE | Protections::READ,
+                exec_id: None,
+                exec_off: 0,
+                target_off: Some(PageNumber::from(page).as_byte_offset()),
+            };
+            let addr = VirtAddr::new((page * NULLPAGE_SIZE) as u64).unwrap();
+            check_access(
+                &secctx,
+                &access_info,
+                Protections::empty(),
+                addr,
+                MemoryAccessKind::Write,
+            )
+        };
+        assert!(fault(4).is_ok());
+        assert!(fault(5).is_ok());
+        // Accesses outside the scope are security violations.
+        for page in [3, 6, 100] {
+            assert!(matches!(
+                fault(page),
+                Err(UpcallInfo::SecurityViolation(info)) if info.access_kind == MemoryAccessKind::Write
+            ));
+        }
+    }
+
+    #[kernel_test]
+    fn test_denied_fault() {
+        let (target, s_key) = signed_object(Protections::READ);
+        let (target_id, s_key) = (target.id(), &s_key);
+        let cap_for = |prots: Protections| {
+            move |ctx_id| {
+                alloc::vec![Cap::new(
+                    target_id,
+                    ctx_id,
+                    prots,
+                    s_key,
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                )
+                .expect("capability creation shouldnt have errored")]
+            }
+        };
+        let secctx = SecCtxMgr::new(context_with_caps(cap_for(Protections::READ)));
+
+        let fault = |secctx: &SecCtxMgr, cause: MemoryAccessKind| {
+            let access_info = AccessInfo {
+                target_id,
+                access_kind: match cause {
+                    MemoryAccessKind::Write => Protections::WRITE | Protections::READ,
+                    _ => Protections::READ,
+                },
+                exec_id: None,
+                exec_off: 0,
+                target_off: Some(PageNumber::from(1).as_byte_offset()),
+            };
+            let addr = VirtAddr::new(NULLPAGE_SIZE as u64).unwrap();
+            check_access(secctx, &access_info, Protections::READ, addr, cause)
+        };
+        assert!(fault(&secctx, MemoryAccessKind::Read).is_ok());
+        // Neither the default protections nor the context's cap grant writes.
+        assert!(matches!(
+            fault(&secctx, MemoryAccessKind::Write),
+            Err(UpcallInfo::SecurityViolation(info)) if info.access_kind == MemoryAccessKind::Write
+        ));
+
+        // A write cap in an attached, inactive context is found by the search.
+        let writer = context_with_caps(cap_for(Protections::READ | Protections::WRITE));
+        let writer_id = writer.id();
+        secctx.attach(writer).unwrap();
+        assert!(matches!(
+            fault(&secctx, MemoryAccessKind::Write),
+            Ok(perms) if perms.ctx == writer_id
+        ));
+    }
+}
@@ -12,7 +12,7 @@ use twizzler_abi::{
         UpcallInfo,
     },
 };
-use twizzler_rt_abi::error::{IoError, RawTwzError, TwzError};
+use twizzler_rt_abi::error::{GenericError, IoError, RawTwzError, ResourceError, TwzError};
 
 use super::{ObjectPageProvider, PageFaultFlags};
 use crate::{
@@ -93,6 +93,25 @@ fn check_settings(
     Ok(())
 }
 
+/// Restrict `prot` for mapping `page`: a read-only view is never mapped writable.
+fn view_prot(page: &PageRef, mut prot: Protections) -> Protections {
+    if page.is_read_only() {
+        prot.remove(Protections::WRITE);
+    }
+    prot
+}
+
+/// A write through a read-only view always faults, even for kernel mappings, which
+/// [check_settings] doesn't otherwise restrict.
+fn check_view(addr: VirtAddr, page: &PageRef, kind: MemoryAccessKind) -> Result<(), UpcallInfo> {
+    if page.is_read_only() && kind == MemoryAccessKind::Write {
+        return Err(UpcallInfo::MemoryContextViolation(
+            MemoryContextViolationInfo::new(addr.raw(), kind),
+        ));
+    }
+    Ok(())
+}
+
 impl MapRegion {
     fn trace_fault(
         &self,
@@ -165,6 +184,9 @@ impl MapRegion {
         &self.object
     }
 
+    /// Bring in the page at `addr` and map it with `mapper`, along with as many of the pages after
+    /// it as its backing allows. If `one_page` is set, only the page at `addr` is mapped, as when
+    /// `perms` only hold for that page.
     pub(super) fn map(
         &self,
         addr: VirtAddr,
@@ -173,6 +195,7 @@ impl MapRegion {
         pfflags: PageFaultFlags,
         perms: PermsInfo,
         default_prot: Protections,
+        one_page: bool,
         start_time: Instant,
         mapper: impl FnOnce(PageNumber, ObjectPageProvider) -> Result<(), UpcallInfo>,
     ) -> Result<(), UpcallInfo> {
@@ -186,22 +209,35 @@ impl MapRegion {
             FrameAllocFlags::ZEROED | FrameAllocFlags::WAIT_OK,
             PHYS_LEVEL_LAYOUTS[0],
         );
-        let get_page_flags = if cause == MemoryAccessKind::Write {
+        // Pages this fault allocates count against the faulting context's page quota.
+        if !is_kern_obj {
+            fa = fa.with_owner(perms.ctx);
+        }
+        let mut get_page_flags = if cause == MemoryAccessKind::Write {
             GetPageFlags::WRITE
         } else {
             GetPageFlags::empty()
         };
+        // A region mapped without write permission only ever gets read-only views of its pages.
+        if !self.prot.contains(Protections::WRITE) {
+            get_page_flags.insert(GetPageFlags::READ_ONLY);
+        }
 
         if let Some(shadow) = &self.shadow {
             if let Some(page) = shadow.get_page(page_number, get_page_flags) {
                 let settings = self.mapping_settings(true, is_kern_obj);
                 let settings = MappingSettings::new(
                     // Provided permissions, restricted by mapping.
-                    (perms.provide | default_prot) & !perms.restrict & settings.perms(),
+                    view_prot(
+                        &page,
+                        (perms.provide | default_prot) & !perms.restrict & settings.perms(),
+                    ),
                     settings.cache(),
                     settings.flags(),
                 );
                 check_settings(addr, &settings, cause)?;
+                check_view(addr, &page, cause)?;
+                let page = if one_page { page.trimmed(1) } else { page };
                 self.trace_fault(addr, ip, cause, pfflags, false, false, start_time);
                 return mapper(
                     PageNumber::from_address(addr),
@@ -219,13 +255,22 @@ impl MapRegion {
         let mut status = obj_page_tree.get_page(page_number, get_page_flags, Some(&mut fa));
         if matches!(status, PageStatus::NoPage) && !self.object.use_pager() {
             log::warn!("fallback allocate in fault to page {}", page_number);
-            if let Some(frame) = fa.try_allocate() {
-                let page = Page::new(frame);
-                obj_page_tree.add_page(
-                    page_number,
-                    PageRef::new(Arc::new(page), 0, 1),
-                    Some(&mut fa),
-                );
+            match Page::try_new_in(&mut fa) {
+                Ok(page) => {
+                    obj_page_tree.add_page(
+                        page_number,
+                        PageRef::new(Arc::new(page), 0, 1),
+                        Some(&mut fa),
+                    );
+                }
+                Err(e) => {
+                    return Err(UpcallInfo::ObjectMemoryFault(ObjectMemoryFaultInfo::new(
+                        self.object().id(),
+                        ObjectMemoryError::BackingFailed(RawTwzError::new(e.raw())),
+                        cause,
+                        addr.raw() as usize,
+                    )));
+                }
             }
             status = obj_page_tree.get_page(page_number, get_page_flags, Some(&mut fa));
             if matches!(status, PageStatus::NoPage) {
@@ -244,21 +289,56 @@ impl MapRegion {
                 pfflags,
                 perms,
                 default_prot,
+                one_page,
                 start_time,
                 mapper,
             );
         }
 
+        // Making a private copy of a shared page failed, most likely because the faulting context
+        // is over its page quota.
+        if matches!(status, PageStatus::AllocFail) {
+            return Err(UpcallInfo::ObjectMemoryFault(ObjectMemoryFaultInfo::new(
+                self.object().id(),
+                ObjectMemoryError::BackingFailed(RawTwzError::new(
+                    TwzError::Resource(ResourceError::OutOfMemory).raw(),
+                )),
+                cause,
+                addr.raw() as usize,
+            )));
+        }
+
+        // Device memory shared with another object can't be made private for a write.
+        if matches!(status, PageStatus::NoCopy) {
+            log::debug!(
+                "write fault on shared device page {} in {}",
+                page_number,
+                self.object().id()
+            );
+            return Err(UpcallInfo::ObjectMemoryFault(ObjectMemoryFaultInfo::new(
+                self.object().id(),
+                ObjectMemoryError::BackingFailed(RawTwzError::new(
+                    TwzError::Generic(GenericError::NotSupported).raw(),
+                )),
+                cause,
+                addr.raw() as usize,
+            )));
+        }
+
         // Step 4: do the mapping. If the page isn't present by now, report data loss.
         if let PageStatus::Ready(page, shared) = status {
             let settings = self.mapping_settings(shared, is_kern_obj);
             let settings = MappingSettings::new(
                 // Provided permissions, restricted by mapping.
-                (perms.provide | default_prot) & !perms.restrict & settings.perms(),
+                view_prot(
+                    &page,
+                    (perms.provide | default_prot) & !perms.restrict & settings.perms(),
+                ),
                 settings.cache(),
                 settings.flags(),
             );
             check_settings(addr, &settings, cause)?;
+            check_view(addr, &page, cause)?;
             if settings.perms().contains(Protections::WRITE) {
                 if self.object().use_pager() {
                     log::debug!(
@@ -310,7 +390,8 @@ impl MapRegion {
                 );
             }
 
-            if page.page_offset() >= large_diff
+            if !one_page
+                && page.page_offset() >= large_diff
                 && large_diff > 0
                 && aligned
                 && !addr.is_kernel()
@@ -350,6 +431,7 @@ impl MapRegion {
                         page.nr_pages()
                     );
                 }
+                let page = if one_page { page.trimmed(1) } else { page };
                 let ret = mapper(
                     PageNumber::from_address(addr),
                     ObjectPageProvider::new(Vec::from([(page, settings)])),
@@ -20,7 +20,9 @@
 //! Allocations can specify a Layout. This is a little more restrictive than standard allocations
 //! in that the layout will be respected, but the physical memory allocator only really allocates
 //! in architecturally-defined chunks (e.g. on x86_64, 4K, 2M, 1G). Large frames can be split into
-//! smaller ones.
+//! smaller ones. An alignment between two levels' (say, 64K) is met by taking a frame from the
+//! first level aligned enough and splitting off its first sub-frame of the size needed, freeing the
+//! rest.
 //!
 //! Note: this code is somewhat cursed, since it needs to do a bunch of funky low-level memory
 //! management without ever triggering the memory manager (can't allocate memory, since that could
@@ -41,7 +43,8 @@ use alloc::vec::Vec;
 use core::{
     alloc::Layout,
     mem::{size_of, transmute},
-    sync::atomic::{AtomicU8, Ordering},
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
 };
 
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
@@ -76,6 +79,9 @@ pub const PHYS_LEVEL_LAYOUTS: [Layout; NR_LEVELS] = [
 struct AllocationRegion {
     indexer: FrameIndexer,
     nr_pages: usize,
+    nr_preserved: usize,
+    // Bytes in admitted frames, free or not. Doesn't count the frame array.
+    admitted_bytes: usize,
     levels: [AllocationRegionLevel; NR_LEVELS],
 }
 
@@ -169,30 +175,58 @@ impl AllocationRegion {
         self.levels[level].free(frame);
     }
 
+    fn free_bytes(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| level.free * level.alloc_size)
+            .sum()
+    }
+
     fn find_level(&self, layout: Layout) -> Option<usize> {
         self.levels
             .iter()
             .position(|level| level.alloc_size >= layout.size() && level.align >= layout.align())
     }
 
-    fn do_allocate(&mut self, try_zero: bool, only_zero: bool, level: usize) -> Option<FrameRef> {
+    /// Allocate a frame at the given level, splitting larger frames if needed. Also returns
+    /// whether a split was needed.
+    fn do_allocate(
+        &mut self,
+        try_zero: bool,
+        only_zero: bool,
+        level: usize,
+    ) -> Option<(FrameRef, bool)> {
         if level >= NR_LEVELS {
             return None;
         }
         if let Some(frame) = self.levels[level].allocate(try_zero, only_zero) {
-            return Some(frame);
+            return Some((frame, false));
         }
 
-        let bigger_frame = self.do_allocate(try_zero, only_zero, level + 1)?;
+        let (bigger_frame, _) = self.do_allocate(try_zero, only_zero, level + 1)?;
         self.split(bigger_frame);
-        self.levels[level].allocate(try_zero, only_zero)
+        let frame = self.levels[level].allocate(try_zero, only_zero)?;
+        Some((frame, true))
     }
 
     fn allocate(&mut self, try_zero: bool, only_zero: bool, layout: Layout) -> Option<FrameRef> {
         let level = self.find_level(layout)?;
-        let frame = self.do_allocate(try_zero, only_zero, level)?;
+        let (frame, split) = self.do_allocate(try_zero, only_zero, level)?;
         assert!(!frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
         frame.set_allocated();
+        // The level was picked for its alignment, so its frames may be bigger than the layout
+        // needs. Frames are aligned to their size, so the first sub-frame that's big enough is
+        // aligned just as well; keep that one and free the rest.
+        let fit = self
+            .levels
+            .iter()
+            .position(|level| level.alloc_size >= layout.size())
+            .unwrap_or(level);
+        if fit < level {
+            ALLOC_HISTOGRAM.record(fit, true);
+            return Some(self.shrink(frame, fit));
+        }
+        ALLOC_HISTOGRAM.record(level, split);
         Some(frame)
     }
 
@@ -229,7 +263,25 @@ impl AllocationRegion {
         );
     }
 
-    fn new(m: &MemoryRegion) -> Option<Self> {
+    fn shrink(&mut self, frame: FrameRef, keep_level: usize) -> FrameRef {
+        let kernel = frame.is_kernel();
+        let owner = frame.owner();
+        while frame.get_level() > keep_level {
+            let level = frame.get_level() - 1;
+            self.split(frame);
+            // Splitting admits every child as free, including the first one, which is the portion
+            // we are keeping. It's the same Frame as the one we split, so take it back out.
+            let mut cursor = unsafe { self.levels[level].non_zeroed.cursor_mut_from_ptr(frame) };
+            cursor.remove().unwrap();
+            self.levels[level].free -= 1;
+            frame.set_allocated();
+        }
+        frame.set_kernel(kernel);
+        frame.set_owner(owner);
+        frame
+    }
+
+    fn new(m: &MemoryRegion, preserved: &[Range<PhysAddr>]) -> Option<Self> {
         let start = m.start.align_up(FRAME_SIZE as u64).unwrap();
         let length = m.length - (start.raw() - m.start.raw()) as usize;
         let nr_pages = length / FRAME_SIZE;
@@ -242,6 +294,17 @@ impl AllocationRegion {
             return None;
         }
 
+        // The frame array is written over the start of the region, so it must not clobber
+        // preserved memory. If it would, start the region after the preserved range instead.
+        if let Some(range) = preserved
+            .iter()
+            .find(|range| overlaps_preserved(range, start, array_pages * FRAME_SIZE))
+        {
+            let skip = (range.end.raw() - m.start.raw()) as usize;
+            let (_, rest) = m.split(skip)?;
+            return Self::new(&rest, preserved);
+        }
+
         let frame_array_ptr = phys_to_virt(start).as_mut_ptr();
 
         let mut levels = [
@@ -261,20 +324,44 @@ impl AllocationRegion {
         };
 
         // Organize into levels.
+        let mut nr_preserved = 0;
+        let mut admitted_bytes = 0;
         let mut cursor = start.offset(array_pages * FRAME_SIZE).unwrap();
         let end = start.offset(nr_pages * FRAME_SIZE).unwrap();
         while cursor < end {
             let remaining = end - cursor;
-            // select level based on alignment and space
-            // Unwrap-Ok: level 0 will always work.
-            let level = (NR_LEVELS - 1)
-                - levels
-                    .iter()
-                    .rev()
-                    .position(|level| {
-                        cursor.is_aligned_to(level.align) && remaining >= level.alloc_size
-                    })
-                    .unwrap();
+            // select level based on alignment, space, and whether the frame holds any preserved
+            // memory. Level 0 only fails if this page itself is preserved.
+            let Some(level) = levels
+                .iter()
+                .rev()
+                .position(|level| {
+                    cursor.is_aligned_to(level.align)
+                        && remaining >= level.alloc_size
+                        && !preserved
+                            .iter()
+                            .any(|range| overlaps_preserved(range, cursor, level.alloc_size))
+                })
+                .map(|pos| (NR_LEVELS - 1) - pos)
+            else {
+                // Track the page as allocated, so it is never handed out. Whoever owns the
+                // preserved memory can free it once they are done with it.
+                // Unwrap-Ok: we know this address is in this region already
+                // Safety: we are allocating a new, untouched frame here
+                let frame = unsafe { indexer.get_frame_mut(cursor) }.unwrap();
+                unsafe {
+                    frame.reset(
+                        cursor,
+                        0,
+                        PhysicalFrameFlags::ALLOCATED | PhysicalFrameFlags::PRESERVED,
+                    )
+                };
+                frame.set_admitted();
+                nr_preserved += 1;
+                admitted_bytes += FRAME_SIZE;
+                cursor = cursor.offset(FRAME_SIZE).unwrap();
+                continue;
+            };
             // Unwrap-Ok: we know this address is in this region already
             // Safety: we are allocating a new, untouched frame here
             let frame = unsafe { indexer.get_frame_mut(cursor) }.unwrap();
@@ -289,6 +376,7 @@ impl AllocationRegion {
                 };
             }
             levels[level].admit_one(frame, cursor, level as u8, PhysicalFrameFlags::empty());
+            admitted_bytes += levels[level].alloc_size;
             cursor = cursor.offset(levels[level].alloc_size).unwrap();
         }
 
@@ -296,10 +384,16 @@ impl AllocationRegion {
             indexer,
             levels,
             nr_pages,
+            nr_preserved,
+            admitted_bytes,
         })
     }
 }
 
+fn overlaps_preserved(range: &Range<PhysAddr>, start: PhysAddr, len: usize) -> bool {
+    range.start.raw() < start.raw() + len as u64 && start < range.end
+}
+
 #[doc(hidden)]
 struct PhysicalFrameAllocator {
     regions: Vec<AllocationRegion>,
@@ -315,6 +409,8 @@ pub struct Frame {
     flags: AtomicU8,
     lock: AtomicU8,
     level: AtomicU8,
+    #[cfg(debug_assertions)]
+    owner: AtomicU8,
     link: LinkedListLink,
 }
 intrusive_adapter!(pub FrameAdapter = &'static Frame: Frame { link: LinkedListLink });
@@ -338,6 +434,7 @@ impl Frame {
         self.lock.store(0, Ordering::SeqCst);
         self.flags.store(init_flags.bits(), Ordering::SeqCst);
         self.level.store(level, Ordering::SeqCst);
+        self.set_owner(FrameOwner::Unknown);
         let pa_ptr = &mut self.pa as *mut _;
         *pa_ptr = pa;
         self.link.force_unlink();
@@ -415,14 +512,81 @@ impl Frame {
         self.get_flags().contains(PhysicalFrameFlags::ZEROED)
     }
 
+    /// Check that the physical memory of this frame is actually all zero, whether or not it is
+    /// marked as zeroed. This reads the whole frame, so it is meant for tests of the zeroing
+    /// paths. Only available in debug builds.
+    #[cfg(debug_assertions)]
+    pub fn verify_zeroed(&self) -> bool {
+        self.lock();
+        let virt = phys_to_virt(self.pa);
+        let slice = unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), self.size()) };
+        let zeroed = slice.iter().all(|b| *b == 0);
+        self.unlock();
+        zeroed
+    }
+
+    /// Mark this frame's contents as no longer needed (as with MADV_FREE). The frame stays
+    /// allocated, but its contents may be zeroed at any point after this call, either by the
+    /// reclaim thread or by the next access (see [Frame::settle_discarded]). Either way, the next
+    /// reader sees zeros.
+    ///
+    /// The frame must not be mapped anywhere (other than the kernel's physical map) when this is
+    /// called, since a write through a mapping would not settle it first. For object pages, use
+    /// [Object::discard_range], which unmaps them.
+    ///
+    /// [Object::discard_range]: crate::obj::Object::discard_range
+    pub fn mark_discardable(&self) {
+        self.lock();
+        self.flags
+            .fetch_or(PhysicalFrameFlags::DISCARDABLE.bits(), Ordering::SeqCst);
+        self.unlock();
+    }
+
+    /// Check if this frame's contents have been discarded but not yet zeroed.
+    pub fn is_discardable(&self) -> bool {
+        self.get_flags().contains(PhysicalFrameFlags::DISCARDABLE)
+    }
+
+    /// If this frame was marked discardable, zero it and clear the mark. Must be called before
+    /// the frame's contents are accessed. Returns true if the frame was zeroed by this call.
+    pub fn settle_discarded(&self) -> bool {
+        if !self.is_discardable() {
+            return false;
+        }
+        self.lock();
+        // Recheck under the lock: a racing access or the reclaim thread may have beaten us to it.
+        if !self.is_discardable() {
+            self.unlock();
+            return false;
+        }
+        if !self.is_zeroed() {
+            let virt = phys_to_virt(self.pa);
+            let ptr: *mut u8 = virt.as_mut_ptr();
+            let slice = unsafe { core::slice::from_raw_parts_mut(ptr, self.size()) };
+            slice.fill(0);
+        }
+        // Don't mark the frame zeroed: it's still allocated, and writes to it aren't tracked, so
+        // the flag would go stale and let a later copy skip real contents.
+        self.flags
+            .fetch_and(!PhysicalFrameFlags::DISCARDABLE.bits(), Ordering::SeqCst);
+        self.unlock();
+        true
+    }
+
     fn set_admitted(&self) {
         self.flags
             .fetch_or(PhysicalFrameFlags::ADMITTED.bits(), Ordering::SeqCst);
     }
 
     fn set_free(&self) {
-        self.flags
-            .fetch_and(!PhysicalFrameFlags::ALLOCATED.bits(), Ordering::SeqCst);
+        self.flags.fetch_and(
+            !(PhysicalFrameFlags::ALLOCATED
+                | PhysicalFrameFlags::PRESERVED
+                | PhysicalFrameFlags::DISCARDABLE)
+                .bits(),
+            Ordering::SeqCst,
+        );
+        self.set_owner(FrameOwner::Unknown);
     }
 
     fn set_allocated(&self) {
@@ -444,6 +608,20 @@ impl Frame {
         self.flags.load(Ordering::SeqCst) & PhysicalFrameFlags::KERNEL.bits() != 0
     }
 
+    /// Record which subsystem owns this frame, for leak diagnosis. Only tracked in debug builds.
+    pub fn set_owner(&self, _owner: FrameOwner) {
+        #[cfg(debug_assertions)]
+        self.owner.store(_owner as u8, Ordering::SeqCst);
+    }
+
+    /// Get the subsystem that owns this frame. Always [FrameOwner::Unknown] in release builds.
+    pub fn owner(&self) -> FrameOwner {
+        #[cfg(debug_assertions)]
+        return FrameOwner::from_raw(self.owner.load(Ordering::SeqCst));
+        #[cfg(not(debug_assertions))]
+        FrameOwner::Unknown
+    }
+
     /// Get the current flags.
     pub fn get_flags(&self) -> PhysicalFrameFlags {
         PhysicalFrameFlags::from_bits_truncate(self.flags.load(Ordering::SeqCst))
@@ -452,6 +630,10 @@ impl Frame {
     /// Copy contents of one frame into another. If the other frame is marked as zeroed, copying
     /// will not happen. Both frames are locked first.
     pub fn copy_contents_from(&self, other: &Frame, doff: usize, soff: usize, len: usize) {
+        // Settle the source first, so that discarded contents are copied as zeros. This takes the
+        // other frame's lock, so do it before taking ours.
+        other.settle_discarded();
+        self.settle_discarded();
         self.lock();
         // We don't need to lock the other frame, since if its contents aren't synchronized with
         // this operation, it could have reordered to before or after.
@@ -488,6 +670,7 @@ impl Frame {
 
     /// Copy from another physical address into this frame.
     pub fn copy_contents_from_physaddr(&self, doff: usize, other: PhysAddr, len: usize) {
+        self.settle_discarded();
         self.lock();
         self.flags
             .fetch_and(!PhysicalFrameFlags::ZEROED.bits(), Ordering::SeqCst);
@@ -517,11 +700,53 @@ bitflags::bitflags! {
         const ADMITTED = 4;
         /// (internal) The frame is owned by the kernel.
         const KERNEL = 8;
+        /// The frame holds memory preserved from before a warm boot, and was never free.
+        const PRESERVED = 16;
+        /// The frame's contents are no longer needed, and will be zeroed before next use.
+        const DISCARDABLE = 32;
+    }
+}
+
+/// The subsystem that allocated a frame. Tracked in debug builds, so that frames that are never
+/// freed can be attributed to the code that leaked them (see [dump_leaked_frames]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum FrameOwner {
+    /// The allocator did not tag the frame.
+    Unknown,
+    /// A page of object data.
+    ObjectPage,
+    /// Kernel-internal object data, e.g. quick-access base pages.
+    KernelObject,
+    /// A page table.
+    PageTable,
+    /// Object memory pinned for DMA.
+    Dma,
+    /// Frames handed to the pager.
+    Pager,
+}
+
+impl FrameOwner {
+    pub const COUNT: usize = 6;
+
+    #[cfg(debug_assertions)]
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            1 => Self::ObjectPage,
+            2 => Self::KernelObject,
+            3 => Self::PageTable,
+            4 => Self::Dma,
+            5 => Self::Pager,
+            _ => Self::Unknown,
+        }
     }
 }
 
 impl PhysicalFrameAllocator {
-    fn new(memory_regions: &[MemoryRegion]) -> PhysicalFrameAllocator {
+    fn new(
+        memory_regions: &[MemoryRegion],
+        preserved: &[Range<PhysAddr>],
+    ) -> PhysicalFrameAllocator {
         Self {
             region_idx: 0,
             admitted_regions: Vec::new(),
@@ -529,7 +754,7 @@ impl PhysicalFrameAllocator {
                 .iter()
                 .filter_map(|m| {
                     if m.kind == MemoryRegionKind::UsableRam {
-                        AllocationRegion::new(m)
+                        AllocationRegion::new(m, preserved)
                     } else {
                         None
                     }
@@ -544,6 +769,23 @@ impl PhysicalFrameAllocator {
             .fold(0, |acc, region| region.nr_pages + acc)
     }
 
+    fn preserved(&self) -> usize {
+        self.regions
+            .iter()
+            .fold(0, |acc, region| region.nr_preserved + acc)
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| region.admitted_bytes)
+            .sum()
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.regions.iter().map(|region| region.free_bytes()).sum()
+    }
+
     fn alloc(&mut self, flags: PhysicalFrameFlags, layout: Layout) -> Option<FrameRef> {
         let frame = self.__do_alloc(flags, layout)?;
         if flags.contains(PhysicalFrameFlags::ZEROED) && !frame.is_zeroed() {
@@ -577,11 +819,92 @@ impl PhysicalFrameAllocator {
             }
         }
     }
+
+    fn free_batch(&mut self, frames: impl Iterator<Item = FrameRef>) {
+        // Frames freed together usually come from the same region, so check the region we used
+        // last before searching all of them.
+        let mut last = 0;
+        for frame in frames {
+            let addr = frame.start_address();
+            if !self.regions.get(last).is_some_and(|reg| reg.contains(addr)) {
+                let Some(idx) = self.regions.iter().position(|reg| reg.contains(addr)) else {
+                    continue;
+                };
+                last = idx;
+            }
+            self.regions[last].free(frame);
+        }
+    }
+
+    fn shrink(&mut self, frame: FrameRef, keep_layout: Layout) -> FrameRef {
+        let Some(reg) = self
+            .regions
+            .iter_mut()
+            .find(|reg| reg.contains(frame.start_address()))
+        else {
+            return frame;
+        };
+        match reg.find_level(keep_layout) {
+            Some(keep_level) if keep_level < frame.get_level() => reg.shrink(frame, keep_level),
+            _ => frame,
+        }
+    }
 }
 
 #[doc(hidden)]
 static PFA: Once<Spinlock<PhysicalFrameAllocator>> = Once::new();
 
+/// Counts of the allocations served by the frame allocator since boot, indexed by level (see
+/// [PHYS_LEVEL_LAYOUTS]). Returned by [frame_alloc_histogram].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameAllocHistogram {
+    /// Allocations served directly from a free frame of the requested level.
+    pub direct: [u64; NR_LEVELS],
+    /// Allocations that had to split a larger frame first.
+    pub split: [u64; NR_LEVELS],
+}
+
+impl FrameAllocHistogram {
+    /// Total allocations served at a level.
+    pub fn total(&self, level: usize) -> u64 {
+        self.direct[level] + self.split[level]
+    }
+}
+
+struct AllocHistogramCounters {
+    direct: [AtomicU64; NR_LEVELS],
+    split: [AtomicU64; NR_LEVELS],
+}
+
+impl AllocHistogramCounters {
+    const fn new() -> Self {
+        Self {
+            direct: [const { AtomicU64::new(0) }; NR_LEVELS],
+            split: [const { AtomicU64::new(0) }; NR_LEVELS],
+        }
+    }
+
+    fn record(&self, level: usize, split: bool) {
+        let counters = if split { &self.split } else { &self.direct };
+        counters[level].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Updated with relaxed atomics, since these are statistics only and are bumped on every
+// allocation.
+static ALLOC_HISTOGRAM: AllocHistogramCounters = AllocHistogramCounters::new();
+
+/// Read the histogram of allocations the frame allocator has served since boot. A high split
+/// count relative to direct allocations means large frames are being broken up often.
+pub fn frame_alloc_histogram() -> FrameAllocHistogram {
+    let mut histogram = FrameAllocHistogram::default();
+    for level in 0..NR_LEVELS {
+        histogram.direct[level] = ALLOC_HISTOGRAM.direct[level].load(Ordering::Relaxed);
+        histogram.split[level] = ALLOC_HISTOGRAM.split[level].load(Ordering::Relaxed);
+    }
+    histogram
+}
+
 #[derive(Clone)]
 struct FrameIndexer {
     start: PhysAddr,
@@ -656,12 +979,15 @@ static FI: Once<Vec<FrameIndexer>> = Once::new();
 /// Initialize the global physical frame allocator.
 /// # Arguments
 ///  * `regions`: An array of memory regions passed from the boot info system.
-pub fn init(regions: &[MemoryRegion]) {
-    let pfa = PhysicalFrameAllocator::new(regions);
+///  * `preserved`: Physical ranges whose contents must survive a warm boot (e.g. kexec). Frames in
+///    these ranges start out allocated and flagged [PhysicalFrameFlags::PRESERVED].
+pub fn init(regions: &[MemoryRegion], preserved: &[Range<PhysAddr>]) {
+    let pfa = PhysicalFrameAllocator::new(regions, preserved);
     let total = pfa.total();
+    let preserved = pfa.preserved();
     FI.call_once(|| pfa.regions.iter().map(|r| r.indexer.clone()).collect());
     PFA.call_once(|| Spinlock::new(pfa));
-    crate::memory::tracker::init(total, total, 0);
+    crate::memory::tracker::init(total, total - preserved, 0);
 }
 
 pub(super) fn raw_alloc_frame(flags: PhysicalFrameFlags, layout: Layout) -> Option<FrameRef> {
@@ -676,18 +1002,66 @@ pub(super) fn raw_alloc_frame(flags: PhysicalFrameFlags, layout: Layout) -> Opti
     Some(frame)
 }
 
-pub(super) fn raw_free_frame(frame: FrameRef) {
+fn check_free_frame(frame: FrameRef) -> bool {
     if !frame.get_flags().contains(PhysicalFrameFlags::ADMITTED) {
         // TODO: this happens when a sub-frame of a larger frame is freed, even though
         // the larger frame was allocated. It'd be nice to make this not happen. But
         // if that's impossible, we can track these freed frames in a list and periodically
         // try to recover the large page if all associated pages are freed, and then free that.
         log::warn!("tried to free non-admitted frame {:?}", frame);
-        return;
+        return false;
+    }
+    assert!(frame.get_flags().contains(PhysicalFrameFlags::ADMITTED));
+    assert!(frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
+    true
+}
+
+pub(super) fn raw_free_frame(frame: FrameRef) {
+    if check_free_frame(frame) {
+        PFA.wait().lock().free(frame);
+    }
+}
+
+/// Free a batch of frames, taking the allocator lock once for the whole batch rather than once
+/// per frame. The frames may come from any number of regions. As with [raw_free_frame],
+/// non-admitted frames are skipped.
+pub(super) fn raw_free_frames(frames: &[FrameRef]) {
+    // Check (and log) outside of the lock. Frames that fail the check aren't admitted, and are
+    // skipped below.
+    for frame in frames {
+        check_free_frame(*frame);
     }
+    PFA.wait().lock().free_batch(
+        frames
+            .iter()
+            .copied()
+            .filter(|frame| frame.get_flags().contains(PhysicalFrameFlags::ADMITTED)),
+    );
+}
+
+/// Shrink an allocated frame to the smallest frame that fits `keep_layout`, freeing the rest.
+///
+/// The kept frame starts at the same address as the original. Since a frame is aligned to its own
+/// size, the kept frame satisfies any alignment up to that of the original frame; if `keep_layout`
+/// needs a frame at least as large as the original, the frame is returned unchanged. At each level
+/// of the split, the remaining child frames are freed to that level. Like [raw_free_frame], this
+/// does not update the memory tracker's accounting.
+pub(super) fn raw_shrink_frame(frame: FrameRef, keep_layout: Layout) -> FrameRef {
     assert!(frame.get_flags().contains(PhysicalFrameFlags::ADMITTED));
     assert!(frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
-    PFA.wait().lock().free(frame);
+    PFA.wait().lock().shrink(frame, keep_layout)
+}
+
+/// Bytes of physical memory managed by the frame allocator, free or allocated, with each frame
+/// counted at its actual size. Memory used for the allocator's own frame arrays is not included.
+pub fn total_bytes() -> usize {
+    PFA.wait().lock().total_bytes()
+}
+
+/// Bytes of physical memory in free frames. A free large frame counts for its whole size, so this
+/// is accurate however the free memory is split between levels.
+pub fn free_bytes() -> usize {
+    PFA.wait().lock().free_bytes()
 }
 
 /// Get a FrameRef from a physical address.
@@ -702,16 +1076,70 @@ pub fn get_frame(pa: PhysAddr) -> Option<FrameRef> {
     None
 }
 
+/// Count the allocated frames attributed to each [FrameOwner], indexed by the owner's
+/// discriminant.
+#[cfg(debug_assertions)]
+pub fn allocated_frames_by_owner() -> [usize; FrameOwner::COUNT] {
+    let mut counts = [0; FrameOwner::COUNT];
+    for fi in FI.wait() {
+        for frame in fi.frame_array() {
+            let flags = frame.get_flags();
+            if flags.contains(PhysicalFrameFlags::ADMITTED | PhysicalFrameFlags::ALLOCATED) {
+                counts[frame.owner() as usize] += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Print the currently allocated frames, grouped by owner. Frames that are allocated but no
+/// longer referenced by their owner show up here as leaks. Only available in debug builds.
+#[cfg(debug_assertions)]
+pub fn dump_leaked_frames() {
+    const MAX_LISTED_PER_OWNER: usize = 8;
+    let counts = allocated_frames_by_owner();
+    logln!("allocated frames by owner:");
+    for raw in 0..FrameOwner::COUNT {
+        let owner = FrameOwner::from_raw(raw as u8);
+        if counts[raw] == 0 {
+            continue;
+        }
+        logln!("  {:?}: {} frames", owner, counts[raw]);
+        let mut listed = 0;
+        'outer: for fi in FI.wait() {
+            for frame in fi.frame_array() {
+                let flags = frame.get_flags();
+                if flags.contains(PhysicalFrameFlags::ADMITTED | PhysicalFrameFlags::ALLOCATED)
+                    && frame.owner() == owner
+                {
+                    if listed == MAX_LISTED_PER_OWNER {
+                        logln!("    ...");
+                        break 'outer;
+                    }
+                    logln!("    {:?} ({} bytes)", frame.start_address(), frame.size());
+                    listed += 1;
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use alloc::vec::Vec;
+    use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+    use core::alloc::Layout;
 
     use twizzler_kernel_macros::kernel_test;
 
     use super::{
-        get_frame, raw_alloc_frame, raw_free_frame, PhysicalFrameFlags, PHYS_LEVEL_LAYOUTS,
+        frame_alloc_histogram, free_bytes, get_frame, raw_alloc_frame, raw_free_frame,
+        raw_free_frames, raw_shrink_frame, total_bytes, AllocationRegion, Frame,
+        FrameAllocHistogram, FrameRef, PhysicalFrameFlags, FRAME_SIZE, PFA, PHYS_LEVEL_LAYOUTS,
+    };
+    use crate::{
+        arch::memory::phys_to_virt,
+        memory::{MemoryRegion, MemoryRegionKind},
     };
-    use crate::utils::quick_random;
 
     #[kernel_test]
     fn test_get_frame() {
@@ -721,29 +1149,456 @@ mod tests {
         assert!(core::ptr::eq(frame as *const _, test_frame as *const _));
     }
 
+    #[cfg(debug_assertions)]
     #[kernel_test]
-    fn stress_test_pmm() {
-        let mut stack = Vec::new();
-        for _ in 0..100000 {
-            let x = quick_random();
-            let y = quick_random();
-            let z = quick_random();
-            if x % 2 == 0 && stack.len() < 1000 {
-                let frame = if y % 3 == 0 {
-                    raw_alloc_frame(PhysicalFrameFlags::ZEROED, PHYS_LEVEL_LAYOUTS[0])
-                } else {
-                    raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[0])
+    fn test_frame_owner() {
+        use super::{allocated_frames_by_owner, dump_leaked_frames, FrameOwner};
+
+        let before = allocated_frames_by_owner()[FrameOwner::Dma as usize];
+        let frame = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        assert_eq!(frame.owner(), FrameOwner::Unknown);
+        frame.set_owner(FrameOwner::Dma);
+        assert_eq!(frame.owner(), FrameOwner::Dma);
+
+        assert_eq!(
+            allocated_frames_by_owner()[FrameOwner::Dma as usize],
+            before + 1
+        );
+        dump_leaked_frames();
+
+        raw_free_frame(frame);
+        assert_eq!(frame.owner(), FrameOwner::Unknown);
+        assert_eq!(
+            allocated_frames_by_owner()[FrameOwner::Dma as usize],
+            before
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[kernel_test]
+    fn test_verify_zeroed() {
+        let frame = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        let contents = unsafe {
+            core::slice::from_raw_parts_mut(
+                phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                frame.size(),
+            )
+        };
+        // Dirty the frame, so a zeroed allocation that reuses it has to really zero it.
+        contents[frame.size() - 1] = 0xaa;
+        assert!(!frame.verify_zeroed());
+        raw_free_frame(frame);
+
+        let frame = raw_alloc_frame(PhysicalFrameFlags::ZEROED, PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        // The flag is cleared on allocation, but the memory itself is zero.
+        assert!(!frame.is_zeroed());
+        assert!(frame.verify_zeroed());
+        raw_free_frame(frame);
+    }
+
+    #[kernel_test]
+    fn test_discard_frame() {
+        let frame = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        let contents = unsafe {
+            core::slice::from_raw_parts_mut(
+                phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+                frame.size(),
+            )
+        };
+        contents.fill(0xaa);
+        frame.set_not_zero();
+
+        frame.mark_discardable();
+        assert!(frame.is_discardable());
+        // The first access after the discard zeroes the frame...
+        assert!(frame.settle_discarded());
+        assert!(!frame.is_discardable());
+        assert!(!frame.is_zeroed());
+        assert!(contents.iter().all(|b| *b == 0));
+        // ...and anyone racing with it (e.g. the reclaim thread) finds nothing left to do, so new
+        // data written after the access is kept.
+        contents[0] = 0x55;
+        frame.set_not_zero();
+        assert!(!frame.settle_discarded());
+        assert_eq!(contents[0], 0x55);
+
+        // Freeing a discardable frame clears the mark.
+        frame.mark_discardable();
+        raw_free_frame(frame);
+        assert!(!frame.is_discardable());
+    }
+
+    #[kernel_test]
+    fn test_shrink_frame() {
+        let large = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[1]).unwrap();
+        let start = large.start_address();
+        let large_size = large.size();
+        large.set_kernel(true);
+
+        let kept = raw_shrink_frame(large, PHYS_LEVEL_LAYOUTS[0]);
+        assert_eq!(kept.start_address(), start);
+        assert_eq!(kept.size(), PHYS_LEVEL_LAYOUTS[0].size());
+        assert!(kept.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
+        assert!(kept.is_kernel());
+
+        // Every other 4K frame in the old 2M frame is now free and can be allocated.
+        let small_size = PHYS_LEVEL_LAYOUTS[0].size();
+        for off in (small_size..large_size).step_by(small_size) {
+            let child = get_frame(start.offset(off).unwrap()).unwrap();
+            let flags = child.get_flags();
+            assert!(flags.contains(PhysicalFrameFlags::ADMITTED));
+            assert!(!flags.contains(PhysicalFrameFlags::ALLOCATED));
+            assert_eq!(child.size(), small_size);
+        }
+
+        // Shrinking to a layout that needs the whole frame is a no-op.
+        assert!(core::ptr::eq(
+            raw_shrink_frame(kept, PHYS_LEVEL_LAYOUTS[0]),
+            kept
+        ));
+        raw_free_frame(kept);
+    }
+
+    #[kernel_test]
+    fn test_alloc_unusual_alignment() {
+        // 64K isn't the alignment of any level.
+        let align = FRAME_SIZE * 16;
+        let layout = Layout::from_size_align(FRAME_SIZE, align).unwrap();
+        let frame = raw_alloc_frame(PhysicalFrameFlags::empty(), layout).unwrap();
+        let start = frame.start_address();
+        assert!(start.is_aligned_to(align));
+        assert_eq!(frame.size(), FRAME_SIZE);
+        assert!(frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
+
+        // It was split off a larger frame, and the rest of that frame is free again.
+        let large_size = PHYS_LEVEL_LAYOUTS[1].size();
+        for off in (FRAME_SIZE..large_size).step_by(FRAME_SIZE) {
+            let child = get_frame(start.offset(off).unwrap()).unwrap();
+            let flags = child.get_flags();
+            assert!(flags.contains(PhysicalFrameFlags::ADMITTED));
+            assert!(!flags.contains(PhysicalFrameFlags::ALLOCATED));
+            assert_eq!(child.size(), FRAME_SIZE);
+        }
+        raw_free_frame(frame);
+    }
+
+    #[kernel_test]
+    fn test_free_frames_batch() {
+        // Draw a few frames from every region directly, so the batch spans regions whenever the
+        // machine has more than one.
+        let mut frames = Vec::new();
+        let nr_regions = {
+            let mut pfa = PFA.wait().lock();
+            for reg in &mut pfa.regions {
+                for _ in 0..4 {
+                    if let Some(frame) = reg.allocate(true, false, PHYS_LEVEL_LAYOUTS[0]) {
+                        frames.push(frame);
+                    }
                 }
-                .unwrap();
-                if z % 5 == 0 {
-                    frame.zero();
+            }
+            pfa.regions.len()
+        };
+        if nr_regions < 2 {
+            logln!("only one memory region, batch free will not span regions");
+        }
+        frames.push(raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[1]).unwrap());
+        for frame in &frames {
+            assert!(frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
+        }
+
+        raw_free_frames(&frames);
+        for frame in &frames {
+            let flags = frame.get_flags();
+            assert!(flags.contains(PhysicalFrameFlags::ADMITTED));
+            assert!(!flags.contains(PhysicalFrameFlags::ALLOCATED));
+        }
+    }
+
+    // Allocate a frame at the given level from a region, noting in `expected` how the histogram
+    // should count it.
+    fn alloc_counted(
+        reg: &mut AllocationRegion,
+        level: usize,
+        expected: &mut FrameAllocHistogram,
+        frames: &mut Vec<FrameRef>,
+    ) {
+        if reg.levels[level].free > 0 {
+            expected.direct[level] += 1;
+        } else {
+            expected.split[level] += 1;
+        }
+        frames.push(
+            reg.allocate(true, false, PHYS_LEVEL_LAYOUTS[level])
+                .unwrap(),
+        );
+    }
+
+    #[kernel_test]
+    fn test_alloc_histogram() {
+        let mut frames = Vec::new();
+        {
+            // Holding the allocator lock keeps other allocations out of the histogram.
+            let mut pfa = PFA.wait().lock();
+            let reg = &mut pfa.regions[0];
+            let before = frame_alloc_histogram();
+            let mut expected = before;
+            for level in [0, 1, 0, 0, 1, 0] {
+                alloc_counted(reg, level, &mut expected, &mut frames);
+            }
+            // Use up the free small frames, so the next small allocation has to split.
+            for _ in 0..4096 {
+                if reg.levels[0].free == 0 {
+                    break;
+                }
+                alloc_counted(reg, 0, &mut expected, &mut frames);
+            }
+            if reg.levels[0].free == 0 && reg.levels[1].free > 0 {
+                alloc_counted(reg, 0, &mut expected, &mut frames);
+                assert!(expected.split[0] > before.split[0]);
+            }
+
+            let after = frame_alloc_histogram();
+            assert_eq!(after, expected);
+            assert_eq!(
+                after.total(0) + after.total(1) - before.total(0) - before.total(1),
+                frames.len() as u64
+            );
+        }
+        raw_free_frames(&frames);
+    }
+
+    #[kernel_test]
+    fn test_preserved_frames() {
+        // Build a private region out of a large frame, with one preserved range over the start
+        // (where the frame array would go) and one in the middle.
+        let backing = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[1]).unwrap();
+        let base = backing.start_address();
+        let region = MemoryRegion {
+            start: base,
+            length: backing.size(),
+            kind: MemoryRegionKind::UsableRam,
+        };
+        let mid = base.offset(backing.size() / 2).unwrap();
+        let preserved = [
+            base..base.offset(FRAME_SIZE).unwrap(),
+            mid..mid.offset(3 * FRAME_SIZE).unwrap(),
+        ];
+        let in_preserved = |frame: FrameRef| {
+            preserved
+                .iter()
+                .any(|range| range.contains(&frame.start_address()))
+        };
+
+        let mut reg = AllocationRegion::new(&region, &preserved).unwrap();
+        assert!(!reg.contains(base));
+        assert_eq!(reg.nr_preserved, 3);
+        for i in 0..3 {
+            let frame = reg.get_frame(mid.offset(i * FRAME_SIZE).unwrap()).unwrap();
+            let flags = frame.get_flags();
+            assert!(flags.contains(PhysicalFrameFlags::PRESERVED));
+            assert!(flags.contains(PhysicalFrameFlags::ALLOCATED));
+        }
+
+        let mut count = 0;
+        while let Some(frame) = reg.allocate(true, false, PHYS_LEVEL_LAYOUTS[0]) {
+            assert!(!in_preserved(frame));
+            count += 1;
+        }
+        assert!(count > 0);
+
+        // Once the owner releases a preserved frame, it becomes an ordinary free frame.
+        let frame = reg.get_frame(mid).unwrap();
+        reg.free(frame);
+        assert!(!frame.get_flags().contains(PhysicalFrameFlags::PRESERVED));
+        let again = reg.allocate(true, false, PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        assert_eq!(again.start_address(), mid);
+
+        raw_free_frame(backing);
+    }
+
+    #[kernel_test]
+    fn test_region_bytes() {
+        let backing = raw_alloc_frame(PhysicalFrameFlags::empty(), PHYS_LEVEL_LAYOUTS[1]).unwrap();
+        let base = backing.start_address();
+        let region = MemoryRegion {
+            start: base,
+            length: backing.size(),
+            kind: MemoryRegionKind::UsableRam,
+        };
+        let mid = base.offset(backing.size() / 2).unwrap();
+        let preserved = [mid..mid.offset(2 * FRAME_SIZE).unwrap()];
+
+        // Everything but the frame array is admitted, including the preserved frames, which
+        // start out allocated.
+        let mut reg = AllocationRegion::new(&region, &preserved).unwrap();
+        let nr_pages = backing.size() / FRAME_SIZE;
+        let array_pages = (nr_pages * core::mem::size_of::<Frame>()).div_ceil(FRAME_SIZE);
+        let total = (nr_pages - array_pages) * FRAME_SIZE;
+        assert_eq!(reg.admitted_bytes, total);
+        assert_eq!(reg.free_bytes(), total - 2 * FRAME_SIZE);
+
+        let frame = reg.allocate(true, false, PHYS_LEVEL_LAYOUTS[0]).unwrap();
+        assert_eq!(reg.free_bytes(), total - 3 * FRAME_SIZE);
+        reg.free(frame);
+        assert_eq!(reg.free_bytes(), total - 2 * FRAME_SIZE);
+        raw_free_frame(backing);
+
+        // The whole allocator counts frames at every level by their size.
+        let (total, free) = (total_bytes(), free_bytes());
+        assert_eq!(total % FRAME_SIZE, 0);
+        assert!(free <= total);
+    }
+
+    /// One step of a scripted allocator run. Scripts are generated from a seed (or written by
+    /// hand), so a failing run can be replayed exactly.
+    #[derive(Clone, Copy, Debug)]
+    enum PmmOp {
+        Alloc {
+            level: usize,
+            zeroed: bool,
+            touch: bool,
+        },
+        /// Free the live frame at this index (mod the number of live frames).
+        Free(usize),
+        /// Shrink the live frame at this index (mod the number of live frames) to the smallest
+        /// level.
+        Shrink(usize),
+    }
+
+    /// The same generator as [quick_random](crate::utils::quick_random), but with an explicit
+    /// seed instead of per-CPU state.
+    struct PmmRng(u32);
+
+    impl PmmRng {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(69069).wrapping_add(5);
+            self.0 >> 16
+        }
+    }
+
+    fn generate_ops(seed: u32, count: usize, max_live: usize) -> Vec<PmmOp> {
+        let mut rng = PmmRng(seed);
+        let mut live = 0;
+        let mut ops = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (x, y, z) = (rng.next(), rng.next(), rng.next());
+            let op = if x % 2 == 0 && live < max_live {
+                live += 1;
+                PmmOp::Alloc {
+                    level: if y % 64 == 0 { 1 } else { 0 },
+                    zeroed: y % 3 == 0,
+                    touch: z % 5 == 0,
                 }
-                stack.push(frame);
+            } else if x % 7 == 1 {
+                PmmOp::Shrink(z as usize)
             } else {
-                if let Some(frame) = stack.pop() {
+                live = live.saturating_sub(1);
+                PmmOp::Free(z as usize)
+            };
+            ops.push(op);
+        }
+        ops
+    }
+
+    /// Run a script against the real allocator, checking after every step that no two live
+    /// frames overlap. Frees everything still live at the end.
+    fn replay(ops: &[PmmOp]) {
+        let mut live: Vec<FrameRef> = Vec::new();
+        let mut ranges: BTreeMap<u64, u64> = BTreeMap::new();
+        for (i, op) in ops.iter().enumerate() {
+            match *op {
+                PmmOp::Alloc {
+                    level,
+                    zeroed,
+                    touch,
+                } => {
+                    let flags = if zeroed {
+                        PhysicalFrameFlags::ZEROED
+                    } else {
+                        PhysicalFrameFlags::empty()
+                    };
+                    let frame = raw_alloc_frame(flags, PHYS_LEVEL_LAYOUTS[level])
+                        .unwrap_or_else(|| panic!("op {} ({:?}): out of memory", i, op));
+                    #[cfg(debug_assertions)]
+                    assert!(
+                        !zeroed || frame.verify_zeroed(),
+                        "op {} ({:?}): frame requested as zeroed has nonzero contents",
+                        i,
+                        op
+                    );
+                    let start = frame.start_address().raw();
+                    let end = start + frame.size() as u64;
+                    let before = ranges.range(..end).next_back();
+                    assert!(
+                        before.is_none_or(|(_, prev_end)| *prev_end <= start),
+                        "op {} ({:?}): {:x}..{:x} overlaps a live frame",
+                        i,
+                        op,
+                        start,
+                        end
+                    );
+                    if touch {
+                        frame.zero();
+                    }
+                    ranges.insert(start, end);
+                    live.push(frame);
+                }
+                PmmOp::Free(idx) if !live.is_empty() => {
+                    let frame = live.swap_remove(idx % live.len());
+                    ranges.remove(&frame.start_address().raw());
                     raw_free_frame(frame);
                 }
+                PmmOp::Shrink(idx) if !live.is_empty() => {
+                    let idx = idx % live.len();
+                    let kept = raw_shrink_frame(live[idx], PHYS_LEVEL_LAYOUTS[0]);
+                    let start = kept.start_address().raw();
+                    assert_eq!(
+                        start,
+                        live[idx].start_address().raw(),
+                        "op {} ({:?})",
+                        i,
+                        op
+                    );
+                    ranges.insert(start, start + kept.size() as u64);
+                    live[idx] = kept;
+                }
+                PmmOp::Free(_) | PmmOp::Shrink(_) => {}
             }
         }
+        raw_free_frames(&live);
+    }
+
+    #[kernel_test]
+    fn stress_test_pmm() {
+        // Change the seed to explore; a failing seed replays the same run every time.
+        const SEED: u32 = 0x7a11;
+        logln!("stress_test_pmm: seed {:#x}", SEED);
+        replay(&generate_ops(SEED, 100000, 1000));
+    }
+
+    #[kernel_test]
+    fn test_replay_split_after_shrink() {
+        // The allocator never coalesces, so split children freed after a shrink must be handed
+        // out again as small frames without ever overlapping the kept frame or each other, and a
+        // later large allocation must not be carved out of them.
+        use PmmOp::*;
+        let small = |zeroed| Alloc {
+            level: 0,
+            zeroed,
+            touch: false,
+        };
+        let large = Alloc {
+            level: 1,
+            zeroed: false,
+            touch: true,
+        };
+        let mut ops = alloc::vec![large, Shrink(0)];
+        ops.extend((0..64).map(|i| small(i % 2 == 0)));
+        ops.extend((0..32).map(|i| Free(i * 2)));
+        ops.push(large);
+        ops.extend((0..32).map(|_| small(false)));
+        ops.push(Shrink(usize::MAX));
+        ops.push(large);
+        replay(&ops);
     }
 }
@@ -46,7 +46,7 @@ impl MemoryRegion {
 }
 
 pub fn init(boot_info: &dyn BootInfo) {
-    frame::init(boot_info.memory_regions());
+    frame::init(boot_info.memory_regions(), boot_info.preserved_regions());
     let kc = context::kernel_context();
     kc.switch_to(KERNEL_SCTX);
     kc.init_allocator();
@@ -5,7 +5,7 @@ use crate::{
         memory::pagetables::{Entry, EntryFlags, Table},
     },
     memory::{
-        frame::{get_frame, FrameRef, PHYS_LEVEL_LAYOUTS},
+        frame::{get_frame, FrameOwner, FrameRef, PHYS_LEVEL_LAYOUTS},
         pagetables::MappingFlags,
         tracker::{try_alloc_frame, FrameAllocFlags},
     },
@@ -62,6 +62,7 @@ impl Table {
                 FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED,
                 PHYS_LEVEL_LAYOUTS[0],
             )?;
+            frame.set_owner(FrameOwner::PageTable);
             *entry = Entry::new(frame.start_address(), flags);
             self.set_count(count + 1);
         }
@@ -1,12 +1,12 @@
 use alloc::vec::Vec;
 use core::{
     alloc::Layout,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 use bitflags::bitflags;
 use intrusive_collections::{intrusive_adapter, LinkedList};
-use twizzler_abi::{pager::PhysRange, thread::ExecutionState};
+use twizzler_abi::{object::ObjID, pager::PhysRange, thread::ExecutionState};
 
 use super::{
     frame::{get_frame, FrameRef, PhysicalFrameFlags, PHYS_LEVEL_LAYOUTS},
@@ -33,9 +33,51 @@ pub struct MemoryTracker {
     pager_outstanding: AtomicUsize,
     reclaim: Once<ReclaimThread>,
     waiters: Spinlock<LinkedList<LinkAdapter>>,
+    watermark: Watermark,
 }
 intrusive_adapter!(pub LinkAdapter = ThreadRef: Thread { mutex_link: intrusive_collections::linked_list::AtomicLink });
 
+/// A low watermark on idle frames, with a callback for when allocation takes idle frames below it.
+/// See [set_low_watermark].
+struct Watermark {
+    // Zero means no watermark is set.
+    low: AtomicUsize,
+    rearm: AtomicUsize,
+    armed: AtomicBool,
+    callback: Spinlock<Option<fn()>>,
+}
+
+impl Watermark {
+    fn new() -> Self {
+        Self {
+            low: AtomicUsize::new(0),
+            rearm: AtomicUsize::new(0),
+            armed: AtomicBool::new(false),
+            callback: Spinlock::new(None),
+        }
+    }
+
+    // Called after an allocation leaves `idle` frames idle.
+    fn after_alloc(&self, idle: usize) {
+        if idle < self.low.load(Ordering::Acquire) && self.armed.swap(false, Ordering::SeqCst) {
+            // Copy the callback out so the lock is released before it runs, since it may well
+            // allocate (or set a new watermark) itself.
+            let callback = *self.callback.lock();
+            if let Some(callback) = callback {
+                callback();
+            }
+        }
+    }
+
+    // Called after a free leaves `idle` frames idle.
+    fn after_free(&self, idle: usize) {
+        let low = self.low.load(Ordering::Acquire);
+        if low > 0 && idle >= self.rearm.load(Ordering::Acquire) {
+            self.armed.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
 impl MemoryTracker {
     fn free_frame(&self, frame: FrameRef) {
         let count = frame.size() / FRAME_SIZE;
@@ -45,9 +87,30 @@ impl MemoryTracker {
             self.page_data.fetch_sub(count, Ordering::SeqCst)
         };
         assert!(old > 0);
-        self.idle.fetch_add(count, Ordering::SeqCst);
+        let idle = self.idle.fetch_add(count, Ordering::SeqCst) + count;
         self.freed.fetch_add(count, Ordering::SeqCst);
         crate::memory::frame::raw_free_frame(frame);
+        self.watermark.after_free(idle);
+        self.wake();
+    }
+
+    fn free_frames(&self, frames: &[FrameRef]) {
+        let (mut kernel, mut data) = (0, 0);
+        for frame in frames {
+            let count = frame.size() / FRAME_SIZE;
+            if frame.is_kernel() {
+                kernel += count;
+            } else {
+                data += count;
+            }
+        }
+        let old_kernel = self.kernel_used.fetch_sub(kernel, Ordering::SeqCst);
+        let old_data = self.page_data.fetch_sub(data, Ordering::SeqCst);
+        assert!(old_kernel >= kernel && old_data >= data);
+        let idle = self.idle.fetch_add(kernel + data, Ordering::SeqCst) + kernel + data;
+        self.freed.fetch_add(kernel + data, Ordering::SeqCst);
+        crate::memory::frame::raw_free_frames(frames);
+        self.watermark.after_free(idle);
         self.wake();
     }
 
@@ -77,6 +140,7 @@ impl MemoryTracker {
                             self.page_data.fetch_add(count, Ordering::SeqCst);
                         }
                         self.allocated.fetch_add(count, Ordering::SeqCst);
+                        self.watermark.after_alloc(idle - count);
                         return Some(frame);
                     } else {
                         self.idle.fetch_add(count, Ordering::SeqCst);
@@ -208,6 +272,15 @@ impl MemoryTracker {
     fn start_reclaim_thread(&self) {
         self.reclaim.call_once(|| ReclaimThread::new());
     }
+
+    fn set_low_watermark(&self, low: usize, rearm: usize, callback: Option<fn()>) {
+        let wm = &self.watermark;
+        *wm.callback.lock() = callback;
+        wm.rearm.store(rearm.max(low), Ordering::SeqCst);
+        wm.low.store(low, Ordering::SeqCst);
+        wm.armed
+            .store(low > 0 && self.idle() >= low, Ordering::SeqCst);
+    }
 }
 
 pub static TRACKER: Once<MemoryTracker> = Once::new();
@@ -283,6 +356,15 @@ pub fn free_frame(frame: FrameRef) {
         .free_frame(frame)
 }
 
+/// Free a batch of physical frames. This is equivalent to calling [free_frame] on each of them, but
+/// only takes the physical frame allocator's lock once.
+pub fn free_frames(frames: &[FrameRef]) {
+    TRACKER
+        .poll()
+        .expect("page tracker not initialized")
+        .free_frames(frames)
+}
+
 /// Track a page as owned by the pager.
 pub fn track_page_pager(count: usize) {
     TRACKER
@@ -329,6 +411,30 @@ pub fn start_reclaim_thread() {
         .start_reclaim_thread();
 }
 
+/// Call `callback` when an allocation takes the number of idle frames below `low`, so that
+/// subsystems holding caches can release memory before allocations start to wait.
+///
+/// The callback fires once per crossing. To keep it from firing over and over while the idle
+/// count hovers around `low`, it isn't armed again until frees bring the idle count back up to at
+/// least `rearm` (which is raised to `low` if it's below it). The callback runs in the allocating
+/// thread, possibly with locks held, so it must be quick and must not allocate frames itself;
+/// typically it wakes up something that does the releasing. Setting a new watermark replaces the
+/// old one.
+pub fn set_low_watermark(low: usize, rearm: usize, callback: fn()) {
+    TRACKER
+        .poll()
+        .expect("page tracker not initialized")
+        .set_low_watermark(low, rearm, Some(callback))
+}
+
+/// Remove the watermark set with [set_low_watermark].
+pub fn clear_low_watermark() {
+    TRACKER
+        .poll()
+        .expect("page tracker not initialized")
+        .set_low_watermark(0, 0, None)
+}
+
 pub fn reclaim(frames: impl IntoIterator<Item = FrameRef>) {
     TRACKER
         .poll()
@@ -342,6 +448,24 @@ pub fn reclaim(frames: impl IntoIterator<Item = FrameRef>) {
     TRACKER.poll().unwrap().reclaim.poll().unwrap().cv.signal();
 }
 
+/// Mark frames' contents as no longer needed (see [Frame::mark_discardable]), and have the
+/// reclaim thread zero them in the background. Frames that are accessed before the reclaim thread
+/// gets to them are zeroed on access instead. The frames must already be unmapped.
+///
+/// [Frame::mark_discardable]: super::frame::Frame::mark_discardable
+pub fn discard(frames: impl IntoIterator<Item = FrameRef>) {
+    let rt = TRACKER.poll().and_then(|tracker| tracker.reclaim.poll());
+    for frame in frames {
+        frame.mark_discardable();
+        if let Some(rt) = rt {
+            rt.discarded.lock().push(frame);
+        }
+    }
+    if let Some(rt) = rt {
+        rt.cv.signal();
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct FrameAllocFlags: u32 {
@@ -357,6 +481,7 @@ bitflags! {
 struct ReclaimThread {
     th: ThreadRef,
     state: Spinlock<Vec<FrameRef>>,
+    discarded: Spinlock<Vec<FrameRef>>,
     cv: CondVar,
 }
 
@@ -368,6 +493,7 @@ impl ReclaimThread {
         Self {
             th: start_new_kernel(Priority::REALTIME, reclaim_start, 0),
             state: Spinlock::new(Vec::new()),
+            discarded: Spinlock::new(Vec::new()),
             cv: CondVar::new(),
         }
     }
@@ -382,6 +508,12 @@ fn reclaim_main() {
     const MAX_RECLAIM_ROUNDS: usize = 1000;
     const MAX_PER_ROUND: usize = 100;
     loop {
+        // Zero any discarded frames that haven't already been zeroed by an access.
+        let discarded = core::mem::take(&mut *rt.discarded.lock());
+        for frame in discarded {
+            frame.settle_discarded();
+        }
+
         let mut count = 0;
         let mut rounds = 0;
         while tracker.should_reclaim() {
@@ -435,6 +567,7 @@ pub fn init(total: usize, idle: usize, kern: usize) {
         pager_outstanding: AtomicUsize::new(0),
         reclaim: Once::new(),
         waiters: Spinlock::new(LinkedList::new(LinkAdapter::NEW)),
+        watermark: Watermark::new(),
     });
 }
 
@@ -442,6 +575,7 @@ pub struct FrameAllocator {
     flags: FrameAllocFlags,
     layout: Layout,
     frames: Vec<FrameRef>,
+    owner: Option<ObjID>,
 }
 
 impl FrameAllocator {
@@ -450,9 +584,22 @@ impl FrameAllocator {
             flags,
             layout,
             frames: Vec::new(),
+            owner: None,
         }
     }
 
+    /// Charge object pages made from this allocator's frames to `owner`'s page quota (see
+    /// [crate::obj::pages::set_page_quota]).
+    pub fn with_owner(mut self, owner: ObjID) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// The owner that pages made from this allocator's frames are charged to, if any.
+    pub fn owner(&self) -> Option<ObjID> {
+        self.owner
+    }
+
     pub fn try_allocate(&mut self) -> Option<FrameRef> {
         if self.frames.len() == 0 {
             try_alloc_frame(self.flags, self.layout)
@@ -512,3 +659,53 @@ impl FrameRegion {
         self.range.len() / FRAME_SIZE
     }
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use twizzler_kernel_macros::kernel_test;
+
+    use super::{clear_low_watermark, free_frames, set_low_watermark, FrameAllocFlags, TRACKER};
+    use crate::memory::frame::PHYS_LEVEL_LAYOUTS;
+
+    static CROSSINGS: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_low_memory() {
+        CROSSINGS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[kernel_test]
+    fn test_low_watermark() {
+        let alloc = |n: usize| -> Vec<_> {
+            (0..n)
+                .map(|_| {
+                    super::try_alloc_frame(FrameAllocFlags::KERNEL, PHYS_LEVEL_LAYOUTS[0]).unwrap()
+                })
+                .collect()
+        };
+        let idle = TRACKER.poll().unwrap().idle();
+        CROSSINGS.store(0, Ordering::SeqCst);
+        set_low_watermark(idle - 8, idle - 2, on_low_memory);
+
+        // Dropping below the watermark fires the callback, but only once.
+        let mut held = alloc(12);
+        assert_eq!(CROSSINGS.load(Ordering::SeqCst), 1);
+
+        // Flapping around the watermark doesn't fire it again...
+        for _ in 0..4 {
+            free_frames(&held.split_off(4));
+            held.extend(alloc(8));
+        }
+        assert_eq!(CROSSINGS.load(Ordering::SeqCst), 1);
+
+        // ...until the idle count has recovered past the rearm level.
+        free_frames(&held);
+        held = alloc(12);
+        assert_eq!(CROSSINGS.load(Ordering::SeqCst), 2);
+
+        clear_low_watermark();
+        free_frames(&held);
+    }
+}
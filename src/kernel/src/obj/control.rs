@@ -15,7 +15,7 @@ use crate::{
             kernel_context, KernelMemoryContext, KernelObject, KernelObjectHandle,
             ObjectContextInfo,
         },
-        frame::FrameRef,
+        frame::{FrameOwner, FrameRef},
         tracker::{alloc_frame, FrameAllocFlags},
     },
     obj::{pages::Page, ObjectRef, PageNumber},
@@ -60,6 +60,7 @@ impl<Base> ControlObjectCacher<Base> {
             let frame = alloc_frame(
                 FrameAllocFlags::ZEROED | FrameAllocFlags::WAIT_OK | FrameAllocFlags::KERNEL,
             );
+            frame.set_owner(FrameOwner::KernelObject);
             let page = Page::new_wired(frame.start_address(), frame.size(), CacheType::WriteBack);
             let base_ptr = unsafe {
                 let ptr = page.get_mut_to_val::<Base>(0);
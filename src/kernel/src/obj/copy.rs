@@ -641,6 +641,7 @@ mod test {
                     .unwrap(),
                 PageStatus::AllocFail => panic!("out of memory"),
                 PageStatus::DataFail => panic!("data loss in copy"),
+                PageStatus::NoCopy => panic!("device page in copy"),
                 PageStatus::Locked(_) => panic!("page lock during test"),
             };
             sp.as_mut_slice().fill(p);
@@ -0,0 +1,135 @@
+//! A Merkle tree over an object's pages, giving a fingerprint of its contents for attestation.
+//!
+//! The tree always spans the whole object, one leaf per page from page 0 up to and including the
+//! metadata page, so its shape doesn't depend on which pages happen to be present. A page that is
+//! absent hashes the same as a present page of zeros, matching what a read of it returns, so the
+//! root depends only on the object's contents and not on how sparsely they are stored. Leaves and
+//! inner nodes are hashed with different prefixes, so a leaf can never be passed off as an inner
+//! node or the other way around.
+
+use alloc::vec::Vec;
+
+use twizzler_abi::object::MAX_SIZE;
+
+use super::{
+    range::{GetPageFlags, PageStatus},
+    Object, PageNumber,
+};
+use crate::crypto::sha256_parts;
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+const NR_PAGES: usize = MAX_SIZE / PageNumber::PAGE_SIZE;
+const LEVELS: usize = NR_PAGES.trailing_zeros() as usize;
+const _: () = assert!(NR_PAGES.is_power_of_two());
+
+fn leaf_hash(page: &[u8]) -> [u8; 32] {
+    sha256_parts([&[LEAF_PREFIX][..], page])
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    sha256_parts([&[NODE_PREFIX][..], left, right])
+}
+
+/// The hash of a subtree of absent pages at each level, from a single page up to the root.
+fn empty_hashes() -> [[u8; 32]; LEVELS + 1] {
+    let mut empty = [leaf_hash(&[0; PageNumber::PAGE_SIZE]); LEVELS + 1];
+    for level in 1..=LEVELS {
+        empty[level] = node_hash(&empty[level - 1], &empty[level - 1]);
+    }
+    empty
+}
+
+/// The hash of the subtree covering the 2^level pages from `start`, given the leaf hashes of the
+/// present pages in it, in page order.
+fn subtree_hash(
+    level: usize,
+    start: usize,
+    leaves: &[(usize, [u8; 32])],
+    empty: &[[u8; 32]; LEVELS + 1],
+) -> [u8; 32] {
+    if leaves.is_empty() {
+        return empty[level];
+    }
+    if level == 0 {
+        return leaves[0].1;
+    }
+    let mid = start + (1 << (level - 1));
+    let (left, right) = leaves.split_at(leaves.partition_point(|(pn, _)| *pn < mid));
+    node_hash(
+        &subtree_hash(level - 1, start, left, empty),
+        &subtree_hash(level - 1, mid, right, empty),
+    )
+}
+
+impl Object {
+    /// Compute the Merkle root over this object's pages (see the [module docs](self)), as a
+    /// tamper-evident fingerprint of its contents that can be signed. The pages are hashed with
+    /// the page tree locked, so the root reflects a single snapshot of the object.
+    ///
+    /// Only pages in memory are hashed. For a pager-backed object, pages that haven't been
+    /// brought in count as zeros, so they must be brought in first for the root to cover their
+    /// contents. Device memory is not read, and counts as zeros too.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let leaves = {
+            let mut tree = self.lock_page_tree();
+            let all = PageNumber::from(0)..PageNumber::from(NR_PAGES);
+            let ranges: Vec<_> = tree
+                .range(all.clone())
+                .map(|(start, value)| *start..(*value.end()).min(all.end))
+                .collect();
+            let mut leaves = Vec::new();
+            for range in ranges {
+                for pn in range.start.num()..range.end.num() {
+                    let PageStatus::Ready(page, _) =
+                        tree.try_get_page(PageNumber::from(pn), GetPageFlags::empty())
+                    else {
+                        continue;
+                    };
+                    if page.is_mmio() {
+                        continue;
+                    }
+                    leaves.push((pn, leaf_hash(&page.as_slice()[..PageNumber::PAGE_SIZE])));
+                }
+            }
+            leaves
+        };
+        subtree_hash(LEVELS, 0, &leaves, &empty_hashes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use twizzler_abi::object::NULLPAGE_SIZE;
+    use twizzler_kernel_macros::kernel_test;
+
+    use super::{empty_hashes, LEVELS};
+    use crate::userinit::create_blank_object;
+
+    #[kernel_test]
+    fn test_merkle_root() {
+        let obj = create_blank_object();
+        let data = [0xa5u8; 64];
+        // The metadata page, which holds the object's nonce, is covered too.
+        let blank = obj.merkle_root();
+        assert_ne!(blank, empty_hashes()[LEVELS]);
+        assert_ne!(blank, create_blank_object().merkle_root());
+
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE);
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE * 40 - 16);
+        let root = obj.merkle_root();
+        assert_ne!(root, blank);
+
+        // Changing a single byte of a single page changes the root...
+        obj.write_bytes([0x5au8].as_ptr(), 1, NULLPAGE_SIZE * 40);
+        assert_ne!(obj.merkle_root(), root);
+        // ...and identical contents give identical roots, however they came about.
+        obj.write_bytes(data.as_ptr(), 1, NULLPAGE_SIZE * 40);
+        assert_eq!(obj.merkle_root(), root);
+
+        // A page of zeros counts the same as an absent page.
+        obj.write_bytes([0u8; 8].as_ptr(), 8, NULLPAGE_SIZE * 100);
+        assert_eq!(obj.merkle_root(), root);
+    }
+}
@@ -16,12 +16,16 @@ use twizzler_abi::{
 };
 use twizzler_rt_abi::object::Nonce;
 
-use self::{pages::Page, thread_sync::SleepInfo};
+use self::{
+    pages::{Page, WriteBack},
+    thread_sync::SleepInfo,
+};
 use crate::{
     arch::memory::frame::FRAME_SIZE,
     idcounter::{IdCounter, SimpleId, StableId},
     memory::{
         context::{kernel_context, Context, ContextRef, UserContext},
+        frame::FrameOwner,
         tracker::{alloc_frame, FrameAllocFlags, FrameAllocator},
         PhysAddr, VirtAddr,
     },
@@ -33,12 +37,54 @@ use crate::{
 pub mod control;
 pub mod copy;
 pub mod id;
+pub mod merkle;
 pub mod pages;
 pub mod pagevec;
 pub mod range;
 pub mod thread_sync;
 pub mod ties;
 
+/// Writes back pages of an object, returning only once they are durable.
+trait PageFlusher {
+    /// A flush that was issued, to wait on.
+    type Flush;
+
+    /// Start writing back `pages` of `obj`, without waiting for them to become durable.
+    fn issue(&self, obj: &ObjectRef, pages: &[PageNumber]) -> Self::Flush;
+
+    /// Wait for a flush to become durable.
+    fn wait(&self, flush: Self::Flush);
+}
+
+struct PagerFlusher;
+
+impl PageFlusher for PagerFlusher {
+    type Flush = Option<crate::pager::Inflight>;
+
+    fn issue(&self, obj: &ObjectRef, pages: &[PageNumber]) -> Self::Flush {
+        crate::pager::start_sync_pages(obj, pages.to_vec())
+    }
+
+    fn wait(&self, flush: Self::Flush) {
+        if let Some(inflight) = flush {
+            crate::pager::wait_inflight(&inflight);
+        }
+    }
+}
+
+trait PageFetcher {
+    /// Bring in page `pn` of `obj`. Returns true if the pager was asked for it.
+    fn fetch_page(&self, obj: &ObjectRef, pn: PageNumber) -> bool;
+}
+
+struct PagerFetcher;
+
+impl PageFetcher for PagerFetcher {
+    fn fetch_page(&self, obj: &ObjectRef, pn: PageNumber) -> bool {
+        crate::pager::get_object_page(obj, pn)
+    }
+}
+
 const OBJ_DELETED: u32 = 1;
 pub struct Object {
     id: ObjID,
@@ -51,6 +97,7 @@ pub struct Object {
     ties: Vec<CreateTieSpec>,
     verified_id: OnceWait<(bool, Protections)>,
     dirty_set: DirtySet,
+    writeback: WriteBack,
 }
 
 #[derive(Default)]
@@ -214,6 +261,7 @@ impl Object {
             } else {
                 let frame = alloc_frame(FrameAllocFlags::ZEROED | FrameAllocFlags::WAIT_OK);
                 let page = Page::new(frame);
+                frame.set_owner(FrameOwner::Dma);
                 v.push(page.physical_address());
                 let page = PageRef::new(Arc::new(page), 0, 1);
                 tree.add_page(start.offset(i), page, None);
@@ -239,6 +287,7 @@ impl Object {
             verified_id: OnceWait::new(),
             lifetime_type,
             dirty_set: DirtySet::new(),
+            writeback: WriteBack::new(),
         }
     }
 
@@ -306,6 +355,38 @@ impl Object {
         &self.dirty_set
     }
 
+    pub fn writeback(&self) -> &WriteBack {
+        &self.writeback
+    }
+
+    /// Write back `data_first` and wait for those pages to become durable, and only then write back
+    /// `then`. Use this when the pages in `then` (e.g. a base or metadata page) refer to the data
+    /// in `data_first`, so that a crash can never leave the metadata durable without the data it
+    /// points to. Does nothing for objects that are not backed by the pager.
+    pub fn sync_ordered(self: &ObjectRef, data_first: &[PageNumber], then: &[PageNumber]) {
+        if !self.use_pager() {
+            return;
+        }
+        self.sync_ordered_with(&PagerFlusher, data_first, then);
+    }
+
+    fn sync_ordered_with(
+        self: &ObjectRef,
+        flusher: &impl PageFlusher,
+        data_first: &[PageNumber],
+        then: &[PageNumber],
+    ) {
+        // Waiting for the first flush to become durable is the barrier between the two sets.
+        if !data_first.is_empty() {
+            let flush = flusher.issue(self, data_first);
+            flusher.wait(flush);
+        }
+        if !then.is_empty() {
+            let flush = flusher.issue(self, then);
+            flusher.wait(flush);
+        }
+    }
+
     pub fn info(&self) -> ObjectInfo {
         let num_pages = {
             let page_tree = self.lock_page_tree();
@@ -508,6 +589,11 @@ impl DirtySet {
         dirty
     }
 
+    /// The dirty pages, in order.
+    fn pages(&self) -> Vec<PageNumber> {
+        self.set.lock().iter().copied().collect()
+    }
+
     fn is_dirty(&self, pn: PageNumber) -> bool {
         self.set.lock().contains(&pn)
     }
@@ -519,12 +605,88 @@ impl DirtySet {
     fn reset_dirty(&self, pn: PageNumber) {
         self.set.lock().remove(&pn);
     }
+
+    fn swap(&self, other: &DirtySet) {
+        let (mut set, mut other_set) = crate::utils::lock_two(&*self.set, &*other.set);
+        core::mem::swap(&mut *set, &mut *other_set);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
     use twizzler_kernel_macros::kernel_test;
 
+    use super::{ObjectRef, PageFlusher, PageNumber};
+    use crate::{
+        sched::schedule,
+        spinlock::Spinlock,
+        thread::{
+            entry::{run_closure_in_new_thread, KthreadClosure},
+            priority::Priority,
+        },
+        userinit::create_blank_object,
+    };
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum FlushEvent {
+        Issued(Vec<PageNumber>),
+        Completed(Vec<PageNumber>),
+    }
+
+    /// A pager stand-in that records when each flush is issued and when it completes. Flushes
+    /// complete on their own thread, some time after they're issued, as with the real pager.
+    struct MockPager {
+        events: Arc<Spinlock<Vec<FlushEvent>>>,
+    }
+
+    impl PageFlusher for MockPager {
+        type Flush = Arc<KthreadClosure<Box<dyn FnOnce() + Send>, ()>>;
+
+        fn issue(&self, _obj: &ObjectRef, pages: &[PageNumber]) -> Self::Flush {
+            self.events.lock().push(FlushEvent::Issued(pages.to_vec()));
+            let events = self.events.clone();
+            let pages = pages.to_vec();
+            let complete: Box<dyn FnOnce() + Send> = Box::new(move || {
+                // Give the issuer plenty of time to run ahead if it isn't going to wait.
+                for _ in 0..10 {
+                    schedule(true);
+                }
+                events.lock().push(FlushEvent::Completed(pages));
+            });
+            run_closure_in_new_thread(Priority::USER, complete).1
+        }
+
+        fn wait(&self, flush: Self::Flush) {
+            flush.wait();
+        }
+    }
+
+    #[kernel_test]
+    fn test_sync_ordered() {
+        let obj = create_blank_object();
+        let pager = MockPager {
+            events: Arc::new(Spinlock::new(Vec::new())),
+        };
+        let data = [PageNumber(3), PageNumber(4), PageNumber(7)];
+        let meta = [PageNumber::base_page()];
+
+        obj.sync_ordered_with(&pager, &data, &meta);
+
+        // The metadata flush is only issued after the data flush has completed.
+        let events = pager.events.lock();
+        assert_eq!(
+            *events,
+            [
+                FlushEvent::Issued(data.to_vec()),
+                FlushEvent::Completed(data.to_vec()),
+                FlushEvent::Issued(meta.to_vec()),
+                FlushEvent::Completed(meta.to_vec()),
+            ]
+        );
+    }
+
     #[kernel_test]
     fn test_page_number_align_down() {
         use super::PageNumber;
@@ -1,29 +1,40 @@
-use alloc::sync::Arc;
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 use core::{
     fmt::Debug,
+    mem::size_of,
     sync::atomic::{AtomicU32, AtomicU64, Ordering},
 };
 
 use twizzler_abi::{
     device::{CacheType, MMIO_OFFSET},
     meta::MetaInfo,
-    object::{Protections, MAX_SIZE, NULLPAGE_SIZE},
+    object::{ObjID, Protections, MAX_SIZE, NULLPAGE_SIZE},
 };
+use twizzler_rt_abi::error::{ArgumentError, GenericError, IoError, ResourceError, TwzError};
 
 use super::{
-    range::{PageRangeTree, PageStatus},
-    Object, ObjectRef, PageNumber,
+    lookup_object,
+    range::{PageRangeTree, PageStatus, PunchedPages},
+    InvalidateMode, LookupFlags, LookupResult, Object, ObjectRef, PageFetcher, PageFlusher,
+    PageNumber, PagerFetcher, PagerFlusher,
 };
 use crate::{
-    arch::memory::phys_to_virt,
+    arch::memory::{device_fence, phys_to_virt, write_combine_fence},
+    condvar::CondVar,
     memory::{
-        frame::{FrameRef, PHYS_LEVEL_LAYOUTS},
+        frame::{FrameOwner, FrameRef, PHYS_LEVEL_LAYOUTS},
         pagetables::{MappingFlags, MappingSettings},
         tracker::{alloc_frame, free_frame, FrameAllocFlags, FrameAllocator},
         PhysAddr, VirtAddr,
     },
-    mutex::LockGuard,
+    mutex::{LockGuard, Mutex},
     obj::range::GetPageFlags,
+    once::Once,
+    spinlock::Spinlock,
+    thread::{current_thread_ref, entry::start_new_kernel, priority::Priority, ThreadRef},
 };
 
 /// An object page can be either a physical frame (allocatable memory) or a static physical address
@@ -49,6 +60,81 @@ struct PageSync {
 pub struct Page {
     frame: FrameOrWired,
     map_settings: MappingSettings,
+    charge: Option<PageCharge>,
+}
+
+/// Pages charged to an owner, and how many it may have charged at once.
+#[derive(Default)]
+struct PageAccount {
+    used: usize,
+    quota: Option<usize>,
+}
+
+/// Per-owner page accounting, keyed by the security context that faulted the pages in. Owners
+/// without a quota are still counted, so that a quota set later sees their current usage.
+static PAGE_ACCOUNTS: Spinlock<BTreeMap<ObjID, PageAccount>> = Spinlock::new(BTreeMap::new());
+
+/// Limit the number of object pages charged to `owner` to `quota`, or remove the limit if `None`.
+/// Pages already charged are kept, even if they exceed the new quota.
+///
+/// A page is charged to the owner whose fault allocated it. Pages shared copy-on-write stay
+/// charged to the owner that allocated them, no matter how many objects share them, and a write
+/// to a shared page charges the private copy to the writer.
+pub fn set_page_quota(owner: ObjID, quota: Option<usize>) {
+    let mut accounts = PAGE_ACCOUNTS.lock();
+    accounts.entry(owner).or_default().quota = quota;
+    if quota.is_none()
+        && accounts
+            .get(&owner)
+            .is_some_and(|account| account.used == 0)
+    {
+        accounts.remove(&owner);
+    }
+}
+
+/// The number of object pages currently charged to `owner`.
+pub fn pages_charged(owner: ObjID) -> usize {
+    PAGE_ACCOUNTS
+        .lock()
+        .get(&owner)
+        .map_or(0, |account| account.used)
+}
+
+/// A number of pages charged against an owner's quota, which are given back when this is dropped.
+#[derive(Debug)]
+pub struct PageCharge {
+    owner: ObjID,
+    pages: usize,
+}
+
+impl PageCharge {
+    /// Charge `pages` pages to `owner`, failing with OutOfMemory if that would put the owner over
+    /// its quota.
+    pub fn try_new(owner: ObjID, pages: usize) -> Result<Self, TwzError> {
+        let mut accounts = PAGE_ACCOUNTS.lock();
+        let account = accounts.entry(owner).or_default();
+        if account
+            .quota
+            .is_some_and(|quota| account.used + pages > quota)
+        {
+            log::debug!("{} is over its page quota", owner);
+            return Err(ResourceError::OutOfMemory.into());
+        }
+        account.used += pages;
+        Ok(Self { owner, pages })
+    }
+}
+
+impl Drop for PageCharge {
+    fn drop(&mut self) {
+        let mut accounts = PAGE_ACCOUNTS.lock();
+        if let Some(account) = accounts.get_mut(&self.owner) {
+            account.used -= self.pages;
+            if account.used == 0 && account.quota.is_none() {
+                accounts.remove(&self.owner);
+            }
+        }
+    }
 }
 
 impl Debug for Page {
@@ -65,6 +151,8 @@ pub struct PageRef {
     page: Arc<Page>,
     pn: usize,
     count: usize,
+    // A read-only view refuses to hand out mutable access to the page.
+    read_only: bool,
 }
 
 impl Drop for Page {
@@ -81,6 +169,7 @@ impl Drop for Page {
 
 impl Page {
     pub fn new(frame: FrameRef) -> Self {
+        frame.set_owner(FrameOwner::ObjectPage);
         Self {
             frame: FrameOrWired::Frame(frame),
             map_settings: MappingSettings::new(
@@ -88,13 +177,28 @@ impl Page {
                 CacheType::WriteBack,
                 MappingFlags::USER,
             ),
+            charge: None,
         }
     }
 
+    /// Allocate a new page from `allocator`, charged to the allocator's owner if it has one.
+    /// Fails with OutOfMemory if no frame is available, or if the owner is over its page quota
+    /// (see [set_page_quota]).
+    pub fn try_new_in(allocator: &mut FrameAllocator) -> Result<Self, TwzError> {
+        let frame = allocator.try_allocate().ok_or(ResourceError::OutOfMemory)?;
+        let mut page = Self::new(frame);
+        if let Some(owner) = allocator.owner() {
+            // If this fails, dropping the page frees the frame again.
+            page.charge = Some(PageCharge::try_new(owner, page.nr_pages())?);
+        }
+        Ok(page)
+    }
+
     pub fn new_wired(pa: PhysAddr, size: usize, cache_type: CacheType) -> Self {
         Self {
             frame: FrameOrWired::Wired(pa, size),
             map_settings: MappingSettings::new(Protections::all(), cache_type, MappingFlags::USER),
+            charge: None,
         }
     }
 
@@ -125,6 +229,12 @@ impl Page {
         }
     }
 
+    pub unsafe fn get_to_val<T>(&self, offset: usize) -> *const T {
+        let va = self.as_virtaddr();
+        let bytes = va.as_ptr::<u8>();
+        bytes.add(offset) as *const T
+    }
+
     pub unsafe fn get_mut_to_val<T>(&self, offset: usize) -> *mut T {
         /* TODO: enforce alignment and size of offset */
         /* TODO: once we start optimizing frame zeroing, we need to make the frame as non-zeroed
@@ -152,11 +262,24 @@ impl Page {
 
     pub fn physical_address(&self) -> PhysAddr {
         match self.frame {
-            FrameOrWired::Frame(f) => f.start_address(),
+            FrameOrWired::Frame(f) => {
+                // Every access to the page's contents goes through here, so this is where
+                // discarded contents get replaced with zeros.
+                f.settle_discarded();
+                f.start_address()
+            }
             FrameOrWired::Wired(p, _) => p,
         }
     }
 
+    /// The frame backing this page, or None if the page is wired.
+    pub fn frame(&self) -> Option<FrameRef> {
+        match self.frame {
+            FrameOrWired::Frame(f) => Some(f),
+            FrameOrWired::Wired(..) => None,
+        }
+    }
+
     pub fn copy_from(&self, other: &Page, doff: usize, soff: usize, len: usize) {
         match self.frame {
             FrameOrWired::Frame(frame) => match other.frame {
@@ -164,7 +287,7 @@ impl Page {
                     frame.copy_contents_from(otherframe, doff, soff, len)
                 }
                 FrameOrWired::Wired(phys_addr, _) => {
-                    frame.copy_contents_from_physaddr(doff, phys_addr.offset(doff).unwrap(), len)
+                    frame.copy_contents_from_physaddr(doff, phys_addr.offset(soff).unwrap(), len)
                 }
             },
             FrameOrWired::Wired(_phys_addr, _) => todo!(),
@@ -174,11 +297,103 @@ impl Page {
     pub fn map_settings(&self) -> MappingSettings {
         self.map_settings
     }
+
+    /// Returns true if this page refers to a fixed physical address rather than an allocated frame.
+    pub fn is_wired(&self) -> bool {
+        matches!(self.frame, FrameOrWired::Wired(_, _))
+    }
+
+    /// Returns true if this page is wired to device memory. Reads of such memory may have side
+    /// effects, so its contents must never be copied.
+    pub fn is_mmio(&self) -> bool {
+        self.is_wired() && self.map_settings.cache() != CacheType::WriteBack
+    }
+
+    /// Read `dst.len()` bytes at `offset` into the page with volatile loads, each as wide as the
+    /// alignment allows (up to 8 bytes), so that device registers see the accesses they expect.
+    /// Unless the page is write-back cached, a fence first makes sure that earlier stores have
+    /// reached the device, so the read can't return state from before them.
+    pub fn read_volatile_into(&self, offset: usize, dst: &mut [u8]) {
+        assert!(offset + dst.len() <= self.nr_pages() * PageNumber::PAGE_SIZE);
+        if self.map_settings.cache() != CacheType::WriteBack {
+            device_fence();
+        }
+        let base = unsafe { self.get_to_val::<u8>(offset) };
+        for (off, width) in access_widths(base as usize, dst.len()) {
+            let dst = &mut dst[off..(off + width)];
+            // Safety: the access lies within the page, and is aligned to its width.
+            unsafe {
+                let src = base.add(off);
+                match width {
+                    8 => dst.copy_from_slice(&src.cast::<u64>().read_volatile().to_ne_bytes()),
+                    4 => dst.copy_from_slice(&src.cast::<u32>().read_volatile().to_ne_bytes()),
+                    2 => dst.copy_from_slice(&src.cast::<u16>().read_volatile().to_ne_bytes()),
+                    _ => dst[0] = src.read_volatile(),
+                }
+            }
+        }
+    }
+
+    /// Write `src` into the page at `offset` with volatile stores, sized as in
+    /// [Self::read_volatile_into]. Unless the page is write-back cached, a fence afterwards makes
+    /// sure the stores have reached the device (draining any write-combining buffers) before this
+    /// returns.
+    pub fn write_volatile_from(&self, offset: usize, src: &[u8]) {
+        assert!(offset + src.len() <= self.nr_pages() * PageNumber::PAGE_SIZE);
+        let base = unsafe { self.get_mut_to_val::<u8>(offset) };
+        for (off, width) in access_widths(base as usize, src.len()) {
+            let src = &src[off..(off + width)];
+            // Safety: the access lies within the page, and is aligned to its width.
+            unsafe {
+                let dst = base.add(off);
+                match width {
+                    8 => dst
+                        .cast::<u64>()
+                        .write_volatile(u64::from_ne_bytes(src.try_into().unwrap())),
+                    4 => dst
+                        .cast::<u32>()
+                        .write_volatile(u32::from_ne_bytes(src.try_into().unwrap())),
+                    2 => dst
+                        .cast::<u16>()
+                        .write_volatile(u16::from_ne_bytes(src.try_into().unwrap())),
+                    _ => dst.write_volatile(src[0]),
+                }
+            }
+        }
+        if self.map_settings.cache() != CacheType::WriteBack {
+            device_fence();
+        }
+    }
+}
+
+/// Split `len` bytes starting at address `base` into (offset, width) accesses, each as wide as
+/// possible (up to 8 bytes) while staying naturally aligned.
+fn access_widths(base: usize, len: usize) -> impl Iterator<Item = (usize, usize)> {
+    let mut off = 0;
+    core::iter::from_fn(move || {
+        let width = [8, 4, 2, 1]
+            .into_iter()
+            .find(|width| (base + off) % width == 0 && len - off >= *width)?;
+        let access = (off, width);
+        off += width;
+        Some(access)
+    })
+}
+
+/// The pages that `len` bytes starting at byte `offset` of an object touch.
+fn pages_in(offset: usize, len: usize) -> impl Iterator<Item = PageNumber> + Clone {
+    let end = (offset + len).div_ceil(PageNumber::PAGE_SIZE);
+    (offset / PageNumber::PAGE_SIZE..end).map(PageNumber::from)
 }
 
 impl PageRef {
     pub fn new(page: Arc<Page>, pn: usize, count: usize) -> Self {
-        Self { page, pn, count }
+        Self {
+            page,
+            pn,
+            count,
+            read_only: false,
+        }
     }
 
     pub fn adjust_down(&self, off: usize) -> Self {
@@ -187,6 +402,7 @@ impl PageRef {
             page: self.page.clone(),
             pn: self.pn - off,
             count: self.count + off,
+            read_only: self.read_only,
         }
     }
 
@@ -196,6 +412,7 @@ impl PageRef {
             page: self.page.clone(),
             pn: self.pn + off,
             count: self.count - off,
+            read_only: self.read_only,
         }
     }
 
@@ -205,9 +422,24 @@ impl PageRef {
             page: self.page.clone(),
             pn: self.pn,
             count,
+            read_only: self.read_only,
+        }
+    }
+
+    /// Get a read-only view of the same pages. Mutable access through the view is refused: the
+    /// `try_` accessors return None, and the others panic, so a stray write through a read-only
+    /// mapping always fails instead of silently modifying shared data.
+    pub fn read_only(&self) -> Self {
+        Self {
+            read_only: true,
+            ..self.clone()
         }
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     pub fn nr_pages(&self) -> usize {
         self.count
     }
@@ -239,15 +471,44 @@ impl PageRef {
     }
 
     pub fn as_mut_slice(&self) -> &mut [u8] {
-        &mut self.page.as_mut_slice(self.pn)[0..(self.count * PageNumber::PAGE_SIZE)]
+        self.try_as_mut_slice()
+            .expect("tried to write through a read-only page view")
     }
 
-    pub unsafe fn get_mut_to_val<T>(&self, offset: usize) -> *mut T {
+    pub fn try_as_mut_slice(&self) -> Option<&mut [u8]> {
+        if self.read_only {
+            return None;
+        }
+        Some(&mut self.page.as_mut_slice(self.pn)[0..(self.count * PageNumber::PAGE_SIZE)])
+    }
+
+    /// Get a pointer for reading the value at `offset`. Unlike [Self::get_mut_to_val], this is
+    /// allowed through a read-only view.
+    pub unsafe fn get_to_val<T>(&self, offset: usize) -> *const T {
         self.page
-            .get_mut_to_val(offset + self.pn * PageNumber::PAGE_SIZE)
+            .get_to_val(offset + self.pn * PageNumber::PAGE_SIZE)
+    }
+
+    pub unsafe fn get_mut_to_val<T>(&self, offset: usize) -> *mut T {
+        self.try_get_mut_to_val(offset)
+            .expect("tried to write through a read-only page view")
+    }
+
+    pub unsafe fn try_get_mut_to_val<T>(&self, offset: usize) -> Option<*mut T> {
+        if self.read_only {
+            return None;
+        }
+        Some(
+            self.page
+                .get_mut_to_val(offset + self.pn * PageNumber::PAGE_SIZE),
+        )
     }
 
     pub fn copy_from(&mut self, other: &Self) {
+        assert!(
+            !self.read_only,
+            "tried to write through a read-only page view"
+        );
         let len = self.count.min(other.count);
         self.page.copy_from(
             &other.page,
@@ -260,6 +521,14 @@ impl PageRef {
     pub fn map_settings(&self) -> MappingSettings {
         self.page.map_settings
     }
+
+    pub fn is_wired(&self) -> bool {
+        self.page.is_wired()
+    }
+
+    pub fn is_mmio(&self) -> bool {
+        self.page.is_mmio()
+    }
 }
 
 impl Object {
@@ -269,7 +538,7 @@ impl Object {
     /// a wakeup will still occur.
     pub unsafe fn try_write_val_and_signal<T>(&self, offset: usize, val: T, wakeup_count: usize) {
         assert!(!self.use_pager());
-        {
+        let written = {
             let mut obj_page_tree = self.lock_page_tree();
             let page_number = PageNumber::from_address(VirtAddr::new(offset as u64).unwrap());
             let page_offset = offset % PageNumber::PAGE_SIZE;
@@ -279,7 +548,13 @@ impl Object {
             {
                 let t = page.get_mut_to_val::<T>(page_offset);
                 *t = val;
+                true
+            } else {
+                false
             }
+        };
+        if written {
+            self.notify_written(offset, core::mem::size_of::<T>());
         }
         self.wakeup_word(offset, wakeup_count);
         crate::syscall::sync::requeue_all();
@@ -294,7 +569,7 @@ impl Object {
         if let PageStatus::Ready(page, _) =
             obj_page_tree.get_page(page_number, GetPageFlags::empty(), None)
         {
-            let t = page.get_mut_to_val::<AtomicU64>(page_offset);
+            let t = page.get_to_val::<AtomicU64>(page_offset);
             (*t).load(Ordering::SeqCst)
         } else {
             0
@@ -374,7 +649,7 @@ impl Object {
             obj_page_tree.get_page(page_number, GetPageFlags::empty(), None)
         {
             unsafe {
-                let t = page.get_mut_to_val::<MetaInfo>(0);
+                let t = page.get_to_val::<MetaInfo>(0);
                 Some(t.read())
             }
         } else {
@@ -388,6 +663,342 @@ impl Object {
         }
     }
 
+    /// Read a value from the meta page, at byte offset `off` from the start of the page (where the
+    /// [MetaInfo] is). Returns None if the value doesn't fit in the page, or the page isn't in
+    /// core and `can_wait` is false.
+    pub fn read_meta_val<T>(self: &ObjectRef, off: usize, can_wait: bool) -> Option<T> {
+        if off.checked_add(size_of::<T>())? > NULLPAGE_SIZE {
+            return None;
+        }
+        let mut obj_page_tree = self.lock_page_tree();
+        let page_number = PageNumber::from_offset(MAX_SIZE - NULLPAGE_SIZE);
+
+        if let PageStatus::Ready(page, _) =
+            obj_page_tree.get_page(page_number, GetPageFlags::empty(), None)
+        {
+            // Safety: we checked that the value lies within the page. It may not be aligned.
+            unsafe {
+                Some(
+                    page.as_virtaddr()
+                        .offset(off)
+                        .ok()?
+                        .as_ptr::<T>()
+                        .read_unaligned(),
+                )
+            }
+        } else {
+            if !can_wait {
+                return None;
+            }
+            let mut _used_pager = false;
+            obj_page_tree = self.ensure_in_core(obj_page_tree, page_number, &mut _used_pager);
+            drop(obj_page_tree);
+            self.read_meta_val(off, can_wait)
+        }
+    }
+
+    /// Read `buf.len()` bytes starting at `offset` into `buf`, and check that the SHA-256 of the
+    /// data read matches `expected`. Pages that are not present read (and hash) as zeros, unless
+    /// the object is pager-backed, in which case they are brought in first. Returns
+    /// [IoError::DataLoss] on mismatch; `buf` still holds the data that was read.
+    pub fn read_verified(
+        self: &ObjectRef,
+        offset: usize,
+        buf: &mut [u8],
+        expected: &[u8; 32],
+    ) -> Result<(), TwzError> {
+        if offset
+            .checked_add(buf.len())
+            .is_none_or(|end| end > MAX_SIZE)
+        {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        drop(self.read_bytes_locked(self.lock_page_tree(), buf, offset));
+
+        if crate::crypto::sha256(buf) != *expected {
+            log::warn!(
+                "{}: checksum mismatch reading {} bytes at {:x}",
+                self.id(),
+                buf.len(),
+                offset
+            );
+            return Err(IoError::DataLoss.into());
+        }
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes at `offset` into `buf`. Pages that are not present read as zeros,
+    /// unless the object is pager-backed, in which case they are brought in first.
+    pub fn read_bytes(self: &ObjectRef, buf: &mut [u8], offset: usize) {
+        drop(self.read_bytes_locked(self.lock_page_tree(), buf, offset));
+    }
+
+    /// Read `buf.len()` bytes at `offset` into `buf` as one snapshot: the page-tree lock is held
+    /// across the whole read, so writes made through the page tree ([Self::write_bytes],
+    /// [Self::write_ranges], [Self::write_base], ...) land either entirely before or entirely
+    /// after it. Use this to read structures larger than [Self::read_atomic_u64] can. For
+    /// pager-backed objects, every page in the range is brought in before the read starts, since
+    /// fetching a page drops the lock. Pages that are still not present read as zeros.
+    ///
+    /// While the read runs, every other read and write of the object through the page tree waits,
+    /// so large reads of a busy object slow everyone down; read no more than the structure needs.
+    /// Stores through user mappings don't take the lock, and aren't ordered with the read.
+    pub fn read_consistent(
+        self: &ObjectRef,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(), TwzError> {
+        offset
+            .checked_add(buf.len())
+            .filter(|end| *end <= MAX_SIZE)
+            .ok_or(ArgumentError::InvalidArgument)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        drop(self.read_consistent_locked(self.lock_page_tree(), offset, buf));
+        Ok(())
+    }
+
+    /// Read `len` bytes at `offset`, pass them to `f` to modify, and write them back, holding the
+    /// page-tree lock from the read through the write. Like [Self::cas_write_range], this makes the
+    /// update atomic with respect to other reads and writes through the page tree, but the update
+    /// can be any computation, for instance incrementing a counter that isn't word-sized. `f` gets
+    /// the range as one contiguous buffer, even if it spans pages; pages that are not present read
+    /// as zeros, and are allocated for the write. The pages are brought in (for pager-backed
+    /// objects) and read as in [Self::read_consistent], and the same caveats apply: `f` runs with
+    /// the lock held, so it must be short, and must not touch the object itself.
+    pub fn update_range<F: FnOnce(&mut [u8])>(
+        self: &ObjectRef,
+        offset: usize,
+        len: usize,
+        f: F,
+    ) -> Result<(), TwzError> {
+        if offset.checked_add(len).is_none_or(|end| end > MAX_SIZE) {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        let mut buf = alloc::vec![0u8; len];
+        let mut obj_page_tree =
+            self.read_consistent_locked(self.lock_page_tree(), offset, &mut buf);
+        f(&mut buf);
+        Self::write_bytes_locked(&mut obj_page_tree, &buf, offset);
+        drop(obj_page_tree);
+        self.queue_writeback(offset, len);
+        self.notify_written(offset, len);
+        Ok(())
+    }
+
+    /// For pager-backed objects, bring in those of `pages` that are not present, so that they
+    /// read as what the pager holds rather than as zeros. The lock is dropped while the pager
+    /// works, and returned held once every page is present, or the pager failed to supply one.
+    fn fetch_missing_locked<'a>(
+        self: &'a ObjectRef,
+        mut obj_page_tree: LockGuard<'a, PageRangeTree>,
+        pages: impl Iterator<Item = PageNumber> + Clone,
+        fetcher: &impl PageFetcher,
+    ) -> LockGuard<'a, PageRangeTree> {
+        if !self.use_pager() {
+            return obj_page_tree;
+        }
+        let mut tried_pager = None;
+        while let Some(missing) = pages.clone().find(|pn| {
+            matches!(
+                obj_page_tree.try_get_page(*pn, GetPageFlags::empty()),
+                PageStatus::NoPage
+            )
+        }) {
+            // Give each page one chance, so a pager that can't supply it doesn't stall us.
+            if tried_pager == Some(missing) {
+                break;
+            }
+            tried_pager = Some(missing);
+            drop(obj_page_tree);
+            fetcher.fetch_page(self, missing);
+            obj_page_tree = self.lock_page_tree();
+        }
+        obj_page_tree
+    }
+
+    /// The body of [Self::read_consistent], for a range already checked to be non-empty and within
+    /// the object. Returns with the lock held since the read, so the caller can act on what it
+    /// read before anyone else gets to write.
+    fn read_consistent_locked<'a>(
+        self: &'a ObjectRef,
+        mut obj_page_tree: LockGuard<'a, PageRangeTree>,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> LockGuard<'a, PageRangeTree> {
+        obj_page_tree =
+            self.fetch_missing_locked(obj_page_tree, pages_in(offset, buf.len()), &PagerFetcher);
+
+        let mut done = 0;
+        while done < buf.len() {
+            let page_offset = (offset + done) % PageNumber::PAGE_SIZE;
+            let thislen = (PageNumber::PAGE_SIZE - page_offset).min(buf.len() - done);
+            let dest = &mut buf[done..(done + thislen)];
+            match obj_page_tree.try_get_page(
+                PageNumber::from_offset(offset + done),
+                GetPageFlags::empty(),
+            ) {
+                PageStatus::Ready(page, _) => {
+                    dest.copy_from_slice(&page.as_slice()[page_offset..(page_offset + thislen)]);
+                }
+                _ => dest.fill(0),
+            }
+            done += thislen;
+        }
+        obj_page_tree
+    }
+
+    /// Copy up to `dst.len()` bytes at `offset` out of the object into `dst`, stopping at the first
+    /// page that is not present, and return the number of bytes copied. This is the read
+    /// counterpart to [Self::write_bytes], for data that the kernel must not invent: unlike
+    /// [Self::read_bytes], holes are not read as zeros, and the pager is never asked for pages, so
+    /// this never waits. A range that starts in a hole copies nothing.
+    pub fn copy_out(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let len = dst.len().min(MAX_SIZE.saturating_sub(offset));
+        let obj_page_tree = self.lock_page_tree();
+        let mut count = 0;
+        while count < len {
+            let page_offset = (offset + count) % PageNumber::PAGE_SIZE;
+            let thislen = (PageNumber::PAGE_SIZE - page_offset).min(len - count);
+            let PageStatus::Ready(page, _) = obj_page_tree.try_get_page(
+                PageNumber::from_offset(offset + count),
+                GetPageFlags::empty(),
+            ) else {
+                break;
+            };
+            dst[count..(count + thislen)]
+                .copy_from_slice(&page.as_slice()[page_offset..(page_offset + thislen)]);
+            count += thislen;
+        }
+        count
+    }
+
+    /// Read `buf.len()` bytes at `offset` into `buf` with the page tree locked. Pages that are not
+    /// present read as zeros, unless the object is pager-backed, in which case they are brought in
+    /// first (which may drop and retake the lock).
+    fn read_bytes_locked<'a>(
+        self: &'a ObjectRef,
+        mut obj_page_tree: LockGuard<'a, PageRangeTree>,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> LockGuard<'a, PageRangeTree> {
+        let mut count = 0;
+        let mut tried_pager = None;
+        while count < buf.len() {
+            let page_number = PageNumber::from_offset(offset + count);
+            let page_offset = (offset + count) % PageNumber::PAGE_SIZE;
+            let thislen = core::cmp::min(PageNumber::PAGE_SIZE - page_offset, buf.len() - count);
+            let dest = &mut buf[count..(count + thislen)];
+
+            match obj_page_tree.try_get_page(page_number, GetPageFlags::empty()) {
+                PageStatus::Ready(page, _) => {
+                    dest.copy_from_slice(&page.as_slice()[page_offset..(page_offset + thislen)]);
+                }
+                _ if self.use_pager() && tried_pager != Some(page_number) => {
+                    tried_pager = Some(page_number);
+                    let mut _used_pager = false;
+                    obj_page_tree =
+                        self.ensure_in_core(obj_page_tree, page_number, &mut _used_pager);
+                    continue;
+                }
+                _ => dest.fill(0),
+            }
+            count += thislen;
+        }
+        obj_page_tree
+    }
+
+    /// Move `len` bytes within the object from `src_off` to `dst_off`, like memmove: the ranges may
+    /// overlap, and the destination ends up holding what the source held before the move. Data is
+    /// moved a page-sized chunk at a time, in the direction that never overwrites source bytes
+    /// that haven't been moved yet, all under the page-tree lock. Pages that are not present read
+    /// as zeros, and destination pages are allocated as needed.
+    pub fn move_range(
+        self: &ObjectRef,
+        src_off: usize,
+        dst_off: usize,
+        len: usize,
+    ) -> Result<(), TwzError> {
+        if [src_off, dst_off]
+            .iter()
+            .any(|off| off.checked_add(len).is_none_or(|end| end > MAX_SIZE))
+        {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if len == 0 || src_off == dst_off {
+            return Ok(());
+        }
+
+        // Distance from off to the end of its page, and from the start of the page containing the
+        // byte before off to off.
+        let to_page_end = |off: usize| PageNumber::PAGE_SIZE - off % PageNumber::PAGE_SIZE;
+        let from_page_start = |off: usize| (off - 1) % PageNumber::PAGE_SIZE + 1;
+
+        let mut buf = alloc::vec![0u8; len.min(PageNumber::PAGE_SIZE)];
+        let mut obj_page_tree = self.lock_page_tree();
+        let mut done = 0;
+        while done < len {
+            // Each chunk lies within a single page of both the source and the destination.
+            let (src, dst, thislen) = if dst_off < src_off {
+                let (src, dst) = (src_off + done, dst_off + done);
+                let thislen = to_page_end(src).min(to_page_end(dst)).min(len - done);
+                (src, dst, thislen)
+            } else {
+                let (src_end, dst_end) = (src_off + len - done, dst_off + len - done);
+                let thislen = from_page_start(src_end)
+                    .min(from_page_start(dst_end))
+                    .min(len - done);
+                (src_end - thislen, dst_end - thislen, thislen)
+            };
+            let chunk = &mut buf[0..thislen];
+            obj_page_tree = self.read_bytes_locked(obj_page_tree, chunk, src);
+            Self::write_bytes_locked(&mut obj_page_tree, chunk, dst);
+            done += thislen;
+        }
+        drop(obj_page_tree);
+        self.queue_writeback(dst_off, len);
+        self.notify_written(dst_off, len);
+        Ok(())
+    }
+
+    /// Fill `len` bytes of the object starting at `offset` with `pattern`, repeated: the byte at
+    /// `offset + i` gets `pattern[i % pattern.len()]`. The pattern carries on across page
+    /// boundaries even if its length doesn't divide the page size. Pages are allocated as needed,
+    /// and the whole range is written under the page-tree lock.
+    pub fn fill_pattern(&self, offset: usize, len: usize, pattern: &[u8]) -> Result<(), TwzError> {
+        if pattern.is_empty() || offset.checked_add(len).is_none_or(|end| end > MAX_SIZE) {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        // A page's worth of the pattern, plus enough to start it at any phase.
+        let buf: alloc::vec::Vec<u8> = pattern
+            .iter()
+            .copied()
+            .cycle()
+            .take(PageNumber::PAGE_SIZE + pattern.len())
+            .collect();
+        let mut obj_page_tree = self.lock_page_tree();
+        let mut done = 0;
+        while done < len {
+            let off = offset + done;
+            let thislen = (PageNumber::PAGE_SIZE - off % PageNumber::PAGE_SIZE).min(len - done);
+            let phase = done % pattern.len();
+            Self::write_bytes_locked(&mut obj_page_tree, &buf[phase..(phase + thislen)], off);
+            done += thislen;
+        }
+        drop(obj_page_tree);
+        self.queue_writeback(offset, len);
+        self.notify_written(offset, len);
+        Ok(())
+    }
+
     pub fn write_meta(&self, meta: MetaInfo, can_wait: bool) -> bool {
         assert!(!self.use_pager());
         let mut obj_page_tree = self.lock_page_tree();
@@ -438,7 +1049,7 @@ impl Object {
         if let PageStatus::Ready(page, _) =
             obj_page_tree.get_page(page_number, GetPageFlags::empty(), None)
         {
-            let t = page.get_mut_to_val::<AtomicU32>(page_offset);
+            let t = page.get_to_val::<AtomicU32>(page_offset);
             (*t).load(Ordering::SeqCst)
         } else {
             0
@@ -455,40 +1066,479 @@ impl Object {
         self.write_bytes(bytes, len, offset);
     }
 
-    pub fn write_bytes(&self, bytes: *const u8, len: usize, mut offset: usize) {
+    pub fn write_bytes(&self, bytes: *const u8, len: usize, offset: usize) {
         unsafe {
             let mut obj_page_tree = self.lock_page_tree();
             let bytes = core::slice::from_raw_parts(bytes, len);
+            Self::write_bytes_locked(&mut obj_page_tree, bytes, offset);
+            drop(obj_page_tree);
+        }
+        // This needn't hold the tree lock: queueing only records which pages to write back, and
+        // the syncer reads them when it flushes, so a write that lands after we unlock is written
+        // back too. Keeping the syncer's locks out from under the tree lock also means they never
+        // have to be ordered against it.
+        self.queue_writeback(offset, len);
+        self.notify_written(offset, len);
+    }
+
+    /// Write several (offset, data) ranges into the object, taking the page tree lock once for all
+    /// of them. Pages are allocated as needed. Ranges are applied in order, so a later range that
+    /// overlaps an earlier one wins.
+    pub fn write_ranges(&self, writes: &[(usize, &[u8])]) {
+        if writes.is_empty() {
+            return;
+        }
+        let mut obj_page_tree = self.lock_page_tree();
+        for (offset, bytes) in writes {
+            Self::write_bytes_locked(&mut obj_page_tree, bytes, *offset);
+        }
+        drop(obj_page_tree);
+        for (offset, bytes) in writes {
+            self.queue_writeback(*offset, bytes.len());
+            self.notify_written(*offset, bytes.len());
+        }
+    }
+
+    /// Apply a set of (offset, bytes) edits to the object, and return the pages they changed, in
+    /// order. Unlike [Self::write_ranges], only the parts of an edit that differ from what the
+    /// object already holds are written, so a page the edits leave as it was (including zeros
+    /// written over a page that isn't present, for objects not backed by the pager) is not copied,
+    /// marked dirty, or written back. The changed pages are added to the object's dirty set and
+    /// queued for writeback, which makes this the building block for syncing small changes to a
+    /// large object. Edits are applied in order, so a later edit that overlaps an earlier one wins.
+    /// Fails, before applying anything, if any edit extends past the end of the object.
+    pub fn apply_patch(
+        &self,
+        patches: &[(usize, &[u8])],
+    ) -> Result<alloc::vec::Vec<PageNumber>, TwzError> {
+        if patches.iter().any(|(offset, bytes)| {
+            offset
+                .checked_add(bytes.len())
+                .is_none_or(|end| end > MAX_SIZE)
+        }) {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        let mut changed = BTreeSet::new();
+        let mut written = alloc::vec::Vec::new();
+        let mut obj_page_tree = self.lock_page_tree();
+        for (start, bytes) in patches {
             let mut count = 0;
-            while count < len {
-                let page_number = PageNumber::from_address(VirtAddr::new(offset as u64).unwrap());
+            while count < bytes.len() {
+                let offset = start + count;
+                let page_number = PageNumber::from_offset(offset);
                 let page_offset = offset % NULLPAGE_SIZE;
-                let thislen = core::cmp::min(NULLPAGE_SIZE - page_offset, len - count);
+                let thislen = core::cmp::min(NULLPAGE_SIZE - page_offset, bytes.len() - count);
+                let chunk = &bytes[count..(count + thislen)];
+                count += thislen;
 
-                if let PageStatus::Ready(page, _) =
-                    obj_page_tree.get_page(page_number, GetPageFlags::WRITE, None)
-                {
-                    let dest = &mut page.as_mut_slice()[page_offset..(page_offset + thislen)];
-                    dest.copy_from_slice(&bytes[count..(count + thislen)]);
-                } else {
-                    let page = Page::new(alloc_frame(
-                        FrameAllocFlags::KERNEL
-                            | FrameAllocFlags::WAIT_OK
-                            | FrameAllocFlags::ZEROED,
-                    ));
-                    let page = PageRef::new(Arc::new(page), 0, 1);
-                    let dest = &mut page.as_mut_slice()[page_offset..(page_offset + thislen)];
-                    dest.copy_from_slice(&bytes[count..(count + thislen)]);
-                    obj_page_tree.add_page(page_number, page, None);
+                let unchanged =
+                    match obj_page_tree.get_page(page_number, GetPageFlags::empty(), None) {
+                        PageStatus::Ready(page, _) => {
+                            page.as_slice()[page_offset..(page_offset + thislen)] == *chunk
+                        }
+                        // A page of a pager-backed object that isn't in memory may hold anything.
+                        _ => !self.use_pager() && chunk.iter().all(|b| *b == 0),
+                    };
+                if unchanged {
+                    continue;
                 }
+                Self::write_bytes_locked(&mut obj_page_tree, chunk, offset);
+                changed.insert(page_number);
+                written.push((offset, thislen));
+            }
+        }
+        drop(obj_page_tree);
 
-                offset += thislen;
-                count += thislen;
+        if changed.is_empty() {
+            return Ok(alloc::vec::Vec::new());
+        }
+        for page_number in &changed {
+            self.dirty_set().add_dirty(*page_number);
+        }
+        self.queue_writeback_pages(changed.iter().copied());
+        for (offset, len) in written {
+            self.notify_written(offset, len);
+        }
+        Ok(changed.into_iter().collect())
+    }
+
+    fn write_bytes_locked(obj_page_tree: &mut PageRangeTree, bytes: &[u8], mut offset: usize) {
+        let mut count = 0;
+        while count < bytes.len() {
+            let page_number = PageNumber::from_address(VirtAddr::new(offset as u64).unwrap());
+            let page_offset = offset % NULLPAGE_SIZE;
+            let thislen = core::cmp::min(NULLPAGE_SIZE - page_offset, bytes.len() - count);
+
+            if let PageStatus::Ready(page, _) =
+                obj_page_tree.get_page(page_number, GetPageFlags::WRITE, None)
+            {
+                let dest = &mut page.as_mut_slice()[page_offset..(page_offset + thislen)];
+                dest.copy_from_slice(&bytes[count..(count + thislen)]);
+            } else {
+                let page = Page::new(alloc_frame(
+                    FrameAllocFlags::KERNEL | FrameAllocFlags::WAIT_OK | FrameAllocFlags::ZEROED,
+                ));
+                let page = PageRef::new(Arc::new(page), 0, 1);
+                let dest = &mut page.as_mut_slice()[page_offset..(page_offset + thislen)];
+                dest.copy_from_slice(&bytes[count..(count + thislen)]);
+                obj_page_tree.add_page(page_number, page, None);
             }
-            if self.use_pager() {
-                crate::pager::sync_object(self.id);
+
+            offset += thislen;
+            count += thislen;
+        }
+    }
+
+    /// Write data to the object only if a version word still holds an expected value.
+    ///
+    /// The u64 at `version_off` is compared against `expected_ver`. If it matches, `data` is
+    /// written at `data_off` and the version word is set to `new_ver`; otherwise nothing is
+    /// written. The check, the write, and the version bump all happen under the page-tree lock.
+    /// A version word in a page that is not yet present reads as zero, unless the object is
+    /// pager-backed, in which case the version and data pages are brought in first. Returns true
+    /// if the write was performed.
+    pub fn cas_write_range(
+        self: &ObjectRef,
+        version_off: usize,
+        expected_ver: u64,
+        new_ver: u64,
+        data_off: usize,
+        data: &[u8],
+    ) -> bool {
+        self.cas_write_range_with(
+            &PagerFetcher,
+            version_off,
+            expected_ver,
+            new_ver,
+            data_off,
+            data,
+        )
+    }
+
+    fn cas_write_range_with(
+        self: &ObjectRef,
+        fetcher: &impl PageFetcher,
+        version_off: usize,
+        expected_ver: u64,
+        new_ver: u64,
+        data_off: usize,
+        data: &[u8],
+    ) -> bool {
+        let version_len = core::mem::size_of::<u64>();
+        assert_eq!(version_off % version_len, 0);
+        assert!(
+            data_off + data.len() <= version_off || version_off + version_len <= data_off,
+            "data range overlaps version word"
+        );
+
+        let page_number = PageNumber::from_address(VirtAddr::new(version_off as u64).unwrap());
+        let page_offset = version_off % PageNumber::PAGE_SIZE;
+        // Data pages are brought in too, since writing part of a page that isn't present would
+        // replace the rest of it with zeros.
+        let mut obj_page_tree = self.fetch_missing_locked(
+            self.lock_page_tree(),
+            core::iter::once(page_number).chain(pages_in(data_off, data.len())),
+            fetcher,
+        );
+
+        let current = match obj_page_tree.get_page(page_number, GetPageFlags::empty(), None) {
+            PageStatus::Ready(page, _) => unsafe {
+                (*page.get_to_val::<AtomicU64>(page_offset)).load(Ordering::SeqCst)
+            },
+            _ => 0,
+        };
+        if current != expected_ver {
+            return false;
+        }
+
+        Self::write_bytes_locked(&mut obj_page_tree, data, data_off);
+        // Publish the new version with an atomic store, so that threads reading the version word
+        // through a mapping never see a torn value.
+        if let PageStatus::Ready(page, _) =
+            obj_page_tree.get_page(page_number, GetPageFlags::WRITE, None)
+        {
+            unsafe {
+                (*page.get_mut_to_val::<AtomicU64>(page_offset)).store(new_ver, Ordering::SeqCst);
+            }
+        } else {
+            Self::write_bytes_locked(&mut obj_page_tree, &new_ver.to_ne_bytes(), version_off);
+        }
+        drop(obj_page_tree);
+
+        self.queue_writeback(data_off, data.len());
+        self.queue_writeback(version_off, version_len);
+        self.notify_written(data_off, data.len());
+        self.notify_written(version_off, version_len);
+        true
+    }
+
+    /// Discard the pages in `range`, releasing their frames. The pages become absent, so reads in
+    /// the hole return zero. For pager-backed objects, the pager is told that the range was freed
+    /// so that it does not page the old data back in.
+    pub fn punch_hole(self: &ObjectRef, range: core::ops::Range<PageNumber>) {
+        if range.start >= range.end {
+            return;
+        }
+        let punched = self.lock_page_tree().punch_hole(range.clone());
+        // The punched pages may still be mapped, so invalidate before letting go of the frames.
+        self.invalidate(range.clone(), InvalidateMode::Full);
+        drop(punched);
+
+        if self.use_pager() {
+            crate::pager::free_range(self, range);
+        }
+    }
+
+    /// Mark the contents of the pages in `range` as no longer needed (see [tracker::discard]). The
+    /// pages stay in the object, but read as zeros from then on. Only pages whose frames lie
+    /// entirely within the range and aren't shared with another object are discarded; the rest
+    /// keep their contents.
+    ///
+    /// [tracker::discard]: crate::memory::tracker::discard
+    pub fn discard_range(self: &ObjectRef, range: core::ops::Range<PageNumber>) {
+        let mut tree = self.lock_page_tree();
+        // Unmap first, while holding the tree lock so that no fault can map the pages again until
+        // they're marked. A page left mapped could be written after it's zeroed, or zeroed after
+        // it's written.
+        self.invalidate(range.clone(), InvalidateMode::Full);
+        let mut frames = alloc::vec::Vec::new();
+        let mut pn = range.start;
+        while pn < range.end {
+            let PageStatus::Ready(page, shared) = tree.try_get_page(pn, GetPageFlags::empty())
+            else {
+                pn = pn.next();
+                continue;
+            };
+            let whole = page.page_offset() == 0
+                && page.nr_pages() == page.page.nr_pages()
+                && pn.offset(page.nr_pages()) <= range.end;
+            if whole && !shared {
+                frames.extend(page.page.frame());
             }
+            pn = pn.offset(page.nr_pages().max(1));
         }
+        crate::memory::tracker::discard(frames);
+        drop(tree);
+    }
+
+    /// Make the pages in `range` of this object also appear in `dest`, starting at `dest_start`.
+    /// Both objects then refer to the same physical pages, so writes through either are visible
+    /// through the other. Absent source pages are allocated (zeroed) first, and source pages that
+    /// are copy-on-write shared are first made private, so that a later write cannot silently
+    /// break the sharing.
+    ///
+    /// A page keeps its own cache type wherever it's mapped, so sharing never changes how a page
+    /// is cached. To keep accesses to the same address in `dest` consistent, a page may only
+    /// replace a `dest` page of the same cache type. Pager-backed objects are not supported, since
+    /// the pager would see the same page as part of two objects. The whole range is checked before
+    /// `dest` is changed, so on error `dest` is left as it was.
+    pub fn share_pages_into(
+        self: &ObjectRef,
+        range: core::ops::Range<PageNumber>,
+        dest: &ObjectRef,
+        dest_start: PageNumber,
+    ) -> Result<(), TwzError> {
+        if range.start >= range.end {
+            return Ok(());
+        }
+        if self.id() == dest.id() {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if self.use_pager() || dest.use_pager() {
+            return Err(GenericError::NotSupported.into());
+        }
+        let count = range.end - range.start;
+        let dest_range = dest_start..dest_start.offset(count);
+
+        let (mut src_tree, mut dest_tree) =
+            crate::utils::lock_two(&self.range_tree, &dest.range_tree);
+        let mut frame_allocator = FrameAllocator::new(
+            FrameAllocFlags::ZEROED | FrameAllocFlags::WAIT_OK,
+            PHYS_LEVEL_LAYOUTS[0],
+        );
+
+        let mut pages = alloc::vec::Vec::with_capacity(count);
+        for i in 0..count {
+            let pn = range.start.offset(i);
+            let page = match src_tree.get_page(pn, GetPageFlags::WRITE, Some(&mut frame_allocator))
+            {
+                PageStatus::Ready(page, _) => page,
+                PageStatus::NoPage => {
+                    let frame = frame_allocator
+                        .try_allocate()
+                        .ok_or(ResourceError::OutOfMemory)?;
+                    let page = PageRef::new(Arc::new(Page::new(frame)), 0, 1);
+                    src_tree
+                        .add_page(pn, page, Some(&mut frame_allocator))
+                        .ok_or(ResourceError::OutOfMemory)?
+                }
+                _ => return Err(ResourceError::OutOfMemory.into()),
+            };
+            let page = page.trimmed(1);
+
+            let dpn = dest_start.offset(i);
+            if let PageStatus::Ready(existing, _) =
+                dest_tree.try_get_page(dpn, GetPageFlags::empty())
+            {
+                if existing.map_settings().cache() != page.map_settings().cache() {
+                    return Err(ArgumentError::InvalidArgument.into());
+                }
+            }
+            pages.push(page);
+        }
+
+        // Every check is done, so dest can now be changed. Clearing the range first means each
+        // page goes into an empty slot, which add_page fills without allocating, so dest is never
+        // left with only part of the range shared.
+        let _punched = dest_tree.punch_hole(dest_range.clone());
+        for (i, page) in pages.into_iter().enumerate() {
+            dest_tree.add_page(dest_start.offset(i), page, None);
+        }
+        drop(src_tree);
+        drop(dest_tree);
+
+        // Any pages dest had in the range are gone, and the source may have had copy-on-write
+        // pages made private, so drop stale mappings of both.
+        dest.invalidate(dest_range, InvalidateMode::Full);
+        self.invalidate(range, InvalidateMode::Full);
+        Ok(())
+    }
+
+    /// Atomically exchange the contents of this object and `other`, e.g. to replace a file with
+    /// a fully-written temporary copy. Both page trees are locked (in a canonical order) for the
+    /// whole exchange, and stale mappings are dropped before either is unlocked, so no reader can
+    /// observe a mix of the two. The metadata pages stay with their objects, since an object's ID
+    /// is derived from its metadata.
+    ///
+    /// For pager-backed objects, both are synced first, and the pager is told to swap their
+    /// backing storage, since it keys that storage by object ID. The pager does that by
+    /// exchanging which stored object backs each ID in one durable update, so after a crash the
+    /// swap either happened or didn't. That happens before the trees are locked, as the pager's
+    /// completions take the tree locks, and if the pager can't do it, nothing is changed. Faults
+    /// in the meantime may page in contents from either side of the storage swap, so once the
+    /// trees are locked, every page that wasn't written since the sync is dropped, to be paged in
+    /// again from the swapped storage. Both objects must be backed the same way.
+    ///
+    /// Everything that can fail is checked before either tree is changed, so on error both
+    /// objects are left as they were.
+    pub fn swap_contents(self: &ObjectRef, other: &ObjectRef) -> Result<(), TwzError> {
+        if self.id() == other.id() {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        let paged = self.use_pager();
+        if paged != other.use_pager() {
+            return Err(GenericError::NotSupported.into());
+        }
+        if paged {
+            self.sync();
+            other.sync();
+            crate::pager::swap_objects(self, other)?;
+        }
+        let meta = PageNumber::from_offset(MAX_SIZE - NULLPAGE_SIZE);
+        let data = PageNumber::from_offset(0)..meta;
+
+        let (mut tree, mut other_tree) =
+            crate::utils::lock_two(&self.range_tree, &other.range_tree);
+
+        // Take the metadata pages out before the exchange, so that putting them back afterwards
+        // lands in an empty slot, which add_page can always fill without allocating.
+        let take_meta = |tree: &mut PageRangeTree| {
+            let page = match tree.try_get_page(meta, GetPageFlags::empty()) {
+                PageStatus::Ready(page, _) => Some(page.trimmed(1)),
+                _ => None,
+            };
+            (page, tree.punch_hole(meta..meta.next()))
+        };
+        let (meta_page, _punched) = take_meta(&mut *tree);
+        let (other_meta_page, _other_punched) = take_meta(&mut *other_tree);
+        tree.swap_pages(&mut other_tree);
+        if let Some(page) = meta_page {
+            tree.add_page(meta, page, None);
+        }
+        if let Some(page) = other_meta_page {
+            other_tree.add_page(meta, page, None);
+        }
+
+        let mut stale = alloc::vec::Vec::new();
+        if paged {
+            // Pages dirtied since the sync moved along with the rest, so their dirty state has to
+            // follow them.
+            self.dirty_set.swap(&other.dirty_set);
+            self.writeback.swap(&other.writeback);
+            stale.extend(punch_clean(&mut tree, &self.dirty_set, data.clone()));
+            stale.extend(punch_clean(&mut other_tree, &other.dirty_set, data.clone()));
+        }
+
+        self.invalidate(data.clone(), InvalidateMode::Full);
+        other.invalidate(data, InvalidateMode::Full);
+        drop(tree);
+        drop(other_tree);
+        drop(stale);
+
+        self.notify_written(0, MAX_SIZE - NULLPAGE_SIZE);
+        other.notify_written(0, MAX_SIZE - NULLPAGE_SIZE);
+        Ok(())
+    }
+
+    /// Make `dest`'s contents an independent copy of this object's, e.g. when this object is
+    /// about to be deleted. Unlike a copy-on-write copy, every resident page is copied into a
+    /// freshly allocated frame right away, so the two objects share nothing afterwards. Pages
+    /// absent in the source are absent in `dest` too, and any pages `dest` already had are
+    /// replaced. As with [Self::swap_contents], the metadata pages stay with their objects.
+    ///
+    /// Pager-backed objects are not supported, since pages the pager has not yet brought in would
+    /// be missed. Neither are objects with wired pages (such as device memory), which can't be
+    /// copied into a frame: reading device memory may have side effects.
+    pub fn deep_copy_into(self: &ObjectRef, dest: &ObjectRef) -> Result<(), TwzError> {
+        if self.id() == dest.id() {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if self.use_pager() || dest.use_pager() {
+            return Err(GenericError::NotSupported.into());
+        }
+        let meta = PageNumber::from_offset(MAX_SIZE - NULLPAGE_SIZE);
+        let data = PageNumber::from_offset(0)..meta;
+        let mut frame_allocator = FrameAllocator::new(
+            FrameAllocFlags::ZEROED | FrameAllocFlags::WAIT_OK,
+            PHYS_LEVEL_LAYOUTS[0],
+        );
+
+        let (mut src_tree, mut dest_tree) =
+            crate::utils::lock_two(&self.range_tree, &dest.range_tree);
+        let ranges: alloc::vec::Vec<_> = src_tree
+            .range(data.clone())
+            .map(|(start, value)| *start..(*value.end()).min(meta))
+            .collect();
+        let mut copies = alloc::vec::Vec::new();
+        for range in ranges {
+            for pn in range.start.num()..range.end.num() {
+                let pn = PageNumber::from(pn);
+                let PageStatus::Ready(page, _) = src_tree.try_get_page(pn, GetPageFlags::empty())
+                else {
+                    continue;
+                };
+                if page.is_wired() {
+                    return Err(GenericError::NotSupported.into());
+                }
+                let new_page = Page::try_new_in(&mut frame_allocator)?;
+                let mut copy = PageRef::new(Arc::new(new_page), 0, 1);
+                copy.copy_from(&page);
+                copies.push((pn, copy));
+            }
+        }
+
+        let _replaced = dest_tree.punch_hole(data.clone());
+        for (pn, copy) in copies {
+            dest_tree
+                .add_page(pn, copy, Some(&mut frame_allocator))
+                .ok_or(ResourceError::OutOfMemory)?;
+        }
+        dest.invalidate(data, InvalidateMode::Full);
+        drop(src_tree);
+        drop(dest_tree);
+
+        dest.notify_written(0, MAX_SIZE - NULLPAGE_SIZE);
+        Ok(())
     }
 
     pub fn map_phys(&self, start: PhysAddr, end: PhysAddr, ct: CacheType) {
@@ -502,4 +1552,1176 @@ impl Object {
             self.add_page(pn, page, None);
         }
     }
+
+    /// Map `len` bytes of physical memory starting at `start` (e.g. device registers) into this
+    /// object at `obj_offset`, with cache type `ct`. Everything must be page-aligned, and the
+    /// range must fit in the object without covering its null page or metadata page.
+    pub fn map_mmio(
+        &self,
+        obj_offset: usize,
+        start: PhysAddr,
+        len: usize,
+        ct: CacheType,
+    ) -> Result<(), TwzError> {
+        let aligned = |x: usize| x % PageNumber::PAGE_SIZE == 0;
+        if len == 0
+            || !aligned(obj_offset)
+            || !aligned(len)
+            || !start.is_aligned_to(PageNumber::PAGE_SIZE)
+        {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        let fits = obj_offset
+            .checked_add(len)
+            .is_some_and(|end| obj_offset >= NULLPAGE_SIZE && end <= MAX_SIZE - NULLPAGE_SIZE);
+        if !fits || start.offset(len).is_err() {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+
+        let pn_start = PageNumber::from_offset(obj_offset);
+        for i in 0..(len / PageNumber::PAGE_SIZE) {
+            let addr = start.offset(i * PageNumber::PAGE_SIZE).unwrap();
+            let page = Page::new_wired(addr, PageNumber::PAGE_SIZE, ct);
+            let page = PageRef::new(Arc::new(page), 0, 1);
+            self.add_page(pn_start.offset(i), page, None);
+        }
+        Ok(())
+    }
+
+    /// Make writes to the write-combined pages in `range` (byte offsets into the object) visible
+    /// to devices, e.g. before telling a display controller to scan out a framebuffer. Stores to
+    /// write-combined memory may sit in the CPU's write-combining buffers; this issues the arch's
+    /// fence for draining them (see [write_combine_fence]). Like the fence, this only covers
+    /// writes made on the current CPU, so it should be called by the thread that did the writes.
+    ///
+    /// Returns the number of write-combined pages in the range. If there are none, no fence is
+    /// needed, and none is issued.
+    pub fn flush_wc(&self, range: core::ops::Range<usize>) -> Result<usize, TwzError> {
+        if range.start > range.end || range.end > MAX_SIZE {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let first = PageNumber::from_offset(range.start);
+        let last = PageNumber::from_offset(range.end - 1);
+        let mut tree = self.lock_page_tree();
+        let wc_pages = (first.num()..=last.num())
+            .filter(|pn| {
+                matches!(
+                    tree.try_get_page((*pn).into(), GetPageFlags::empty()),
+                    PageStatus::Ready(page, _)
+                        if page.map_settings().cache() == CacheType::WriteCombining
+                )
+            })
+            .count();
+        drop(tree);
+
+        if wc_pages > 0 {
+            write_combine_fence();
+        }
+        Ok(wc_pages)
+    }
+}
+
+/// Pages of a pager-backed object that were written through the kernel (with
+/// [Object::write_bytes] and friends) but haven't been written back yet. Writes just record their
+/// pages here and queue the object for the background syncer, so they don't wait on the pager;
+/// [Object::sync] is the point at which they are known to be durable.
+pub struct WriteBack {
+    pending: Spinlock<BTreeSet<PageNumber>>,
+    // Held for the whole of each flush, so that sync can wait for one that's in progress.
+    flushing: Mutex<()>,
+}
+
+impl WriteBack {
+    pub fn new() -> Self {
+        Self {
+            pending: Spinlock::new(BTreeSet::new()),
+            flushing: Mutex::new(()),
+        }
+    }
+
+    pub fn is_pending(&self, pn: PageNumber) -> bool {
+        self.pending.lock().contains(&pn)
+    }
+
+    fn swap(&self, other: &WriteBack) {
+        core::mem::swap(&mut *self.pending.lock(), &mut *other.pending.lock());
+    }
+}
+
+struct Syncer {
+    queue: Spinlock<BTreeSet<ObjID>>,
+    cv: CondVar,
+}
+
+static SYNCER: Syncer = Syncer {
+    queue: Spinlock::new(BTreeSet::new()),
+    cv: CondVar::new(),
+};
+
+static SYNC_THREAD: Once<ThreadRef> = Once::new();
+
+extern "C" fn kthread_syncer() {
+    loop {
+        let mut queue = SYNCER.queue.lock();
+        let Some(id) = queue.pop_first() else {
+            SYNCER.cv.wait(queue);
+            continue;
+        };
+        drop(queue);
+        if let LookupResult::Found(obj) = lookup_object(id, LookupFlags::empty()) {
+            obj.flush_writeback(&PagerFlusher);
+        }
+    }
+}
+
+fn start_sync_thread() {
+    if current_thread_ref().is_some() {
+        SYNC_THREAD.call_once(|| start_new_kernel(Priority::BACKGROUND, kthread_syncer, 0));
+    }
+}
+
+impl Object {
+    /// Record that `len` bytes at `offset` were written, and have the background syncer write
+    /// them back. Does nothing for objects that are not backed by the pager.
+    fn queue_writeback(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = PageNumber::from_offset(offset);
+        let last = PageNumber::from_offset(offset + len - 1);
+        self.queue_writeback_pages((first.num()..=last.num()).map(PageNumber::from));
+    }
+
+    /// Have the background syncer write back `pages`. Does nothing for objects that are not backed
+    /// by the pager.
+    fn queue_writeback_pages(&self, pages: impl IntoIterator<Item = PageNumber>) {
+        if !self.use_pager() {
+            return;
+        }
+        self.writeback.pending.lock().extend(pages);
+
+        SYNCER.queue.lock().insert(self.id());
+        SYNCER.cv.signal();
+        start_sync_thread();
+    }
+
+    /// Write back the pages written so far, and wait for them to become durable. This also waits
+    /// for a background flush that's already in progress, so once it returns, every write made
+    /// before the call is durable. Data pages are written back before the base and metadata
+    /// pages, as with [Object::sync_ordered], and then the pager is asked to make the whole object
+    /// durable. Does nothing for objects that are not backed by the pager.
+    pub fn sync(self: &ObjectRef) {
+        if !self.use_pager() {
+            return;
+        }
+        self.flush_writeback(&PagerFlusher);
+        crate::pager::sync_object(self.id());
+    }
+
+    fn flush_writeback(self: &ObjectRef, flusher: &impl PageFlusher) {
+        let _flushing = self.writeback.flushing.lock();
+        let pages = core::mem::take(&mut *self.writeback.pending.lock());
+        let (meta, data): (alloc::vec::Vec<_>, alloc::vec::Vec<_>) = pages
+            .into_iter()
+            .partition(|pn| pn.is_meta() || *pn == PageNumber::base_page());
+        self.sync_ordered_with(flusher, &data, &meta);
+    }
+}
+
+/// Punch every page in `range` that isn't in `dirty` out of `tree`. The punched pages are returned,
+/// so that they can be kept until their mappings are invalidated.
+fn punch_clean(
+    tree: &mut PageRangeTree,
+    dirty: &super::DirtySet,
+    range: core::ops::Range<PageNumber>,
+) -> alloc::vec::Vec<PunchedPages> {
+    let mut punched = alloc::vec::Vec::new();
+    let mut start = range.start;
+    for pn in dirty.pages() {
+        if pn >= range.end {
+            break;
+        }
+        if pn < start {
+            continue;
+        }
+        if start < pn {
+            punched.push(tree.punch_hole(start..pn));
+        }
+        start = pn.next();
+    }
+    if start < range.end {
+        punched.push(tree.punch_hole(start..range.end));
+    }
+    punched
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{sync::Arc, vec::Vec};
+    use core::{
+        ops::Range,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use twizzler_abi::{
+        device::CacheType,
+        object::{MAX_SIZE, NULLPAGE_SIZE},
+        syscall::LifetimeType,
+    };
+    use twizzler_kernel_macros::kernel_test;
+    use twizzler_rt_abi::error::{ArgumentError, IoError, ResourceError};
+
+    use super::{pages_charged, set_page_quota, Page, PageRef};
+    use crate::{
+        memory::{
+            frame::{get_frame, PhysicalFrameFlags, PHYS_LEVEL_LAYOUTS},
+            tracker::{alloc_frame, free_frame, FrameAllocFlags, FrameAllocator},
+            PhysAddr,
+        },
+        mutex::Mutex,
+        obj::{
+            copy::copy_ranges,
+            id::backup_id_gen,
+            range::{GetPageFlags, PageStatus},
+            thread_sync::RangeWaker,
+            Object, ObjectRef, PageFetcher, PageFlusher, PageNumber,
+        },
+        thread::{entry::run_closure_in_new_thread, priority::Priority},
+        userinit::create_blank_object,
+    };
+
+    fn read_data(obj: &ObjectRef, off: usize, len: usize) -> alloc::vec::Vec<u8> {
+        let mut tree = obj.lock_page_tree();
+        let pn = PageNumber::from_offset(off);
+        let PageStatus::Ready(page, _) = tree.get_page(pn, GetPageFlags::empty(), None) else {
+            panic!("no page at offset {}", off);
+        };
+        let page_off = off % PageNumber::PAGE_SIZE;
+        page.as_slice()[page_off..(page_off + len)].to_vec()
+    }
+
+    #[kernel_test]
+    fn test_cas_write_range() {
+        let obj = create_blank_object();
+        let version_off = NULLPAGE_SIZE;
+        let data_off = NULLPAGE_SIZE * 2 + 16;
+
+        // A version word in an empty object reads as zero.
+        assert!(obj.cas_write_range(version_off, 0, 1, data_off, b"first"));
+        assert_eq!(unsafe { obj.read_atomic_u64(version_off) }, 1);
+        assert_eq!(read_data(&obj, data_off, 5), b"first");
+
+        // Two writers both read version 1. One commits first and bumps the version...
+        assert!(obj.cas_write_range(version_off, 1, 2, data_off, b"racer"));
+        // ...so the other's write must be rejected, leaving the winner's data and version intact.
+        assert!(!obj.cas_write_range(version_off, 1, 2, data_off, b"stale"));
+        assert_eq!(unsafe { obj.read_atomic_u64(version_off) }, 2);
+        assert_eq!(read_data(&obj, data_off, 5), b"racer");
+
+        // Retrying against the current version succeeds.
+        assert!(obj.cas_write_range(version_off, 2, 3, data_off, b"retry"));
+        assert_eq!(unsafe { obj.read_atomic_u64(version_off) }, 3);
+        assert_eq!(read_data(&obj, data_off, 5), b"retry");
+    }
+
+    /// Stands in for the pager, holding a version word on the backing store.
+    struct MockFetcher {
+        version_off: usize,
+        version: u64,
+        fetched: Mutex<Vec<PageNumber>>,
+    }
+
+    impl PageFetcher for MockFetcher {
+        fn fetch_page(&self, obj: &ObjectRef, pn: PageNumber) -> bool {
+            self.fetched.lock().push(pn);
+            let mut contents = alloc::vec![0u8; PageNumber::PAGE_SIZE];
+            if pn == PageNumber::from_offset(self.version_off) {
+                let off = self.version_off % PageNumber::PAGE_SIZE;
+                contents[off..(off + 8)].copy_from_slice(&self.version.to_ne_bytes());
+            }
+            Object::write_bytes_locked(&mut obj.lock_page_tree(), &contents, pn.as_byte_offset());
+            true
+        }
+    }
+
+    #[kernel_test]
+    fn test_cas_write_range_paged() {
+        // A persistent object that is never registered, so the background syncer leaves it be.
+        let obj: ObjectRef = Arc::new(Object::new(backup_id_gen(), LifetimeType::Persistent, &[]));
+        let version_off = NULLPAGE_SIZE * 3;
+        let data_off = NULLPAGE_SIZE * 5 + 16;
+        let pager = MockFetcher {
+            version_off,
+            version: 7,
+            fetched: Mutex::new(Vec::new()),
+        };
+
+        // The version word isn't in memory, but on the backing store it's 7, not 0.
+        assert!(!obj.cas_write_range_with(&pager, version_off, 0, 1, data_off, b"stale"));
+        assert_eq!(
+            *pager.fetched.lock(),
+            [PageNumber::from(3), PageNumber::from(5)]
+        );
+        assert_eq!(read_data(&obj, data_off, 5), [0u8; 5]);
+
+        assert!(obj.cas_write_range_with(&pager, version_off, 7, 8, data_off, b"fresh"));
+        assert_eq!(read_data(&obj, version_off, 8), 8u64.to_ne_bytes());
+        assert_eq!(read_data(&obj, data_off, 5), b"fresh");
+        // The pages were already in memory.
+        assert_eq!(pager.fetched.lock().len(), 2);
+    }
+
+    #[kernel_test]
+    fn test_write_ranges() {
+        let obj = create_blank_object();
+        let fill = alloc::vec![0x55_u8; NULLPAGE_SIZE * 3];
+        obj.write_bytes(fill.as_ptr(), fill.len(), NULLPAGE_SIZE);
+
+        // The second range straddles a page boundary, and the last lands in a fresh page.
+        let straddle = NULLPAGE_SIZE * 3 - 2;
+        let fresh = NULLPAGE_SIZE * 5 + 100;
+        obj.write_ranges(&[
+            (NULLPAGE_SIZE + 8, b"one"),
+            (straddle, b"two!"),
+            (fresh, b"three"),
+        ]);
+
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE + 8, 3), b"one");
+        assert_eq!(read_data(&obj, straddle, 2), b"tw");
+        assert_eq!(read_data(&obj, straddle + 2, 2), b"o!");
+        assert_eq!(read_data(&obj, fresh, 5), b"three");
+
+        // Bytes around each range are untouched.
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE + 7, 1), [0x55]);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE + 11, 1), [0x55]);
+        assert_eq!(read_data(&obj, straddle - 1, 1), [0x55]);
+        assert_eq!(read_data(&obj, straddle + 4, 1), [0x55]);
+        assert_eq!(read_data(&obj, fresh - 1, 1), [0]);
+        assert_eq!(read_data(&obj, fresh + 5, 1), [0]);
+    }
+
+    #[kernel_test]
+    fn test_read_consistent() {
+        const WORDS: usize = 64;
+        const ROUNDS: u64 = 2000;
+        // The structure straddles a page boundary, so each update is two writes.
+        let off = NULLPAGE_SIZE * 2 - WORDS * 4;
+        let obj = create_blank_object();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writer_obj = obj.clone();
+        let writer_done = done.clone();
+        let writer = run_closure_in_new_thread(Priority::REALTIME, move || {
+            for round in 1..=ROUNDS {
+                let bytes: Vec<u8> = (0..WORDS).flat_map(|_| round.to_le_bytes()).collect();
+                let (lo, hi) = bytes.split_at(bytes.len() / 2);
+                writer_obj.write_ranges(&[(off, lo), (off + lo.len(), hi)]);
+            }
+            writer_done.store(true, Ordering::SeqCst);
+        });
+
+        // Every snapshot holds a single round, and rounds never go backwards.
+        let snapshot = || {
+            let mut buf = [0u8; WORDS * 8];
+            obj.read_consistent(off, &mut buf).unwrap();
+            let first = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+            assert!(buf
+                .chunks(8)
+                .all(|w| u64::from_le_bytes(w.try_into().unwrap()) == first));
+            first
+        };
+        let mut last = 0;
+        while !done.load(Ordering::SeqCst) {
+            let round = snapshot();
+            assert!(round >= last);
+            last = round;
+        }
+        writer.1.wait();
+        assert_eq!(snapshot(), ROUNDS);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            obj.read_consistent(MAX_SIZE - 4, &mut buf),
+            Err(ArgumentError::InvalidArgument.into())
+        );
+    }
+
+    #[kernel_test]
+    fn test_update_range() {
+        const THREADS: usize = 4;
+        const ROUNDS: u128 = 200;
+        // A 16-byte counter that straddles a page boundary.
+        const OFF: usize = NULLPAGE_SIZE * 3 - 8;
+        let obj = create_blank_object();
+        let increment = |obj: &ObjectRef| {
+            obj.update_range(OFF, 16, |bytes| {
+                let val = u128::from_le_bytes(bytes.try_into().unwrap());
+                bytes.copy_from_slice(&(val + 1).to_le_bytes());
+            })
+            .unwrap();
+        };
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let obj = obj.clone();
+                run_closure_in_new_thread(Priority::REALTIME, move || {
+                    for _ in 0..ROUNDS {
+                        increment(&obj);
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..ROUNDS {
+            increment(&obj);
+        }
+        for thread in threads {
+            thread.1.wait();
+        }
+
+        // No increment was lost to a racing one.
+        let mut buf = [0u8; 16];
+        obj.read_consistent(OFF, &mut buf).unwrap();
+        assert_eq!(u128::from_le_bytes(buf), ROUNDS * (THREADS as u128 + 1));
+
+        assert_eq!(
+            obj.update_range(MAX_SIZE - 8, 16, |_| panic!("called for a bad range")),
+            Err(ArgumentError::InvalidArgument.into())
+        );
+    }
+
+    #[kernel_test]
+    fn test_share_pages_into() {
+        let a = create_blank_object();
+        let b = create_blank_object();
+        a.write_bytes(b"before".as_ptr(), 6, NULLPAGE_SIZE * 2);
+
+        let shared =
+            PageNumber::from_offset(NULLPAGE_SIZE * 2)..PageNumber::from_offset(NULLPAGE_SIZE * 4);
+        let dest_start = PageNumber::from_offset(NULLPAGE_SIZE * 8);
+        a.share_pages_into(shared.clone(), &b, dest_start).unwrap();
+        assert_eq!(read_data(&b, NULLPAGE_SIZE * 8, 6), b"before");
+
+        // Writes through either object are seen through the other, including in the page that was
+        // absent in the source before sharing.
+        a.write_bytes(b"from a".as_ptr(), 6, NULLPAGE_SIZE * 2 + 100);
+        assert_eq!(read_data(&b, NULLPAGE_SIZE * 8 + 100, 6), b"from a");
+        b.write_bytes(b"from b".as_ptr(), 6, NULLPAGE_SIZE * 9 + 8);
+        assert_eq!(read_data(&a, NULLPAGE_SIZE * 3 + 8, 6), b"from b");
+
+        // Sharing into the same object is rejected.
+        let one =
+            PageNumber::from_offset(NULLPAGE_SIZE * 2)..PageNumber::from_offset(NULLPAGE_SIZE * 3);
+        assert!(a.share_pages_into(one, &a, dest_start).is_err());
+
+        // A cache-type mismatch on the last page fails the whole share, before any of dest
+        // changes.
+        let c = create_blank_object();
+        let ps = PageNumber::PAGE_SIZE;
+        c.write_bytes(b"intact".as_ptr(), 6, NULLPAGE_SIZE * 8);
+        let frame = alloc_frame(FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED);
+        let uncached = Page::new_wired(frame.start_address(), ps, CacheType::Uncacheable);
+        let uncached = PageRef::new(Arc::new(uncached), 0, 1);
+        c.add_page(dest_start.next(), uncached, None);
+        assert!(a.share_pages_into(shared, &c, dest_start).is_err());
+        assert_eq!(read_data(&c, NULLPAGE_SIZE * 8, 6), b"intact");
+        a.write_bytes(b"a only".as_ptr(), 6, NULLPAGE_SIZE * 2);
+        assert_eq!(read_data(&c, NULLPAGE_SIZE * 8, 6), b"intact");
+    }
+
+    #[kernel_test]
+    fn test_wired_page_cow() {
+        let ps = PageNumber::PAGE_SIZE;
+        let pn = PageNumber::from_offset(NULLPAGE_SIZE * 2);
+        let mut allocator = FrameAllocator::new(
+            FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED,
+            PHYS_LEVEL_LAYOUTS[0],
+        );
+        // Wired pages are never freed, so these frames stay with the test objects.
+        let wire = |ct: CacheType| {
+            let frame = alloc_frame(FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED);
+            let page = Page::new_wired(frame.start_address(), ps, ct);
+            PageRef::new(Arc::new(page), 0, 1)
+        };
+
+        // A wired page of ordinary memory is copied into a fresh frame on write, and the copy
+        // diverges from the original.
+        let src = create_blank_object();
+        let dest = create_blank_object();
+        let wired = wire(CacheType::WriteBack);
+        wired.as_mut_slice()[..5].copy_from_slice(b"wired");
+        src.add_page(pn, wired.clone(), None);
+        copy_ranges(
+            &src,
+            pn.as_byte_offset(),
+            &dest,
+            pn.as_byte_offset(),
+            ps,
+            &mut allocator,
+        );
+        {
+            let mut tree = dest.lock_page_tree();
+            let PageStatus::Ready(page, shared) =
+                tree.get_page(pn, GetPageFlags::WRITE, Some(&mut allocator))
+            else {
+                panic!("failed to get private copy of wired page");
+            };
+            assert!(!shared);
+            assert!(!page.is_wired());
+            assert_ne!(page.physical_address(), wired.physical_address());
+            assert_eq!(&page.as_slice()[..5], b"wired");
+            page.as_mut_slice()[..5].copy_from_slice(b"copy!");
+        }
+        assert_eq!(read_data(&dest, pn.as_byte_offset(), 5), b"copy!");
+        assert_eq!(&wired.as_slice()[..5], b"wired");
+
+        // Device memory is never copied.
+        let src = create_blank_object();
+        let dest = create_blank_object();
+        src.add_page(pn, wire(CacheType::Uncacheable), None);
+        copy_ranges(
+            &src,
+            pn.as_byte_offset(),
+            &dest,
+            pn.as_byte_offset(),
+            ps,
+            &mut allocator,
+        );
+        let mut tree = dest.lock_page_tree();
+        assert!(matches!(
+            tree.get_page(pn, GetPageFlags::WRITE, Some(&mut allocator)),
+            PageStatus::NoCopy
+        ));
+    }
+
+    #[kernel_test]
+    fn test_subscribe_range() {
+        struct Recorder(Mutex<Vec<Range<PageNumber>>>);
+
+        impl RangeWaker for Recorder {
+            fn wake(&self, pages: Range<PageNumber>) {
+                self.0.lock().push(pages);
+            }
+        }
+
+        let obj = create_blank_object();
+        let pn = |n: usize| PageNumber::from_offset(NULLPAGE_SIZE * n);
+        let recorder = || Arc::new(Recorder(Mutex::new(Vec::new())));
+        let (wide, narrow, elsewhere) = (recorder(), recorder(), recorder());
+        let wide_sub = obj.subscribe_range(pn(2)..pn(6), wide.clone());
+        let _narrow_sub = obj.subscribe_range(pn(3)..pn(4), narrow.clone());
+        let _elsewhere_sub = obj.subscribe_range(pn(8)..pn(9), elsewhere.clone());
+
+        // A write straddling pages 3 and 4 wakes both overlapping subscribers, each with only the
+        // part of the write that falls in its range.
+        obj.write_bytes(b"change".as_ptr(), 6, NULLPAGE_SIZE * 4 - 2);
+        assert_eq!(*wide.0.lock(), [pn(3)..pn(5)]);
+        assert_eq!(*narrow.0.lock(), [pn(3)..pn(4)]);
+        assert!(elsewhere.0.lock().is_empty());
+
+        // Once unsubscribed, writes in the range no longer wake it.
+        drop(wide_sub);
+        obj.write_ranges(&[(NULLPAGE_SIZE * 2, b"one"), (NULLPAGE_SIZE * 8, b"two")]);
+        assert_eq!(wide.0.lock().len(), 1);
+        assert_eq!(*elsewhere.0.lock(), [pn(8)..pn(9)]);
+    }
+
+    #[kernel_test]
+    fn test_apply_patch() {
+        let obj = create_blank_object();
+        let fill = alloc::vec![0x55_u8; NULLPAGE_SIZE * 64];
+        obj.write_bytes(fill.as_ptr(), fill.len(), NULLPAGE_SIZE);
+        obj.dirty_set().drain_all();
+
+        // One edit straddles pages 10 and 11, one rewrites bytes page 40 already holds, and one
+        // writes zeros into a page that isn't present.
+        let straddle = NULLPAGE_SIZE * 11 - 2;
+        let same = [0x55_u8; 16];
+        let changed = obj
+            .apply_patch(&[
+                (NULLPAGE_SIZE * 3 + 8, b"one"),
+                (straddle, b"two!"),
+                (NULLPAGE_SIZE * 40, &same),
+                (NULLPAGE_SIZE * 100, &[0u8; 8]),
+            ])
+            .unwrap();
+        let pn = |n: usize| PageNumber::from_offset(NULLPAGE_SIZE * n);
+        assert_eq!(changed, [pn(3), pn(10), pn(11)]);
+
+        // Only the changed pages are dirty.
+        assert_eq!(obj.dirty_set().drain_all(), changed);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 3 + 8, 3), b"one");
+        assert_eq!(read_data(&obj, straddle, 2), b"tw");
+        assert_eq!(read_data(&obj, straddle + 2, 2), b"o!");
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 3 + 11, 1), [0x55]);
+        let mut tree = obj.lock_page_tree();
+        assert!(!matches!(
+            tree.get_page(pn(100), GetPageFlags::empty(), None),
+            PageStatus::Ready(..)
+        ));
+        drop(tree);
+
+        // Reapplying the same patch changes nothing, and edits past the end are refused.
+        assert_eq!(obj.apply_patch(&[(straddle, b"two!")]), Ok(alloc::vec![]));
+        assert!(obj.dirty_set().drain_all().is_empty());
+        assert_eq!(
+            obj.apply_patch(&[(NULLPAGE_SIZE, b"ok"), (MAX_SIZE - 1, b"no")]),
+            Err(ArgumentError::InvalidArgument.into())
+        );
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE, 2), [0x55, 0x55]);
+    }
+
+    #[kernel_test]
+    fn test_map_mmio() {
+        let obj = create_blank_object();
+        let regs = PhysAddr::new(0xfeb0_0000).unwrap();
+        let off = NULLPAGE_SIZE * 16;
+        obj.map_mmio(off, regs, NULLPAGE_SIZE * 2, CacheType::Uncacheable)
+            .unwrap();
+
+        let mut tree = obj.lock_page_tree();
+        for i in 0..2 {
+            let pn = PageNumber::from_offset(off + i * NULLPAGE_SIZE);
+            let PageStatus::Ready(page, _) = tree.try_get_page(pn, GetPageFlags::empty()) else {
+                panic!("no page at page number {:?}", pn);
+            };
+            assert!(page.is_mmio());
+            assert_eq!(
+                page.physical_address().raw(),
+                regs.raw() + (i * NULLPAGE_SIZE) as u64
+            );
+        }
+        drop(tree);
+
+        // Misaligned, empty, or out-of-bounds ranges are rejected.
+        let ct = CacheType::Uncacheable;
+        assert!(obj.map_mmio(off + 8, regs, NULLPAGE_SIZE, ct).is_err());
+        assert!(obj
+            .map_mmio(off, regs.offset(8).unwrap(), NULLPAGE_SIZE, ct)
+            .is_err());
+        assert!(obj.map_mmio(off, regs, 100, ct).is_err());
+        assert!(obj.map_mmio(off, regs, 0, ct).is_err());
+        assert!(obj.map_mmio(0, regs, NULLPAGE_SIZE, ct).is_err());
+        assert!(obj
+            .map_mmio(MAX_SIZE - NULLPAGE_SIZE, regs, NULLPAGE_SIZE, ct)
+            .is_err());
+        assert!(obj
+            .map_mmio(usize::MAX & !(NULLPAGE_SIZE - 1), regs, NULLPAGE_SIZE, ct)
+            .is_err());
+    }
+
+    #[kernel_test]
+    fn test_flush_wc() {
+        let obj = create_blank_object();
+        let off = NULLPAGE_SIZE * 4;
+        let frame = alloc_frame(FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED);
+        let page = Page::new_wired(
+            frame.start_address(),
+            PageNumber::PAGE_SIZE,
+            CacheType::WriteCombining,
+        );
+        let page = PageRef::new(Arc::new(page), 0, 1);
+        page.as_mut_slice()[..6].copy_from_slice(b"pixels");
+        obj.add_page(PageNumber::from_offset(off), page, None);
+
+        // Ranges that only touch part of the page still flush it.
+        assert_eq!(obj.flush_wc(off..off + 6), Ok(1));
+        assert_eq!(obj.flush_wc(NULLPAGE_SIZE..off + 1), Ok(1));
+        // Ordinary and absent pages need no flush.
+        obj.write_bytes(b"data".as_ptr(), 4, NULLPAGE_SIZE);
+        assert_eq!(obj.flush_wc(NULLPAGE_SIZE..off), Ok(0));
+        assert_eq!(obj.flush_wc(off..off), Ok(0));
+        assert!(obj.flush_wc(off..MAX_SIZE + 1).is_err());
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[kernel_test]
+    fn test_volatile_access() {
+        assert!(super::access_widths(0x1003, 13).eq([(0, 1), (1, 4), (5, 8)]));
+        assert!(super::access_widths(0x1000, 3).eq([(0, 2), (2, 1)]));
+        assert_eq!(super::access_widths(0x1000, 0).count(), 0);
+
+        for cache_type in [CacheType::Uncacheable, CacheType::WriteBack] {
+            let frame = alloc_frame(FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED);
+            let page = Page::new_wired(frame.start_address(), PageNumber::PAGE_SIZE, cache_type);
+
+            // A register-sized write is seen by a matching read, and by plain access.
+            page.write_volatile_from(0x10, &0xdead_beef_u32.to_ne_bytes());
+            let mut reg = [0u8; 4];
+            page.read_volatile_into(0x10, &mut reg);
+            assert_eq!(u32::from_ne_bytes(reg), 0xdead_beef);
+            assert_eq!(&page.as_slice(0)[0x10..0x14], &reg);
+
+            // Unaligned ranges are split up, but read back the same.
+            let data: Vec<u8> = (1..=29).collect();
+            page.write_volatile_from(0x23, &data);
+            let mut back = [0u8; 29];
+            page.read_volatile_into(0x23, &mut back);
+            assert_eq!(back.as_slice(), data.as_slice());
+            assert_eq!(page.as_slice(0)[0x22], 0);
+            assert_eq!(page.as_slice(0)[0x23 + 29], 0);
+            drop(page);
+            free_frame(frame);
+        }
+    }
+
+    #[kernel_test]
+    fn test_read_only_page_view() {
+        let obj = create_blank_object();
+        obj.write_bytes(b"ro".as_ptr(), 2, NULLPAGE_SIZE);
+        let pn = PageNumber::from_offset(NULLPAGE_SIZE);
+
+        let mut tree = obj.lock_page_tree();
+        let PageStatus::Ready(page, _) = tree.get_page(pn, GetPageFlags::READ_ONLY, None) else {
+            panic!("no page at offset {}", NULLPAGE_SIZE);
+        };
+        assert!(page.is_read_only());
+        assert_eq!(&page.as_slice()[0..2], b"ro");
+        assert!(page.try_as_mut_slice().is_none());
+        assert!(unsafe { page.try_get_mut_to_val::<u64>(0) }.is_none());
+        // Reading through the view is still fine.
+        assert_eq!(unsafe { page.get_to_val::<[u8; 2]>(0).read() }, *b"ro");
+        // The restriction follows the view when it's narrowed.
+        assert!(page.trimmed(1).try_as_mut_slice().is_none());
+
+        // Other views of the same page are unaffected.
+        let PageStatus::Ready(page, _) = tree.get_page(pn, GetPageFlags::empty(), None) else {
+            panic!("no page at offset {}", NULLPAGE_SIZE);
+        };
+        assert!(!page.is_read_only());
+        assert!(page.try_as_mut_slice().is_some());
+    }
+
+    #[kernel_test]
+    fn test_swap_contents() {
+        let a = create_blank_object();
+        let b = create_blank_object();
+        let meta_off = MAX_SIZE - NULLPAGE_SIZE;
+        let (a_meta, b_meta) = (read_data(&a, meta_off, 64), read_data(&b, meta_off, 64));
+
+        a.write_bytes(b"aaaa".as_ptr(), 4, NULLPAGE_SIZE);
+        b.write_bytes(b"bbbb".as_ptr(), 4, NULLPAGE_SIZE * 5);
+
+        a.swap_contents(&b).unwrap();
+        assert_eq!(read_data(&a, NULLPAGE_SIZE * 5, 4), b"bbbb");
+        assert_eq!(read_data(&b, NULLPAGE_SIZE, 4), b"aaaa");
+        let pn = PageNumber::from_offset(NULLPAGE_SIZE);
+        assert!(matches!(
+            a.lock_page_tree().try_get_page(pn, GetPageFlags::empty()),
+            PageStatus::NoPage
+        ));
+        // Each object keeps its own metadata.
+        assert_eq!(read_data(&a, meta_off, 64), a_meta);
+        assert_eq!(read_data(&b, meta_off, 64), b_meta);
+
+        // Swapping back restores the originals.
+        b.swap_contents(&a).unwrap();
+        assert_eq!(read_data(&a, NULLPAGE_SIZE, 4), b"aaaa");
+        assert_eq!(read_data(&b, NULLPAGE_SIZE * 5, 4), b"bbbb");
+        assert!(a.swap_contents(&a).is_err());
+    }
+
+    #[kernel_test]
+    fn test_swap_contents_readers() {
+        const LEN: usize = 256;
+        const ROUNDS: usize = 500;
+        // The range straddles a page boundary, so a half-done swap would show up as a mix.
+        let off = NULLPAGE_SIZE * 3 - LEN / 2;
+        let a = create_blank_object();
+        let b = create_blank_object();
+        a.write_bytes([b'a'; LEN].as_ptr(), LEN, off);
+        b.write_bytes([b'b'; LEN].as_ptr(), LEN, off);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let (swap_a, swap_b, swap_done) = (a.clone(), b.clone(), done.clone());
+        let swapper = run_closure_in_new_thread(Priority::REALTIME, move || {
+            for _ in 0..ROUNDS {
+                swap_a.swap_contents(&swap_b).unwrap();
+            }
+            swap_done.store(true, Ordering::SeqCst);
+        });
+
+        // Every read sees all of one object's contents or all of the other's.
+        loop {
+            let finished = done.load(Ordering::SeqCst);
+            for obj in [&a, &b] {
+                let mut buf = [0u8; LEN];
+                obj.read_consistent(off, &mut buf).unwrap();
+                assert!(
+                    buf.iter().all(|c| *c == buf[0]),
+                    "saw a mix of both objects"
+                );
+                assert!(buf[0] == b'a' || buf[0] == b'b');
+            }
+            if finished {
+                break;
+            }
+        }
+        swapper.1.wait();
+
+        // An even number of swaps leaves each object with its own contents.
+        assert_eq!(read_data(&a, off, LEN), [b'a'; LEN]);
+        assert_eq!(read_data(&b, off, LEN), [b'b'; LEN]);
+    }
+
+    #[kernel_test]
+    fn test_deep_copy_into() {
+        let src = create_blank_object();
+        let dest = create_blank_object();
+        let data = alloc::vec![0x5a_u8; NULLPAGE_SIZE * 2];
+        src.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE);
+        src.write_bytes(b"far".as_ptr(), 3, NULLPAGE_SIZE * 40);
+        // Pages dest already has are replaced, including where the source has a hole.
+        dest.write_bytes(b"old".as_ptr(), 3, NULLPAGE_SIZE);
+        dest.write_bytes(b"old".as_ptr(), 3, NULLPAGE_SIZE * 7);
+
+        src.deep_copy_into(&dest).unwrap();
+        assert_eq!(read_data(&dest, NULLPAGE_SIZE, 4), [0x5a; 4]);
+        assert_eq!(read_data(&dest, NULLPAGE_SIZE * 3 - 1, 1), [0x5a]);
+        assert_eq!(read_data(&dest, NULLPAGE_SIZE * 40, 3), b"far");
+        let hole = PageNumber::from_offset(NULLPAGE_SIZE * 7);
+        assert!(matches!(
+            dest.lock_page_tree()
+                .try_get_page(hole, GetPageFlags::empty()),
+            PageStatus::NoPage
+        ));
+
+        // The copy is independent: writes to either object don't show up in the other.
+        src.write_bytes(b"new".as_ptr(), 3, NULLPAGE_SIZE);
+        assert_eq!(read_data(&dest, NULLPAGE_SIZE, 3), [0x5a; 3]);
+        dest.write_bytes(b"dst".as_ptr(), 3, NULLPAGE_SIZE * 40);
+        assert_eq!(read_data(&src, NULLPAGE_SIZE * 40, 3), b"far");
+        assert!(src.deep_copy_into(&src).is_err());
+
+        // Device memory is never copied.
+        let dev = create_blank_object();
+        dev.map_mmio(
+            NULLPAGE_SIZE * 16,
+            PhysAddr::new(0xfeb0_0000).unwrap(),
+            NULLPAGE_SIZE,
+            CacheType::Uncacheable,
+        )
+        .unwrap();
+        assert!(dev.deep_copy_into(&dest).is_err());
+    }
+
+    #[kernel_test]
+    fn test_punch_hole() {
+        let obj = create_blank_object();
+        let data = alloc::vec![0xaa_u8; NULLPAGE_SIZE * 4];
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE);
+
+        let hole =
+            PageNumber::from_offset(NULLPAGE_SIZE * 2)..PageNumber::from_offset(NULLPAGE_SIZE * 4);
+        let hole_frames: alloc::vec::Vec<_> = (hole.start.num()..hole.end.num())
+            .map(|pn| {
+                let mut tree = obj.lock_page_tree();
+                let PageStatus::Ready(page, _) =
+                    tree.get_page(pn.into(), GetPageFlags::empty(), None)
+                else {
+                    panic!("page {} missing before punch", pn);
+                };
+                page.physical_address()
+            })
+            .collect();
+
+        obj.punch_hole(hole.clone());
+
+        // The frames are released (check before faulting anything in, which may reuse them)...
+        for pa in hole_frames {
+            let frame = get_frame(pa).unwrap();
+            assert!(!frame.get_flags().contains(PhysicalFrameFlags::ALLOCATED));
+        }
+
+        // ...and the hole reads as zeros once faulted back in.
+        for pn in hole.start.num()..hole.end.num() {
+            let tree = obj.lock_page_tree();
+            let mut used_pager = false;
+            let mut tree = obj.ensure_in_core(tree, pn.into(), &mut used_pager);
+            let PageStatus::Ready(page, _) = tree.get_page(pn.into(), GetPageFlags::empty(), None)
+            else {
+                panic!("page {} missing after fault-in", pn);
+            };
+            assert!(page.as_slice().iter().all(|b| *b == 0));
+        }
+
+        // Pages on either side of the hole keep their data.
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE, 4), [0xaa; 4]);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 4, 4), [0xaa; 4]);
+    }
+
+    #[kernel_test]
+    fn test_discard_range() {
+        let obj = create_blank_object();
+        let data = alloc::vec![0xaa_u8; NULLPAGE_SIZE * 4];
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE);
+
+        let range =
+            PageNumber::from_offset(NULLPAGE_SIZE * 2)..PageNumber::from_offset(NULLPAGE_SIZE * 4);
+        obj.discard_range(range);
+
+        // The discarded pages read as zeros, and the others keep their data.
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 2, 4), [0; 4]);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 4 - 4, 4), [0; 4]);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE, 4), [0xaa; 4]);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 4, 4), [0xaa; 4]);
+
+        // Writes after the discard are kept.
+        obj.write_bytes(b"new".as_ptr(), 3, NULLPAGE_SIZE * 2);
+        assert_eq!(read_data(&obj, NULLPAGE_SIZE * 2, 3), b"new");
+    }
+
+    #[kernel_test]
+    fn test_copy_out() {
+        let obj = create_blank_object();
+        let data: Vec<u8> = (0..(NULLPAGE_SIZE * 2)).map(|i| i as u8).collect();
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE);
+
+        // A populated range, across a page boundary.
+        let mut buf = [0u8; 64];
+        let off = NULLPAGE_SIZE * 2 - 32;
+        assert_eq!(obj.copy_out(off, &mut buf), buf.len());
+        assert_eq!(
+            buf[..],
+            data[(off - NULLPAGE_SIZE)..(off - NULLPAGE_SIZE + 64)]
+        );
+
+        // A range that runs into a hole stops at the hole.
+        let mut buf = alloc::vec![0xffu8; NULLPAGE_SIZE];
+        let off = NULLPAGE_SIZE * 3 - 16;
+        assert_eq!(obj.copy_out(off, &mut buf), 16);
+        assert_eq!(buf[..16], data[(data.len() - 16)..]);
+        assert!(buf[16..].iter().all(|b| *b == 0xff));
+
+        // A range that starts in a hole copies nothing.
+        assert_eq!(obj.copy_out(NULLPAGE_SIZE * 3, &mut buf), 0);
+        assert_eq!(obj.copy_out(MAX_SIZE, &mut buf), 0);
+    }
+
+    #[kernel_test]
+    fn test_read_verified() {
+        let obj = create_blank_object();
+        let off = NULLPAGE_SIZE * 2 - 8;
+        let data = [0x5au8; 16];
+        obj.write_bytes(data.as_ptr(), data.len(), off);
+
+        // A read that spans two pages matches the hash of what was written.
+        let good = crate::crypto::sha256(&data);
+        let mut buf = [0u8; 16];
+        assert!(obj.read_verified(off, &mut buf, &good).is_ok());
+        assert_eq!(buf, data);
+
+        // Pages that were never written hash as zeros.
+        let sparse_off = NULLPAGE_SIZE * 8;
+        let mut sparse = alloc::vec![0xffu8; NULLPAGE_SIZE * 2];
+        let zeros = crate::crypto::sha256(&alloc::vec![0u8; NULLPAGE_SIZE * 2]);
+        assert!(obj.read_verified(sparse_off, &mut sparse, &zeros).is_ok());
+        assert!(sparse.iter().all(|b| *b == 0));
+
+        // Flip a bit directly in the backing page, behind the object's back.
+        {
+            let mut tree = obj.lock_page_tree();
+            let pn = PageNumber::from_offset(off);
+            let PageStatus::Ready(page, _) = tree.get_page(pn, GetPageFlags::empty(), None) else {
+                panic!("no page at offset {}", off);
+            };
+            page.as_mut_slice()[off % PageNumber::PAGE_SIZE] ^= 1;
+        }
+        let res = obj.read_verified(off, &mut buf, &good);
+        assert_eq!(res.unwrap_err(), IoError::DataLoss.into());
+        assert_ne!(buf, data);
+    }
+
+    #[kernel_test]
+    fn test_move_range() {
+        let obj = create_blank_object();
+        let base = NULLPAGE_SIZE;
+        let span = NULLPAGE_SIZE * 4;
+        let mut model: Vec<u8> = (0..span).map(|i| (i % 251) as u8).collect();
+        obj.write_bytes(model.as_ptr(), model.len(), base);
+
+        let read_all = |obj: &ObjectRef| {
+            let mut buf = alloc::vec![0u8; span];
+            drop(obj.read_bytes_locked(obj.lock_page_tree(), &mut buf, base));
+            buf
+        };
+
+        // Overlapping moves down and up, each spanning page boundaries at unaligned offsets, then
+        // a move that overlaps within a single page.
+        for (src, dst, len) in [
+            (NULLPAGE_SIZE + 100, 300, NULLPAGE_SIZE * 2),
+            (50, NULLPAGE_SIZE - 7, NULLPAGE_SIZE * 2 + 13),
+            (NULLPAGE_SIZE * 3 + 10, NULLPAGE_SIZE * 3 + 20, 1000),
+        ] {
+            obj.move_range(base + src, base + dst, len).unwrap();
+            model.copy_within(src..(src + len), dst);
+            assert_eq!(read_all(&obj), model, "move {} -> {} ({})", src, dst, len);
+        }
+
+        // Moving from a page that was never written brings zeros.
+        let far = NULLPAGE_SIZE * 16;
+        obj.move_range(far, base, 64).unwrap();
+        model[0..64].fill(0);
+        assert_eq!(read_all(&obj), model);
+
+        assert!(obj.move_range(MAX_SIZE - 8, base, 16).is_err());
+    }
+
+    #[kernel_test]
+    fn test_page_quota() {
+        let ps = PageNumber::PAGE_SIZE;
+        let pn = PageNumber::from_offset(NULLPAGE_SIZE * 2);
+        // Use fresh IDs as owners, so other tests' accounting doesn't get in the way.
+        let limited = create_blank_object().id();
+        let other = create_blank_object().id();
+        let allocator_for = |owner| {
+            FrameAllocator::new(
+                FrameAllocFlags::KERNEL | FrameAllocFlags::ZEROED,
+                PHYS_LEVEL_LAYOUTS[0],
+            )
+            .with_owner(owner)
+        };
+
+        set_page_quota(limited, Some(2));
+        let mut fa = allocator_for(limited);
+        let pages: Vec<_> = (0..2).map(|_| Page::try_new_in(&mut fa).unwrap()).collect();
+        assert_eq!(pages_charged(limited), 2);
+        assert_eq!(
+            Page::try_new_in(&mut fa).unwrap_err(),
+            ResourceError::OutOfMemory.into()
+        );
+        assert_eq!(pages_charged(limited), 2);
+
+        // Other owners are unaffected.
+        let mut other_fa = allocator_for(other);
+        let other_page = Page::try_new_in(&mut other_fa).unwrap();
+        assert_eq!(pages_charged(other), 1);
+
+        // Freeing pages gives their charge back.
+        drop(pages);
+        assert_eq!(pages_charged(limited), 0);
+
+        // A copy-on-write page stays charged to whoever allocated it, and the private copy made by
+        // a write is charged to the writer, if the writer has room for it.
+        let src = create_blank_object();
+        let dest = create_blank_object();
+        let page = Page::try_new_in(&mut fa).unwrap();
+        src.add_page(pn, PageRef::new(Arc::new(page), 0, 1), None);
+        copy_ranges(
+            &src,
+            pn.as_byte_offset(),
+            &dest,
+            pn.as_byte_offset(),
+            ps,
+            &mut allocator_for(other),
+        );
+        assert_eq!(pages_charged(limited), 1);
+        assert_eq!(pages_charged(other), 1);
+        {
+            let mut tree = dest.lock_page_tree();
+            set_page_quota(other, Some(1));
+            assert!(matches!(
+                tree.get_page(pn, GetPageFlags::WRITE, Some(&mut other_fa)),
+                PageStatus::AllocFail
+            ));
+            set_page_quota(other, None);
+            assert!(matches!(
+                tree.get_page(pn, GetPageFlags::WRITE, Some(&mut other_fa)),
+                PageStatus::Ready(_, false)
+            ));
+        }
+        assert_eq!(pages_charged(limited), 1);
+        assert_eq!(pages_charged(other), 2);
+
+        drop(other_page);
+        set_page_quota(limited, None);
+    }
+
+    #[kernel_test]
+    fn test_fill_pattern() {
+        let obj = create_blank_object();
+        let ps = PageNumber::PAGE_SIZE;
+        let pattern = [0xde, 0xad, 0xbe, 0xef, 0x42];
+        // Start partway into a page and end partway into another, with a pattern whose length
+        // doesn't divide the page size.
+        let offset = NULLPAGE_SIZE + 1000;
+        let len = ps * 3 + 123;
+        obj.write_bytes([0xffu8; 8].as_ptr(), 8, offset - 8);
+        obj.write_bytes([0xffu8; 8].as_ptr(), 8, offset + len);
+        obj.fill_pattern(offset, len, &pattern).unwrap();
+
+        let mut data = alloc::vec![0u8; len + 16];
+        drop(obj.read_bytes_locked(obj.lock_page_tree(), &mut data, offset - 8));
+        assert_eq!(data[0..8], [0xff; 8]);
+        assert_eq!(data[(len + 8)..], [0xff; 8]);
+        for (i, b) in data[8..(len + 8)].iter().enumerate() {
+            assert_eq!(*b, pattern[i % pattern.len()], "byte {} of fill", i);
+        }
+
+        assert!(obj.fill_pattern(offset, len, &[]).is_err());
+        assert!(obj.fill_pattern(MAX_SIZE - 8, 16, &pattern).is_err());
+    }
+
+    /// Records the pages of each flush instead of handing them to the pager.
+    struct RecordingFlusher(Mutex<Vec<Vec<PageNumber>>>);
+
+    impl PageFlusher for RecordingFlusher {
+        type Flush = ();
+
+        fn issue(&self, _obj: &ObjectRef, pages: &[PageNumber]) {
+            self.0.lock().push(pages.to_vec());
+        }
+
+        fn wait(&self, _flush: ()) {}
+    }
+
+    #[kernel_test]
+    fn test_writes_durable_after_sync() {
+        // A persistent object that is never registered, so the background syncer can't look it
+        // up, and the only flushes are the ones made here.
+        let obj: ObjectRef = Arc::new(Object::new(backup_id_gen(), LifetimeType::Persistent, &[]));
+        let ps = PageNumber::PAGE_SIZE;
+        let data = [0x5au8; 200];
+        obj.write_bytes(data.as_ptr(), data.len(), ps * 6 - 100);
+        obj.write_base(&0x1234u64);
+
+        // The writes are in the object, but none of them has been written back.
+        let mut buf = [0u8; 200];
+        drop(obj.read_bytes_locked(obj.lock_page_tree(), &mut buf, ps * 6 - 100));
+        assert_eq!(buf, data);
+        for pn in [
+            PageNumber::base_page(),
+            PageNumber::from(5),
+            PageNumber::from(6),
+        ] {
+            assert!(obj.writeback().is_pending(pn));
+        }
+
+        let flusher = RecordingFlusher(Mutex::new(Vec::new()));
+        obj.flush_writeback(&flusher);
+        // Data is flushed before the base page that refers to it.
+        assert_eq!(
+            *flusher.0.lock(),
+            [
+                alloc::vec![PageNumber::from(5), PageNumber::from(6)],
+                alloc::vec![PageNumber::base_page()],
+            ]
+        );
+        for pn in [
+            PageNumber::base_page(),
+            PageNumber::from(5),
+            PageNumber::from(6),
+        ] {
+            assert!(!obj.writeback().is_pending(pn));
+        }
+
+        // Nothing is left to flush until the object is written again.
+        obj.flush_writeback(&flusher);
+        assert_eq!(flusher.0.lock().len(), 2);
+        obj.write_bytes(data.as_ptr(), 8, ps * 9);
+        obj.flush_writeback(&flusher);
+        assert_eq!(flusher.0.lock()[2], [PageNumber::from(9)]);
+    }
 }
@@ -1,4 +1,4 @@
-use alloc::{format, string::String, sync::Arc};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use core::{ops::Range, usize};
 
 use nonoverlapping_interval_tree::NonOverlappingIntervalTree;
@@ -99,7 +99,7 @@ impl PageVec {
             let thisrange = (*k)..(*entry.end());
             // TODO: use larger pages
             for i in 0..entry.nr_pages() {
-                let new_page = Arc::new(Page::new(allocator.try_allocate()?));
+                let new_page = Arc::new(Page::try_new_in(allocator).ok()?);
                 let mut new_page = PageRef::new(new_page, 0, 1);
                 new_page.copy_from(&entry.adjust(i));
                 pv.tree.insert(thisrange.clone(), new_page);
@@ -120,6 +120,33 @@ impl PageVec {
         let _k = self.tree.insert_replace(range.clone(), page.clone());
         page
     }
+
+    /// Remove the pages in `hole` from the vector, returning the removed entries. An entry that
+    /// only partly overlaps the hole keeps the pages outside of it.
+    pub fn punch(&mut self, hole: Range<usize>) -> Vec<PageRef> {
+        let entries: Vec<_> = self
+            .tree
+            .range(hole.clone())
+            .filter(|(_, entry)| *entry.end() > hole.start)
+            .map(|(start, entry)| (*start, *entry.end()))
+            .collect();
+        let mut removed = Vec::new();
+        for (start, end) in entries {
+            let Some(page) = self.tree.remove(&start) else {
+                continue;
+            };
+            if start < hole.start {
+                self.tree
+                    .insert(start..hole.start, page.trimmed(hole.start - start));
+            }
+            if end > hole.end {
+                self.tree
+                    .insert(hole.end..end, page.adjust(hole.end - start));
+            }
+            removed.push(page);
+        }
+        removed
+    }
 }
 
 mod tests {
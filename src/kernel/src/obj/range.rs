@@ -153,6 +153,24 @@ impl PageRange {
         self.pv_ref_count() > 1
     }
 
+    /// Discard `count` pages of backing starting at backing offset `off`, returning the removed
+    /// pages. Must not be called on a shared range.
+    fn discard(&mut self, off: usize, count: usize) -> Vec<PageRef> {
+        assert!(!self.is_shared());
+        let hole = off..(off + count);
+        match &self.backing {
+            BackingPages::Nothing => Vec::new(),
+            BackingPages::Single(page_ref) => {
+                let mut pv = PageVec::new();
+                pv.add_page(0, page_ref.clone());
+                let removed = pv.punch(hole);
+                self.backing = BackingPages::Many(Arc::new(Mutex::new(pv)));
+                removed
+            }
+            BackingPages::Many(pv_ref) => pv_ref.lock().punch(hole),
+        }
+    }
+
     pub fn gc_pagevec(&mut self) {
         if self.is_shared() {
             // TODO: maybe we can do something smarter here, but it may be dangerous. In particular,
@@ -240,6 +258,16 @@ impl Display for PageRange {
     }
 }
 
+/// Pages removed from a tree by [PageRangeTree::punch_hole]. The pages stay allocated until this is
+/// dropped, so that mappings of them can be invalidated first.
+#[derive(Default)]
+pub struct PunchedPages {
+    #[expect(dead_code, reason = "held only to keep the pages alive until dropped")]
+    ranges: Vec<PageRange>,
+    #[expect(dead_code, reason = "held only to keep the pages alive until dropped")]
+    pages: Vec<PageRef>,
+}
+
 #[derive(Default)]
 pub struct PageRangeTree {
     tree: NonOverlappingIntervalTree<PageNumber, PageRange>,
@@ -251,6 +279,9 @@ pub enum PageStatus {
     NoPage,
     AllocFail,
     DataFail,
+    /// The page is wired to device memory, so it cannot be copied to give the caller a private
+    /// version.
+    NoCopy,
     Locked(Arc<RangeSleep>),
 }
 
@@ -259,6 +290,8 @@ bitflags::bitflags! {
     pub struct GetPageFlags : u32 {
         const WRITE = 1;
         const STABLE = 2;
+        /// Return a read-only view of the page, which refuses mutable access.
+        const READ_ONLY = 4;
     }
 }
 
@@ -305,7 +338,7 @@ impl PageRangeTree {
                 Some(match backing {
                     BackingPages::Nothing => BackingPages::Nothing,
                     BackingPages::Single(page_ref) => {
-                        let new_page = Arc::new(Page::new(allocator.try_allocate()?));
+                        let new_page = Arc::new(Page::try_new_in(allocator).ok()?);
                         let mut new_page = PageRef::new(new_page, 0, page_ref.nr_pages());
                         new_page.copy_from(&page_ref);
                         BackingPages::Single(new_page)
@@ -361,9 +394,18 @@ impl PageRangeTree {
             let range = self.get_mut(pn).unwrap();
             return PageStatus::Locked(range.sleeper());
         }
+        if flags.contains(GetPageFlags::READ_ONLY) {
+            return PageStatus::Ready(page.read_only(), shared);
+        }
         if !shared || !flags.contains(GetPageFlags::WRITE) {
             return PageStatus::Ready(page, shared);
         }
+        // A shared wired page is copied into a newly allocated frame below, same as any other
+        // shared page, so the writer never ends up writing to the fixed physical address. Device
+        // memory can't be copied like that, since reading it may have side effects.
+        if page.is_mmio() {
+            return PageStatus::NoCopy;
+        }
         if let Some(allocator) = allocator {
             log::debug!("split into three: {} {:?}", pn, flags);
             if !self.split_into_three(pn, false, allocator) {
@@ -385,6 +427,9 @@ impl PageRangeTree {
             let range = self.get_mut(pn).unwrap();
             return PageStatus::Locked(range.sleeper());
         }
+        if flags.contains(GetPageFlags::READ_ONLY) {
+            return PageStatus::Ready(page.read_only(), shared);
+        }
         PageStatus::Ready(page, shared)
     }
 
@@ -420,6 +465,62 @@ impl PageRangeTree {
         self.tree.range_mut(r)
     }
 
+    /// Remove the pages in `hole` from the tree, so that they are absent. Pages that only this
+    /// tree refers to are removed from their backing, and are released when the returned
+    /// [PunchedPages] is dropped. Ranges that share their backing are split around the hole
+    /// instead, leaving the pages to the other sharers.
+    pub fn punch_hole(&mut self, hole: core::ops::Range<PageNumber>) -> PunchedPages {
+        let mut punched = PunchedPages::default();
+        let starts: Vec<_> = self
+            .tree
+            .range(hole.clone())
+            .filter(|(_, value)| *value.end() > hole.start)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in starts {
+            let Some(mut range) = self.tree.remove(&start) else {
+                continue;
+            };
+            let end = range.start.offset(range.length);
+            let cut_start = range.start.max(hole.start);
+            let cut_end = end.min(hole.end);
+            if cut_start == range.start && cut_end == end {
+                punched.ranges.push(range);
+                continue;
+            }
+            if range.is_shared() {
+                if cut_start > range.start {
+                    let r1 = range.new_from(range.start, range.offset, cut_start - range.start);
+                    let res = self.insert_replace(r1.range(), r1);
+                    assert_eq!(res.len(), 0);
+                }
+                if cut_end < end {
+                    let r3 = range.new_from(
+                        cut_end,
+                        range.offset + (cut_end - range.start),
+                        end - cut_end,
+                    );
+                    let res = self.insert_replace(r3.range(), r3);
+                    assert_eq!(res.len(), 0);
+                }
+                punched.ranges.push(range);
+            } else {
+                let off = range.offset + (cut_start - range.start);
+                punched
+                    .pages
+                    .extend(range.discard(off, cut_end - cut_start));
+                let res = self.insert_replace(range.range(), range);
+                assert_eq!(res.len(), 0);
+            }
+        }
+        punched
+    }
+
+    /// Exchange every page in this tree with those in `other`. Each tree keeps its object ID.
+    pub fn swap_pages(&mut self, other: &mut PageRangeTree) {
+        core::mem::swap(&mut self.tree, &mut other.tree);
+    }
+
     pub fn gc_tree(&mut self) {
         todo!()
     }
@@ -1,8 +1,13 @@
-use alloc::collections::BTreeMap;
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::ops::Range;
 
 use twizzler_abi::syscall::{ThreadSyncFlags, ThreadSyncOp};
 
-use super::Object;
+use super::{Object, ObjectRef, PageNumber};
 use crate::{
     syscall::sync::add_to_requeue,
     thread::{current_thread_ref, ThreadRef},
@@ -24,6 +29,35 @@ impl Drop for SleepEntry {
 
 pub struct SleepInfo {
     words: BTreeMap<usize, SleepEntry>,
+    ranges: BTreeMap<u64, RangeSubscriber>,
+    next_range_id: u64,
+}
+
+/// Something to notify when pages of an object are written. See [Object::subscribe_range].
+pub trait RangeWaker: Send + Sync {
+    /// Called after a write to `pages`, which is the part of the written range that falls within
+    /// the subscribed range. Called without any object locks held.
+    fn wake(&self, pages: Range<PageNumber>);
+}
+
+struct RangeSubscriber {
+    range: Range<PageNumber>,
+    waker: Arc<dyn RangeWaker>,
+}
+
+/// Interest in writes to a range of an object's pages, returned by [Object::subscribe_range].
+/// Dropping this unsubscribes.
+pub struct RangeSubscription {
+    obj: Weak<Object>,
+    id: u64,
+}
+
+impl Drop for RangeSubscription {
+    fn drop(&mut self) {
+        if let Some(obj) = self.obj.upgrade() {
+            obj.sleep_info.lock().ranges.remove(&self.id);
+        }
+    }
 }
 
 impl SleepEntry {
@@ -38,6 +72,8 @@ impl SleepInfo {
     pub fn new() -> Self {
         SleepInfo {
             words: BTreeMap::new(),
+            ranges: BTreeMap::new(),
+            next_range_id: 0,
         }
     }
 
@@ -147,4 +183,51 @@ impl Object {
         let mut sleep_info = self.sleep_info.lock();
         sleep_info.remove(offset, thread.id());
     }
+
+    /// Register `waker` to be called whenever one of the kernel's write helpers (write_bytes,
+    /// write_ranges, cas_write_range, and try_write_val_and_signal) modifies a page in `range`.
+    /// Writes through user mappings are not seen. Any number of subscriptions may cover
+    /// overlapping ranges, and each one whose range overlaps a write is woken once for that write.
+    pub fn subscribe_range(
+        self: &ObjectRef,
+        range: Range<PageNumber>,
+        waker: Arc<dyn RangeWaker>,
+    ) -> RangeSubscription {
+        let mut sleep_info = self.sleep_info.lock();
+        let id = sleep_info.next_range_id;
+        sleep_info.next_range_id += 1;
+        sleep_info
+            .ranges
+            .insert(id, RangeSubscriber { range, waker });
+        RangeSubscription {
+            obj: Arc::downgrade(self),
+            id,
+        }
+    }
+
+    /// Wake the range subscribers interested in the `len` bytes written at `offset`.
+    pub(super) fn notify_written(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let written =
+            PageNumber::from_offset(offset)..PageNumber::from_offset(offset + len - 1).offset(1);
+        let to_wake: Vec<_> = {
+            let sleep_info = self.sleep_info.lock();
+            sleep_info
+                .ranges
+                .values()
+                .filter(|sub| sub.range.start < written.end && written.start < sub.range.end)
+                .map(|sub| {
+                    let start = sub.range.start.max(written.start);
+                    let end = sub.range.end.min(written.end);
+                    (sub.waker.clone(), start..end)
+                })
+                .collect()
+        };
+        // Wake outside the lock, so wakers may subscribe or unsubscribe.
+        for (waker, pages) in to_wake {
+            waker.wake(pages);
+        }
+    }
 }
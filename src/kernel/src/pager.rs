@@ -1,17 +1,22 @@
-use alloc::vec::Vec;
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 
 use inflight::InflightManager;
 use request::ReqKind;
 use twizzler_abi::{
-    object::{ObjID, MAX_SIZE},
+    device::CacheType,
+    object::{ObjID, Protections, MAX_SIZE},
     pager::{PagerFlags, PhysRange},
-    syscall::{ObjectCreate, SyncInfo},
+    syscall::{MapFlags, ObjectCreate, SyncFlags, SyncInfo},
 };
+use twizzler_rt_abi::error::TwzError;
 
 use crate::{
     memory::{
-        context::virtmem::region::{MapRegion, Shadow},
-        frame::PHYS_LEVEL_LAYOUTS,
+        context::{
+            virtmem::region::{MapRegion, Shadow},
+            ObjectContextInfo,
+        },
+        frame::{FrameOwner, PHYS_LEVEL_LAYOUTS},
         tracker::FrameAllocFlags,
     },
     mutex::Mutex,
@@ -25,6 +30,7 @@ mod inflight;
 mod queues;
 mod request;
 
+pub use inflight::Inflight;
 pub use queues::init_pager_queue;
 pub use request::Request;
 
@@ -32,6 +38,9 @@ pub const MAX_PAGER_OUTSTANDING_FRAMES: usize = 65536;
 pub const DEFAULT_PAGER_OUTSTANDING_FRAMES: usize = 1024 * 8;
 
 static INFLIGHT_MGR: Once<Mutex<InflightManager>> = Once::new();
+/// Errors the pager returned for object swaps, keyed by the pair of objects, for
+/// [swap_objects] to pick up once the request completes.
+static SWAP_ERRORS: Mutex<BTreeMap<(ObjID, ObjID), TwzError>> = Mutex::new(BTreeMap::new());
 
 fn inflight_mgr() -> &'static Mutex<InflightManager> {
     INFLIGHT_MGR.call_once(|| Mutex::new(InflightManager::new()))
@@ -111,6 +120,32 @@ pub fn sync_object(id: ObjID) {
     cmd_object(ReqKind::new_sync(id));
 }
 
+/// Tell the pager that a range of an object's pages was discarded, and should read as zeros.
+pub fn free_range(obj: &ObjectRef, range: core::ops::Range<PageNumber>) {
+    cmd_object(ReqKind::new_free_range(
+        obj.id(),
+        range.start.num(),
+        range.end - range.start,
+    ));
+}
+
+/// Tell the pager that the contents of two objects were exchanged (see
+/// [crate::obj::Object::swap_contents]), so that it swaps their backing storage too. Returns the
+/// pager's error if it couldn't, in which case the storage is unchanged.
+pub fn swap_objects(obj: &ObjectRef, other: &ObjectRef) -> Result<(), TwzError> {
+    let key = (obj.id(), other.id());
+    SWAP_ERRORS.lock().remove(&key);
+    cmd_object(ReqKind::new_swap(key.0, key.1));
+    match SWAP_ERRORS.lock().remove(&key) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn record_swap_error(obj: ObjID, other: ObjID, err: TwzError) {
+    SWAP_ERRORS.lock().insert((obj, other), err);
+}
+
 pub fn del_object(id: ObjID) {
     cmd_object(ReqKind::new_del(id));
 }
@@ -145,6 +180,50 @@ pub fn sync_region(
     };
 }
 
+/// Start writing back the given pages of an object. Returns the request to wait on with
+/// [wait_inflight] for the pages to become durable, or None if the pager isn't running yet.
+pub fn start_sync_pages(obj: &ObjectRef, pages: Vec<PageNumber>) -> Option<Inflight> {
+    let info = ObjectContextInfo::new(
+        obj.clone(),
+        Protections::READ,
+        CacheType::WriteBack,
+        MapFlags::empty(),
+    );
+    let shadow = Shadow::new(&info);
+    // The request only carries the SyncInfo along. The pager path never reads or releases its
+    // words: for syncs from a mapping, the caller releases them itself once the request is done
+    // (see MapRegion::ctrl). A kernel-initiated sync has nothing to release, and waits on the
+    // request instead, so null is never dereferenced.
+    let sync_info = SyncInfo {
+        release: core::ptr::null(),
+        release_compare: 0,
+        release_set: 0,
+        durable: core::ptr::null(),
+        flags: SyncFlags::DURABLE,
+    };
+    let req = ReqKind::new_sync_region(obj.id(), shadow, pages, sync_info, 0);
+    let mut mgr = inflight_mgr().lock();
+    if !mgr.is_ready() {
+        return None;
+    }
+    let inflight = mgr.add_request(req);
+    drop(mgr);
+    inflight.for_each_pager_req(|pager_req| {
+        queues::submit_pager_request(pager_req);
+    });
+    Some(inflight)
+}
+
+/// Wait for a request to the pager to complete.
+pub fn wait_inflight(inflight: &Inflight) {
+    let mut mgr = inflight_mgr().lock();
+    let thread = current_thread_ref().unwrap();
+    if let Some(guard) = mgr.setup_wait(inflight, &thread) {
+        drop(mgr);
+        finish_blocking(guard);
+    };
+}
+
 pub fn ensure_in_core(obj: &ObjectRef, start: PageNumber, len: usize, flags: PagerFlags) -> bool {
     if !obj.use_pager() {
         return false;
@@ -239,6 +318,7 @@ fn get_memory_for_pager(min_frames: usize) -> Vec<PhysRange> {
             FrameAllocFlags::ZEROED,
             PHYS_LEVEL_LAYOUTS[level],
         ) {
+            frame.set_owner(FrameOwner::Pager);
             let thiscount = PHYS_LEVEL_LAYOUTS[level].size() / PHYS_LEVEL_LAYOUTS[0].size();
             count += thiscount;
             crate::memory::tracker::track_page_pager(thiscount);
@@ -251,6 +331,7 @@ fn get_memory_for_pager(min_frames: usize) -> Vec<PhysRange> {
                 FrameAllocFlags::ZEROED,
                 PHYS_LEVEL_LAYOUTS[0],
             ) {
+                frame.set_owner(FrameOwner::Pager);
                 count += 1;
                 crate::memory::tracker::track_page_pager(1);
                 ranges.push(PhysRange::new(
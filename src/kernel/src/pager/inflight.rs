@@ -47,6 +47,11 @@ impl Inflight {
                 flags: ObjectEvictFlags::SYNC | ObjectEvictFlags::FENCE,
             }),
             ReqKind::Del(obj_id) => KernelCommand::ObjectDel(*obj_id),
+            ReqKind::FreeRange(obj_id, s, l) => KernelCommand::ObjectRangeFree(
+                *obj_id,
+                ObjectRange::new((s * NULLPAGE_SIZE) as u64, ((s + l) * NULLPAGE_SIZE) as u64),
+            ),
+            ReqKind::Swap(obj_id, other) => KernelCommand::ObjectSwap(*obj_id, *other),
             ReqKind::Create(obj_id, create, nonce) => KernelCommand::ObjectCreate(
                 *obj_id,
                 ObjectInfo::new(
@@ -305,6 +305,22 @@ pub(super) fn pager_compl_handler_main() {
                     inflight_mgr().lock().cmd_ready(info.obj_id, true);
                 }
             }
+            KernelCommand::ObjectRangeFree(obj_id, _) => {
+                if matches!(
+                    completion.1.data(),
+                    twizzler_abi::pager::KernelCompletionData::Okay
+                ) {
+                    inflight_mgr().lock().cmd_ready(obj_id, true);
+                }
+            }
+            // The swap is waited on whether or not the pager managed it. An error is handed to the
+            // waiter, so that it leaves the objects' contents alone too.
+            KernelCommand::ObjectSwap(obj_id, other_id) => {
+                if let twizzler_abi::pager::KernelCompletionData::Error(err) = completion.1.data() {
+                    super::record_swap_error(obj_id, other_id, err.error());
+                }
+                inflight_mgr().lock().cmd_ready(obj_id, true);
+            }
             _ => {}
         }
 
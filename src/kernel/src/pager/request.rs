@@ -53,6 +53,8 @@ pub enum ReqKind {
     Sync(ObjID),
     SyncRegion(SyncRegionInfo),
     Del(ObjID),
+    FreeRange(ObjID, usize, usize),
+    Swap(ObjID, ObjID),
     Create(ObjID, ObjectCreate, u128),
     Pages(PhysRange),
 }
@@ -175,6 +177,14 @@ impl ReqKind {
         ReqKind::Del(obj_id)
     }
 
+    pub fn new_free_range(obj_id: ObjID, start: usize, len: usize) -> Self {
+        ReqKind::FreeRange(obj_id, start, len)
+    }
+
+    pub fn new_swap(obj_id: ObjID, other: ObjID) -> Self {
+        ReqKind::Swap(obj_id, other)
+    }
+
     pub fn new_create(obj_id: ObjID, create: &ObjectCreate, nonce: u128) -> Self {
         ReqKind::Create(obj_id, *create, nonce)
     }
@@ -199,6 +209,8 @@ impl ReqKind {
     pub fn needs_sync(&self) -> bool {
         matches!(self, ReqKind::Sync(_))
             || matches!(self, ReqKind::Del(_))
+            || matches!(self, ReqKind::FreeRange(_, _, _))
+            || matches!(self, ReqKind::Swap(_, _))
             || matches!(self, ReqKind::SyncRegion(_))
     }
 
@@ -213,6 +225,8 @@ impl ReqKind {
             ReqKind::Sync(obj_id) => *obj_id,
             ReqKind::SyncRegion(info) => info.id,
             ReqKind::Del(obj_id) => *obj_id,
+            ReqKind::FreeRange(obj_id, _, _) => *obj_id,
+            ReqKind::Swap(obj_id, _) => *obj_id,
             ReqKind::Create(obj_id, _, _) => *obj_id,
             ReqKind::Pages(_) => return None,
         })
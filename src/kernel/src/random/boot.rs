@@ -0,0 +1,95 @@
+//! Seeding the CSPRNG at boot, before the entropy sources have had a chance to fill the pools.
+//!
+//! Everything available this early is hashed together with SHA-256: the seed handed over by the
+//! bootloader (if any), the CPU's hardware RNG (if present), and jitter between back-to-back timer
+//! reads. Each source is credited with a conservative estimate of the entropy it provides, and the
+//! hash is only used as a seed once the credits add up to [MIN_SEED_BITS]. No one source is relied
+//! on, since the hardware RNG may be missing, or, as with RNDRS on ARM, fail often.
+
+use alloc::vec::Vec;
+
+use sha2::{Digest, Sha256};
+
+use super::{cpu_trng::CpuEntropy, jitter::get_nstime, EntropySource};
+use crate::{time::TICK_SOURCES, BootInfo};
+
+/// The entropy, in bits, that must be gathered before the generator is seeded.
+pub const MIN_SEED_BITS: usize = 256;
+
+// Hardware RNG output is credited at half its size, since there's no way to check how good it is.
+const CPU_BITS_PER_BYTE: usize = 4;
+const CPU_SEED_BYTES: usize = 64;
+
+// Timer jitter is credited with one bit for every few reads whose spacing changed, up to a small
+// cap: the spacing depends on the machine more than on chance, so it can't make a seed on its own.
+const JITTER_SAMPLES: usize = 4096;
+const JITTER_CHANGES_PER_BIT: usize = 8;
+const JITTER_MAX_BITS: usize = 64;
+
+/// Collects boot entropy into a single seed.
+#[derive(Default)]
+pub struct BootSeed {
+    hasher: Sha256,
+    credited_bits: usize,
+}
+
+impl BootSeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mix in `bytes`, crediting them with `bits` of entropy (at most 8 per byte).
+    pub fn add(&mut self, bytes: &[u8], bits: usize) {
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+        self.credited_bits += bits.min(bytes.len() * 8);
+    }
+
+    pub fn credited_bits(&self) -> usize {
+        self.credited_bits
+    }
+
+    /// Returns the seed, or None if not enough entropy was credited to make one.
+    pub fn finish(self) -> Option<[u8; 32]> {
+        (self.credited_bits >= MIN_SEED_BITS).then(|| self.hasher.finalize().into())
+    }
+}
+
+/// Gather boot entropy from every source available. The bootloader's seed is trusted in full.
+pub fn gather(boot_info: &dyn BootInfo) -> BootSeed {
+    let mut seed = BootSeed::new();
+    let loader_seed = boot_info.entropy_seed();
+    seed.add(loader_seed, loader_seed.len() * 8);
+    add_cpu_entropy(&mut seed);
+    add_timer_jitter(&mut seed);
+    seed
+}
+
+fn add_cpu_entropy(seed: &mut BootSeed) {
+    let Ok(mut cpu) = CpuEntropy::try_new() else {
+        return;
+    };
+    let mut buf = [0u8; CPU_SEED_BYTES];
+    let filled = cpu.try_fill_entropy_nonblocking(&mut buf);
+    seed.add(&buf[0..filled], filled * CPU_BITS_PER_BYTE);
+}
+
+fn add_timer_jitter(seed: &mut BootSeed) {
+    if TICK_SOURCES.lock().is_empty() {
+        return;
+    }
+    let mut deltas = Vec::with_capacity(JITTER_SAMPLES);
+    let mut last = get_nstime();
+    for _ in 0..JITTER_SAMPLES {
+        let now = get_nstime();
+        deltas.push(now.wrapping_sub(last));
+        last = now;
+    }
+    // A coarse timer gives the same spacing every time, which is worth nothing.
+    let changes = deltas.windows(2).filter(|w| w[0] != w[1]).count();
+    let bytes: Vec<u8> = deltas.iter().flat_map(|d| d.to_le_bytes()).collect();
+    seed.add(
+        &bytes,
+        (changes / JITTER_CHANGES_PER_BIT).min(JITTER_MAX_BITS),
+    );
+}
@@ -37,6 +37,41 @@ impl EntropySource for CpuEntropy {
     fn try_fill_entropy(&mut self, dest: &mut [u8]) -> Result<(), ()> {
         Ok(self.cpu.try_fill_bytes(dest).map_err(|_| ())?)
     }
+
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+    fn try_fill_entropy_nonblocking(&mut self, dest: &mut [u8]) -> usize {
+        self.cpu.try_fill_entropy_nonblocking(dest)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn try_fill_entropy_nonblocking(&mut self, dest: &mut [u8]) -> usize {
+        fill_from_words(dest, || {
+            let mut word = 0;
+            // Safety: try_new only succeeds if the CPU supports RDSEED.
+            (unsafe { rdseed_once(&mut word) }).then_some(word)
+        })
+    }
+}
+
+/// Execute RDSEED once, without the retries the rdrand crate does.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed_once(word: &mut u64) -> bool {
+    core::arch::x86_64::_rdseed64_step(word) == 1
+}
+
+/// Fill dest 8 bytes at a time from next, stopping as soon as next has nothing. Returns the number
+/// of bytes filled.
+pub(super) fn fill_from_words(dest: &mut [u8], mut next: impl FnMut() -> Option<u64>) -> usize {
+    let mut filled = 0;
+    for chunk in dest.chunks_mut(8) {
+        let Some(word) = next() else {
+            break;
+        };
+        chunk.copy_from_slice(&word.to_ne_bytes()[0..chunk.len()]);
+        filled += chunk.len();
+    }
+    filled
 }
 
 pub fn maybe_add_cpu_entropy_source() -> bool {
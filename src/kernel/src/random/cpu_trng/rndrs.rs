@@ -86,4 +86,7 @@ impl EntropySource for Rndrs {
         }
         Ok(())
     }
+    fn try_fill_entropy_nonblocking(&mut self, dest: &mut [u8]) -> usize {
+        super::fill_from_words(dest, || self.0.rndrss())
+    }
 }
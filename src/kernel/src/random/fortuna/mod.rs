@@ -52,6 +52,7 @@ pub struct Accumulator {
     reseed_ct: usize,
     pools: [Pool; POOL_COUNT],
     last_reseed_timestamp: Instant,
+    entropy_bits: usize,
 }
 
 impl Accumulator {
@@ -68,6 +69,7 @@ impl Accumulator {
                 .try_into()
                 .expect("Vec should have the correct number of elements"),
             last_reseed_timestamp: Instant::zero(),
+            entropy_bits: 0,
         }
     }
 
@@ -75,6 +77,27 @@ impl Accumulator {
         self.reseed_ct != 0
     }
 
+    /// Seed the generator directly, without waiting for the pools to fill. The seed must already
+    /// carry enough entropy, estimated at `bits`; this is used at boot, before any events have
+    /// been added.
+    pub fn seed(&mut self, seed: &[u8; 32], bits: usize) {
+        self.reseed_ct += 1;
+        self.generator.reseed(seed);
+        self.credit_entropy(bits);
+    }
+
+    /// Record that `bits` of entropy went into the seed or the pools. Events carry no estimate of
+    /// their own, so whoever adds them credits them here.
+    pub fn credit_entropy(&mut self, bits: usize) {
+        self.entropy_bits = self.entropy_bits.saturating_add(bits);
+    }
+
+    /// The entropy, in bits, credited so far. This only ever grows: reseeding moves entropy from
+    /// the pools into the generator, but doesn't lose it.
+    pub fn entropy_bits(&self) -> usize {
+        self.entropy_bits
+    }
+
     // 9.5.5
     pub fn try_fill_random_data(&mut self, out: &mut [u8]) -> Result<(), self::error::Error> {
         let now = Instant::now();
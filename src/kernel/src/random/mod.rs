@@ -1,3 +1,4 @@
+mod boot;
 pub mod cpu_trng;
 mod fortuna;
 mod jitter;
@@ -8,20 +9,46 @@ use core::{borrow::BorrowMut, time::Duration};
 use cpu_trng::maybe_add_cpu_entropy_source;
 use fortuna::{Accumulator, Contributor};
 use jitter::maybe_add_jitter_entropy_source;
+use twizzler_rt_abi::error::{ResourceError, TwzError};
+use twizzler_security::{SigningKey, SigningScheme, VerifyingKey};
 
 use crate::{
     mutex::{LockGuard, Mutex},
     once::Once,
     thread::{entry::run_closure_in_new_thread, priority::Priority},
+    BootInfo,
 };
 
 const POLL_AMOUNT: usize = 64;
 
+/// The entropy, in bits, that must be gathered before [entropy_ready] reports the generator ready
+/// for making keys. The same as is needed to seed it at boot.
+pub const ENTROPY_READY_BITS: usize = boot::MIN_SEED_BITS;
+
+// Sources that don't say otherwise are credited at half their output size, as hardware RNGs are
+// at boot.
+const DEFAULT_SOURCE_BITS_PER_BYTE: usize = 4;
+
 pub trait EntropySource {
     fn try_new() -> Result<Self, ()>
     where
         Self: Sized;
     fn try_fill_entropy(&mut self, dest: &mut [u8]) -> Result<(), ()>;
+    /// Fill as much of dest as possible with entropy that is available right now, without
+    /// retrying or waiting. Returns the number of bytes filled, starting from the front of dest.
+    /// Sources that can't produce entropy immediately fill nothing.
+    fn try_fill_entropy_nonblocking(&mut self, _dest: &mut [u8]) -> usize {
+        0
+    }
+    /// How many bits of entropy each byte of this source's output is worth, at most 8. This is
+    /// what [entropy_estimate_bits] adds up, so sources should err on the low side.
+    fn entropy_bits_per_byte(&self) -> usize {
+        DEFAULT_SOURCE_BITS_PER_BYTE
+    }
+}
+
+fn credited_bits(source: &dyn EntropySource, bytes: usize) -> usize {
+    bytes * source.entropy_bits_per_byte().min(8)
 }
 
 struct EntropySources {
@@ -63,10 +90,33 @@ impl EntropySources {
                     accumulator
                         .add_random_event(&mut source.1, &buf)
                         .expect("event should be properly sized");
+                    accumulator.credit_entropy(credited_bits(&*source.0, buf.len()));
                 }
             }
         }
     }
+
+    /// Like contribute_entropy, but only mixes in entropy the sources have available immediately.
+    /// Returns the number of bytes contributed.
+    pub fn contribute_available_entropy(&mut self, accumulator: &mut Accumulator) -> usize {
+        let mut buf: [u8; 32] = [0u8; 32];
+        let mut total = 0;
+
+        for source in &mut self.sources {
+            for _ in 0..fortuna::POOL_COUNT * 2 {
+                let filled = source.0.try_fill_entropy_nonblocking(&mut buf);
+                if filled == 0 {
+                    break;
+                }
+                accumulator
+                    .add_random_event(&mut source.1, &buf[0..filled])
+                    .expect("event should be properly sized");
+                accumulator.credit_entropy(credited_bits(&*source.0, filled));
+                total += filled;
+            }
+        }
+        total
+    }
 }
 
 static ACCUMULATOR: Once<Mutex<Accumulator>> = Once::new();
@@ -92,6 +142,14 @@ pub fn getrandom(out: &mut [u8], nonblocking: bool) -> bool {
         .call_once(|| Mutex::new(EntropySources::new()))
         .lock();
     if entropy_sources.has_sources() {
+        // Latency-sensitive callers first try to get seeded from whatever entropy is available
+        // right away, before falling back to the (slow) full contribution.
+        if nonblocking
+            && entropy_sources.contribute_available_entropy(acc.borrow_mut()) > 0
+            && acc.try_fill_random_data(out).is_ok()
+        {
+            return true;
+        }
         entropy_sources.contribute_entropy(acc.borrow_mut());
         let _ = acc.try_fill_random_data(out).inspect_err(|_| {
             logln!("warning -- should be seeded now & therefore shouldn't return an error")
@@ -117,6 +175,70 @@ pub fn getrandom(out: &mut [u8], nonblocking: bool) -> bool {
     }
 }
 
+/// Seed the generator from the entropy available at boot (see [boot]). If there isn't enough, the
+/// generator stays unseeded until the entropy sources have filled the pools.
+pub fn seed_at_boot(boot_info: &dyn BootInfo) {
+    let seed = boot::gather(boot_info);
+    let credited = seed.credited_bits();
+    match seed.finish() {
+        Some(seed) => ACCUMULATOR
+            .call_once(|| Mutex::new(Accumulator::new()))
+            .lock()
+            .seed(&seed, credited),
+        None => logln!(
+            "[kernel::random] warning -- only {} bits of entropy at boot, need {}",
+            credited,
+            boot::MIN_SEED_BITS
+        ),
+    }
+}
+
+/// An estimate of the entropy, in bits, gathered so far from the boot seed and the registered
+/// entropy sources. Only counts what has actually been mixed in, at the rate each source is
+/// credited with (see [EntropySource::entropy_bits_per_byte]).
+pub fn entropy_estimate_bits() -> usize {
+    ACCUMULATOR
+        .call_once(|| Mutex::new(Accumulator::new()))
+        .lock()
+        .entropy_bits()
+}
+
+/// Whether the generator is seeded and at least [ENTROPY_READY_BITS] of entropy have been gathered,
+/// so that what it produces is fit for making keys. Never waits, and never gathers entropy itself.
+pub fn entropy_ready() -> bool {
+    is_ready(
+        &ACCUMULATOR
+            .call_once(|| Mutex::new(Accumulator::new()))
+            .lock(),
+    )
+}
+
+fn is_ready(acc: &Accumulator) -> bool {
+    acc.is_seeded() && acc.entropy_bits() >= ENTROPY_READY_BITS
+}
+
+/// Generate a kernel signing keypair. Never waits for entropy: until [entropy_ready] holds, this
+/// fails with [ResourceError::Unavailable] rather than make a key that could be guessed.
+pub fn new_kernel_keypair(scheme: &SigningScheme) -> Result<(SigningKey, VerifyingKey), TwzError> {
+    let mut acc = ACCUMULATOR
+        .call_once(|| Mutex::new(Accumulator::new()))
+        .lock();
+    keypair_from(&mut acc, scheme)
+}
+
+fn keypair_from(
+    acc: &mut Accumulator,
+    scheme: &SigningScheme,
+) -> Result<(SigningKey, VerifyingKey), TwzError> {
+    if !is_ready(acc) {
+        return Err(ResourceError::Unavailable.into());
+    }
+    let mut bytes = [0u8; 32];
+    acc.try_fill_random_data(&mut bytes)
+        .map_err(|_| ResourceError::Unavailable)?;
+    SigningKey::new_kernel_keypair(scheme, bytes)
+}
+
 /// Be sure to contribute at least one byte and at most 32 bytes.
 pub fn contribute_entropy(
     contributor: &mut Contributor,
@@ -182,4 +304,137 @@ mod test {
         let mut into = [0u8; 1024];
         assert_eq!(getrandom(&mut into, false), true);
     }
+
+    const LIMITED_BYTES: usize = 40;
+
+    // A source that only ever has LIMITED_BYTES of entropy to give.
+    struct Limited {
+        remaining: usize,
+    }
+
+    impl EntropySource for Limited {
+        fn try_new() -> Result<Self, ()> {
+            Ok(Self {
+                remaining: LIMITED_BYTES,
+            })
+        }
+
+        fn try_fill_entropy(&mut self, _dest: &mut [u8]) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn try_fill_entropy_nonblocking(&mut self, dest: &mut [u8]) -> usize {
+            let n = dest.len().min(self.remaining);
+            dest[0..n].fill(0x5a);
+            self.remaining -= n;
+            n
+        }
+    }
+
+    #[kernel_test]
+    fn test_keygen_before_seeding() {
+        let unavailable =
+            |r: Result<_, TwzError>| r.err() == Some(ResourceError::Unavailable.into());
+        let mut acc = Accumulator::new();
+        assert!(unavailable(keypair_from(&mut acc, &SigningScheme::Ecdsa)));
+
+        // Too little entropy: no seed is made, and keys still can't be generated.
+        let mut seed = boot::BootSeed::new();
+        seed.add(&[0x42; 64], 128);
+        assert_eq!(seed.credited_bits(), 128);
+        assert!(seed.finish().is_none());
+        assert!(unavailable(keypair_from(&mut acc, &SigningScheme::Ecdsa)));
+
+        let mut seed = boot::BootSeed::new();
+        seed.add(&[0x42; 16], 128);
+        seed.add(&[0x17; 32], 1000);
+        assert_eq!(seed.credited_bits(), 128 + 256);
+        let credited = seed.credited_bits();
+        acc.seed(&seed.finish().unwrap(), credited);
+        assert!(keypair_from(&mut acc, &SigningScheme::Ecdsa).is_ok());
+    }
+
+    // Entropy bytes left for Metered sources to hand out, across all of them.
+    static METERED_BUDGET: core::sync::atomic::AtomicUsize =
+        core::sync::atomic::AtomicUsize::new(0);
+    const METERED_BITS_PER_BYTE: usize = 2;
+
+    // A source that has exactly as much entropy as the test hands it.
+    struct Metered;
+
+    impl EntropySource for Metered {
+        fn try_new() -> Result<Self, ()> {
+            Ok(Self)
+        }
+
+        fn try_fill_entropy(&mut self, _dest: &mut [u8]) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn try_fill_entropy_nonblocking(&mut self, dest: &mut [u8]) -> usize {
+            use core::sync::atomic::Ordering;
+            let available = METERED_BUDGET.load(Ordering::SeqCst);
+            let n = dest.len().min(available);
+            METERED_BUDGET.fetch_sub(n, Ordering::SeqCst);
+            dest[0..n].fill(0xc3);
+            n
+        }
+
+        fn entropy_bits_per_byte(&self) -> usize {
+            METERED_BITS_PER_BYTE
+        }
+    }
+
+    #[kernel_test]
+    fn test_entropy_readiness() {
+        use core::sync::atomic::Ordering;
+
+        let mut sources = EntropySources::new();
+        sources.try_register_source::<Metered>().unwrap();
+        let mut acc = Accumulator::new();
+        let mut out = [0u8; 32];
+        assert_eq!(acc.entropy_bits(), 0);
+        assert!(!is_ready(&acc));
+
+        // Not enough entropy yet, even though some has been gathered.
+        let short = ENTROPY_READY_BITS / METERED_BITS_PER_BYTE / 2;
+        METERED_BUDGET.store(short, Ordering::SeqCst);
+        assert_eq!(sources.contribute_available_entropy(&mut acc), short);
+        assert_eq!(acc.entropy_bits(), short * METERED_BITS_PER_BYTE);
+        assert!(!is_ready(&acc));
+        assert!(keypair_from(&mut acc, &SigningScheme::Ecdsa).is_err());
+
+        // Enough to fill the first pool (one event in every POOL_COUNT goes there) and then some,
+        // so the generator reseeds from the pools the next time it is asked for data.
+        let events = fortuna::POOL_COUNT * 2;
+        METERED_BUDGET.store(events * 32, Ordering::SeqCst);
+        assert_eq!(sources.contribute_available_entropy(&mut acc), events * 32);
+        assert!(acc.entropy_bits() >= ENTROPY_READY_BITS);
+        // Reseeds are rate-limited, so it may take a couple of tries.
+        for _ in 0..3 {
+            if acc.try_fill_random_data(&mut out).is_ok() {
+                break;
+            }
+        }
+        assert!(is_ready(&acc));
+        assert!(keypair_from(&mut acc, &SigningScheme::Ecdsa).is_ok());
+    }
+
+    #[kernel_test]
+    fn test_nonblocking_fill() {
+        let mut source = Limited::try_new().unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(source.try_fill_entropy_nonblocking(&mut buf), 32);
+        buf.fill(0);
+        assert_eq!(source.try_fill_entropy_nonblocking(&mut buf), 8);
+        assert_eq!(&buf[0..8], &[0x5a; 8]);
+        assert_eq!(&buf[8..], &[0; 24]);
+        assert_eq!(source.try_fill_entropy_nonblocking(&mut buf), 0);
+
+        let mut sources = EntropySources::new();
+        sources.try_register_source::<Limited>().unwrap();
+        let mut acc = Accumulator::new();
+        assert_eq!(sources.contribute_available_entropy(&mut acc), LIMITED_BYTES);
+        assert_eq!(sources.contribute_available_entropy(&mut acc), 0);
+    }
 }
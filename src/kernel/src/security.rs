@@ -1,13 +1,18 @@
-use alloc::{collections::BTreeMap, sync::Arc};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::mem::size_of;
 
 use twizzler_abi::{
     device::CacheType,
-    object::{ObjID, Protections},
+    meta::{MetaExt, MetaInfo},
+    object::{ObjID, Protections, MAX_SIZE, NULLPAGE_SIZE},
     syscall::MapFlags,
 };
-use twizzler_rt_abi::error::{NamingError, ObjectError};
+use twizzler_rt_abi::error::{GenericError, IoError, NamingError, ObjectError, TwzError};
 pub use twizzler_security::PermsInfo;
-use twizzler_security::{Cap, CtxMapItemType, SecCtxBase, VerifyingKey};
+use twizzler_security::{
+    label_permits, Cap, CapId, CtxMapItemType, CtxQuorum, ObjectSeal, RevocationList, SealHasher,
+    SecCtxBase, SecLabel, VerifyingKey, MEXT_CTX_QUORUM, MEXT_INTEGRITY_SEAL, MEXT_SEC_LABEL,
+};
 
 use crate::{
     memory::context::{
@@ -15,10 +20,11 @@ use crate::{
         UserContext,
     },
     mutex::Mutex,
-    obj::{lookup_object, LookupFlags, LookupResult},
+    obj::{lookup_object, LookupFlags, LookupResult, ObjectRef, PageNumber},
     once::Once,
     spinlock::Spinlock,
     thread::current_memory_context,
+    time::TICK_SOURCES,
 };
 
 #[derive(Clone)]
@@ -38,7 +44,76 @@ pub struct SecCtxMgr {
 /// A single security context.
 pub struct SecurityContext {
     kobj: Option<KernelObject<SecCtxBase>>,
-    cache: Mutex<BTreeMap<ObjID, PermsInfo>>,
+    cache: Mutex<PermsCache>,
+}
+
+/// Cached lookup results, valid for one epoch of the global revocation list.
+#[derive(Default)]
+struct PermsCache {
+    epoch: u64,
+    entries: BTreeMap<ObjID, PermsInfo>,
+}
+
+/// Capabilities revoked for every security context. See [revoke_global].
+static REVOCATION_LIST: Mutex<RevocationList> = Mutex::new(RevocationList::new());
+
+/// Revoke the capability with ID `id` in every security context, e.g. because the key that
+/// signed it was compromised. Contexts drop their cached permissions on their next lookup. The
+/// list is bounded (see [twizzler_security::MAX_REVOKED_CAPS]), and revoking fails with
+/// OutOfResources once it's full.
+pub fn revoke_global(id: CapId) -> twizzler_rt_abi::Result<()> {
+    REVOCATION_LIST.lock().revoke(id)
+}
+
+/// The current epoch of the global revocation list. Any cached verification results from an
+/// earlier epoch are stale.
+pub fn revocation_epoch() -> u64 {
+    REVOCATION_LIST.lock().epoch()
+}
+
+/// The current time on the clock capability expirations are given in (the real-time clock), or
+/// zero if there isn't one yet.
+fn revocation_now() -> u128 {
+    TICK_SOURCES
+        .lock()
+        .get(1)
+        .map(|clock| clock.read().as_nanos())
+        .unwrap_or(0)
+}
+
+/// The protections a capability grants, if it hasn't been revoked globally or expired, and its
+/// signature checks out. This doesn't take a use of a limited capability; see
+/// [SecurityContext::check_map].
+fn cap_protections(cap: &Cap, v_key: &VerifyingKey, now: u128) -> Protections {
+    if REVOCATION_LIST.lock().is_revoked(&cap.id()) || cap.revocation.is_expired(now) {
+        return Protections::empty();
+    }
+    if cap.verify_sig(v_key).is_err() {
+        return Protections::empty();
+    }
+    cap.protections
+}
+
+/// The result of looking up an object in a context, with the capabilities limited to a number of
+/// uses (see [Cap::new_limited]) kept apart, so that a use is only taken when one of them is what
+/// grants an access.
+struct Grants {
+    /// The permissions granted by unlimited capabilities.
+    perms: PermsInfo,
+    /// Limited capabilities, with the protections each would grant (already masked).
+    limited: Vec<(Cap, Protections)>,
+}
+
+impl Grants {
+    /// The permissions granted by all the capabilities, limited ones included, without taking any
+    /// of their uses.
+    fn all(&self) -> PermsInfo {
+        let mut perms = self.perms;
+        for (_, prots) in &self.limited {
+            perms.provide |= *prots;
+        }
+        perms
+    }
 }
 
 impl core::fmt::Debug for SecurityContext {
@@ -66,21 +141,62 @@ pub struct AccessInfo {
     pub exec_id: Option<ObjID>,
     /// Offset into the exec object for the instruction pointer
     pub exec_off: usize,
+    /// Offset into the target object of the page being accessed, or None if the access is to the
+    /// object as a whole (e.g. mapping it)
+    pub target_off: Option<usize>,
 }
 
 impl SecurityContext {
     /// Lookup the permission info for an object, and maybe cache it.
     pub fn lookup(&self, _id: ObjID) -> PermsInfo {
-        if let Some(cache_entry) = self.cache.lock().get(&_id) {
-            return *cache_entry;
+        self.lookup_at(_id, None)
+    }
+
+    /// Lookup the permission info for the page at byte offset `page_off` of an object, or for the
+    /// object as a whole if None. Capabilities scoped to part of the object (see
+    /// [Cap::new_scoped]) only count toward pages that lie entirely inside their scope, and not
+    /// toward the whole object. They do count when mapping it (see [SecurityContext::check_map]),
+    /// since each fault on the mapping is checked against their scope.
+    ///
+    /// Capabilities for an object labeled with a [SecLabel] only count if this context's label
+    /// dominates the object's.
+    ///
+    /// Limited capabilities count here without taking a use, which is only done when mapping (see
+    /// [SecurityContext::check_map]). The mapping's protections bound what faults on it can do.
+    pub fn lookup_at(&self, _id: ObjID, page_off: Option<usize>) -> PermsInfo {
+        let epoch = revocation_epoch();
+        {
+            let mut cache = self.cache.lock();
+            if cache.epoch != epoch {
+                // A capability may have been revoked since we cached these.
+                cache.entries.clear();
+                cache.epoch = epoch;
+            }
+            if let Some(cache_entry) = cache.entries.get(&_id) {
+                return *cache_entry;
+            }
+        }
+
+        let (grants, cacheable) = self.grants(_id, page_off, false);
+        let perms = grants.all();
+        if cacheable {
+            self.cache_insert(epoch, _id, perms);
         }
+        perms
+    }
 
-        let mut granted_perms =
-            PermsInfo::new(self.id(), Protections::empty(), Protections::empty());
+    /// Look up the permissions each capability in this context grants for the page at `page_off`
+    /// of an object (or the whole object), after masking. Scoped capabilities only count toward
+    /// the whole object for a map. Also returns whether the result may be cached.
+    fn grants(&self, _id: ObjID, page_off: Option<usize>, for_map: bool) -> (Grants, bool) {
+        let mut grants = Grants {
+            perms: PermsInfo::new(self.id(), Protections::empty(), Protections::empty()),
+            limited: Vec::new(),
+        };
 
         let Some(ref obj) = self.kobj.clone() else {
             // if there is no object underneath the kobj, return nothing;
-            return granted_perms;
+            return (grants, true);
         };
 
         let base = obj.base();
@@ -88,77 +204,148 @@ impl SecurityContext {
         // check for possible items
         let Some(results) = base.map.get(&_id) else {
             // if no entries for the target, return already granted perms
-            return granted_perms;
+            return (grants, true);
         };
 
-        let v_obj = {
+        let (v_obj, target_label) = {
             let target_obj = match lookup_object(_id, LookupFlags::empty()) {
                 LookupResult::Found(obj) => obj,
-                _ => return PermsInfo::new(self.id(), Protections::empty(), Protections::empty()),
+                _ => return (grants, true),
             };
 
             let Some(meta) = target_obj.read_meta(true) else {
                 // failed to read meta, no perms granted
-                return PermsInfo::new(self.id(), Protections::empty(), Protections::empty());
+                return (grants, true);
             };
-            match lookup_object(meta.kuid, LookupFlags::empty()) {
-                LookupResult::Found(v_obj) => {
-                    let k_ctx = kernel_context();
-
-                    let handle =
-                        k_ctx.insert_kernel_object::<VerifyingKey>(ObjectContextInfo::new(
-                            v_obj,
-                            Protections::READ,
-                            CacheType::WriteBack,
-                            MapFlags::STABLE,
-                        ));
-                    handle
-                }
+            let Some(v_obj) = verifying_key(meta.kuid) else {
                 // verifying key wasnt found, return no perms
-                _ => return PermsInfo::new(self.id(), Protections::empty(), Protections::empty()),
-            }
+                return (grants, true);
+            };
+            (v_obj, object_label(&target_obj, &meta))
         };
 
+        // Capabilities for a labeled target only count in contexts whose label dominates the
+        // target's. Labels can change without moving the revocation epoch, so these lookups
+        // aren't cached.
+        if target_label.is_some() && !label_permits(self.label(), target_label) {
+            return (grants, false);
+        }
+
         let v_key = v_obj.base();
+        // final permissions will be ,
+        // granted_perms & permmask & (global_mask | override_mask),
+        // or granted_perms & global_mask if there's no mask for the target object
+        let mask = match base.masks.get(&_id) {
+            Some(mask) => mask.permmask & (base.global_mask | mask.ovrmask),
+            None => base.global_mask,
+        };
+        let now = revocation_now();
+        let mut cacheable = target_label.is_none();
 
         for entry in results {
             match entry.item_type {
-                CtxMapItemType::Del => {
-                    todo!("Delegations not supported yet for lookup")
-                }
+                // Delegations aren't supported yet, so they grant nothing. This runs on every
+                // page fault, so it must not panic.
+                CtxMapItemType::Del => continue,
 
                 CtxMapItemType::Cap => {
                     //NOTE: is this going to return the same as Object.lea?
                     let Some(cap) = obj.lea_raw(entry.offset as *const Cap) else {
                         // something weird going on, entry offset not inside object bounds,
                         // return already granted perms to avoid panic
-                        return granted_perms;
+                        return (grants, false);
                     };
 
-                    if cap.verify_sig(v_key).is_ok() {
-                        granted_perms.provide = granted_perms.provide | cap.protections;
-                    };
+                    // Which pages a scoped cap grants access to isn't part of the cache key, and
+                    // limited caps may be used up or revoked without moving the epoch.
+                    cacheable &= cap.max_uses().is_none() && cap.scope().is_none();
+                    match page_off {
+                        Some(off) if !cap.covers(off as u64, PageNumber::PAGE_SIZE as u64) => {
+                            continue
+                        }
+                        None if !for_map && cap.scope().is_some() => continue,
+                        _ => {}
+                    }
+                    let prots = cap_protections(cap, v_key, now) & mask;
+                    if cap.max_uses().is_some() {
+                        if !prots.is_empty() {
+                            grants.limited.push((*cap, prots));
+                        }
+                    } else {
+                        grants.perms.provide |= prots;
+                    }
                 }
             }
         }
 
-        // lookup mask for obj in base
-        let Some(mask) = base.masks.get(&_id) else {
-            // no mask for target object
-            // final perms are granted_perms & global_mask
-            granted_perms.provide &= base.global_mask;
-            self.cache.lock().insert(_id, granted_perms.clone());
-            return granted_perms;
-        };
+        (grants, cacheable)
+    }
 
-        // final permissions will be ,
-        // granted_perms & permmask & (global_mask | override_mask)
-        granted_perms.provide =
-            granted_perms.provide & mask.permmask & (base.global_mask | mask.ovrmask);
+    /// Check a request to map an object with protections `requested` against this context.
+    /// Returns the protections to map with, or AccessDenied if `requested` asks for more than the
+    /// effective protections (see [check_map_protections]).
+    ///
+    /// Limited capabilities only count if `use_limited` is set. If the request is only granted
+    /// thanks to one of them, this takes one of its uses, and denies the request if it has none
+    /// left. Requests granted without one don't use any.
+    pub fn check_map(
+        &self,
+        target_id: ObjID,
+        default_prot: Protections,
+        requested: Protections,
+        use_limited: bool,
+    ) -> twizzler_rt_abi::Result<Protections> {
+        let (grants, _) = self.grants(target_id, None, true);
+        let mut perms = grants.perms;
+        let granted = check_map_protections(effective_protections(&perms, default_prot), requested);
+        if granted.is_ok() || !use_limited {
+            return granted;
+        }
+        let now = revocation_now();
+        for (cap, prots) in &grants.limited {
+            perms.provide = grants.perms.provide | *prots;
+            let Ok(prot) =
+                check_map_protections(effective_protections(&perms, default_prot), requested)
+            else {
+                continue;
+            };
+            // Only a cap that makes the difference is used, and only once its signature has
+            // checked out, so forged capabilities can't burn uses.
+            let max_uses = cap.max_uses().unwrap();
+            if REVOCATION_LIST
+                .lock()
+                .consume_use(cap.id(), max_uses, cap.revocation, now)
+            {
+                return Ok(prot);
+            }
+        }
+        Err(GenericError::AccessDenied.into())
+    }
 
-        self.cache.lock().insert(_id, granted_perms.clone());
+    /// Whether this context holds a capability for `id` that is scoped to part of it, so that
+    /// what it grants depends on the page.
+    pub fn has_scoped_caps(&self, id: ObjID) -> bool {
+        let Some(ref obj) = self.kobj else {
+            return false;
+        };
+        let base = obj.base();
+        let Some(results) = base.map.get(&id) else {
+            return false;
+        };
+        results.iter().any(|entry| {
+            matches!(entry.item_type, CtxMapItemType::Cap)
+                && obj
+                    .lea_raw(entry.offset as *const Cap)
+                    .is_some_and(|cap| cap.scope().is_some())
+        })
+    }
 
-        granted_perms
+    // Cache a lookup result, unless the revocation list changed while we were looking it up.
+    fn cache_insert(&self, epoch: u64, id: ObjID, perms: PermsInfo) {
+        let mut cache = self.cache.lock();
+        if cache.epoch == epoch {
+            cache.entries.insert(id, perms);
+        }
     }
 
     pub fn new(kobj: Option<KernelObject<SecCtxBase>>) -> Self {
@@ -174,12 +361,26 @@ impl SecurityContext {
             .map(|kobj| kobj.id())
             .unwrap_or(KERNEL_SCTX)
     }
+
+    /// The label of this context (see [SecLabel]), recorded in its metadata. Contexts without one,
+    /// including the kernel's, have the lowest label.
+    pub fn label(&self) -> SecLabel {
+        let Some(kobj) = self.kobj.as_ref() else {
+            return SecLabel::default();
+        };
+        let LookupResult::Found(obj) = lookup_object(kobj.id(), LookupFlags::empty()) else {
+            return SecLabel::default();
+        };
+        obj.read_meta(true)
+            .and_then(|meta| object_label(&obj, &meta))
+            .unwrap_or_default()
+    }
 }
 
 impl SecCtxMgr {
     /// Lookup the permission info for an object in the active context, and maybe cache it.
     pub fn lookup(&self, id: ObjID) -> PermsInfo {
-        self.inner.lock().active.lookup(id)
+        self.active().lookup(id)
     }
 
     /// Get the active context.
@@ -194,40 +395,105 @@ impl SecCtxMgr {
     }
 
     /// Check access rights in the active context.
-    pub fn check_active_access(&self, _access_info: &AccessInfo) -> PermsInfo {
+    pub fn check_active_access(&self, access_info: &AccessInfo) -> PermsInfo {
         //TODO: will probably have to hook up the gate check here as well?
-        // WARN: actually doing the lookup is causing the kernel to die so just skipping that for
-        // now for some reason
-        // let perms = self.lookup(_access_info.target_id);
-        let perms = PermsInfo {
-            ctx: self.active_id(),
-            provide: Protections::all(),
-            restrict: Protections::empty(),
+        self.active()
+            .lookup_at(access_info.target_id, access_info.target_off)
+    }
+
+    /// Search all attached contexts for access. Returns the permissions of the first context
+    /// (active or not) that grants the access, or of the active context if none does.
+    pub fn search_access(&self, access_info: &AccessInfo) -> PermsInfo {
+        // Look up outside the lock, since lookups may have to bring in objects.
+        let (active, inactive): (_, Vec<_>) = {
+            let inner = self.inner.lock();
+            (
+                inner.active.clone(),
+                inner.inactive.values().cloned().collect(),
+            )
         };
+        let active_perms = active.lookup_at(access_info.target_id, access_info.target_off);
+        let grants =
+            |perms: &PermsInfo| (perms.provide & !perms.restrict).contains(access_info.access_kind);
+        if grants(&active_perms) {
+            return active_perms;
+        }
+        inactive
+            .iter()
+            .map(|ctx| ctx.lookup_at(access_info.target_id, access_info.target_off))
+            .find(grants)
+            .unwrap_or(active_perms)
+    }
 
-        perms
+    /// Whether any attached context, active or not, holds a capability for `id` that is scoped
+    /// to part of it. Faults on such an object map only the faulting page, as its neighbours
+    /// may lie outside the scope.
+    pub fn has_scoped_caps(&self, id: ObjID) -> bool {
+        let contexts: Vec<_> = {
+            let inner = self.inner.lock();
+            core::iter::once(&inner.active)
+                .chain(inner.inactive.values())
+                .cloned()
+                .collect()
+        };
+        contexts.iter().any(|ctx| ctx.has_scoped_caps(id))
     }
 
-    /// Search all attached contexts for access.
-    pub fn search_access(&self, _access_info: &AccessInfo) -> PermsInfo {
-        //TODO: need to actually look through all the contexts, this is just temporary
-        // let mut greatest_perms = self.lookup(_access_info.target_id);
-
-        // for (_, ctx) in &self.inner.lock().inactive {
-        //     let perms = ctx.lookup(_access_info.target_id);
-        //     // how do you determine what prots is more expressive? like more
-        //     // lets just return if its anything other than empty
-        //     if perms.provide & !perms.restrict != Protections::empty() {
-        //         greatest_perms = perms
-        //     }
-        // }
-        // greatest_perms
-
-        PermsInfo {
-            ctx: self.active_id(),
-            provide: Protections::all(),
-            restrict: Protections::empty(),
+    /// Check a request to map an object with protections `requested`. The active context is
+    /// checked first, followed by all attached contexts, as is done on page fault. Returns the
+    /// protections to map with, or AccessDenied if `requested` asks for more than the effective
+    /// protections (see [check_map_protections]).
+    ///
+    /// Limited capabilities are only tried once no context grants the request without them, so
+    /// that their uses aren't spent on requests that didn't need them.
+    pub fn map_access(
+        &self,
+        target_id: ObjID,
+        default_prot: Protections,
+        requested: Protections,
+    ) -> twizzler_rt_abi::Result<Protections> {
+        let (active, inactive): (_, Vec<_>) = {
+            let inner = self.inner.lock();
+            (
+                inner.active.clone(),
+                inner.inactive.values().cloned().collect(),
+            )
+        };
+        let mut result = Err(GenericError::AccessDenied.into());
+        for use_limited in [false, true] {
+            for ctx in core::iter::once(&active).chain(&inactive) {
+                result = ctx.check_map(target_id, default_prot, requested, use_limited);
+                if result.is_ok() {
+                    return result;
+                }
+            }
         }
+        result
+    }
+
+    /// Check that every context in `required` is attached (or active), and on its own provides
+    /// `requested` for `target_id`. This is the AND of contexts an object can demand with a
+    /// [CtxQuorum]; see [required_contexts].
+    pub fn check_quorum(
+        &self,
+        target_id: ObjID,
+        required: &[ObjID],
+        requested: Protections,
+    ) -> twizzler_rt_abi::Result<()> {
+        // Look up outside the lock, since lookups may have to bring in objects.
+        let inner = self.inner.lock().clone();
+        for id in required {
+            let ctx = if inner.active.id() == *id {
+                &inner.active
+            } else {
+                inner.inactive.get(id).ok_or(GenericError::AccessDenied)?
+            };
+            let perms = ctx.lookup(target_id);
+            if !(perms.provide & !perms.restrict).contains(requested) {
+                return Err(GenericError::AccessDenied.into());
+            }
+        }
+        Ok(())
     }
 
     /// Build a new SctxMgr for user threads.
@@ -272,6 +538,14 @@ impl SecCtxMgr {
         }
     }
 
+    /// Returns true if the context `id` is attached, either active or not.
+    pub fn is_attached(&self, id: ObjID) -> bool {
+        if *self.active_id.lock() == id {
+            return true;
+        }
+        self.inner.lock().inactive.contains_key(&id)
+    }
+
     /// Attach a security context.
     pub fn attach(&self, sctx: SecurityContextRef) -> twizzler_rt_abi::Result<()> {
         let mut inner = self.inner.lock();
@@ -305,6 +579,105 @@ impl Clone for SecCtxMgr {
     }
 }
 
+/// The protections granted by a context's permission info for an object with the given default
+/// protections.
+pub fn effective_protections(perms: &PermsInfo, default_prot: Protections) -> Protections {
+    (perms.provide | default_prot) & !perms.restrict
+}
+
+/// Check a map request against the effective protections for an object. The granted protections
+/// are the intersection of the effective protections and the request. Rather than silently
+/// mapping with fewer protections than asked for (which would only show up as a fault later), a
+/// request that exceeds the effective protections fails with AccessDenied.
+pub fn check_map_protections(
+    effective: Protections,
+    requested: Protections,
+) -> Result<Protections, TwzError> {
+    if !effective.contains(requested) {
+        return Err(GenericError::AccessDenied.into());
+    }
+    Ok(effective & requested)
+}
+
+/// Map the verifying key object `kuid` into the kernel, if it exists.
+fn verifying_key(kuid: ObjID) -> Option<KernelObject<VerifyingKey>> {
+    match lookup_object(kuid, LookupFlags::empty()) {
+        LookupResult::Found(v_obj) => Some(kernel_context().insert_kernel_object::<VerifyingKey>(
+            ObjectContextInfo::new(
+                v_obj,
+                Protections::READ,
+                CacheType::WriteBack,
+                MapFlags::STABLE,
+            ),
+        )),
+        _ => None,
+    }
+}
+
+/// Find the value of the meta extension tagged `tag` in `obj`'s metadata.
+fn find_meta_ext(obj: &ObjectRef, meta: &MetaInfo, tag: u64) -> Option<u64> {
+    for i in 0..meta.extcount as usize {
+        let off = size_of::<MetaInfo>() + i * size_of::<MetaExt>();
+        let ext = obj.read_meta_val::<MetaExt>(off, true)?;
+        if ext.tag == tag {
+            return Some(ext.value);
+        }
+    }
+    None
+}
+
+/// The label recorded in `obj`'s metadata, if it has one.
+fn object_label(obj: &ObjectRef, meta: &MetaInfo) -> Option<SecLabel> {
+    find_meta_ext(obj, meta, MEXT_SEC_LABEL).map(SecLabel::from_ext_value)
+}
+
+/// The security contexts that must all be attached to access `obj`, as declared by a [CtxQuorum]
+/// in its metadata. Empty if the object doesn't declare one.
+pub fn required_contexts(obj: &ObjectRef) -> Vec<ObjID> {
+    let Some(meta) = obj.read_meta(true) else {
+        return Vec::new();
+    };
+    find_meta_ext(obj, &meta, MEXT_CTX_QUORUM)
+        .and_then(|off| obj.read_meta_val::<CtxQuorum>(off as usize, true))
+        .map(|quorum| quorum.contexts().to_vec())
+        .unwrap_or_default()
+}
+
+/// Check `obj` against the [ObjectSeal] in its metadata, if it has one, by hashing the sealed
+/// bytes and verifying the seal's signature with the object's verifying key. Fails with DataLoss
+/// if the seal doesn't match (or can't be checked), so that a tampered object is never mapped.
+/// Objects without a seal always pass.
+pub fn check_integrity(obj: &ObjectRef) -> twizzler_rt_abi::Result<()> {
+    let Some(meta) = obj.read_meta(true) else {
+        return Ok(());
+    };
+    let Some(off) = find_meta_ext(obj, &meta, MEXT_INTEGRITY_SEAL) else {
+        return Ok(());
+    };
+    let seal = obj
+        .read_meta_val::<ObjectSeal>(off as usize, true)
+        .ok_or(IoError::DataLoss)?;
+    if seal.len() > MAX_SIZE - 2 * NULLPAGE_SIZE {
+        return Err(IoError::DataLoss.into());
+    }
+    let v_obj = verifying_key(meta.kuid).ok_or(IoError::DataLoss)?;
+
+    let mut hasher = SealHasher::new();
+    let mut buf = alloc::vec![0u8; seal.len().min(NULLPAGE_SIZE)];
+    let mut done = 0;
+    while done < seal.len() {
+        let chunk = &mut buf[0..(seal.len() - done).min(NULLPAGE_SIZE)];
+        obj.read_bytes(chunk, NULLPAGE_SIZE + done);
+        hasher.update(chunk);
+        done += chunk.len();
+    }
+    if seal.verify(&hasher.finish(), v_obj.base()).is_err() {
+        log::warn!("{}: contents don't match the object's seal", obj.id());
+        return Err(IoError::DataLoss.into());
+    }
+    Ok(())
+}
+
 struct GlobalSecCtxMgr {
     contexts: Mutex<BTreeMap<ObjID, SecurityContextRef>>,
 }
@@ -354,22 +727,85 @@ impl Drop for SecCtxMgr {
     }
 }
 
+/// Helpers for tests that need security contexts holding real, signed capabilities.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use alloc::vec::Vec;
+    use core::mem::size_of;
+
+    use twizzler_abi::{
+        meta::{MetaFlags, MetaInfo},
+        object::{ObjID, Protections},
+    };
+    use twizzler_rt_abi::object::Nonce;
+    use twizzler_security::{
+        Cap, CtxMapItem, CtxMapItemType, SecCtxBase, SigningKey, SigningScheme, OBJECT_ROOT_OFFSET,
+    };
+
+    use super::{get_sctx, SecurityContextRef};
+    use crate::{obj::ObjectRef, random::new_kernel_keypair, userinit::create_blank_object};
+
+    /// Create an object with the given default protections whose metadata names a fresh
+    /// verifying key. Returns the object and the key to sign capabilities for it with.
+    pub fn signed_object(default_prot: Protections) -> (ObjectRef, SigningKey) {
+        let (s_key, v_key) =
+            new_kernel_keypair(&SigningScheme::Ecdsa).expect("shouldnt have errored");
+        let key_obj = create_blank_object();
+        key_obj.write_base(&v_key);
+        key_obj.sync();
+
+        let obj = create_blank_object();
+        let meta = MetaInfo {
+            nonce: Nonce(0),
+            kuid: key_obj.id(),
+            default_prot,
+            flags: MetaFlags::empty(),
+            fotcount: 0,
+            extcount: 0,
+        };
+        assert!(obj.write_meta(meta, true));
+        (obj, s_key)
+    }
+
+    /// Create a security context holding the capabilities `caps` returns when given the new
+    /// context's ID.
+    pub fn context_with_caps(caps: impl FnOnce(ObjID) -> Vec<Cap>) -> SecurityContextRef {
+        let ctx_obj = create_blank_object();
+        let mut base = SecCtxBase::default();
+        let mut off = OBJECT_ROOT_OFFSET.next_multiple_of(0x10);
+        for cap in caps(ctx_obj.id()) {
+            ctx_obj.write_at(&cap, off);
+            if base.map.get(&cap.target).is_none() {
+                base.map.insert(cap.target, Default::default()).unwrap();
+            }
+            base.map
+                .get_mut(&cap.target)
+                .unwrap()
+                .push(CtxMapItem {
+                    item_type: CtxMapItemType::Cap,
+                    offset: off,
+                })
+                .unwrap();
+            off += size_of::<Cap>().next_multiple_of(0x10);
+        }
+        ctx_obj.write_base(&base);
+        ctx_obj.sync();
+        get_sctx(ctx_obj.id()).unwrap()
+    }
+}
+
 mod tests {
     use core::hint::black_box;
 
     use twizzler_abi::object::Protections;
     use twizzler_kernel_macros::kernel_test;
-    use twizzler_security::{Cap, SigningKey, SigningScheme};
+    use twizzler_security::{Cap, SigningScheme};
 
-    use crate::{random::getrandom, utils::benchmark};
+    use crate::{random::new_kernel_keypair, utils::benchmark};
     #[kernel_test]
     fn bench_capability_verification() {
-        let mut rand_bytes = [0; 32];
-
-        getrandom(&mut rand_bytes, false);
-
-        let (s_key, v_key) = SigningKey::new_kernel_keypair(&SigningScheme::Ecdsa, rand_bytes)
-            .expect("shouldnt have errored");
+        let (s_key, v_key) =
+            new_kernel_keypair(&SigningScheme::Ecdsa).expect("shouldnt have errored");
 
         let cap = Cap::new(
             0x123.into(),
@@ -387,5 +823,478 @@ mod tests {
         });
     }
 
+    #[kernel_test]
+    fn test_map_protections() {
+        use twizzler_rt_abi::error::{GenericError, TwzError};
+
+        use super::{check_map_protections, effective_protections, PermsInfo};
+
+        let cap_prot = Protections::READ | Protections::WRITE;
+        let perms = PermsInfo::new(0.into(), cap_prot, Protections::empty());
+        let effective = effective_protections(&perms, Protections::empty());
+
+        // Within the cap.
+        assert_eq!(
+            check_map_protections(effective, Protections::READ).ok(),
+            Some(Protections::READ)
+        );
+        // Equal to the cap.
+        assert_eq!(
+            check_map_protections(effective, cap_prot).ok(),
+            Some(cap_prot)
+        );
+        // Exceeding the cap.
+        assert!(matches!(
+            check_map_protections(effective, cap_prot | Protections::EXEC),
+            Err(TwzError::Generic(GenericError::AccessDenied))
+        ));
+
+        // Default protections add to the cap, and restrictions remove from both.
+        let perms = PermsInfo::new(0.into(), Protections::READ, Protections::WRITE);
+        let effective = effective_protections(&perms, Protections::WRITE | Protections::EXEC);
+        assert_eq!(effective, Protections::READ | Protections::EXEC);
+        assert!(check_map_protections(effective, Protections::READ | Protections::WRITE).is_err());
+    }
+
+    #[kernel_test]
+    fn test_revoke_global() {
+        use alloc::sync::Arc;
+
+        use super::{cap_protections, revocation_epoch, revoke_global, PermsInfo, SecurityContext};
+
+        let (s_key, v_key) =
+            new_kernel_keypair(&SigningScheme::Ecdsa).expect("shouldnt have errored");
+        let target = 0x456.into();
+        let cap = Cap::new(
+            target,
+            0x100.into(),
+            Protections::READ | Protections::WRITE,
+            &s_key,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .expect("capability creation shouldnt have errored");
+        assert_eq!(cap_protections(&cap, &v_key, 0), cap.protections);
+
+        // Two contexts that have already looked up (and cached) the access this cap grants.
+        let contexts = [
+            Arc::new(SecurityContext::new(None)),
+            Arc::new(SecurityContext::new(None)),
+        ];
+        let epoch = revocation_epoch();
+        for ctx in &contexts {
+            let perms = PermsInfo::new(ctx.id(), cap.protections, Protections::empty());
+            ctx.cache_insert(epoch, target, perms);
+            assert_eq!(ctx.lookup(target).provide, cap.protections);
+        }
+
+        revoke_global(cap.id()).unwrap();
+        assert!(revocation_epoch() > epoch);
+        assert_eq!(cap_protections(&cap, &v_key, 0), Protections::empty());
+        for ctx in &contexts {
+            assert_eq!(ctx.lookup(target).provide, Protections::empty());
+        }
+
+        // Revoking again is harmless.
+        revoke_global(cap.id()).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_one_time_cap() {
+        use alloc::vec::Vec;
+        use core::num::NonZeroU16;
+
+        use twizzler_security::DEFAULT_CAP_USES;
+
+        use super::test_util::{context_with_caps, signed_object};
+        use crate::thread::{entry::run_closure_in_new_thread, priority::Priority};
+
+        // A context with a READ cap and a limited WRITE cap for one object, and a limited READ
+        // cap with `uses` uses for another.
+        let setup = |uses| {
+            let (target, s_key) = signed_object(Protections::empty());
+            let (limited_target, l_key) = signed_object(Protections::empty());
+            let ctx = context_with_caps(|ctx_id| {
+                alloc::vec![
+                    Cap::new(
+                        target.id(),
+                        ctx_id,
+                        Protections::READ,
+                        &s_key,
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                    )
+                    .expect("capability creation shouldnt have errored"),
+                    Cap::new_limited(
+                        target.id(),
+                        ctx_id,
+                        Protections::WRITE,
+                        &s_key,
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        DEFAULT_CAP_USES,
+                    )
+                    .expect("capability creation shouldnt have errored"),
+                    Cap::new_limited(
+                        limited_target.id(),
+                        ctx_id,
+                        Protections::READ,
+                        &l_key,
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        uses,
+                    )
+                    .expect("capability creation shouldnt have errored"),
+                ]
+            });
+            (ctx, target.id(), limited_target.id())
+        };
+        let read = Protections::READ;
+        let write = Protections::READ | Protections::WRITE;
+
+        let (ctx, target, limited_target) = setup(DEFAULT_CAP_USES);
+        // Lookups and accesses the unlimited cap grants on its own don't take a use.
+        for _ in 0..2 {
+            assert_eq!(ctx.lookup(target).provide, write);
+            assert_eq!(ctx.lookup(limited_target).provide, read);
+            assert_eq!(
+                ctx.check_map(target, Protections::empty(), read, true),
+                Ok(read)
+            );
+        }
+        // Accesses only the limited cap grants take its one use.
+        assert!(ctx
+            .check_map(target, Protections::empty(), write, false)
+            .is_err());
+        assert_eq!(
+            ctx.check_map(target, Protections::empty(), write, true),
+            Ok(write)
+        );
+        assert!(ctx
+            .check_map(target, Protections::empty(), write, true)
+            .is_err());
+        assert_eq!(
+            ctx.check_map(target, Protections::empty(), read, true),
+            Ok(read)
+        );
+        assert_eq!(
+            ctx.check_map(limited_target, Protections::empty(), read, true),
+            Ok(read)
+        );
+        assert!(ctx
+            .check_map(limited_target, Protections::empty(), read, true)
+            .is_err());
+
+        // Two threads race for the last use of a two-use cap; exactly one of them gets it.
+        let (ctx, _, limited_target) = setup(NonZeroU16::new(2).unwrap());
+        assert!(ctx
+            .check_map(limited_target, Protections::empty(), read, true)
+            .is_ok());
+        let racers: Vec<_> = (0..2)
+            .map(|_| {
+                let ctx = ctx.clone();
+                run_closure_in_new_thread(Priority::USER, move || {
+                    ctx.check_map(limited_target, Protections::empty(), read, true)
+                })
+                .1
+            })
+            .collect();
+        let granted = racers
+            .into_iter()
+            .filter(|racer| racer.wait().is_ok())
+            .count();
+        assert_eq!(granted, 1);
+        assert!(ctx
+            .check_map(limited_target, Protections::empty(), read, true)
+            .is_err());
+    }
+
+    #[kernel_test]
+    fn test_ctx_quorum() {
+        use alloc::sync::Arc;
+        use core::mem::size_of;
+
+        use twizzler_abi::{
+            meta::{MetaExt, MetaInfo},
+            object::{MAX_SIZE, NULLPAGE_SIZE},
+        };
+        use twizzler_security::{CtxQuorum, MEXT_CTX_QUORUM};
+
+        use super::{
+            required_contexts,
+            test_util::{context_with_caps, signed_object},
+            SecCtxMgr,
+        };
+
+        let (target, s_key) = signed_object(Protections::empty());
+        // Two contexts, each holding a signed capability for reading the target.
+        let read_cap = |ctx_id| {
+            alloc::vec![Cap::new(
+                target.id(),
+                ctx_id,
+                Protections::READ,
+                &s_key,
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            )
+            .expect("capability creation shouldnt have errored")]
+        };
+        let ctx_a = context_with_caps(read_cap);
+        let ctx_b = context_with_caps(read_cap);
+        let mgr = SecCtxMgr::new(Arc::clone(&ctx_a));
+
+        // Objects without a quorum don't need any particular context.
+        assert!(required_contexts(&target).is_empty());
+
+        // Declare a quorum of both contexts in the target's metadata.
+        let meta_off = MAX_SIZE - NULLPAGE_SIZE;
+        let quorum_off = (size_of::<MetaInfo>() + size_of::<MetaExt>()).next_multiple_of(0x10);
+        let mut meta = target.read_meta(true).unwrap();
+        meta.extcount = 1;
+        assert!(target.write_meta(meta, true));
+        let ext = MetaExt {
+            tag: MEXT_CTX_QUORUM,
+            value: quorum_off as u64,
+        };
+        target.write_at(&ext, meta_off + size_of::<MetaInfo>());
+        let quorum = CtxQuorum::new(&[ctx_a.id(), ctx_b.id()]).unwrap();
+        target.write_at(&quorum, meta_off + quorum_off);
+        let required = required_contexts(&target);
+        assert_eq!(required, [ctx_a.id(), ctx_b.id()]);
+
+        assert!(mgr
+            .check_quorum(target.id(), &required, Protections::READ)
+            .is_err());
+
+        mgr.attach(Arc::clone(&ctx_b)).unwrap();
+        assert!(mgr
+            .check_quorum(target.id(), &required, Protections::READ)
+            .is_ok());
+        // Each context must grant the access on its own.
+        assert!(mgr
+            .check_quorum(target.id(), &required, Protections::WRITE)
+            .is_err());
+    }
+
+    #[kernel_test]
+    fn test_labels_enforced() {
+        use core::mem::size_of;
+
+        use twizzler_abi::{
+            meta::{MetaExt, MetaInfo},
+            object::{MAX_SIZE, NULLPAGE_SIZE},
+        };
+        use twizzler_security::{SecLabel, MEXT_SEC_LABEL};
+
+        use super::test_util::{context_with_caps, signed_object};
+        use crate::obj::{lookup_object, LookupFlags, LookupResult, ObjectRef};
+
+        // Record `label` as the only meta extension of `obj`.
+        let set_label = |obj: &ObjectRef, label: SecLabel| {
+            let mut meta = obj.read_meta(true).unwrap();
+            meta.extcount = 1;
+            assert!(obj.write_meta(meta, true));
+            let ext = MetaExt {
+                tag: MEXT_SEC_LABEL,
+                value: label.to_ext_value(),
+            };
+            obj.write_at(&ext, MAX_SIZE - NULLPAGE_SIZE + size_of::<MetaInfo>());
+        };
+
+        let (target, s_key) = signed_object(Protections::empty());
+        let ctx = context_with_caps(|ctx_id| {
+            alloc::vec![Cap::new(
+                target.id(),
+                ctx_id,
+                Protections::READ,
+                &s_key,
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            )
+            .expect("capability creation shouldnt have errored")]
+        });
+        let LookupResult::Found(ctx_obj) = lookup_object(ctx.id(), LookupFlags::empty()) else {
+            panic!("context object should exist");
+        };
+        assert_eq!(ctx.lookup(target.id()).provide, Protections::READ);
+
+        // A validly signed cap grants nothing once the target's label is above the context's.
+        set_label(&target, SecLabel::new(2, 1));
+        assert_eq!(ctx.label(), SecLabel::default());
+        assert_eq!(ctx.lookup(target.id()).provide, Protections::empty());
+        set_label(&ctx_obj, SecLabel::new(1, 1));
+        assert_eq!(ctx.lookup(target.id()).provide, Protections::empty());
+
+        set_label(&ctx_obj, SecLabel::new(3, 1));
+        assert_eq!(ctx.label(), SecLabel::new(3, 1));
+        assert_eq!(ctx.lookup(target.id()).provide, Protections::READ);
+    }
+
+    #[kernel_test]
+    fn test_integrity_seal() {
+        use core::mem::size_of;
+
+        use twizzler_abi::{
+            meta::{MetaExt, MetaFlags, MetaInfo},
+            object::{MAX_SIZE, NULLPAGE_SIZE},
+        };
+        use twizzler_rt_abi::{
+            error::{IoError, TwzError},
+            object::Nonce,
+        };
+        use twizzler_security::{ObjectSeal, SealHasher, MEXT_INTEGRITY_SEAL, SEAL_OFFSET};
+
+        use super::check_integrity;
+        use crate::userinit::create_blank_object;
+
+        let (s_key, v_key) =
+            new_kernel_keypair(&SigningScheme::Ecdsa).expect("shouldnt have errored");
+        let key_obj = create_blank_object();
+        key_obj.write_base(&v_key);
+        key_obj.sync();
+
+        let obj = create_blank_object();
+        let meta_off = MAX_SIZE - NULLPAGE_SIZE;
+        let meta = MetaInfo {
+            nonce: Nonce(0),
+            kuid: key_obj.id(),
+            default_prot: Protections::all(),
+            flags: MetaFlags::empty(),
+            fotcount: 0,
+            extcount: 1,
+        };
+        assert!(obj.write_meta(meta, true));
+        let ext = MetaExt {
+            tag: MEXT_INTEGRITY_SEAL,
+            value: SEAL_OFFSET as u64,
+        };
+        obj.write_at(&ext, meta_off + size_of::<MetaInfo>());
+
+        let data = b"sealed object contents, spanning more than one page";
+        let len = NULLPAGE_SIZE + data.len();
+        let reseal = || {
+            let mut buf = alloc::vec![0u8; len];
+            obj.read_bytes(&mut buf, NULLPAGE_SIZE);
+            let mut hasher = SealHasher::new();
+            hasher.update(&buf);
+            let seal = ObjectSeal::sign(len, &hasher.finish(), &s_key).unwrap();
+            obj.write_at(&seal, meta_off + SEAL_OFFSET);
+        };
+        obj.write_bytes(data.as_ptr(), data.len(), NULLPAGE_SIZE * 2);
+        reseal();
+        assert!(check_integrity(&obj).is_ok());
+
+        // Changing a sealed byte without re-signing makes the object unmappable.
+        obj.write_bytes(b"S".as_ptr(), 1, NULLPAGE_SIZE * 2);
+        assert_eq!(
+            check_integrity(&obj),
+            Err(TwzError::from(IoError::DataLoss))
+        );
+
+        // A legitimate update is accepted once it's been resealed.
+        reseal();
+        assert!(check_integrity(&obj).is_ok());
+
+        // Bytes past the sealed length aren't covered.
+        obj.write_bytes(b"x".as_ptr(), 1, NULLPAGE_SIZE + len);
+        assert!(check_integrity(&obj).is_ok());
+
+        // Objects without a seal are not checked.
+        assert!(check_integrity(&create_blank_object()).is_ok());
+    }
+
+    #[kernel_test]
+    fn test_scoped_cap() {
+        use twizzler_abi::object::NULLPAGE_SIZE;
+        use twizzler_security::CapScope;
+
+        use super::test_util::{context_with_caps, signed_object};
+
+        let (target, s_key) = signed_object(Protections::empty());
+        // A context holding a cap for pages 4 through 5 of the target.
+        let scope = CapScope::new(NULLPAGE_SIZE as u64 * 4, NULLPAGE_SIZE as u64 * 2);
+        let prots = Protections::READ | Protections::WRITE;
+        let ctx = context_with_caps(|ctx_id| {
+            alloc::vec![Cap::new_scoped(
+                target.id(),
+                ctx_id,
+                prots,
+                &s_key,
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                scope,
+            )
+            .expect("capability creation shouldnt have errored")]
+        });
+
+        let granted = |page: usize| {
+            ctx.lookup_at(target.id(), Some(NULLPAGE_SIZE * page))
+                .provide
+        };
+        assert_eq!(granted(4), prots);
+        assert_eq!(granted(5), prots);
+        // Pages on either side of the scope fault.
+        assert_eq!(granted(3), Protections::empty());
+        assert_eq!(granted(6), Protections::empty());
+        // The cap grants nothing for the object as a whole, but it can still be mapped, as each
+        // fault on the mapping is checked against the scope.
+        assert_eq!(ctx.lookup(target.id()).provide, Protections::empty());
+        assert_eq!(
+            ctx.check_map(target.id(), Protections::empty(), prots, false),
+            Ok(prots)
+        );
+        assert!(ctx.has_scoped_caps(target.id()));
+    }
+
+    #[kernel_test]
+    fn test_map_enforced() {
+        use twizzler_abi::{
+            meta::{MetaFlags, MetaInfo},
+            syscall::{HandleType, MapFlags},
+        };
+        use twizzler_rt_abi::{
+            error::{GenericError, TwzError},
+            object::Nonce,
+        };
+
+        use crate::{
+            syscall::object::{sys_new_handle, sys_object_map, sys_unbind_handle},
+            userinit::create_blank_object,
+        };
+
+        // An object anyone may read, but only capabilities may grant more.
+        let target = create_blank_object();
+        let meta = MetaInfo {
+            nonce: Nonce(0),
+            kuid: 0.into(),
+            default_prot: Protections::READ,
+            flags: MetaFlags::empty(),
+            fotcount: 0,
+            extcount: 0,
+        };
+        assert!(target.write_meta(meta, true));
+
+        let handle = create_blank_object().id();
+        sys_new_handle(handle, HandleType::VmContext).unwrap();
+        let slot = 0x1000;
+        let map = |prot| sys_object_map(target.id(), slot, prot, Some(handle), MapFlags::empty());
+
+        // This thread's context holds no capabilities for the target, so only the default
+        // protections apply.
+        assert_eq!(
+            map(Protections::READ | Protections::WRITE),
+            Err(TwzError::from(GenericError::AccessDenied))
+        );
+        assert_eq!(map(Protections::READ), Ok(slot));
+        sys_unbind_handle(handle);
+    }
+
     //TODO: write a thorough security context test when that stuff is implemented
 }
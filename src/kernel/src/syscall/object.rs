@@ -120,6 +120,21 @@ pub fn sys_object_map(
             None => return Err(ObjectError::NoSuchObject.into()),
         },
     };
+    // The mapping is granted the intersection of the requested protections and those the caller
+    // has for the object; asking for more than that is an error.
+    let (_, default_prot) = obj.check_id();
+    // Sealed objects whose contents were tampered with are never mapped.
+    crate::security::check_integrity(&obj)?;
+    let prot = match current_thread_ref() {
+        Some(ct) => {
+            let prot = ct.secctx.map_access(id, default_prot, prot)?;
+            // Objects may also require a set of contexts to all be attached.
+            let required = crate::security::required_contexts(&obj);
+            ct.secctx.check_quorum(id, &required, prot)?;
+            prot
+        }
+        None => prot,
+    };
     // TODO
     let _res = crate::operations::map_object_into_context(slot, obj, vm, prot.into(), flags);
     Ok(slot)
@@ -256,10 +271,18 @@ pub fn sys_sctx_attach(id: ObjID) -> Result<u32> {
     Ok(0)
 }
 
+fn object_sync(id: ObjID) -> Result<()> {
+    let obj = lookup_object(id, LookupFlags::empty()).ok_or(TwzError::INVALID_ARGUMENT)?;
+    obj.sync();
+    Ok(())
+}
+
 pub fn object_ctrl(id: ObjID, cmd: ObjectControlCmd) -> (u64, u64) {
     match cmd {
         ObjectControlCmd::Sync => {
-            crate::pager::sync_object(id);
+            if let Err(e) = object_sync(id) {
+                return (1, e.raw());
+            }
         }
         ObjectControlCmd::Delete(_) => {
             let mut invoke_pager = true;
@@ -284,6 +307,19 @@ pub fn object_ctrl(id: ObjID, cmd: ObjectControlCmd) -> (u64, u64) {
                 return (1, TwzError::INVALID_ARGUMENT.raw());
             }
         }
+        ObjectControlCmd::PreloadPages(count) => {
+            if let Some(obj) = crate::pager::lookup_object_and_wait(id) {
+                let max = MAX_SIZE / PageNumber::PAGE_SIZE - PageNumber::base_page().num();
+                crate::pager::ensure_in_core(
+                    &obj,
+                    PageNumber::base_page(),
+                    (count as usize).min(max),
+                    PagerFlags::PREFETCH,
+                );
+            } else {
+                return (1, TwzError::INVALID_ARGUMENT.raw());
+            }
+        }
 
         _ => {}
     }
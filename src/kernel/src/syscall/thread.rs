@@ -61,6 +61,13 @@ pub fn thread_ctrl(cmd: ThreadControl, target: Option<ObjID>, arg: u64, arg2: u6
                 _ => [0, 0],
             };
         }
+        ThreadControl::HasSctx => {
+            let id = ObjID::from_parts([arg, arg2]);
+            return [
+                current_thread_ref().unwrap().secctx.is_attached(id) as u64,
+                0,
+            ];
+        }
         ThreadControl::ReadRegisters => {
             let thread = if let Some(target) = target {
                 crate::sched::lookup_thread_repr(target)
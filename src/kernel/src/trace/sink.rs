@@ -30,6 +30,7 @@ impl TraceSink {
             start: TRACE_DATA_START,
             end: AtomicU64::new(TRACE_DATA_START),
         });
+        obj.sync();
         Ok(Self {
             prime_object: obj.clone(),
             current_object: obj,
@@ -111,6 +112,7 @@ impl TraceSink {
                 start: TRACE_DATA_START,
                 end: AtomicU64::new(TRACE_DATA_START),
             });
+            obj.sync();
 
             self.offset += self.write(&(
                 TraceEntryHead::new_next_object(id),
@@ -32,6 +32,7 @@ fn create_name_object() -> ObjectRef {
         init_info.add_name(KernelInitName::new(name, obj.id()));
     }
     obj.write_base(&init_info);
+    obj.sync();
     obj
 }
 
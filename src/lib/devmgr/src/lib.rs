@@ -9,13 +9,13 @@ use twizzler_rt_abi::{
 };
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, secgate::StableLayout)]
 pub struct DriverSpec {
     pub supported: Supported,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, secgate::StableLayout)]
 pub enum Supported {
     PcieClass(u8, u8, u8),
     Vendor(u16, u16),
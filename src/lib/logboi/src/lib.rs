@@ -1,7 +1,12 @@
 #[link(name = "logboi_srv")]
 extern "C" {}
 
-use secgate::util::{Descriptor, Handle, SimpleBuffer};
+use std::sync::Mutex;
+
+use secgate::{
+    util::{Descriptor, Handle, SimpleBuffer},
+    GateTraceEvent, GateTraceSink,
+};
 use twizzler_rt_abi::{error::TwzError, object::MapFlags};
 
 /// An open handle to the logging service.
@@ -61,3 +66,33 @@ impl LogHandle {
         }
     }
 }
+
+/// A [GateTraceSink] that posts the trace events of a compartment's gates to the logger.
+struct GateTraceLogger {
+    // Opened on the first event, since the sink is set up before anything is traced.
+    handle: Mutex<Option<LogHandle>>,
+}
+
+impl GateTraceSink for GateTraceLogger {
+    fn record(&self, event: &GateTraceEvent) {
+        let Ok(mut handle) = self.handle.lock() else {
+            return;
+        };
+        if handle.is_none() {
+            *handle = LogHandle::new();
+        }
+        if let Some(handle) = handle.as_mut() {
+            handle.log(event.to_string().as_bytes());
+        }
+    }
+}
+
+static GATE_TRACE_LOGGER: GateTraceLogger = GateTraceLogger {
+    handle: Mutex::new(None),
+};
+
+/// Send the trace events of this compartment's traced gates (see `#[secure_gate(trace)]`) to the
+/// logger. Returns false if the compartment already has a trace sink.
+pub fn install_gate_tracing() -> bool {
+    secgate::set_gate_trace_sink(&GATE_TRACE_LOGGER).is_ok()
+}
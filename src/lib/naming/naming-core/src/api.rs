@@ -6,6 +6,7 @@ use crate::{GetFlags, NsNode, Result};
 // maybe this can be a macro or it's just bad design :(
 pub trait NamerAPI {
     fn put(&self, desc: Descriptor, name_len: usize, id: ObjID) -> Result<()>;
+    fn put_if_absent(&self, desc: Descriptor, name_len: usize, id: ObjID) -> Result<NsNode>;
     fn mkns(&self, desc: Descriptor, name_len: usize, persist: bool) -> Result<()>;
     fn link(&self, desc: Descriptor, name_len: usize, link_name: usize) -> Result<()>;
     fn get(&self, desc: Descriptor, name_len: usize, flags: GetFlags) -> Result<NsNode>;
@@ -9,6 +9,7 @@ use crate::{api::NamerAPI, handle::NamingHandle, GetFlags, NsNode, Result};
 pub struct DynamicNamerAPI {
     _handle: &'static CompartmentHandle,
     put: DynamicSecGate<'static, (Descriptor, usize, ObjID), ()>,
+    put_if_absent: DynamicSecGate<'static, (Descriptor, usize, ObjID), NsNode>,
     mkns: DynamicSecGate<'static, (Descriptor, usize, bool), ()>,
     link: DynamicSecGate<'static, (Descriptor, usize, usize), ()>,
     get: DynamicSecGate<'static, (Descriptor, usize, GetFlags), NsNode>,
@@ -25,6 +26,10 @@ impl NamerAPI for DynamicNamerAPI {
         (self.put)(desc, name_len, id)
     }
 
+    fn put_if_absent(&self, desc: Descriptor, name_len: usize, id: ObjID) -> Result<NsNode> {
+        (self.put_if_absent)(desc, name_len, id)
+    }
+
     fn get(&self, desc: Descriptor, name_len: usize, flags: GetFlags) -> Result<NsNode> {
         (self.get)(desc, name_len, flags)
     }
@@ -77,6 +82,11 @@ pub fn dynamic_namer_api() -> &'static DynamicNamerAPI {
                     .dynamic_gate("put")
                     .expect("failed to find put gate call")
             },
+            put_if_absent: unsafe {
+                handle
+                    .dynamic_gate("put_if_absent")
+                    .expect("failed to find put_if_absent gate call")
+            },
             mkns: unsafe {
                 handle
                     .dynamic_gate("mkns")
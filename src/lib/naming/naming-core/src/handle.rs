@@ -51,6 +51,35 @@ impl<'a, API: NamerAPI> NamingHandle<'a, API> {
         self.api.put(self.desc, name_len, id)
     }
 
+    /// Name `id` as `path`, unless `path` already names an object, in which case that object is
+    /// returned instead. The check and the put happen atomically in the naming service.
+    pub fn put_if_absent<P: AsRef<Path>>(&mut self, path: P, id: ObjID) -> Result<NsNode> {
+        let name_len = self.write_buffer(path)?;
+        self.api.put_if_absent(self.desc, name_len, id)
+    }
+
+    /// Get the object named `path`, creating it with `create` and naming it if there isn't one.
+    /// Concurrent callers converge on a single object: if another caller names `path` between
+    /// our lookup and our put, the object we created is handed to `discard` and theirs is
+    /// returned. Also returns whether the object was created by this call.
+    pub fn get_or_create(
+        &mut self,
+        path: &str,
+        create: impl FnOnce() -> Result<ObjID>,
+        discard: impl FnOnce(ObjID),
+    ) -> Result<(ObjID, bool)> {
+        if let Ok(node) = self.get(path, GetFlags::FOLLOW_SYMLINK) {
+            return Ok((node.id, false));
+        }
+        let id = create()?;
+        let node = self.put_if_absent(path, id).inspect_err(|_| discard(id))?;
+        if node.id != id {
+            discard(id);
+            return Ok((node.id, false));
+        }
+        Ok((id, true))
+    }
+
     pub fn get(&mut self, path: &str, flags: GetFlags) -> Result<NsNode> {
         let name_len = self.write_buffer(path)?;
         self.api.get(self.desc, name_len, flags)
@@ -116,6 +145,17 @@ impl<'a, API: NamerAPI> NamingHandle<'a, API> {
         self.api.mkns(self.desc, name_len, persist)
     }
 
+    /// Create a symlink named `path` that points at `target`. Lookups with
+    /// [GetFlags::FOLLOW_SYMLINK] resolve through it (up to a fixed number of hops), while lookups
+    /// without it return the link itself.
+    pub fn put_symlink<P: AsRef<Path>, L: AsRef<Path>>(
+        &mut self,
+        path: P,
+        target: L,
+    ) -> Result<()> {
+        self.symlink(path, target)
+    }
+
     pub fn symlink<P: AsRef<Path>, L: AsRef<Path>>(&mut self, path: P, link: L) -> Result<()> {
         let name_len = self.write_buffer(path)?;
         let link_len = self.write_buffer_at(link, name_len)?;
@@ -1,7 +1,7 @@
 use core::str;
 use std::{
     path::{Component, Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use bitflags::bitflags;
@@ -19,7 +19,7 @@ use crate::{Result, MAX_KEY_SIZE};
 mod ext;
 mod nsobj;
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, secgate::StableLayout)]
 #[repr(C)]
 pub enum NsNodeKind {
     Namespace,
@@ -30,7 +30,9 @@ unsafe impl Invariant for NsNodeKind {}
 
 const NSID_EXTERNAL: ObjID = ObjID::new(1);
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, twizzler::Invariant)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, twizzler::Invariant, secgate::StableLayout,
+)]
 #[repr(C)]
 pub struct NsNode {
     name: [u8; MAX_KEY_SIZE],
@@ -146,6 +148,9 @@ trait Namespace {
 pub struct NameStore {
     nameroot: Arc<dyn Namespace>,
     dataroot: ObjID,
+    // Held across the check and insert of a name on every path that inserts one, so concurrent
+    // puts, mkns and links can't both insert it.
+    insert_lock: Mutex<()>,
 }
 
 unsafe impl Send for NameStore {}
@@ -156,6 +161,7 @@ impl NameStore {
         let this = NameStore {
             nameroot: Arc::new(NamespaceObject::new(false, None, None).unwrap()),
             dataroot: 0.into(),
+            insert_lock: Mutex::new(()),
         };
         this.nameroot
             .insert(NsNode::ns("ext", NSID_EXTERNAL).unwrap());
@@ -182,6 +188,7 @@ impl NameStore {
         Ok(Self {
             nameroot: Arc::new(namespace),
             dataroot: id,
+            insert_lock: Mutex::new(()),
         })
     }
 
@@ -310,12 +317,16 @@ impl NameSession<'_> {
                         if deref || !is_last {
                             let ldname = thisnode.readlink()?;
                             tracing::trace!("search with: {}", ldname);
+                            // Once we decide to follow a link, we follow it all the way to
+                            // the final target, each hop counting against the same budget.
                             let (lnode, lcont) =
-                                self.namei_exist(Some(namespace), ldname, nr_derefs - 1, deref)?;
+                                self.namei_exist(Some(namespace), ldname, nr_derefs - 1, true)?;
                             node = Some(lnode);
                             namespace = lcont;
                         }
                     }
+                    // Unwrap-Ok: we just set node above.
+                    let thisnode = node.unwrap();
                     if !is_last && thisnode.kind == NsNodeKind::Namespace {
                         let parent_info = ParentInfo::new(namespace, thisnode.name()?);
                         namespace = self.open_namespace(
@@ -351,6 +362,7 @@ impl NameSession<'_> {
     }
 
     pub fn mkns<P: AsRef<Path>>(&self, name: P, persist: bool) -> Result<()> {
+        let _guard = self.store.insert_lock.lock().unwrap();
         let (node, container) = self.namei(None, &name, Self::MAX_SYMLINK_DEREF, false)?;
         let Err(name) = node else {
             return Err(NamingError::AlreadyExists.into());
@@ -369,6 +381,7 @@ impl NameSession<'_> {
 
     pub fn put<P: AsRef<Path>>(&self, name: P, id: ObjID) -> Result<()> {
         tracing::debug!("put {:?}: {}", name.as_ref(), id);
+        let _guard = self.store.insert_lock.lock().unwrap();
         let (node, container) = self.namei(None, &name, Self::MAX_SYMLINK_DEREF, false)?;
         let Err(name) = node else {
             return Err(NamingError::AlreadyExists.into());
@@ -378,6 +391,35 @@ impl NameSession<'_> {
         Ok(())
     }
 
+    /// Return the object named `name`, or if there isn't one, call `create` and name the object it
+    /// returns. Concurrent callers converge on one object: exactly one of them calls `create`,
+    /// and the rest get its object. Also returns whether this call created the object.
+    pub fn put_if_absent_with<P: AsRef<Path>>(
+        &self,
+        name: P,
+        create: impl FnOnce() -> Result<ObjID>,
+    ) -> Result<(NsNode, bool)> {
+        tracing::debug!("put_if_absent {:?}", name.as_ref());
+        let _guard = self.store.insert_lock.lock().unwrap();
+        let (node, container) = self.namei(None, &name, Self::MAX_SYMLINK_DEREF, true)?;
+        let name = match node {
+            Ok(node) if node.kind == NsNodeKind::Object => return Ok((node, false)),
+            Ok(_) => return Err(NamingError::WrongNameKind.into()),
+            Err(name) => name,
+        };
+
+        let node = NsNode::obj(name, create()?)?;
+        container.insert(node);
+        Ok((node, true))
+    }
+
+    /// Name `id` as `name`, unless `name` already names an object, in which case that object is
+    /// returned instead.
+    pub fn put_if_absent<P: AsRef<Path>>(&self, name: P, id: ObjID) -> Result<NsNode> {
+        self.put_if_absent_with(name, || Ok(id))
+            .map(|(node, _)| node)
+    }
+
     pub fn get<P: AsRef<Path>>(&self, name: P, flags: GetFlags) -> Result<NsNode> {
         tracing::debug!("get {:?}: {:?}", name.as_ref(), flags);
         let (node, _) = self.namei_exist(
@@ -439,6 +481,7 @@ impl NameSession<'_> {
     }
 
     pub fn link<P: AsRef<Path>, L: AsRef<Path>>(&self, name: P, link: L) -> Result<()> {
+        let _guard = self.store.insert_lock.lock().unwrap();
         let (node, container) = self.namei(None, &name, Self::MAX_SYMLINK_DEREF, false)?;
         let Err(name) = node else {
             return Err(NamingError::AlreadyExists.into());
@@ -456,7 +499,134 @@ impl NameSession<'_> {
 
 bitflags! {
     #[derive(Clone, Copy, Default, Debug, PartialEq, PartialOrd, Ord, Eq, Hash)]
+    #[repr(transparent)]
     pub struct GetFlags: u32 {
         const FOLLOW_SYMLINK = 1;
     }
 }
+
+// Safety: GetFlags is a transparent wrapper around a u32.
+unsafe impl secgate::StableLayout for GetFlags {}
+
+#[cfg(test)]
+mod tests {
+    use twizzler_rt_abi::error::{NamingError, TwzError};
+
+    use super::*;
+
+    #[test]
+    fn symlink_chain() {
+        let store = NameStore::new();
+        let session = store.root_session();
+        session.put("target", 42.into()).unwrap();
+        session.link("a", "b").unwrap();
+        session.link("b", "target").unwrap();
+
+        let node = session.get("a", GetFlags::FOLLOW_SYMLINK).unwrap();
+        assert_eq!(node.kind, NsNodeKind::Object);
+        assert_eq!(node.id, 42.into());
+    }
+
+    #[test]
+    fn symlink_no_follow() {
+        let store = NameStore::new();
+        let session = store.root_session();
+        session.put("target", 42.into()).unwrap();
+        session.link("a", "target").unwrap();
+
+        let node = session.get("a", GetFlags::empty()).unwrap();
+        assert_eq!(node.kind, NsNodeKind::SymLink);
+        assert_eq!(node.readlink().unwrap(), "target");
+    }
+
+    #[test]
+    fn symlink_to_namespace() {
+        let store = NameStore::new();
+        let session = store.root_session();
+        session.mkns("dir", false).unwrap();
+        session.put("dir/target", 42.into()).unwrap();
+        session.link("alias", "dir").unwrap();
+
+        let node = session.get("alias/target", GetFlags::empty()).unwrap();
+        assert_eq!(node.id, 42.into());
+    }
+
+    #[test]
+    fn put_if_absent_race() {
+        const CREATORS: u64 = 8;
+        let store = NameStore::new();
+        let created = std::sync::atomic::AtomicU64::new(0);
+        let ids = std::thread::scope(|s| {
+            let threads = (1..=CREATORS)
+                .map(|i| {
+                    let store = &store;
+                    let created = &created;
+                    s.spawn(move || {
+                        let session = store.root_session();
+                        let (node, _) = session
+                            .put_if_absent_with("shared", || {
+                                created.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                                Ok(i.into())
+                            })
+                            .unwrap();
+                        node.id
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        let session = store.root_session();
+        assert_eq!(session.get("shared", GetFlags::empty()).unwrap().id, ids[0]);
+
+        // Once named, the existing object wins over a new one.
+        let node = session.put_if_absent("shared", 1234.into()).unwrap();
+        assert_eq!(node.id, ids[0]);
+    }
+
+    #[test]
+    fn insert_kinds_race() {
+        const ROUNDS: usize = 64;
+        let store = NameStore::new();
+        for round in 0..ROUNDS {
+            let name = format!("name{}", round);
+            let results = std::thread::scope(|s| {
+                let name = name.as_str();
+                let store = &store;
+                let threads = [
+                    s.spawn(move || store.root_session().put(name, 42.into())),
+                    s.spawn(move || store.root_session().mkns(name, false)),
+                    s.spawn(move || store.root_session().link(name, "target")),
+                ];
+                threads.map(|t| t.join().unwrap())
+            });
+
+            // Exactly one kind of name wins, and the others see it.
+            assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+            for err in results.iter().filter_map(|r| r.as_ref().err()) {
+                assert_eq!(*err, TwzError::from(NamingError::AlreadyExists));
+            }
+        }
+    }
+
+    #[test]
+    fn symlink_cycle() {
+        let store = NameStore::new();
+        let session = store.root_session();
+        session.link("a", "b").unwrap();
+        session.link("b", "a").unwrap();
+
+        let err = session.get("a", GetFlags::FOLLOW_SYMLINK).unwrap_err();
+        assert_eq!(err, TwzError::from(NamingError::LinkLoop));
+        // Without following, the link itself is still visible.
+        assert_eq!(
+            session.get("a", GetFlags::empty()).unwrap().kind,
+            NsNodeKind::SymLink
+        );
+    }
+}
@@ -13,6 +13,10 @@ impl NamerAPI for StaticNamingAPI {
         naming_srv::put(desc, name_len, id)
     }
 
+    fn put_if_absent(&self, desc: Descriptor, name_len: usize, id: ObjID) -> Result<NsNode> {
+        naming_srv::put_if_absent(desc, name_len, id)
+    }
+
     fn get(&self, desc: Descriptor, name_len: usize, flags: GetFlags) -> Result<NsNode> {
         naming_srv::get(desc, name_len, flags)
     }
@@ -6,15 +6,47 @@
 use darling::FromMeta;
 use proc_macro::{Diagnostic, Level};
 use proc_macro2::{Ident, TokenStream};
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use syn::{
     parse2, parse_quote,
     punctuated::Punctuated,
+    spanned::Spanned,
     token::{Pub, Unsafe},
-    Attribute, BareFnArg, Error, ForeignItemFn, ItemFn, LitStr, ReturnType, Signature, Token, Type,
-    TypeBareFn, Visibility,
+    Attribute, BareFnArg, Data, DeriveInput, Error, ForeignItemFn, ItemFn, LitStr, ReturnType,
+    Signature, Token, Type, TypeBareFn, Visibility,
 };
 
+/// Turn a function into a secure gate.
+///
+/// Options are passed as `#[secure_gate(options(...))]`:
+///  - `info`: the first argument of the function is a `&GateCallInfo` describing the caller.
+///  - `api`: only generate the caller-side API, not the gate implementation.
+///  - `out`: the last argument of the function is a `&mut secgate::OutParam<T>`, a second value the
+///    callee hands back alongside the return value. Callers pass their own out-param, and read it
+///    after the call. See `secgate::OutParam` for when it is set, and `secgate::ReturnWithOut` for
+///    how it is laid out.
+///
+/// A gate can be restricted to callers from specific security contexts with one or more
+/// `allow(ctx = "...")` arguments, each giving a context's object ID in hex (with or without a
+/// leading `0x`), e.g. `#[secure_gate(allow(ctx = "0x1234"), allow(ctx = "0x5678"))]`. The check
+/// runs before the gate's implementation, and a call from any other context, or without a source
+/// context, fails with `GenericError::AccessDenied`. The source context must be attached to the
+/// calling thread according to the kernel, and must not be the gate's own context (see
+/// `secgate::check_caller_allowed`, which also says what this doesn't prove).
+///
+/// With `trace`, e.g. `#[secure_gate(trace)]`, the gate reports each call on entry and on exit to
+/// the compartment's `secgate::GateTraceSink`, with the gate's name, the caller's context, and the
+/// result. Reporting is compiled out unless secgate's `trace` feature is enabled.
+///
+/// With `encrypt_args`, the caller encrypts the gate's arguments, and the gate's entry point
+/// decrypts them, with a key both compartments set with `secgate::set_gate_key` under the gate's
+/// package name and its own name. See `secgate::seal_gate_args` for what this does and doesn't
+/// protect against.
+///
+/// The implementation may be an `async fn`. Callers still call the gate synchronously; the gate's
+/// entry point runs the future to completion with `secgate::block_on_gate`, on the executor the
+/// callee set with `secgate::set_gate_executor` (see `secgate::GateExecutor` for what it must
+/// provide).
 #[proc_macro_attribute]
 pub fn secure_gate(
     attr: proc_macro::TokenStream,
@@ -26,6 +58,84 @@ pub fn secure_gate(
     }
 }
 
+/// Implement `secgate::StableLayout` for a type, checking that its layout is fixed: the type must
+/// be `#[repr(C)]` or `#[repr(transparent)]` (enums may instead have a primitive representation,
+/// like `#[repr(u8)]`), and all of its fields must be StableLayout.
+#[proc_macro_derive(StableLayout)]
+pub fn derive_stable_layout(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match handle_stable_layout(item.into()) {
+        Ok(ts) => ts.into(),
+        Err(err) => proc_macro::TokenStream::from(err.to_compile_error()),
+    }
+}
+
+const PRIMITIVE_REPRS: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+];
+
+fn has_stable_repr(input: &DeriveInput) -> Result<bool, Error> {
+    let is_enum = matches!(input.data, Data::Enum(_));
+    let mut stable = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                stable = true;
+            } else if is_enum && PRIMITIVE_REPRS.iter().any(|p| meta.path.is_ident(p)) {
+                stable = true;
+            } else if meta.input.peek(syn::token::Paren) {
+                // align(N) and packed(N) don't affect whether the layout is fixed.
+                let _content;
+                syn::parenthesized!(_content in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(stable)
+}
+
+fn handle_stable_layout(item: proc_macro2::TokenStream) -> Result<proc_macro2::TokenStream, Error> {
+    let input = syn::parse2::<DeriveInput>(item)?;
+    if !has_stable_repr(&input)? {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "StableLayout requires #[repr(C)] or #[repr(transparent)]",
+        ));
+    }
+
+    let field_types: Vec<&Type> = match &input.data {
+        Data::Struct(data) => data.fields.iter().map(|f| &f.ty).collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|v| v.fields.iter().map(|f| &f.ty))
+            .collect(),
+        Data::Union(data) => data.fields.named.iter().map(|f| &f.ty).collect(),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut predicates: Vec<TokenStream> = where_clause
+        .map(|wc| wc.predicates.iter().map(|p| p.to_token_stream()).collect())
+        .unwrap_or_default();
+    // These bounds fail to hold (and so fail to compile) for concrete field types that aren't
+    // StableLayout.
+    predicates.extend(
+        field_types
+            .iter()
+            .map(|ty| quote_spanned! {ty.span()=> #ty: secgate::StableLayout}),
+    );
+
+    Ok(quote! {
+        unsafe impl #impl_generics secgate::StableLayout for #name #ty_generics
+            where #(#predicates),*
+        {}
+    })
+}
+
 const PREFIX: &str = "__twz_secgate_impl_";
 
 #[allow(dead_code)]
@@ -42,12 +152,35 @@ struct Info {
     pub ret_type: ReturnType,
     pub arg_names: Vec<Ident>,
     pub has_info: bool,
+    pub out_type: Option<Box<Type>>,
+    pub is_async: bool,
+    pub allowed_ctxs: Vec<u128>,
+    pub trace: bool,
+    pub encrypt_args: bool,
+}
+
+#[derive(Debug, FromMeta)]
+struct AllowArgs {
+    ctx: LitStr,
 }
 
 #[derive(Debug, FromMeta)]
 struct MacroArgs {
     #[darling(default)]
     options: darling::util::PathList,
+    #[darling(default, multiple)]
+    allow: Vec<AllowArgs>,
+    #[darling(default)]
+    trace: bool,
+    #[darling(default)]
+    encrypt_args: bool,
+}
+
+fn parse_ctx_id(ctx: &LitStr) -> Result<u128, Error> {
+    let value = ctx.value();
+    let hex = value.strip_prefix("0x").unwrap_or(&value);
+    u128::from_str_radix(hex, 16)
+        .map_err(|_| Error::new(ctx.span(), "expected a security context ID in hex"))
 }
 
 fn build_names(
@@ -56,6 +189,11 @@ fn build_names(
     ret_type: ReturnType,
     arg_names: Vec<Ident>,
     has_info: bool,
+    out_type: Option<Box<Type>>,
+    is_async: bool,
+    allowed_ctxs: Vec<u128>,
+    trace: bool,
+    encrypt_args: bool,
 ) -> Info {
     Info {
         mod_name: Ident::new(&format!("{}{}_mod", PREFIX, base), base.span()),
@@ -70,6 +208,11 @@ fn build_names(
         arg_names,
         ret_type,
         has_info,
+        out_type,
+        is_async,
+        allowed_ctxs,
+        trace,
+        encrypt_args,
     }
 }
 
@@ -104,6 +247,7 @@ fn handle_secure_gate(
 
     let opt_info: Ident = parse_quote!(info);
     let opt_api: Ident = parse_quote!(api);
+    let opt_out: Ident = parse_quote!(out);
 
     let entry_only = attr_args.options.iter().any(|item| item.is_ident(&opt_api));
 
@@ -154,10 +298,42 @@ fn handle_secure_gate(
         false
     };
 
+    let out_type = if attr_args.options.iter().any(|item| item.is_ident(&opt_out)) {
+        let min_args = if has_info { 2 } else { 1 };
+        if types.len() < min_args {
+            return Err(Error::new(
+                tree.sig.ident.span(),
+                "option out requires the last argument to be the out-param",
+            ));
+        }
+        Some(get_out_type(types.last().unwrap())?)
+    } else {
+        None
+    };
+
+    let allowed_ctxs: Vec<u128> = attr_args
+        .allow
+        .iter()
+        .map(|allow| parse_ctx_id(&allow.ctx))
+        .try_collect()?;
+
     let ret_type = tree.sig.output.clone();
 
+    let is_async = tree.sig.asyncness.is_some();
+
     let fn_name = tree.sig.ident.clone();
-    let names = build_names(fn_name, types, ret_type, arg_names, has_info);
+    let names = build_names(
+        fn_name,
+        types,
+        ret_type,
+        arg_names,
+        has_info,
+        out_type,
+        is_async,
+        allowed_ctxs,
+        attr_args.trace,
+        attr_args.encrypt_args,
+    );
     let trampoline = build_trampoline(&tree, &names)?;
     let extern_trampoline = build_extern_trampoline(&tree, &names)?;
     let public_call_point = build_public_call(&tree, &names)?;
@@ -211,8 +387,46 @@ fn handle_secure_gate(
     }
 }
 
+/// Get `T` from the type of an out-param argument, which must be `&mut OutParam<T>`.
+fn get_out_type(ty: &Type) -> Result<Box<Type>, Error> {
+    let err = || Error::new(ty.span(), "an out-param must have type `&mut OutParam<T>`");
+    let Type::Reference(tr) = ty else {
+        return Err(err());
+    };
+    if tr.mutability.is_none() {
+        return Err(err());
+    }
+    let Type::Path(path) = &*tr.elem else {
+        return Err(err());
+    };
+    let last = path.path.segments.last().ok_or_else(err)?;
+    if last.ident != "OutParam" {
+        return Err(err());
+    }
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return Err(err());
+    };
+    match args.args.first() {
+        Some(syn::GenericArgument::Type(out)) if args.args.len() == 1 => Ok(Box::new(out.clone())),
+        _ => Err(err()),
+    }
+}
+
+/// The arguments that are marshaled into the gate's Arguments, leaving out the info struct and the
+/// out-param, which are passed separately.
+fn marshaled<'a, T>(all: &'a [T], names: &Info) -> &'a [T] {
+    let start = if names.has_info { 1 } else { 0 };
+    let end = if names.out_type.is_some() {
+        all.len() - 1
+    } else {
+        all.len()
+    };
+    &all[start..end]
+}
+
 fn get_entry_sig(tree: &ItemFn) -> Signature {
     let mut sig = tree.sig.clone();
+    sig.asyncness = None;
     sig.abi = parse_quote!( extern "C" );
     sig.inputs = Punctuated::new();
     sig.inputs
@@ -236,6 +450,7 @@ fn build_trampoline(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenStr
         name: Some(LitStr::new("C", proc_macro2::Span::mixed_site())),
     });
     let entry_sig = get_entry_sig(tree);
+    call_point.sig.asyncness = None;
     call_point.sig.output = entry_sig.output;
     call_point.sig.inputs = entry_sig.inputs;
     call_point.sig.ident = names.trampoline_name.clone();
@@ -287,35 +502,114 @@ fn build_entry(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenStream,
         internal_fn_name,
         arg_names: all_arg_names,
         has_info,
+        is_async,
+        allowed_ctxs,
+        trace,
+        encrypt_args,
+        fn_name,
         ..
     } = names;
     call_point.sig.ident = entry_name.clone();
 
-    let arg_names = if *has_info {
-        &all_arg_names[1..]
+    // Each of these expands to nothing for untraced gates.
+    let gate_name = fn_name.to_string();
+    let trace_point = |call: TokenStream| {
+        if *trace {
+            quote! {secgate::#call;}
+        } else {
+            quote! {}
+        }
+    };
+    let trace_entry = trace_point(quote! {trace_gate_entry(#gate_name, unsafe {&*info})});
+    let trace_denied = trace_point(
+        quote! {trace_gate_exit(#gate_name, unsafe {&*info}, secgate::GateTraceStatus::Denied)},
+    );
+    let trace_failed = trace_point(
+        quote! {trace_gate_exit(#gate_name, unsafe {&*info}, secgate::GateTraceStatus::Err)},
+    );
+    let trace_result = trace_point(quote! {trace_gate_result(#gate_name, unsafe {&*info}, &wret)});
+
+    let acl_check = if allowed_ctxs.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            if secgate::check_caller_allowed(unsafe {&*info}, &[#(#allowed_ctxs),*]).is_err() {
+                #trace_denied
+                let ret = unsafe {ret.as_mut().unwrap()};
+                ret.fail(secgate::GateError::PermissionDenied);
+                return;
+            }
+        }
+    };
+
+    // Implementations that take the GateCallInfo may act on its source context, which the caller
+    // filled in, so have the kernel vouch for it first. The allowlist check already does this.
+    let source_check = if *has_info && allowed_ctxs.is_empty() {
+        quote! {
+            if secgate::check_source_context(unsafe {&*info}).is_err() {
+                #trace_denied
+                let ret = unsafe {ret.as_mut().unwrap()};
+                ret.fail(secgate::GateError::PermissionDenied);
+                return;
+            }
+        }
     } else {
-        all_arg_names
+        quote! {}
     };
 
+    let arg_names = marshaled(all_arg_names, names);
+
     let unpacked_args = if arg_names.is_empty() {
         quote! {}
+    } else if *encrypt_args {
+        // Decrypt into a copy, so the plaintext is never written back to the caller's memory.
+        quote! {
+            let args = match unsafe {secgate::open_gate_args(env!("CARGO_PKG_NAME"), #gate_name, &*info, args)} {
+                Ok(args) => args,
+                Err(e) => {
+                    #trace_failed
+                    let ret = unsafe {ret.as_mut().unwrap()};
+                    ret.fail(e);
+                    return;
+                }
+            };
+            let (#(#arg_names),*,) = args.into_inner();
+        }
     } else {
         quote! {let (#(#arg_names),*,) = unsafe {*args}.into_inner();}
     };
 
-    let call_args = if *has_info {
-        quote! {unsafe {&(*info).canonicalize()}, #(#arg_names),*}
+    let mut call_args: Vec<TokenStream> = arg_names.iter().map(|n| quote! {#n}).collect();
+    if *has_info {
+        call_args.insert(0, quote! {unsafe {(*info).canonicalized()}});
+    }
+    // The callee fills in the out-param directly in the caller's return memory.
+    let out_param = if names.out_type.is_some() {
+        call_args.push(quote! {out});
+        quote! {let out = unsafe {ret.as_mut().unwrap()}.out();}
+    } else {
+        quote! {}
+    };
+    let call_args = quote! {#(#call_args),*};
+
+    // Async implementations are run to completion here, so the caller sees a synchronous call.
+    let call_impl = if *is_async {
+        quote! {secgate::block_on_gate(#internal_fn_name(#call_args))}
     } else {
-        quote! {#(#arg_names),*}
+        quote! {#internal_fn_name(#call_args)}
     };
 
     call_point.block = Box::new(parse2(quote::quote! {
         {
+            #trace_entry
+            #acl_check
+            #source_check
             if unsafe {(*info)}.source_context().is_some() {
                 let pe_ret = secgate::runtime_preentry();
                 match pe_ret {
                     Ok(_) => {},
                     Err(e) => {
+                        #trace_failed
                         let ret = unsafe {ret.as_mut().unwrap()};
                         ret.set(Err(e));
                         return;
@@ -323,21 +617,19 @@ fn build_entry(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenStream,
                 }
             }
             #unpacked_args
+            #out_param
 
-            // Call the user-written implementation, catching unwinds.
-            let impl_ret = std::panic::catch_unwind(|| #internal_fn_name(#call_args));
-            // If we panic'd, report to user and return error.
-            if impl_ret.is_err() {
-                std::process::Termination::report(std::process::ExitCode::from(101u8));
-            }
-            let wret = match impl_ret {
-                Ok(r) => r,
-                Err(_) => Err(twizzler_rt_abi::error::GenericError::Internal.into()),
-            };
+            // Call the user-written implementation. A panic must not unwind back across the gate,
+            // so it's turned into an error for the caller.
+            let wret = secgate::catch_callee_panic(|| #call_impl);
+            #trace_result
 
-            // Success -- write the return value.
+            // Write the return value, or record that the implementation panicked.
             let ret = unsafe {ret.as_mut().unwrap()};
-            ret.set(wret);
+            match wret {
+                Ok(wret) => ret.set(wret),
+                Err(e) => ret.fail(e),
+            }
         }
     })?);
 
@@ -348,6 +640,7 @@ fn build_public_call(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenSt
     let mut call_point = tree.clone();
     call_point.attrs.push(parse_quote!(#[inline(always)]));
     call_point.vis = Visibility::Public(Pub::default());
+    call_point.sig.asyncness = None;
 
     let ret_type = names.ret_type.clone();
 
@@ -360,17 +653,30 @@ fn build_public_call(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenSt
     let Info {
         mod_name,
         trampoline_name_without_prefix,
-        arg_names,
+        arg_names: all_arg_names,
         has_info,
+        out_type,
+        encrypt_args,
+        fn_name,
         ..
     } = names;
 
-    let arg_names = if *has_info {
+    if *has_info {
         let args = call_point.sig.inputs.into_iter().skip(1).collect();
         call_point.sig.inputs = args;
-        &arg_names[1..]
+    }
+    let arg_names = marshaled(all_arg_names, names);
+
+    // The caller's out-param stays in its signature, and is written once the call returns. It is
+    // cleared up front, so it is unset if the call fails before reaching the gate.
+    let (clear_out, into_result) = if out_type.is_some() {
+        let out_name = all_arg_names.last().unwrap();
+        (
+            quote! {#out_name.clear();},
+            quote! {ret.into_gate_result(#out_name)},
+        )
     } else {
-        arg_names
+        (quote! {}, quote! {ret.into_gate_result()})
     };
 
     let args_tuple = if arg_names.is_empty() {
@@ -381,24 +687,41 @@ fn build_public_call(tree: &ItemFn, names: &Info) -> Result<proc_macro2::TokenSt
         }
     };
 
+    let seal_args = if *encrypt_args && !arg_names.is_empty() {
+        let gate_name = fn_name.to_string();
+        quote! {
+            if let Err(e) = unsafe {secgate::seal_gate_args(env!("CARGO_PKG_NAME"), #gate_name, info, args as *mut _)} {
+                return Err(e);
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     call_point.block = Box::new(parse2(quote::quote! {
         {
             #args_tuple
-            let frame = secgate::frame();
+            #clear_out
+            // Restores the caller's frame when dropped, even if we unwind.
+            let frame = secgate::FrameGuard::new();
+            let probe = secgate::StackProbe::new();
             // Allocate stack space for args + ret. Args::with_alloca also inits the memory.
             let ret = secgate::GateCallInfo::with_alloca(secgate::get_thread_id(), secgate::get_sctx_id(), |info| {
                 #mod_name::Args::with_alloca(tuple, |args| {
                     #mod_name::Ret::with_alloca(|ret| {
+                        info.set_call_sizes(#mod_name::ARGS_SIZE, #mod_name::RET_SIZE);
+                        #seal_args
+                        probe.record();
                         // Call the trampoline in the mod.
                         unsafe {
                             #mod_name::#trampoline_name_without_prefix(info as *const _, args as *const _, ret as *mut _);
                         }
-                        ret.into_inner()
+                        #into_result
                     })
                 })
             });
-            secgate::restore_frame(frame);
-            ret.ok_or(twizzler_rt_abi::error::ResourceError::Unavailable)?
+            drop(frame);
+            Ok(ret?)
         }
     })?);
 
@@ -432,9 +755,9 @@ fn build_types(tree: &ItemFn, names: &Info) -> Result<TokenStream, Error> {
         mod_name: _mod_name,
         entry_type_name,
         fn_name,
-        types,
+        types: all_types,
         ret_type,
-        has_info,
+        out_type,
         ..
     } = names;
     let entry_sig = get_entry_sig(tree);
@@ -469,7 +792,7 @@ fn build_types(tree: &ItemFn, names: &Info) -> Result<TokenStream, Error> {
     let mut name_bytes = fn_name.to_string().into_bytes();
     name_bytes.push(0);
 
-    let types = if *has_info { &types[1..] } else { types };
+    let types = marshaled(all_types, names);
 
     let arg_types = if types.is_empty() {
         quote! {secgate::Arguments<()>}
@@ -479,11 +802,29 @@ fn build_types(tree: &ItemFn, names: &Info) -> Result<TokenStream, Error> {
         }
     };
 
+    let ret_def = match out_type {
+        Some(out_type) => quote! {secgate::ReturnWithOut<#ret_type, #out_type>},
+        None => quote! {secgate::Return<#ret_type>},
+    };
+
+    // Arguments, the return value, and any out-param are copied between compartments, so their
+    // layout must be fixed. Check each one separately, so the error points at the offending
+    // type.
+    let layout_checks = types
+        .iter()
+        .chain(core::iter::once(&ret_type))
+        .chain(out_type)
+        .map(|ty| quote_spanned! {ty.span()=> assert_stable_layout::<#ty>();});
+
     Ok(quote! {
+        const _: () = {
+            const fn assert_stable_layout<T: secgate::StableLayout>() {}
+            #(#layout_checks)*
+        };
         #[allow(non_camel_case_types)]
         pub type #entry_type_name = #ty;
         pub type Args = #arg_types;
-        pub type Ret = secgate::Return<#ret_type>;
+        pub type Ret = #ret_def;
         pub const ARGS_SIZE: usize = core::mem::size_of::<Args>();
         pub const RET_SIZE: usize = core::mem::size_of::<Ret>();
     })
@@ -0,0 +1,151 @@
+//! Encryption of gate arguments in transit, for gates that pass secrets (keys, tokens).
+//!
+//! For a gate declared with `#[secure_gate(encrypt_args)]`, the caller encrypts the marshaled
+//! arguments with ChaCha20-Poly1305 before calling the gate, and the gate's entry point decrypts
+//! and authenticates them into its own copy before running the implementation. Each call uses a
+//! fresh random nonce, which travels in the [GateCallInfo] along with the authentication tag.
+//!
+//! Keys are per gate, and a gate is named by the compartment that exports it (its package name)
+//! together with its own name, so that gates with the same name in different compartments don't
+//! share a key. The two compartments each register the key with [set_gate_key], typically after
+//! deriving it from a master key they both hold with
+//! `twizzler_security::SigningKey::derive_gate_key`, so the key itself never crosses a gate.
+//!
+//! # Threat model
+//!
+//! This defends against passive capture of the argument bytes between caller and callee, such as
+//! a forwarding layer that copies (and maybe logs) raw arguments using
+//! [GateCallInfo::args_len], and the ciphertext is authenticated, so an intermediary that tampers
+//! with it gets the call refused. It does not protect the arguments from the callee, which
+//! decrypts them, or from anything that can read either compartment's memory, and with it the
+//! key. Nor does it stop a captured call from being replayed, and return values are not encrypted
+//! at all.
+
+use std::{collections::BTreeMap, fmt::Debug, marker::Tuple, mem::MaybeUninit, sync::Mutex};
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit};
+pub use twizzler_abi::security::GATE_KEY_LEN;
+use twizzler_abi::syscall::{sys_get_random, GetRandomFlags};
+
+use crate::{Arguments, Crossing, GateCallInfo, GateError};
+
+/// The length in bytes of the nonce an encrypted call carries in its [GateCallInfo].
+pub const ARGS_NONCE_LEN: usize = 12;
+
+/// The length in bytes of the authentication tag an encrypted call carries in its
+/// [GateCallInfo].
+pub const ARGS_TAG_LEN: usize = 16;
+
+/// A key for encrypting the arguments of calls to one gate.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct GateKey([u8; GATE_KEY_LEN]);
+
+impl GateKey {
+    pub fn from_bytes(bytes: [u8; GATE_KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Debug for GateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep the key out of logs.
+        write!(f, "GateKey(..)")
+    }
+}
+
+// Keyed by (compartment, gate).
+static GATE_KEYS: Mutex<BTreeMap<(String, String), GateKey>> = Mutex::new(BTreeMap::new());
+
+/// Set the key used to encrypt arguments for calls to `gate` in `compartment` made from this
+/// compartment, or, in the gate's own compartment, to decrypt them. Replaces any key already set
+/// for the gate.
+pub fn set_gate_key(compartment: &str, gate: &str, key: GateKey) {
+    GATE_KEYS
+        .lock()
+        .unwrap()
+        .insert((compartment.to_owned(), gate.to_owned()), key);
+}
+
+/// Forget the key for `gate` in `compartment`. Encrypted calls to or from it fail until a key is
+/// set again.
+pub fn clear_gate_key(compartment: &str, gate: &str) {
+    GATE_KEYS
+        .lock()
+        .unwrap()
+        .remove(&(compartment.to_owned(), gate.to_owned()));
+}
+
+fn gate_key(compartment: &str, gate: &str) -> Option<GateKey> {
+    GATE_KEYS
+        .lock()
+        .unwrap()
+        .get(&(compartment.to_owned(), gate.to_owned()))
+        .copied()
+}
+
+fn fresh_nonce() -> [u8; ARGS_NONCE_LEN] {
+    let mut nonce = [MaybeUninit::uninit(); ARGS_NONCE_LEN];
+    let mut filled = 0;
+    while filled < ARGS_NONCE_LEN {
+        filled += sys_get_random(&mut nonce[filled..], GetRandomFlags::empty())
+            .expect("failed to get randomness from kernel");
+    }
+    // Safety: the loop above filled every byte.
+    nonce.map(|b| unsafe { b.assume_init() })
+}
+
+/// Encrypt the arguments for a call to `gate` in `compartment` in place, and record the nonce and
+/// tag in `info`. Called by the caller side of gates declared with `encrypt_args`. Fails with
+/// [GateError::Unreachable], before anything is encrypted, if no key is set for the gate.
+///
+/// # Safety
+/// `args` must point to initialized arguments. Afterwards it holds ciphertext, and must not be read
+/// as arguments again.
+pub unsafe fn seal_gate_args<A: Tuple + Crossing + Copy>(
+    compartment: &str,
+    gate: &str,
+    info: &mut GateCallInfo,
+    args: *mut Arguments<A>,
+) -> Result<(), GateError> {
+    let key = gate_key(compartment, gate).ok_or(GateError::Unreachable)?;
+    let nonce = fresh_nonce();
+    // The arguments are encrypted as the raw bytes a forwarding layer would copy, padding included.
+    let bytes = std::slice::from_raw_parts_mut(args.cast::<u8>(), size_of::<Arguments<A>>());
+    let tag = ChaCha20Poly1305::new(&key.0.into())
+        .encrypt_in_place_detached(&nonce.into(), &[], bytes)
+        .map_err(|_| GateError::Unreachable)?;
+    info.args_sealed = true;
+    info.args_nonce = nonce;
+    info.args_tag = tag.into();
+    Ok(())
+}
+
+/// Decrypt the arguments of a call to `gate` in `compartment` into a copy, leaving the caller's
+/// ciphertext as it is. Called by the entry point of gates declared with `encrypt_args`. A call
+/// whose arguments weren't encrypted, or fail to authenticate, or one made while this compartment
+/// has no key for the gate, fails with [GateError::PermissionDenied].
+///
+/// # Safety
+/// `args` must point to arguments encrypted with [seal_gate_args].
+pub unsafe fn open_gate_args<A: Tuple + Crossing + Copy>(
+    compartment: &str,
+    gate: &str,
+    info: &GateCallInfo,
+    args: *const Arguments<A>,
+) -> Result<Arguments<A>, GateError> {
+    let Some(key) = gate_key(compartment, gate).filter(|_| info.args_sealed) else {
+        return Err(GateError::PermissionDenied);
+    };
+    let mut plain = MaybeUninit::<Arguments<A>>::uninit();
+    let len = size_of::<Arguments<A>>();
+    std::ptr::copy_nonoverlapping(args.cast::<u8>(), plain.as_mut_ptr().cast::<u8>(), len);
+    ChaCha20Poly1305::new(&key.0.into())
+        .decrypt_in_place_detached(
+            &info.args_nonce.into(),
+            &[],
+            std::slice::from_raw_parts_mut(plain.as_mut_ptr().cast::<u8>(), len),
+            &info.args_tag.into(),
+        )
+        .map_err(|_| GateError::PermissionDenied)?;
+    Ok(plain.assume_init())
+}
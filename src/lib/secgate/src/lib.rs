@@ -9,18 +9,36 @@
 
 use core::ffi::{c_char, CStr};
 use std::{
-    cell::UnsafeCell,
+    cell::{RefCell, UnsafeCell},
     fmt::Debug,
+    future::Future,
     marker::{PhantomData, Tuple},
     mem::MaybeUninit,
+    panic::AssertUnwindSafe,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    task::{Context, Wake, Waker},
 };
 
+// Lets the derives from secgate-macros, which name `secgate::...`, be used in this crate.
+extern crate self as secgate;
+
 pub use secgate_macros::*;
 use twizzler_abi::object::ObjID;
-use twizzler_rt_abi::error::{ResourceError, TwzError};
+use twizzler_rt_abi::error::{GenericError, ResourceError, TwzError};
 
+mod encrypt;
+mod registry;
+mod trace;
 pub mod util;
 
+pub use encrypt::*;
+pub use registry::*;
+pub use trace::*;
+
 /// A struct of information about a secure gate. These are auto-generated by the
 /// [crate::secure_gate] macro, and stored in a special ELF section (.twz_secgate_info) as an array.
 /// The dynamic linker and monitor can then use this to easily enumerate gates.
@@ -81,6 +99,11 @@ impl<Args: Tuple + Crossing + Copy> Arguments<Args> {
     where
         F: FnOnce(&mut Self) -> R,
     {
+        if size_of::<Self>() == 0 {
+            // Nothing to marshal (e.g. a gate that takes no arguments), so skip the alloca. The
+            // callee never reads through the pointer to a zero-sized value.
+            return f(&mut Self { args });
+        }
         alloca::alloca(|stack_space| {
             stack_space.write(Self { args });
             // Safety: we init the MaybeUninit just above.
@@ -88,17 +111,36 @@ impl<Args: Tuple + Crossing + Copy> Arguments<Args> {
         })
     }
 
+    /// Like [Self::with_alloca], but only allocates if the arguments take at most `max` bytes.
+    /// Otherwise, `oversized` is called with their size instead, so a call with an unreasonably
+    /// large argument tuple can fail cleanly rather than overflow the stack.
+    /// [DEFAULT_MAX_ARGS_SIZE] is a reasonable limit for most callers.
+    pub fn try_with_alloca<F, E, R>(args: Args, max: usize, f: F, oversized: E) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+        E: FnOnce(usize) -> R,
+    {
+        if size_of::<Self>() > max {
+            return oversized(size_of::<Self>());
+        }
+        Self::with_alloca(args, f)
+    }
+
     pub fn into_inner(self) -> Args {
         self.args
     }
 }
 
+/// The default limit, in bytes, on the size of gate arguments for [Arguments::try_with_alloca].
+pub const DEFAULT_MAX_ARGS_SIZE: usize = 0x4000;
+
 /// Return value to be filled by the secure call. Concrete versions of this are generated by the
 /// macro.
 #[derive(Copy)]
 #[repr(C)]
 pub struct Return<T: Crossing + Copy> {
     isset: bool,
+    failure: Option<GateError>,
     ret: MaybeUninit<T>,
 }
 
@@ -116,6 +158,7 @@ impl<T: Crossing + Copy> Return<T> {
         alloca::alloca(|stack_space| {
             stack_space.write(Self {
                 isset: false,
+                failure: None,
                 ret: MaybeUninit::uninit(),
             });
             // Safety: we init the MaybeUninit just above.
@@ -137,6 +180,7 @@ impl<T: Crossing + Copy> Return<T> {
     pub fn new_uninit() -> Self {
         Self {
             isset: false,
+            failure: None,
             ret: MaybeUninit::uninit(),
         }
     }
@@ -146,11 +190,189 @@ impl<T: Crossing + Copy> Return<T> {
         self.ret.write(val);
         self.isset = true;
     }
+
+    /// Record that the gate call failed without the callee producing a value.
+    pub fn fail(&mut self, err: GateError) {
+        self.failure = Some(err);
+    }
+}
+
+impl<R: Crossing + Copy> Return<Result<R, TwzError>> {
+    /// Convert the outcome of a gate call into a result, distinguishing how the call failed.
+    pub fn into_gate_result(self) -> Result<R, GateError> {
+        if let Some(err) = self.failure {
+            return Err(err);
+        }
+        match self.into_inner() {
+            Some(ret) => ret.map_err(GateError::Callee),
+            None => Err(GateError::NoReturn),
+        }
+    }
+}
+
+/// A second value a gate hands back alongside its return value, for gates declared with
+/// `options(out)`. The caller passes a `&mut OutParam<T>` as the gate's last argument and reads it
+/// with [OutParam::get] once the call returns; the callee fills it in with [OutParam::set].
+///
+/// The out-param is delivered whenever the callee's implementation returned, whether with `Ok` or
+/// with `Err`, so a gate can report (say) how many bytes it wrote before failing. If the callee
+/// didn't set it, or the call failed without the implementation returning (it was denied, the
+/// callee panicked, and so on), the caller's out-param is left unset, and [OutParam::get] returns
+/// None. Whatever the caller's out-param held before the call is never seen by the callee, and is
+/// replaced in every case.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct OutParam<T: Crossing + Copy> {
+    isset: bool,
+    val: MaybeUninit<T>,
+}
+
+impl<T: Crossing + Copy> OutParam<T> {
+    /// Construct a new, unset out-param.
+    pub fn new() -> Self {
+        Self {
+            isset: false,
+            val: MaybeUninit::uninit(),
+        }
+    }
+
+    /// Set the value. Future calls to get will return Some(val).
+    pub fn set(&mut self, val: T) {
+        self.val.write(val);
+        self.isset = true;
+    }
+
+    /// Get the value, or None if it hasn't been set.
+    pub fn get(&self) -> Option<T> {
+        if self.isset {
+            Some(unsafe { self.val.assume_init() })
+        } else {
+            None
+        }
+    }
+
+    /// Unset the value.
+    pub fn clear(&mut self) {
+        self.isset = false;
+    }
+}
+
+impl<T: Crossing + Copy> Default for OutParam<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return value and out-param to be filled by a secure call to a gate declared with
+/// `options(out)`. Concrete versions of this are generated by the macro.
+///
+/// The out-param travels in the same caller-allocated memory as the return value, laid out
+/// directly after it (`#[repr(C)]`, so with padding to the out-param's alignment), so the gate ABI
+/// is unchanged: the callee still gets a single return pointer, and [GateCallInfo::ret_len] covers
+/// both.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ReturnWithOut<T: Crossing + Copy, O: Crossing + Copy> {
+    ret: Return<T>,
+    out: OutParam<O>,
+}
+
+impl<T: Crossing + Copy, O: Crossing + Copy> ReturnWithOut<T, O> {
+    pub fn with_alloca<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        alloca::alloca(|stack_space| {
+            stack_space.write(Self {
+                ret: Return::new_uninit(),
+                out: OutParam::new(),
+            });
+            // Safety: we init the MaybeUninit just above.
+            f(unsafe { stack_space.assume_init_mut() })
+        })
+    }
+
+    /// Set the return value. See [Return::set].
+    pub fn set(&mut self, val: T) {
+        self.ret.set(val);
+    }
+
+    /// Record that the gate call failed. See [Return::fail].
+    pub fn fail(&mut self, err: GateError) {
+        self.ret.fail(err);
+    }
+
+    /// The out-param, for the callee to fill in. It is cleared first, so the callee never sees
+    /// what the caller's memory held.
+    pub fn out(&mut self) -> &mut OutParam<O> {
+        self.out.clear();
+        &mut self.out
+    }
+}
+
+impl<R: Crossing + Copy, O: Crossing + Copy> ReturnWithOut<Result<R, TwzError>, O> {
+    /// Convert the outcome of a gate call into a result, as [Return::into_gate_result] does, and
+    /// deliver the out-param into `out` (see [OutParam] for when it is set).
+    pub fn into_gate_result(self, out: &mut OutParam<O>) -> Result<R, GateError> {
+        *out = if self.ret.isset && self.ret.failure.is_none() {
+            self.out
+        } else {
+            OutParam::new()
+        };
+        self.ret.into_gate_result()
+    }
+}
+
+/// Ways in which a secure gate call can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum GateError {
+    /// The gate could not be called (e.g. it has no entry point).
+    Unreachable,
+    /// The gate returned without setting a return value.
+    NoReturn,
+    /// The call gave up waiting for the gate to return.
+    Timeout,
+    /// The caller is not allowed to call this gate.
+    PermissionDenied,
+    /// The gate's implementation panicked.
+    CalleePanicked,
+    /// The gate's implementation ran, and returned an error. The error is exactly the one the
+    /// callee returned, and converting this into a TwzError gives it back unchanged.
+    Callee(TwzError),
+}
+
+impl core::fmt::Display for GateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GateError::Unreachable => write!(f, "gate unreachable"),
+            GateError::NoReturn => write!(f, "gate did not return a value"),
+            GateError::Timeout => write!(f, "gate call timed out"),
+            GateError::PermissionDenied => write!(f, "gate call not permitted"),
+            GateError::CalleePanicked => write!(f, "gate panicked"),
+            GateError::Callee(e) => write!(f, "gate returned error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GateError {}
+
+impl From<GateError> for TwzError {
+    fn from(value: GateError) -> Self {
+        match value {
+            GateError::Unreachable | GateError::NoReturn => ResourceError::Unavailable.into(),
+            GateError::Timeout => GenericError::TimedOut.into(),
+            GateError::PermissionDenied => GenericError::AccessDenied.into(),
+            GateError::CalleePanicked => GenericError::Internal.into(),
+            GateError::Callee(e) => e,
+        }
+    }
 }
 
 /// An auto trait that limits the types that can be send across to another compartment. These are:
 /// 1. Types other than references, UnsafeCell, raw pointers, slices.
 /// 2. #[repr(C)] structs and enums made from Crossing types.
+/// 3. [Option] and `Result<T, TwzError>` of Crossing types, so gates can return them directly.
 ///
 /// # Safety
 /// The type must meet the above requirements.
@@ -165,6 +387,106 @@ impl<T> !Crossing for &[T] {}
 impl<T> !Crossing for &mut [T] {}
 
 unsafe impl<T: Crossing + Copy> Crossing for Result<T, TwzError> {}
+unsafe impl<T: Crossing + Copy> Crossing for Option<T> {}
+
+/// Types whose layout is fixed, and so is the same in every compartment. Every argument and return
+/// type of a [secure_gate] must implement this, which the macro checks at compile time. Crossing
+/// alone is not enough: it is an auto trait, so a `#[repr(Rust)]` struct of Crossing fields is
+/// Crossing, but its layout may differ between compiler invocations.
+///
+/// Implement this with `#[derive(StableLayout)]`, which fails to compile unless the type is
+/// `#[repr(C)]` or `#[repr(transparent)]` (or, for enums, has a primitive representation), and all
+/// its fields are StableLayout.
+///
+/// Primitives, raw pointers, arrays, and the C ABI types from twizzler-rt-abi implement this. So do
+/// tuples, [Option], and `Result<T, TwzError>` of StableLayout types, even though they are
+/// `#[repr(Rust)]`; see the safety comment on those impls for why.
+///
+/// # Safety
+/// The type's layout must not depend on the compiler invocation.
+///
+/// ```compile_fail
+/// // A repr(Rust) struct can't be used as a gate argument.
+/// #[derive(Clone, Copy)]
+/// struct NotC {
+///     x: u32,
+///     y: u64,
+/// }
+///
+/// #[secgate::secure_gate(options(api))]
+/// pub fn takes_not_c(arg: NotC) -> Result<u32, twizzler_rt_abi::error::TwzError> {
+///     Ok(0)
+/// }
+/// ```
+///
+/// Adding `#[repr(C)]` and `#[derive(StableLayout)]` to `NotC` fixes this.
+pub unsafe trait StableLayout {}
+
+macro_rules! stable_layout {
+    ($($ty:ty),* $(,)?) => {
+        $(unsafe impl StableLayout for $ty {})*
+    };
+}
+
+macro_rules! stable_layout_tuple {
+    ($($name:ident),+) => {
+        unsafe impl<$($name: StableLayout),+> StableLayout for ($($name,)+) {}
+    };
+}
+
+stable_layout!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+);
+
+// C ABI types from twizzler-rt-abi.
+stable_layout!(
+    ObjID,
+    TwzError,
+    twizzler_rt_abi::object::MapFlags,
+    twizzler_rt_abi::thread::ThreadSpawnArgs,
+    twizzler_rt_abi::debug::DlPhdrInfo,
+    twizzler_rt_abi::debug::LinkMap,
+);
+
+// Safety: tuples, Option, and Result are repr(Rust), so in general their layout is up to the
+// compiler. But the gate ABI itself is built out of them: every call passes its arguments as a
+// tuple inside Arguments, and its return value as a Result inside Return. Gates therefore only work
+// at all between compartments built by the same compiler, for the same target, with the same
+// layout flags (in particular, without -Zrandomize-layout), which is how the build system builds
+// every compartment. Under that requirement, these types, composed only of StableLayout types,
+// have the same layout in every compartment. This is only assumed for these types, which can't be
+// given a fixed repr and which the gate ABI can't do without. Types that can have a fixed repr must
+// use one, so that they don't add to what compartments have to agree on.
+stable_layout_tuple!(A);
+stable_layout_tuple!(A, B);
+stable_layout_tuple!(A, B, C);
+stable_layout_tuple!(A, B, C, D);
+stable_layout_tuple!(A, B, C, D, E);
+stable_layout_tuple!(A, B, C, D, E, F);
+stable_layout_tuple!(A, B, C, D, E, F, G);
+stable_layout_tuple!(A, B, C, D, E, F, G, H);
+
+unsafe impl<T> StableLayout for *const T {}
+unsafe impl<T> StableLayout for *mut T {}
+unsafe impl<T: StableLayout, const N: usize> StableLayout for [T; N] {}
+unsafe impl<T: StableLayout> StableLayout for Option<T> {}
+unsafe impl<T: StableLayout> StableLayout for Result<T, TwzError> {}
 
 /// Required to put in your source if you call any secure gates.
 // TODO: this isn't ideal, but it's the only solution I have at the moment. For some reason,
@@ -185,16 +507,38 @@ macro_rules! secgate_prelude {
 pub struct GateCallInfo {
     thread_id: ObjID,
     src_ctx: ObjID,
+    // Added after the IDs, so that their offsets don't change.
+    args_len: usize,
+    ret_len: usize,
+    // For gates declared with encrypt_args: whether the arguments were encrypted, and if so, the
+    // nonce and authentication tag they were encrypted with.
+    args_sealed: bool,
+    args_nonce: [u8; ARGS_NONCE_LEN],
+    args_tag: [u8; ARGS_TAG_LEN],
 }
 
 impl GateCallInfo {
+    /// Construct a new GateCallInfo. A zero ID for either field means "unknown" for the thread, and
+    /// "not cross-context" for the source context.
+    pub fn new(thread_id: ObjID, src_ctx: ObjID) -> Self {
+        Self {
+            thread_id,
+            src_ctx,
+            args_len: 0,
+            ret_len: 0,
+            args_sealed: false,
+            args_nonce: [0; ARGS_NONCE_LEN],
+            args_tag: [0; ARGS_TAG_LEN],
+        }
+    }
+
     /// Allocate a new GateCallInfo on the stack for the closure.
     pub fn with_alloca<F, R>(thread_id: ObjID, src_ctx: ObjID, f: F) -> R
     where
         F: FnOnce(&mut Self) -> R,
     {
         alloca::alloca(|stack_space| {
-            stack_space.write(Self { thread_id, src_ctx });
+            stack_space.write(Self::new(thread_id, src_ctx));
             // Safety: we init the MaybeUninit just above.
             f(unsafe { stack_space.assume_init_mut() })
         })
@@ -209,22 +553,60 @@ impl GateCallInfo {
         }
     }
 
-    /// Get the ID of the calling thread.
+    /// Get the ID of the calling thread. If the caller didn't fill it in, this asks the kernel.
     pub fn thread_id(&self) -> ObjID {
         if self.thread_id.raw() == 0 {
+            #[cfg(test)]
+            THREAD_ID_QUERIES.with(|queries| queries.set(queries.get() + 1));
             twizzler_abi::syscall::sys_thread_self_id()
         } else {
             self.thread_id
         }
     }
 
+    /// Record the sizes of the call's marshaled arguments and return value. Callers fill these in
+    /// before calling the gate.
+    pub fn set_call_sizes(&mut self, args_len: usize, ret_len: usize) {
+        self.args_len = args_len;
+        self.ret_len = ret_len;
+    }
+
+    /// The size in bytes of the call's marshaled arguments. A callee that forwards calls can use
+    /// this to copy the raw argument bytes without knowing their type.
+    pub fn args_len(&self) -> usize {
+        self.args_len
+    }
+
+    /// The size in bytes of the space the caller set aside for the return value.
+    pub fn ret_len(&self) -> usize {
+        self.ret_len
+    }
+
     /// Ensures that the data is filled out (may read thread ID from kernel if necessary).
     pub fn canonicalize(self) -> Self {
         Self {
             thread_id: self.thread_id(),
-            src_ctx: self.src_ctx,
+            ..self
         }
     }
+
+    /// Access info that the caller already canonicalized. Gate callers fill in the thread ID
+    /// before the call, so the callee need not pay for a syscall to find it on every call. Debug
+    /// builds check that this was done; otherwise, a missing ID is still looked up on use.
+    pub fn canonicalized(&self) -> &Self {
+        debug_assert_ne!(
+            self.thread_id.raw(),
+            0,
+            "gate called without a thread ID in its GateCallInfo"
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // Counts how many times a GateCallInfo had to ask the kernel for the thread ID on this thread.
+    static THREAD_ID_QUERIES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
 }
 
 pub fn get_thread_id() -> ObjID {
@@ -239,6 +621,106 @@ pub fn runtime_preentry() -> Result<(), TwzError> {
     twizzler_rt_abi::core::twz_rt_cross_compartment_entry()
 }
 
+/// Check that the source context a gate call claims is one the calling thread really holds. The
+/// caller fills in the [GateCallInfo] itself, so a callee that acts on the source context must not
+/// trust it unchecked. The kernel knows which contexts are attached to the thread, and a thread
+/// can only claim calls on behalf of those (see [dynamic_gate_call_as]). Calls without a source
+/// context pass.
+pub fn check_source_context(info: &GateCallInfo) -> Result<(), TwzError> {
+    match info.source_context() {
+        Some(src) if !twizzler_abi::syscall::sys_thread_has_sctx(src) => {
+            Err(GenericError::AccessDenied.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that a gate call comes from one of the allowed source contexts (given as raw object IDs),
+/// as declared with `#[secure_gate(allow(ctx = "..."))]`. Calls without a source context are
+/// denied, and the source context must be attached to the calling thread according to the kernel
+/// (see [check_source_context]), so a caller can't get in by naming an allowed context it doesn't
+/// hold. Calls claiming to come from the callee's own (active) context are denied too, since every
+/// caller holds that context once it has crossed into the gate.
+///
+/// This still isn't proof of where the call came from: the kernel doesn't record which context
+/// was active before the crossing, so a caller that holds an allowed context, but made the call
+/// from another one, passes.
+pub fn check_caller_allowed(info: &GateCallInfo, allowed: &[u128]) -> Result<(), TwzError> {
+    let src = info.source_context().ok_or(GenericError::AccessDenied)?;
+    if !allowed.contains(&src.raw()) || src == get_sctx_id() {
+        return Err(GenericError::AccessDenied.into());
+    }
+    check_source_context(info)
+}
+
+/// Run the implementation of a secure gate, converting a panic into an error for the caller.
+///
+/// The caller reaches a gate through a raw call into the trampoline, so a panic must never unwind
+/// out of the gate's entry point. Instead, the panic is caught here (after the panic hook has
+/// reported it), and the caller gets back [GateError::CalleePanicked].
+pub fn catch_callee_panic<T>(
+    f: impl FnOnce() -> Result<T, TwzError>,
+) -> Result<Result<T, TwzError>, GateError> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(|_| GateError::CalleePanicked)
+}
+
+/// Drives the futures of async secure gates to completion, in the callee's compartment.
+///
+/// An async gate (a [secure_gate] on an `async fn`) still looks synchronous to its caller: the
+/// gate's entry point hands the future to [block_on_gate], which must not return until the future
+/// has resolved. The calling thread is the one running the gate, so the executor must be able to
+/// make progress on this future from it, without relying on the calling thread being free to do
+/// other work. In particular, whatever wakes the future (timers, I/O completions) must run on some
+/// other thread, or be driven by the executor itself while it waits.
+pub trait GateExecutor: Sync {
+    /// Run the future until it resolves.
+    fn block_on(&self, fut: Pin<&mut dyn Future<Output = ()>>);
+}
+
+static GATE_EXECUTOR: OnceLock<&'static dyn GateExecutor> = OnceLock::new();
+
+/// Set the executor that runs async gates in this compartment. This can only be set once, and
+/// returns the executor back if one was already set. Without one, async gates are run by parking
+/// the calling thread until the future is woken.
+pub fn set_gate_executor(
+    executor: &'static dyn GateExecutor,
+) -> Result<(), &'static dyn GateExecutor> {
+    GATE_EXECUTOR.set(executor)
+}
+
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn park_block_on(mut fut: Pin<&mut dyn Future<Output = ()>>) {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    while fut.as_mut().poll(&mut cx).is_pending() {
+        std::thread::park();
+    }
+}
+
+/// Run the future of an async gate to completion on this compartment's [GateExecutor]. Called by
+/// the entry point that [secure_gate] generates for async gates.
+pub fn block_on_gate<F: Future>(fut: F) -> F::Output {
+    let mut out = None;
+    {
+        let mut task = pin!(async {
+            out = Some(fut.await);
+        });
+        match GATE_EXECUTOR.get() {
+            Some(executor) => executor.block_on(task.as_mut()),
+            None => park_block_on(task.as_mut()),
+        }
+    }
+    out.expect("gate executor returned before the gate's future resolved")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SecFrame {
     tp: usize,
     sctx: ObjID,
@@ -258,27 +740,168 @@ pub fn frame() -> SecFrame {
 }
 
 pub fn restore_frame(frame: SecFrame) {
-    if frame.tp != 0 {
-        twizzler_abi::syscall::sys_thread_settls(frame.tp as u64);
-    }
+    restore_tp(frame.tp);
     twizzler_abi::syscall::sys_thread_set_active_sctx_id(frame.sctx).unwrap();
 }
 
+fn restore_tp(tp: usize) {
+    if tp != 0 {
+        twizzler_abi::syscall::sys_thread_settls(tp as u64);
+    }
+}
+
+thread_local! {
+    // Frames saved by live FrameGuards on this thread, outermost first.
+    static FRAME_STACK: RefCell<Vec<SecFrame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The number of frames currently saved on this thread's frame stack, that is, how many gate calls
+/// made by this compartment on this thread have not yet returned.
+pub fn frame_depth() -> usize {
+    FRAME_STACK.with_borrow(|stack| stack.len())
+}
+
+/// Saves the current [SecFrame] on a per-thread stack, and restores it when dropped. Because the
+/// restore happens in drop, the caller's frame is restored even if we unwind out of a gate call.
+///
+/// Each guard remembers its position in the stack, so nested gate calls always restore their own
+/// frame, and a guard that is dropped while deeper frames are still saved discards those deeper
+/// frames rather than leaving them to be restored later.
+pub struct FrameGuard {
+    // Saved outside of the stack too, since the stack lives in thread-local storage, and we may
+    // need to switch back to the caller's thread pointer before we can reach it.
+    tp: usize,
+    depth: usize,
+}
+
+impl FrameGuard {
+    /// Save the current frame.
+    pub fn new() -> Self {
+        let frame = frame();
+        let depth = FRAME_STACK.with_borrow_mut(|stack| {
+            stack.push(frame);
+            stack.len() - 1
+        });
+        Self {
+            tp: frame.tp,
+            depth,
+        }
+    }
+}
+
+impl Default for FrameGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        restore_tp(self.tp);
+        let frame = FRAME_STACK.with_borrow_mut(|stack| {
+            if stack.len() <= self.depth {
+                return None;
+            }
+            stack.truncate(self.depth + 1);
+            stack.pop()
+        });
+        if let Some(frame) = frame {
+            restore_frame(frame);
+        }
+    }
+}
+
+// The deepest any gate call's boundary has reached into the caller's stack, in bytes.
+static GATE_STACK_HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+/// The most stack, in bytes, that the gate-call boundary (the call info, arguments, and return
+/// value it allocates on the caller's stack) has used in any gate call so far. Use this to size
+/// compartment stacks. Only measured in debug builds; always 0 in release builds.
+pub fn gate_stack_high_water() -> usize {
+    GATE_STACK_HIGH_WATER.load(Ordering::Relaxed)
+}
+
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rsp", out(reg) sp, options(nomem, nostack, preserves_flags));
+        #[cfg(not(target_arch = "x86_64"))]
+        core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags));
+    }
+    sp
+}
+
+/// Measures the stack used between where it's created and where [StackProbe::record] is called,
+/// feeding [gate_stack_high_water]. Compiled out in release builds.
+#[derive(Clone, Copy)]
+pub struct StackProbe {
+    #[cfg(debug_assertions)]
+    base: usize,
+}
+
+impl StackProbe {
+    /// Mark the stack position before a gate call allocates anything.
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            base: stack_pointer(),
+        }
+    }
+
+    /// Record the stack used since this probe was created (the stack grows down).
+    #[inline(always)]
+    pub fn record(&self) {
+        #[cfg(debug_assertions)]
+        {
+            let used = self.base.saturating_sub(stack_pointer());
+            GATE_STACK_HIGH_WATER.fetch_max(used, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for StackProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The lifecycle of the compartment a [DynamicSecGate] calls into, as far as gate calls are
+/// concerned. Whatever hands out gates for a compartment (e.g. monitor-api's compartment handles)
+/// implements this, so that secgate needn't know how compartments are managed.
+///
+/// # Teardown races
+/// A gate call checks [TargetLifecycle::is_tearing_down] as its very last step before jumping to
+/// the gate, and fails with [GateError::Unreachable] if it's set, instead of jumping into code that
+/// may no longer be mapped. This is still check-then-act: teardown can begin after the check passes
+/// and before the callee is entered. Closing that window entirely would need the monitor to hold
+/// off teardown for the length of every call. What bounds it is that a gate borrows the handle it
+/// came from (the `'comp` lifetime), and the monitor doesn't unmap a compartment while handles to it
+/// are alive, so the check's job is to catch compartments that have exited, or are running
+/// destructors, but are still mapped.
+pub trait TargetLifecycle: Sync {
+    /// Whether the compartment is being torn down, or already has been.
+    fn is_tearing_down(&self) -> bool;
+}
+
 #[derive(Clone, Copy)]
 pub struct DynamicSecGate<'comp, A, R> {
     address: usize,
+    target: Option<&'comp dyn TargetLifecycle>,
     _pd: PhantomData<&'comp (A, R)>,
 }
 
 impl<'a, A: Tuple + Crossing + Copy, R: Crossing + Copy> Fn<A> for DynamicSecGate<'a, A, R> {
     extern "rust-call" fn call(&self, args: A) -> Self::Output {
-        unsafe { dynamic_gate_call(*self, args) }
+        unsafe { dynamic_gate_call(*self, args) }.map_err(Into::into)
     }
 }
 
 impl<'a, A: Tuple + Crossing + Copy, R: Crossing + Copy> FnMut<A> for DynamicSecGate<'a, A, R> {
     extern "rust-call" fn call_mut(&mut self, args: A) -> Self::Output {
-        unsafe { dynamic_gate_call(*self, args) }
+        unsafe { dynamic_gate_call(*self, args) }.map_err(Into::into)
     }
 }
 
@@ -286,7 +909,7 @@ impl<'a, A: Tuple + Crossing + Copy, R: Crossing + Copy> FnOnce<A> for DynamicSe
     type Output = Result<R, TwzError>;
 
     extern "rust-call" fn call_once(self, args: A) -> Self::Output {
-        unsafe { dynamic_gate_call(self, args) }
+        unsafe { dynamic_gate_call(self, args) }.map_err(Into::into)
     }
 }
 
@@ -306,20 +929,84 @@ impl<'comp, A, R> DynamicSecGate<'comp, A, R> {
     pub unsafe fn new(address: usize) -> Self {
         Self {
             address,
+            target: None,
             _pd: PhantomData,
         }
     }
+
+    /// Check `target` before each call through this gate, and fail the call with
+    /// [GateError::Unreachable] if it's being torn down. See [TargetLifecycle].
+    pub fn with_lifecycle(self, target: &'comp dyn TargetLifecycle) -> Self {
+        Self {
+            target: Some(target),
+            ..self
+        }
+    }
+
+    fn target_tearing_down(&self) -> bool {
+        self.target.is_some_and(|target| target.is_tearing_down())
+    }
 }
 
+/// Call a gate at a dynamically-known address.
+///
+/// # Safety
+/// The address must point to a secure gate trampoline that takes arguments A and returns R.
 pub unsafe fn dynamic_gate_call<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
     target: DynamicSecGate<A, R>,
     args: A,
-) -> Result<R, TwzError> {
-    let frame = frame();
+) -> Result<R, GateError> {
+    unsafe { gate_call_from(target, args, get_sctx_id()) }
+}
+
+/// Call a gate at a dynamically-known address on behalf of the security context `as_ctx`, which
+/// the callee then sees as the source context of the call. This lets a dispatcher forward a call
+/// for one of its clients. The calling thread must have `as_ctx` attached; otherwise, the call
+/// fails with [GateError::PermissionDenied]. This is checked here to fail early, but the callee
+/// checks it again with the kernel (see [check_source_context]), since it can't trust the caller
+/// to have done so.
+///
+/// # Safety
+/// The address must point to a secure gate trampoline that takes arguments A and returns R.
+pub unsafe fn dynamic_gate_call_as<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+    target: DynamicSecGate<A, R>,
+    args: A,
+    as_ctx: ObjID,
+) -> Result<R, GateError> {
+    check_may_act_as(as_ctx)?;
+    unsafe { gate_call_from(target, args, as_ctx) }
+}
+
+fn check_may_act_as(ctx: ObjID) -> Result<(), GateError> {
+    if ctx.raw() == 0 || !twizzler_abi::syscall::sys_thread_has_sctx(ctx) {
+        return Err(GateError::PermissionDenied);
+    }
+    Ok(())
+}
+
+unsafe fn gate_call_from<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+    target: DynamicSecGate<A, R>,
+    args: A,
+    src_ctx: ObjID,
+) -> Result<R, GateError> {
+    if target.address == 0 {
+        return Err(GateError::Unreachable);
+    }
+    let frame = FrameGuard::new();
+    let probe = StackProbe::new();
     // Allocate stack space for args + ret. Args::with_alloca also inits the memory.
-    let ret = GateCallInfo::with_alloca(get_thread_id(), get_sctx_id(), |info| {
+    let ret = GateCallInfo::with_alloca(get_thread_id(), src_ctx, |info| {
         Arguments::<A>::with_alloca(args, |args| {
             Return::<Result<R, TwzError>>::with_alloca(|ret| {
+                info.set_call_sizes(
+                    size_of::<Arguments<A>>(),
+                    size_of::<Return<Result<R, TwzError>>>(),
+                );
+                probe.record();
+                // Check this last, to keep the window for a teardown to go unnoticed small.
+                if target.target_tearing_down() {
+                    return Err(GateError::Unreachable);
+                }
                 // Call the trampoline in the mod.
                 unsafe {
                         //#mod_name::#trampoline_name_without_prefix(info as *const _, args as *const _, ret as *mut _);
@@ -328,10 +1015,697 @@ pub unsafe fn dynamic_gate_call<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
                         #[cfg(not(target_arch = "x86_64"))]
                         todo!()
                     }
-                ret.into_inner()
+                ret.into_gate_result()
             })
         })
     });
-    restore_frame(frame);
-    ret.ok_or(ResourceError::Unavailable)?
+    drop(frame);
+    ret
+}
+
+/// Something that provides secure gates by name, and that can load whatever provides those gates
+/// on demand. See [call_or_load].
+pub trait GateProvider {
+    /// Look up the named gate.
+    ///
+    /// # Safety
+    /// The caller must ensure that the named gate takes arguments A and returns R.
+    unsafe fn lookup_gate<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+        &self,
+        name: &str,
+    ) -> Result<DynamicSecGate<'_, A, R>, TwzError>;
+
+    /// Load the compartment that provides this handle's gates.
+    fn load_provider(&self) -> Result<(), TwzError>;
+}
+
+fn is_unavailable(e: &TwzError) -> bool {
+    *e == TwzError::from(ResourceError::Unavailable)
+}
+
+/// Call the named gate through `handle`. If the gate isn't available, because the compartment
+/// providing it isn't loaded yet, ask the handle to load it and try once more. An error from the
+/// load is returned as-is.
+///
+/// # Safety
+/// The caller must ensure that the named gate takes arguments A and returns R.
+pub unsafe fn call_or_load<P: GateProvider, A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+    handle: &P,
+    name: &str,
+    args: A,
+) -> Result<R, TwzError> {
+    let first = unsafe { handle.lookup_gate::<A, R>(name) }
+        .and_then(|gate| unsafe { dynamic_gate_call(gate, args) }.map_err(Into::into));
+    match first {
+        Err(e) if is_unavailable(&e) => {
+            handle.load_provider()?;
+            let gate = unsafe { handle.lookup_gate::<A, R>(name) }?;
+            unsafe { dynamic_gate_call(gate, args) }.map_err(Into::into)
+        }
+        r => r,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn caller_allowlist() {
+        use twizzler_abi::syscall::{sys_object_create, sys_sctx_attach, ObjectCreate};
+
+        let thread = get_thread_id();
+        let own = get_sctx_id();
+        let attached = sys_object_create(ObjectCreate::default(), &[], &[]).unwrap();
+        sys_sctx_attach(attached).unwrap();
+        let stranger = ObjID::new(0x5678);
+        let allowed = [own.raw(), attached.raw(), stranger.raw()];
+
+        let info = GateCallInfo::new(thread, attached);
+        assert_eq!(check_caller_allowed(&info, &allowed), Ok(()));
+
+        // The callee's own context is attached to every thread that gets this far, so it proves
+        // nothing about the caller.
+        let info = GateCallInfo::new(thread, own);
+        assert_eq!(
+            check_caller_allowed(&info, &allowed),
+            Err(GenericError::AccessDenied.into())
+        );
+
+        let info = GateCallInfo::new(thread, ObjID::new(0x9abc));
+        assert_eq!(
+            check_caller_allowed(&info, &allowed),
+            Err(GenericError::AccessDenied.into())
+        );
+
+        // Naming an allowed context isn't enough if the thread doesn't hold it.
+        let info = GateCallInfo::new(thread, stranger);
+        assert_eq!(
+            check_caller_allowed(&info, &allowed),
+            Err(GenericError::AccessDenied.into())
+        );
+
+        // Neither is leaving the source context out.
+        let info = GateCallInfo::new(thread, ObjID::new(0));
+        assert_eq!(
+            check_caller_allowed(&info, &[0, own.raw()]),
+            Err(GenericError::AccessDenied.into())
+        );
+    }
+
+    #[derive(Clone, Copy)]
+    enum FakeOutcome {
+        Value(u32),
+        Error,
+        Panic,
+        Denied,
+        Timeout,
+        Nothing,
+    }
+
+    // Stands in for a generated gate entry point, filling in the return the same ways it can.
+    fn fake_gate(outcome: FakeOutcome, ret: &mut Return<Result<u32, TwzError>>) {
+        match outcome {
+            FakeOutcome::Value(v) => ret.set(Ok(v)),
+            FakeOutcome::Error => ret.set(Err(ResourceError::OutOfMemory.into())),
+            FakeOutcome::Panic => {
+                match catch_callee_panic(|| -> Result<u32, TwzError> { panic!("fake gate panic") })
+                {
+                    Ok(r) => ret.set(r),
+                    Err(e) => ret.fail(e),
+                }
+            }
+            FakeOutcome::Denied => ret.fail(GateError::PermissionDenied),
+            FakeOutcome::Timeout => ret.fail(GateError::Timeout),
+            FakeOutcome::Nothing => {}
+        }
+    }
+
+    fn call_fake(outcome: FakeOutcome) -> Result<u32, GateError> {
+        Return::<Result<u32, TwzError>>::with_alloca(|ret| {
+            fake_gate(outcome, ret);
+            ret.into_gate_result()
+        })
+    }
+
+    #[test]
+    fn gate_errors() {
+        assert_eq!(call_fake(FakeOutcome::Value(7)), Ok(7));
+        assert_eq!(
+            call_fake(FakeOutcome::Error),
+            Err(GateError::Callee(ResourceError::OutOfMemory.into()))
+        );
+        assert_eq!(
+            call_fake(FakeOutcome::Panic),
+            Err(GateError::CalleePanicked)
+        );
+        assert_eq!(
+            call_fake(FakeOutcome::Denied),
+            Err(GateError::PermissionDenied)
+        );
+        assert_eq!(call_fake(FakeOutcome::Timeout), Err(GateError::Timeout));
+        assert_eq!(call_fake(FakeOutcome::Nothing), Err(GateError::NoReturn));
+
+        let gate = unsafe { DynamicSecGate::<(u32,), u32>::new(0) };
+        assert_eq!(
+            unsafe { dynamic_gate_call(gate, (1,)) },
+            Err(GateError::Unreachable)
+        );
+    }
+
+    // Allocates a call boundary the way a gate call does, without calling anything.
+    #[cfg(debug_assertions)]
+    fn probe_boundary<A: Tuple + Crossing + Copy>(args: A) {
+        let probe = StackProbe::new();
+        GateCallInfo::with_alloca(ObjID::new(1), ObjID::new(0), |_| {
+            Arguments::<A>::with_alloca(args, |_| {
+                Return::<Result<u32, TwzError>>::with_alloca(|_| probe.record())
+            })
+        })
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn stack_high_water() {
+        probe_boundary(([0u8; 64],));
+        let small = gate_stack_high_water();
+        assert!(small >= 64);
+
+        probe_boundary(([0u8; 16384],));
+        let large = gate_stack_high_water();
+        assert!(large >= 16384);
+        assert!(large > small);
+
+        // Smaller calls never lower the mark.
+        probe_boundary(((),));
+        assert!(gate_stack_high_water() >= large);
+    }
+
+    #[test]
+    fn oversized_args() {
+        let small = Arguments::try_with_alloca(
+            (1u64, 2u8),
+            DEFAULT_MAX_ARGS_SIZE,
+            |args| Ok(args.into_inner()),
+            Err,
+        );
+        assert_eq!(small, Ok((1, 2)));
+
+        let large = Arguments::try_with_alloca(
+            ([0u8; 2 * DEFAULT_MAX_ARGS_SIZE],),
+            DEFAULT_MAX_ARGS_SIZE,
+            |_| Ok(()),
+            Err,
+        );
+        assert_eq!(large, Err(2 * DEFAULT_MAX_ARGS_SIZE));
+
+        // The limit is inclusive.
+        let exact = Arguments::try_with_alloca(([0u8; 64],), 64, |_| Ok(()), Err);
+        assert_eq!(exact, Ok(()));
+    }
+
+    // Stands in for the trampoline of a gate that returns the error it's given.
+    extern "C" fn error_gate(
+        _info: *const GateCallInfo,
+        args: *const Arguments<(TwzError,)>,
+        ret: *mut Return<Result<u32, TwzError>>,
+    ) {
+        let (err,) = unsafe { (*args).into_inner() };
+        unsafe { (*ret).set(Err(err)) };
+    }
+
+    #[test]
+    fn callee_errors_round_trip() {
+        use twizzler_rt_abi::error::{ArgumentError, IoError, NamingError, ObjectError};
+
+        let gate = unsafe { DynamicSecGate::<(TwzError,), u32>::new(error_gate as usize) };
+        let errors: [TwzError; 8] = [
+            ArgumentError::InvalidArgument.into(),
+            GenericError::AccessDenied.into(),
+            ResourceError::OutOfMemory.into(),
+            // The same errors the gate machinery uses for its own failures, which callers must
+            // still be able to tell apart from the callee's.
+            ResourceError::Unavailable.into(),
+            GenericError::Internal.into(),
+            IoError::DataLoss.into(),
+            NamingError::AlreadyBound.into(),
+            ObjectError::NoSuchObject.into(),
+        ];
+        for err in errors {
+            let r = unsafe { dynamic_gate_call(gate, (err,)) };
+            assert_eq!(r, Err(GateError::Callee(err)));
+            let received: TwzError = r.unwrap_err().into();
+            assert_eq!(received.raw(), err.raw());
+
+            // Calling the gate as a function hands back the error as a TwzError.
+            assert_eq!(gate(err).unwrap_err().raw(), err.raw());
+        }
+    }
+
+    struct FakeTarget(std::sync::atomic::AtomicBool);
+
+    impl TargetLifecycle for FakeTarget {
+        fn is_tearing_down(&self) -> bool {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn call_into_torn_down_compartment() {
+        let target = FakeTarget(false.into());
+        let gate = unsafe { DynamicSecGate::<(TwzError,), u32>::new(error_gate as usize) }
+            .with_lifecycle(&target);
+        let err: TwzError = ResourceError::OutOfMemory.into();
+        assert_eq!(
+            unsafe { dynamic_gate_call(gate, (err,)) },
+            Err(GateError::Callee(err))
+        );
+
+        // Once the target is marked for teardown, the call fails without reaching the gate.
+        target.0.store(true, Ordering::SeqCst);
+        assert_eq!(
+            unsafe { dynamic_gate_call(gate, (err,)) },
+            Err(GateError::Unreachable)
+        );
+        assert_eq!(gate(err), Err(ResourceError::Unavailable.into()));
+    }
+
+    // Stands in for the trampoline of a gate that takes no arguments.
+    extern "C" fn no_arg_gate(
+        _info: *const GateCallInfo,
+        _args: *const Arguments<()>,
+        ret: *mut Return<Result<u64, TwzError>>,
+    ) {
+        unsafe { (*ret).set(Ok(0xfeed_f00d)) };
+    }
+
+    // Stands in for the trampoline of a gate that reports the sizes it was called with.
+    extern "C" fn sizes_gate(
+        info: *const GateCallInfo,
+        _args: *const Arguments<(u64, u8)>,
+        ret: *mut Return<Result<(usize, usize), TwzError>>,
+    ) {
+        let info = unsafe { &*info };
+        unsafe { (*ret).set(Ok((info.args_len(), info.ret_len()))) };
+    }
+
+    // Stands in for the trampoline of a gate that takes the call info.
+    extern "C" fn thread_id_gate(
+        info: *const GateCallInfo,
+        _args: *const Arguments<()>,
+        ret: *mut Return<Result<ObjID, TwzError>>,
+    ) {
+        let info = unsafe { (*info).canonicalized() };
+        unsafe { (*ret).set(Ok(info.thread_id())) };
+    }
+
+    #[test]
+    fn prefilled_thread_id() {
+        let queries = || THREAD_ID_QUERIES.with(|queries| queries.get());
+        let before = queries();
+        let gate = unsafe { DynamicSecGate::<(), ObjID>::new(thread_id_gate as usize) };
+        assert_eq!(gate(), Ok(get_thread_id()));
+        assert_eq!(queries(), before);
+
+        // Info without a thread ID still works, but has to ask the kernel.
+        let info = GateCallInfo::new(ObjID::new(0), ObjID::new(0));
+        assert_eq!(info.thread_id(), get_thread_id());
+        assert_eq!(queries(), before + 1);
+    }
+
+    // Stands in for the trampoline of a gate that reports who called it.
+    extern "C" fn source_gate(
+        info: *const GateCallInfo,
+        _args: *const Arguments<()>,
+        ret: *mut Return<Result<Option<ObjID>, TwzError>>,
+    ) {
+        unsafe { (*ret).set(Ok((*info).source_context())) };
+    }
+
+    // Stands in for the trampoline of a gate that takes its GateCallInfo, and so checks the source
+    // context before reporting it.
+    extern "C" fn checked_source_gate(
+        info: *const GateCallInfo,
+        _args: *const Arguments<()>,
+        ret: *mut Return<Result<Option<ObjID>, TwzError>>,
+    ) {
+        let info = unsafe { &*info };
+        let ret = unsafe { ret.as_mut().unwrap() };
+        match check_source_context(info) {
+            Ok(()) => ret.set(Ok(info.source_context())),
+            Err(_) => ret.fail(GateError::PermissionDenied),
+        }
+    }
+
+    #[test]
+    fn gate_call_as() {
+        use twizzler_abi::syscall::{sys_object_create, sys_sctx_attach, ObjectCreate};
+
+        let gate = unsafe { DynamicSecGate::<(), Option<ObjID>>::new(source_gate as usize) };
+        let own = get_sctx_id();
+
+        // A dispatcher with a client's context attached may call on the client's behalf.
+        let client = sys_object_create(ObjectCreate::default(), &[], &[]).unwrap();
+        sys_sctx_attach(client).unwrap();
+        assert_eq!(
+            unsafe { dynamic_gate_call_as(gate, (), client) },
+            Ok(Some(client))
+        );
+        assert_eq!(get_sctx_id(), own);
+
+        // Contexts that aren't attached can't be claimed.
+        let stranger = ObjID::new(0xdead_beef);
+        assert_eq!(
+            unsafe { dynamic_gate_call_as(gate, (), stranger) },
+            Err(GateError::PermissionDenied)
+        );
+        assert_eq!(get_sctx_id(), own);
+
+        // A caller that skips the check and fills in the stranger itself is still rejected by the
+        // callee, which asks the kernel.
+        let checked =
+            unsafe { DynamicSecGate::<(), Option<ObjID>>::new(checked_source_gate as usize) };
+        assert_eq!(
+            unsafe { gate_call_from(checked, (), stranger) },
+            Err(GateError::PermissionDenied)
+        );
+        assert_eq!(
+            unsafe { gate_call_from(checked, (), client) },
+            Ok(Some(client))
+        );
+        assert_eq!(unsafe { gate_call_from(checked, (), own) }, Ok(Some(own)));
+    }
+
+    #[test]
+    fn gate_call_sizes() {
+        let gate = unsafe { DynamicSecGate::<(u64, u8), (usize, usize)>::new(sizes_gate as usize) };
+        assert_eq!(
+            gate(7, 1),
+            Ok((
+                size_of::<Arguments<(u64, u8)>>(),
+                size_of::<Return<Result<(usize, usize), TwzError>>>()
+            ))
+        );
+
+        let info = GateCallInfo::new(ObjID::new(1), ObjID::new(0));
+        assert_eq!((info.args_len(), info.ret_len()), (0, 0));
+    }
+
+    // Stands in for the trampoline of a gate that returns an Option.
+    extern "C" fn option_gate(
+        _info: *const GateCallInfo,
+        args: *const Arguments<(u32,)>,
+        ret: *mut Return<Result<Option<u32>, TwzError>>,
+    ) {
+        let (x,) = unsafe { *args }.into_inner();
+        unsafe { (*ret).set(Ok(x.checked_sub(1))) };
+    }
+
+    #[test]
+    fn option_gate_call() {
+        let gate = unsafe { DynamicSecGate::<(u32,), Option<u32>>::new(option_gate as usize) };
+        assert_eq!(gate(5), Ok(Some(4)));
+        assert_eq!(gate(0), Ok(None));
+    }
+
+    #[secure_gate(trace)]
+    fn traced_gate(x: u32) -> Result<u32, TwzError> {
+        x.checked_mul(2).ok_or(ResourceError::OutOfMemory.into())
+    }
+
+    // Records trace events. Like a sink that posts to a logging gate, it calls a traced gate
+    // itself, which must not be traced in turn.
+    struct TraceRecorder(std::sync::Mutex<Vec<GateTraceEvent>>);
+
+    impl GateTraceSink for TraceRecorder {
+        fn record(&self, event: &GateTraceEvent) {
+            assert_eq!(traced_gate(1), Ok(2));
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    static TRACE_RECORDER: TraceRecorder = TraceRecorder(std::sync::Mutex::new(Vec::new()));
+
+    #[test]
+    fn traced_gate_call() {
+        assert!(set_gate_trace_sink(&TRACE_RECORDER).is_ok());
+        assert_eq!(traced_gate(4), Ok(8));
+        assert_eq!(
+            traced_gate(u32::MAX),
+            Err(ResourceError::OutOfMemory.into())
+        );
+
+        let event = |point| GateTraceEvent {
+            gate: "traced_gate",
+            src_ctx: GateCallInfo::new(get_thread_id(), get_sctx_id()).source_context(),
+            point,
+        };
+        assert_eq!(
+            *TRACE_RECORDER.0.lock().unwrap(),
+            [
+                event(GateTracePoint::Entry),
+                event(GateTracePoint::Exit(GateTraceStatus::Ok)),
+                event(GateTracePoint::Entry),
+                event(GateTracePoint::Exit(GateTraceStatus::Err)),
+            ]
+        );
+    }
+
+    // Reports how much it would have read even when it fails, and nothing for an empty read.
+    #[secure_gate(options(out))]
+    fn read_gate(want: u32, len: &mut OutParam<usize>) -> Result<u32, TwzError> {
+        if want == 0 {
+            return Ok(0);
+        }
+        len.set(want as usize * 2);
+        if want > 100 {
+            return Err(ResourceError::OutOfMemory.into());
+        }
+        Ok(want)
+    }
+
+    #[test]
+    fn out_param_gate_call() {
+        let mut len = OutParam::new();
+        assert_eq!(read_gate(4, &mut len), Ok(4));
+        assert_eq!(len.get(), Some(8));
+
+        // The callee's out-param is delivered along with an error, too.
+        assert_eq!(
+            read_gate(200, &mut len),
+            Err(ResourceError::OutOfMemory.into())
+        );
+        assert_eq!(len.get(), Some(400));
+
+        // A callee that sets only the return value leaves the out-param unset, whatever it held.
+        assert_eq!(read_gate(0, &mut len), Ok(0));
+        assert_eq!(len.get(), None);
+    }
+
+    #[secure_gate(encrypt_args)]
+    fn secret_gate(token: u64, salt: u32) -> Result<u64, TwzError> {
+        Ok(token ^ salt as u64)
+    }
+
+    #[test]
+    fn encrypted_args_round_trip() {
+        // Without a key, the caller doesn't make the call at all.
+        assert_eq!(secret_gate(1, 2), Err(GateError::Unreachable.into()));
+
+        // A key for a gate of the same name in another compartment doesn't help.
+        set_gate_key(
+            "other",
+            "secret_gate",
+            GateKey::from_bytes([7; GATE_KEY_LEN]),
+        );
+        assert_eq!(secret_gate(1, 2), Err(GateError::Unreachable.into()));
+        clear_gate_key("other", "secret_gate");
+
+        set_gate_key(
+            "secgate",
+            "secret_gate",
+            GateKey::from_bytes([7; GATE_KEY_LEN]),
+        );
+        assert_eq!(secret_gate(0xdead_0000, 0xbeef), Ok(0xdead_beef));
+
+        // Anything that copies the arguments on their way to the gate only sees ciphertext.
+        let plain = (0x5ec2_e700_0000_0001u64, 9u32);
+        let mut info = GateCallInfo::new(get_thread_id(), get_sctx_id());
+        let mut args = Arguments { args: plain };
+        unsafe { seal_gate_args("secgate", "secret_gate", &mut info, &mut args) }.unwrap();
+        assert_ne!(args.args, plain);
+        let opened = unsafe { open_gate_args("secgate", "secret_gate", &info, &args) };
+        assert_eq!(opened.map(|args| args.into_inner()), Ok(plain));
+
+        // Ciphertext changed on the way fails to authenticate.
+        let mut tampered = args;
+        tampered.args.0 ^= 1;
+        let opened = unsafe { open_gate_args("secgate", "secret_gate", &info, &tampered) };
+        assert_eq!(opened.err(), Some(GateError::PermissionDenied));
+
+        // Arguments sent in the clear, or without the gate's key, are refused.
+        let clear = GateCallInfo::new(get_thread_id(), get_sctx_id());
+        let opened = unsafe { open_gate_args("secgate", "secret_gate", &clear, &args) };
+        assert_eq!(opened.err(), Some(GateError::PermissionDenied));
+        clear_gate_key("secgate", "secret_gate");
+        let opened = unsafe { open_gate_args("secgate", "secret_gate", &info, &args) };
+        assert_eq!(opened.err(), Some(GateError::PermissionDenied));
+    }
+
+    #[test]
+    fn no_arg_gate_call() {
+        assert_eq!(size_of::<Arguments<()>>(), 0);
+
+        let gate = unsafe { DynamicSecGate::<(), u64>::new(no_arg_gate as usize) };
+        assert_eq!(unsafe { dynamic_gate_call(gate, ()) }, Ok(0xfeed_f00d));
+        assert_eq!(gate(), Ok(0xfeed_f00d));
+    }
+
+    // A provider whose gate only shows up once it has been loaded.
+    struct LazyProvider {
+        loaded: Cell<bool>,
+        loads: Cell<u32>,
+        load_result: Result<(), TwzError>,
+    }
+
+    impl LazyProvider {
+        fn new(load_result: Result<(), TwzError>) -> Self {
+            Self {
+                loaded: Cell::new(false),
+                loads: Cell::new(0),
+                load_result,
+            }
+        }
+    }
+
+    impl GateProvider for LazyProvider {
+        unsafe fn lookup_gate<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+            &self,
+            name: &str,
+        ) -> Result<DynamicSecGate<'_, A, R>, TwzError> {
+            assert_eq!(name, "no_arg_gate");
+            if self.loaded.get() {
+                Ok(unsafe { DynamicSecGate::new(no_arg_gate as usize) })
+            } else {
+                Err(ResourceError::Unavailable.into())
+            }
+        }
+
+        fn load_provider(&self) -> Result<(), TwzError> {
+            self.loads.set(self.loads.get() + 1);
+            self.load_result?;
+            self.loaded.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn call_or_load_retries() {
+        let provider = LazyProvider::new(Ok(()));
+        let r = unsafe { call_or_load::<_, (), u64>(&provider, "no_arg_gate", ()) };
+        assert_eq!(r, Ok(0xfeed_f00d));
+        assert_eq!(provider.loads.get(), 1);
+
+        // Once loaded, calls go straight through.
+        let r = unsafe { call_or_load::<_, (), u64>(&provider, "no_arg_gate", ()) };
+        assert_eq!(r, Ok(0xfeed_f00d));
+        assert_eq!(provider.loads.get(), 1);
+    }
+
+    #[test]
+    fn call_or_load_load_fails() {
+        let provider = LazyProvider::new(Err(ResourceError::OutOfResources.into()));
+        let r = unsafe { call_or_load::<_, (), u64>(&provider, "no_arg_gate", ()) };
+        assert_eq!(r, Err(ResourceError::OutOfResources.into()));
+        assert_eq!(provider.loads.get(), 1);
+    }
+
+    // A future that is pending until a background thread wakes it after a delay.
+    struct Timer {
+        fired: Arc<AtomicUsize>,
+        started: bool,
+        delay: std::time::Duration,
+    }
+
+    impl Future for Timer {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<()> {
+            if self.fired.load(Ordering::SeqCst) != 0 {
+                return std::task::Poll::Ready(());
+            }
+            if !self.started {
+                self.started = true;
+                let fired = self.fired.clone();
+                let waker = cx.waker().clone();
+                let delay = self.delay;
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    fired.store(1, Ordering::SeqCst);
+                    waker.wake();
+                });
+            }
+            std::task::Poll::Pending
+        }
+    }
+
+    // What an async gate's implementation looks like once the macro has turned it into a future.
+    async fn async_gate_impl(x: u64) -> Result<u64, TwzError> {
+        Timer {
+            fired: Arc::new(AtomicUsize::new(0)),
+            started: false,
+            delay: std::time::Duration::from_millis(20),
+        }
+        .await;
+        Ok(x + 1)
+    }
+
+    // Stands in for the entry point generated for an async gate.
+    extern "C" fn async_gate(
+        _info: *const GateCallInfo,
+        args: *const Arguments<(u64,)>,
+        ret: *mut Return<Result<u64, TwzError>>,
+    ) {
+        let (x,) = unsafe { *args }.into_inner();
+        match catch_callee_panic(|| block_on_gate(async_gate_impl(x))) {
+            Ok(r) => unsafe { (*ret).set(r) },
+            Err(e) => unsafe { (*ret).fail(e) },
+        }
+    }
+
+    #[test]
+    fn async_gate_call() {
+        let start = std::time::Instant::now();
+        let gate = unsafe { DynamicSecGate::<(u64,), u64>::new(async_gate as usize) };
+        assert_eq!(unsafe { dynamic_gate_call(gate, (41,)) }, Ok(42));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn gate_error_compat() {
+        let as_twz = |e: GateError| -> TwzError { e.into() };
+        assert_eq!(
+            as_twz(GateError::Unreachable),
+            ResourceError::Unavailable.into()
+        );
+        assert_eq!(
+            as_twz(GateError::NoReturn),
+            ResourceError::Unavailable.into()
+        );
+        assert_eq!(as_twz(GateError::Timeout), GenericError::TimedOut.into());
+        assert_eq!(
+            as_twz(GateError::PermissionDenied),
+            GenericError::AccessDenied.into()
+        );
+        assert_eq!(
+            as_twz(GateError::CalleePanicked),
+            GenericError::Internal.into()
+        );
+        assert_eq!(
+            as_twz(GateError::Callee(ResourceError::OutOfMemory.into())),
+            ResourceError::OutOfMemory.into()
+        );
+    }
 }
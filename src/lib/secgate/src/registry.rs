@@ -0,0 +1,122 @@
+//! A registry of gates added at runtime, for gates that aren't known at compile time (e.g. those
+//! provided by plugins).
+//!
+//! Registered gates are looked up by name, like static gates, and called through a
+//! [DynamicSecGate]. The registry is shared by all threads in the compartment: registration and
+//! lookup may happen concurrently from any thread, and a gate becomes visible to lookups on every
+//! thread as soon as [register_gate] returns.
+
+use std::{
+    any::TypeId,
+    collections::BTreeMap,
+    marker::Tuple,
+    sync::{Mutex, OnceLock},
+};
+
+use twizzler_rt_abi::error::{NamingError, TwzError};
+
+use crate::{Arguments, Crossing, DynamicSecGate, GateCallInfo, Return};
+
+/// The entry point of a runtime-registered gate. This is the same ABI that the trampolines of
+/// static gates call into, so a registered gate is called exactly like a static one.
+pub type GateEntry<A, R> =
+    extern "C" fn(*const GateCallInfo, *const Arguments<A>, *mut Return<Result<R, TwzError>>);
+
+struct RegisteredGate {
+    address: usize,
+    // The argument and return types, so that a gate can't be looked up with the wrong types.
+    types: (TypeId, TypeId),
+}
+
+fn registry() -> &'static Mutex<BTreeMap<String, RegisteredGate>> {
+    static REGISTRY: OnceLock<Mutex<BTreeMap<String, RegisteredGate>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Register a gate under a name. Fails with [NamingError::AlreadyExists] if a gate with that name
+/// is already registered.
+pub fn register_gate<A, R>(name: &str, entry: GateEntry<A, R>) -> Result<(), TwzError>
+where
+    A: Tuple + Crossing + Copy + 'static,
+    R: Crossing + Copy + 'static,
+{
+    let mut registry = registry().lock().unwrap();
+    if registry.contains_key(name) {
+        return Err(NamingError::AlreadyExists.into());
+    }
+    registry.insert(
+        name.to_string(),
+        RegisteredGate {
+            address: entry as usize,
+            types: (TypeId::of::<A>(), TypeId::of::<R>()),
+        },
+    );
+    Ok(())
+}
+
+/// Remove a registered gate, returning true if it was registered. Existing [DynamicSecGate]s for
+/// it remain callable, since they point directly at the entry function.
+pub fn unregister_gate(name: &str) -> bool {
+    registry().lock().unwrap().remove(name).is_some()
+}
+
+/// The names of all registered gates, in sorted order.
+pub fn registered_gates() -> Vec<String> {
+    registry().lock().unwrap().keys().cloned().collect()
+}
+
+impl<A, R> DynamicSecGate<'static, A, R>
+where
+    A: Tuple + Crossing + Copy + 'static,
+    R: Crossing + Copy + 'static,
+{
+    /// Look up a registered gate by name. Returns None if no gate has that name, or if it was
+    /// registered with different argument or return types.
+    pub fn by_name(name: &str) -> Option<Self> {
+        let registry = registry().lock().unwrap();
+        let gate = registry.get(name)?;
+        if gate.types != (TypeId::of::<A>(), TypeId::of::<R>()) {
+            return None;
+        }
+        // Safety: the address is a GateEntry<A, R>, as checked above.
+        Some(unsafe { Self::new(gate.address) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" fn add_one(
+        _info: *const GateCallInfo,
+        args: *const Arguments<(u32,)>,
+        ret: *mut Return<Result<u32, TwzError>>,
+    ) {
+        let (x,) = unsafe { *args }.into_inner();
+        unsafe { &mut *ret }.set(Ok(x + 1));
+    }
+
+    #[test]
+    fn register_and_lookup() {
+        register_gate("registry_test_add_one", add_one as GateEntry<(u32,), u32>).unwrap();
+        assert!(registered_gates().contains(&"registry_test_add_one".to_string()));
+        assert!(register_gate("registry_test_add_one", add_one as GateEntry<_, _>).is_err());
+
+        let gate = DynamicSecGate::<(u32,), u32>::by_name("registry_test_add_one").unwrap();
+        assert_eq!(gate.address, add_one as usize);
+        assert!(DynamicSecGate::<(u64,), u32>::by_name("registry_test_add_one").is_none());
+        assert!(DynamicSecGate::<(u32,), u32>::by_name("registry_test_missing").is_none());
+
+        // Call the entry the way a gate call would.
+        let ret = Arguments::with_alloca((41,), |args| {
+            Return::<Result<u32, TwzError>>::with_alloca(|ret| {
+                add_one(core::ptr::null(), args, ret);
+                ret.into_gate_result()
+            })
+        });
+        assert_eq!(ret, Ok(42));
+
+        assert!(unregister_gate("registry_test_add_one"));
+        assert!(DynamicSecGate::<(u32,), u32>::by_name("registry_test_add_one").is_none());
+    }
+}
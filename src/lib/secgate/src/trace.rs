@@ -0,0 +1,132 @@
+//! Tracing of gate calls, for debugging a compartment's gates.
+//!
+//! The entry point of a gate declared with `#[secure_gate(trace)]` reports each call to the
+//! compartment's [GateTraceSink], once on entry and once on exit, with the gate's name, the
+//! caller's security context, and how the call went. A sink is set with [set_gate_trace_sink];
+//! the `logboi` library provides one that posts these to the logger compartment.
+//!
+//! Reporting is only compiled in when this crate's `trace` feature is enabled. Without it, the
+//! calls that traced gates make here are empty and get inlined away.
+
+use std::{cell::Cell, fmt::Display, sync::OnceLock};
+
+use twizzler_abi::object::ObjID;
+use twizzler_rt_abi::error::TwzError;
+
+use crate::{GateCallInfo, GateError};
+
+/// How a traced gate call ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateTraceStatus {
+    /// The gate returned Ok.
+    Ok,
+    /// The gate returned an error, or failed before it ran.
+    Err,
+    /// The caller's context is not allowed to call the gate.
+    Denied,
+    /// The gate's implementation panicked.
+    Panicked,
+}
+
+/// Where in a traced gate call an event was reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateTracePoint {
+    Entry,
+    Exit(GateTraceStatus),
+}
+
+/// One trace event for a gate call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GateTraceEvent {
+    /// The name of the gate.
+    pub gate: &'static str,
+    /// The caller's security context, or None if the call was not cross-context.
+    pub src_ctx: Option<ObjID>,
+    pub point: GateTracePoint,
+}
+
+impl Display for GateTraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gate {}", self.gate)?;
+        match self.src_ctx {
+            Some(ctx) => write!(f, " from {}", ctx)?,
+            None => write!(f, " from own context")?,
+        }
+        match self.point {
+            GateTracePoint::Entry => write!(f, ": entry"),
+            GateTracePoint::Exit(status) => write!(f, ": exit ({:?})", status),
+        }
+    }
+}
+
+/// Something that receives the trace events of this compartment's traced gates.
+pub trait GateTraceSink: Sync {
+    fn record(&self, event: &GateTraceEvent);
+}
+
+static GATE_TRACE_SINK: OnceLock<&'static dyn GateTraceSink> = OnceLock::new();
+
+/// Set the sink for this compartment's gate trace events. This can only be set once, and returns
+/// the sink back if one was already set. Until a sink is set, trace events are dropped.
+pub fn set_gate_trace_sink(
+    sink: &'static dyn GateTraceSink,
+) -> Result<(), &'static dyn GateTraceSink> {
+    GATE_TRACE_SINK.set(sink)
+}
+
+const TRACING: bool = cfg!(any(feature = "trace", test));
+
+thread_local! {
+    static IN_SINK: Cell<bool> = const { Cell::new(false) };
+}
+
+fn report(gate: &'static str, info: &GateCallInfo, point: GateTracePoint) {
+    let Some(sink) = GATE_TRACE_SINK.get() else {
+        return;
+    };
+    // A sink that calls a traced gate (for example, a logging gate) would otherwise report that
+    // call too, and so on without end. Calls made from within the sink aren't traced.
+    if IN_SINK.with(|in_sink| in_sink.replace(true)) {
+        return;
+    }
+    sink.record(&GateTraceEvent {
+        gate,
+        src_ctx: info.source_context(),
+        point,
+    });
+    IN_SINK.with(|in_sink| in_sink.set(false));
+}
+
+/// Report entry to a traced gate. Called by the entry point that [secure_gate](crate::secure_gate)
+/// generates.
+#[inline(always)]
+pub fn trace_gate_entry(gate: &'static str, info: &GateCallInfo) {
+    if TRACING {
+        report(gate, info, GateTracePoint::Entry);
+    }
+}
+
+/// Report that a traced gate call ended with `status`, without the gate having returned.
+#[inline(always)]
+pub fn trace_gate_exit(gate: &'static str, info: &GateCallInfo, status: GateTraceStatus) {
+    if TRACING {
+        report(gate, info, GateTracePoint::Exit(status));
+    }
+}
+
+/// Report the result of a traced gate's implementation.
+#[inline(always)]
+pub fn trace_gate_result<T>(
+    gate: &'static str,
+    info: &GateCallInfo,
+    result: &Result<Result<T, TwzError>, GateError>,
+) {
+    if TRACING {
+        let status = match result {
+            Ok(Ok(_)) => GateTraceStatus::Ok,
+            Ok(Err(_)) => GateTraceStatus::Err,
+            Err(_) => GateTraceStatus::Panicked,
+        };
+        report(gate, info, GateTracePoint::Exit(status));
+    }
+}
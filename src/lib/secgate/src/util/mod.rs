@@ -2,6 +2,8 @@
 
 mod buffer;
 mod handle;
+mod scratch;
 
 pub use buffer::*;
 pub use handle::*;
+pub use scratch::*;
@@ -0,0 +1,303 @@
+use twizzler_abi::{
+    meta::{MetaExt, MetaInfo},
+    object::{ObjID, Protections, NULLPAGE_SIZE},
+    syscall::{
+        sys_object_create, sys_object_stat, BackingType, LifetimeType, ObjectCreate,
+        ObjectCreateFlags,
+    },
+};
+use twizzler_rt_abi::{
+    error::{ArgumentError, GenericError, ResourceError, TwzError},
+    object::{MapFlags, ObjectHandle},
+};
+
+use super::SimpleBuffer;
+use crate::{check_source_context, get_sctx_id, GateCallInfo};
+
+/// The meta extension tag that marks an object as a [ScratchObject]. The extension's value is the
+/// offset, from the start of the meta page, of the ID of the security context that created it.
+pub const MEXT_SCRATCH_OWNER: u64 = 0x7363_7261_7463_68;
+
+// Where a scratch object records its owner: halfway through the meta page, leaving the space
+// before it for the extension array, and the end of the page for other records (such as an
+// integrity seal).
+const OWNER_OFFSET: usize = NULLPAGE_SIZE / 2;
+
+/// A scratch object owned by the caller of a secure gate, which the callee may borrow for the
+/// duration of a call instead of allocating its own buffer.
+///
+/// # Lifetime rules
+/// 1. The caller creates the scratch object and keeps this handle alive across every call that is
+///    passed a [ScratchRef] to it. Dropping the handle unmaps the object, after which the callee
+///    will refuse to map it.
+/// 2. The callee may only use the object for the duration of the gate call, and must drop the
+///    [SimpleBuffer] it got from [ScratchRef::map] before returning.
+/// 3. Once the call returns, the caller owns the contents again and can read back the results.
+///    Scratch objects are not cleared between calls, so the caller should not reuse one across
+///    mutually-distrusting callees.
+/// 4. A scratch object records the security context that created it, and only calls from that
+///    context may lend it out. Calls must therefore be made from the same context the object was
+///    created in.
+pub struct ScratchObject {
+    buffer: SimpleBuffer,
+}
+
+impl core::fmt::Debug for ScratchObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScratchObject")
+            .field("id", &self.id())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScratchObject {
+    /// Create a new, volatile scratch object and map it read-write.
+    pub fn new() -> Result<Self, TwzError> {
+        let id = sys_object_create(
+            ObjectCreate::new(
+                BackingType::Normal,
+                LifetimeType::Volatile,
+                None,
+                ObjectCreateFlags::empty(),
+                Protections::all(),
+            ),
+            &[],
+            &[],
+        )?;
+        let handle =
+            twizzler_rt_abi::object::twz_rt_map_object(id, MapFlags::READ | MapFlags::WRITE)?;
+        set_owner(&handle, get_sctx_id())?;
+        Ok(Self {
+            buffer: SimpleBuffer::new(handle),
+        })
+    }
+
+    /// Get the ID of the scratch object.
+    pub fn id(&self) -> ObjID {
+        self.buffer.handle().id()
+    }
+
+    /// Get a reference to the scratch object that can be passed across a secure gate.
+    pub fn scratch_ref(&self) -> ScratchRef {
+        ScratchRef { id: self.id() }
+    }
+
+    /// Get the underlying buffer, to read back what the callee wrote.
+    pub fn buffer(&self) -> &SimpleBuffer {
+        &self.buffer
+    }
+
+    /// Get the underlying buffer mutably, to fill in data for the callee.
+    pub fn buffer_mut(&mut self) -> &mut SimpleBuffer {
+        &mut self.buffer
+    }
+}
+
+/// A reference to a caller-provided [ScratchObject], suitable for passing as a secure gate
+/// argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, secgate::StableLayout)]
+#[repr(C)]
+pub struct ScratchRef {
+    id: ObjID,
+}
+
+impl ScratchRef {
+    /// Get the ID of the referenced scratch object.
+    pub fn id(&self) -> ObjID {
+        self.id
+    }
+
+    /// Map the scratch object on the callee side of a gate, for the call described by `info`.
+    ///
+    /// Before mapping, this checks that the object can actually be lent out as scratch space: it
+    /// must be volatile (so a caller cannot trick the callee into scribbling over persistent
+    /// data), and it must currently be mapped by someone (the caller holding the
+    /// [ScratchObject]).
+    ///
+    /// The callee maps the object with its own rights, so it must not write to an object just
+    /// because the caller named it. The object must have been created as a [ScratchObject] by the
+    /// call's source context, which the kernel vouches for (see [check_source_context]). Only
+    /// someone who can already write an object can mark it, so a caller cannot get the callee to
+    /// write to an object the caller could not write itself.
+    pub fn map(self, info: &GateCallInfo) -> Result<SimpleBuffer, TwzError> {
+        check_source_context(info)?;
+        let src = info.source_context().ok_or(GenericError::AccessDenied)?;
+        let stat = sys_object_stat(self.id)?;
+        if stat.life != LifetimeType::Volatile {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+        if stat.maps == 0 {
+            return Err(GenericError::AccessDenied.into());
+        }
+        // Check the owner before asking for write access.
+        let handle = twizzler_rt_abi::object::twz_rt_map_object(self.id, MapFlags::READ)?;
+        if owner(&handle) != Some(src) {
+            return Err(GenericError::AccessDenied.into());
+        }
+        drop(handle);
+        let handle =
+            twizzler_rt_abi::object::twz_rt_map_object(self.id, MapFlags::READ | MapFlags::WRITE)?;
+        Ok(SimpleBuffer::new(handle))
+    }
+}
+
+// Record `ctx` as the owner of a freshly created scratch object, mapped writable. Any other meta
+// extensions the object has are kept, and an existing owner is replaced.
+fn set_owner(handle: &ObjectHandle, ctx: ObjID) -> Result<(), TwzError> {
+    let meta = handle.meta().cast::<MetaInfo>();
+    // Safety: the meta page is mapped along with the object, and the owner and extensions lie
+    // within it. The object was just created, so nobody else is using its meta page.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        let extcount = (*meta).extcount as usize;
+        let idx = (0..extcount)
+            .find(|i| (*exts.add(*i)).tag == MEXT_SCRATCH_OWNER)
+            .unwrap_or(extcount);
+        if size_of::<MetaInfo>() + (idx + 1) * size_of::<MetaExt>() > OWNER_OFFSET {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        meta.cast::<u8>()
+            .add(OWNER_OFFSET)
+            .cast::<ObjID>()
+            .write(ctx);
+        exts.add(idx).write(MetaExt {
+            tag: MEXT_SCRATCH_OWNER,
+            value: OWNER_OFFSET as u64,
+        });
+        if idx == extcount {
+            (*meta).extcount += 1;
+        }
+    }
+    Ok(())
+}
+
+// Read the owner a scratch object records, if it is one.
+fn owner(handle: &ObjectHandle) -> Option<ObjID> {
+    let meta = handle.meta().cast::<MetaInfo>().cast_const();
+    // Safety: the meta page is mapped along with the object. The extension count and the owner's
+    // offset come from the object, so they're bounded to the meta page before use.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        let max_exts = (NULLPAGE_SIZE - size_of::<MetaInfo>()) / size_of::<MetaExt>();
+        let off = (0..((*meta).extcount as usize).min(max_exts))
+            .map(|i| exts.add(i).read())
+            .find(|ext| ext.tag == MEXT_SCRATCH_OWNER)?
+            .value as usize;
+        if off != OWNER_OFFSET {
+            return None;
+        }
+        Some(meta.cast::<u8>().add(off).cast::<ObjID>().read())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn borrow_scratch() {
+        let mut scratch = ScratchObject::new().unwrap();
+        scratch.buffer_mut().write(b"from caller");
+        let info = GateCallInfo::new(crate::get_thread_id(), get_sctx_id());
+
+        let mut lent = scratch.scratch_ref().map(&info).unwrap();
+        let mut buf = [0u8; 11];
+        assert_eq!(lent.read(&mut buf), buf.len());
+        assert_eq!(&buf, b"from caller");
+        lent.write(b"from callee");
+        drop(lent);
+
+        assert_eq!(scratch.buffer().read(&mut buf), buf.len());
+        assert_eq!(&buf, b"from callee");
+    }
+
+    #[test]
+    fn owner_keeps_other_extensions() {
+        const OTHER_TAG: u64 = 0x6f74_6865_72;
+
+        let id = sys_object_create(
+            ObjectCreate::new(
+                BackingType::Normal,
+                LifetimeType::Volatile,
+                None,
+                ObjectCreateFlags::empty(),
+                Protections::all(),
+            ),
+            &[],
+            &[],
+        )
+        .unwrap();
+        let handle =
+            twizzler_rt_abi::object::twz_rt_map_object(id, MapFlags::READ | MapFlags::WRITE)
+                .unwrap();
+        let meta = handle.meta().cast::<MetaInfo>();
+        let ext = |i: usize| unsafe {
+            meta.cast::<u8>()
+                .add(size_of::<MetaInfo>())
+                .cast::<MetaExt>()
+                .add(i)
+                .read()
+        };
+        // Give the object an extension of its own before marking it.
+        unsafe {
+            meta.cast::<u8>()
+                .add(size_of::<MetaInfo>())
+                .cast::<MetaExt>()
+                .write(MetaExt {
+                    tag: OTHER_TAG,
+                    value: 42,
+                });
+            (*meta).extcount = 1;
+        }
+
+        let ctx = get_sctx_id();
+        set_owner(&handle, ObjID::new(0x1234)).unwrap();
+        // Setting it again updates the owner rather than adding another extension.
+        set_owner(&handle, ctx).unwrap();
+        assert_eq!(unsafe { (*meta).extcount }, 2);
+        assert_eq!((ext(0).tag, ext(0).value), (OTHER_TAG, 42));
+        assert_eq!(ext(1).tag, MEXT_SCRATCH_OWNER);
+        assert_eq!(owner(&handle), Some(ctx));
+    }
+
+    #[test]
+    fn scratch_must_be_owned() {
+        let scratch = ScratchObject::new().unwrap();
+        let thread = crate::get_thread_id();
+
+        // An object that wasn't created as scratch space can't be lent out, even if it's mapped.
+        let other = sys_object_create(
+            ObjectCreate::new(
+                BackingType::Normal,
+                LifetimeType::Volatile,
+                None,
+                ObjectCreateFlags::empty(),
+                Protections::all(),
+            ),
+            &[],
+            &[],
+        )
+        .unwrap();
+        let _other =
+            twizzler_rt_abi::object::twz_rt_map_object(other, MapFlags::READ | MapFlags::WRITE)
+                .unwrap();
+        let info = GateCallInfo::new(thread, get_sctx_id());
+        let not_scratch = ScratchRef { id: other };
+        assert_eq!(
+            not_scratch.map(&info).map(|_| ()),
+            Err(GenericError::AccessDenied.into())
+        );
+
+        // Nor can a scratch object be lent out by a call claiming some other source context.
+        let info = GateCallInfo::new(thread, ObjID::new(0xdead_beef));
+        assert!(scratch.scratch_ref().map(&info).is_err());
+        let info = GateCallInfo::new(thread, ObjID::new(0));
+        assert!(scratch.scratch_ref().map(&info).is_err());
+    }
+}
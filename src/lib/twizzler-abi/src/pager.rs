@@ -40,6 +40,8 @@ impl RequestFromKernel {
             KernelCommand::ObjectDel(objid) => Some(objid),
             KernelCommand::ObjectCreate(objid, _) => Some(objid),
             KernelCommand::DramPages(_) => None,
+            KernelCommand::ObjectRangeFree(objid, _) => Some(objid),
+            KernelCommand::ObjectSwap(objid, _) => Some(objid),
         }
     }
 }
@@ -52,6 +54,11 @@ pub enum KernelCommand {
     ObjectDel(ObjID),
     ObjectCreate(ObjID, ObjectInfo),
     DramPages(PhysRange),
+    /// The given range of the object was discarded, and should read as zeros from now on.
+    ObjectRangeFree(ObjID, ObjectRange),
+    /// The contents of the two objects were exchanged, and their backing storage should be too.
+    /// The metadata pages stay with their objects.
+    ObjectSwap(ObjID, ObjID),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Ord, Eq)]
@@ -2,6 +2,10 @@ use bitflags::bitflags;
 
 use crate::object::ObjID;
 
+/// The length in bytes of a key for encrypting a secure gate's arguments. Defined here, rather
+/// than in twizzler-security, so that secgate can use it without depending on that crate.
+pub const GATE_KEY_LEN: usize = 32;
+
 #[repr(C)]
 pub struct SecurityContextBase {
     caps_data: ObjID,
@@ -45,6 +45,12 @@ impl From<MapFlags> for twizzler_rt_abi::object::MapFlags {
 }
 
 /// Map an object into the address space with the specified protections.
+///
+/// The mapping is granted the intersection of `prot` and the caller's effective protections for
+/// the object (its default protections plus any capabilities in the caller's security contexts).
+/// If `prot` asks for anything beyond the effective protections, the call fails with
+/// [GenericError::AccessDenied](twizzler_rt_abi::error::GenericError::AccessDenied) instead of
+/// mapping with fewer protections.
 pub fn sys_object_map(
     handle: Option<ObjID>,
     id: ObjID,
@@ -25,6 +25,9 @@ pub enum ObjectControlCmd {
     Sync,
     /// Preload an object's data
     Preload,
+    /// Start loading (without waiting) up to the given number of pages of an object's data,
+    /// starting from the base page.
+    PreloadPages(u64),
 }
 
 impl From<ObjectControlCmd> for (u64, u64) {
@@ -34,6 +37,7 @@ impl From<ObjectControlCmd> for (u64, u64) {
             ObjectControlCmd::Delete(x) => (1, x.bits()),
             ObjectControlCmd::Sync => (2, 0),
             ObjectControlCmd::Preload => (3, 0),
+            ObjectControlCmd::PreloadPages(n) => (4, n),
         }
     }
 }
@@ -48,6 +52,7 @@ impl TryFrom<(u64, u64)> for ObjectControlCmd {
             ),
             2 => ObjectControlCmd::Sync,
             3 => ObjectControlCmd::Preload,
+            4 => ObjectControlCmd::PreloadPages(value.1),
             _ => return Err(ArgumentError::InvalidArgument.into()),
         })
     }
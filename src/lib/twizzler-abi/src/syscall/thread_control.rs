@@ -69,6 +69,8 @@ pub enum ThreadControl {
     SetTraceEvents = 20,
     /// Get trace events.
     GetTraceEvents = 21,
+    /// Check whether a security context is attached to the calling thread.
+    HasSctx = 22,
 }
 
 /// Exit the thread. The code will be written to the [crate::thread::ThreadRepr] for the current
@@ -125,6 +127,25 @@ pub fn sys_thread_active_sctx_id() -> ObjID {
     ObjID::from_parts([hi, lo])
 }
 
+/// Returns true if the security context `id` is attached to the calling thread (active or not).
+/// Unlike anything the thread says about itself, this can be trusted by code in another
+/// compartment running on the thread, e.g. to check the source context of a gate call.
+pub fn sys_thread_has_sctx(id: ObjID) -> bool {
+    let (code, _) = unsafe {
+        raw_syscall(
+            Syscall::ThreadCtrl,
+            &[
+                0,
+                0,
+                ThreadControl::HasSctx as u64,
+                id.parts()[0],
+                id.parts()[1],
+            ],
+        )
+    };
+    code != 0
+}
+
 /// Get the active security context ID for the calling thread.
 pub fn sys_thread_set_active_sctx_id(id: ObjID) -> Result<(), TwzError> {
     let (code, val) = unsafe {
@@ -1,3 +1,6 @@
+use alloc::vec::Vec;
+use core::num::NonZeroU16;
+
 #[cfg(feature = "log")]
 use log::debug;
 use sha2::{Digest, Sha256};
@@ -5,7 +8,7 @@ use twizzler_abi::object::{ObjID, Protections};
 
 use crate::{
     flags::{CapFlags, HashingAlgo},
-    Gates, Revoc, SecurityError, Signature, SigningKey, VerifyingKey,
+    CapScope, Gates, Revoc, SecurityError, Signature, SigningKey, SigningScheme, VerifyingKey,
 };
 
 /// A capability that represents authorization for a [Security Context](`crate::sec_ctx::SecCtx`) to
@@ -26,6 +29,8 @@ use crate::{
 /// * `flags` - Specifies the cryptographic primitives used to form the signature
 /// * `gates` - Allows access into an object in a specified range
 /// * `revocation` - Specifies when the capability is invalid
+/// * `uses` - How many times the capability may be used, or 0 for no limit
+/// * `scope` - The byte range of the target that the capability grants access to
 /// * `signature` - the signature of the capability
 ///
 /// # Examples
@@ -54,11 +59,48 @@ pub struct Cap {
     /// Specifies when this capability is invalid, i.e. expiration.
     pub revocation: Revoc,
 
+    /// Number of accesses this capability grants before it's used up, or 0 for no limit.
+    uses: u16,
+
+    /// The part of the object this capability grants access to, or None for all of it.
+    scope: Option<CapScope>,
+
     /// The signature inside the capability
     sig: Signature,
 }
 
+/// Length of the signed fields of a capability that isn't scoped. This is the layout capabilities
+/// had before scopes were added, so capabilities signed back then still verify.
 const CAP_SERIALIZED_LEN: usize = 78;
+/// Length of the signed fields of a scoped capability: the unscoped layout, followed by the scope.
+const CAP_SCOPED_SERIALIZED_LEN: usize = CAP_SERIALIZED_LEN + 16;
+
+/// The number of uses a limited capability gets if not otherwise specified; that is, the
+/// capability is good for exactly one access.
+pub const DEFAULT_CAP_USES: NonZeroU16 = NonZeroU16::MIN;
+
+/// Versions of the encoding produced by [`Cap::to_bytes`]. Version 1 carries the unscoped fields,
+/// and is what unscoped capabilities are still encoded as; version 2 adds the scope.
+const CAP_WIRE_VERSION: u8 = 1;
+const CAP_WIRE_VERSION_SCOPED: u8 = 2;
+/// Version byte, signing scheme, and signature length, not counting the serialized fields.
+const CAP_WIRE_HEADER_LEN: usize = 1 + 1 + 2;
+
+/// Identifies a capability independently of where it is stored, for global revocation (see
+/// [`crate::RevocationList`]). The ID is a hash of the capability's contents, not including the
+/// signature.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct CapId([u8; 32]);
+
+impl CapId {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
 
 impl Cap {
     /// creating a new capability, revoc specified in expiration data in ns from unix epoch
@@ -70,6 +112,87 @@ impl Cap {
         revocation: Revoc,
         gates: Gates,
         hashing_algo: HashingAlgo,
+    ) -> Result<Self, SecurityError> {
+        Self::new_with_uses(
+            target,
+            accessor,
+            prots,
+            target_priv_key,
+            revocation,
+            gates,
+            hashing_algo,
+            0,
+            None,
+        )
+    }
+
+    /// Creates a capability that is only good for `uses` accesses (see [DEFAULT_CAP_USES] for a
+    /// one-time capability). The kernel counts down each access it grants with this capability,
+    /// and refuses it once the count reaches zero. Used-up capabilities are remembered until they
+    /// expire, so limited capabilities should be issued with an expiring `revocation`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_limited(
+        target: ObjID,
+        accessor: ObjID,
+        prots: Protections,
+        target_priv_key: &SigningKey,
+        revocation: Revoc,
+        gates: Gates,
+        hashing_algo: HashingAlgo,
+        uses: NonZeroU16,
+    ) -> Result<Self, SecurityError> {
+        Self::new_with_uses(
+            target,
+            accessor,
+            prots,
+            target_priv_key,
+            revocation,
+            gates,
+            hashing_algo,
+            uses.get(),
+            None,
+        )
+    }
+
+    /// Creates a capability that only grants access to the `scope` byte range of the target,
+    /// e.g. to share one region of a large object. The kernel only maps pages of the target that
+    /// lie entirely inside the range, and accesses to any other page fault, so the range should
+    /// be page-aligned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_scoped(
+        target: ObjID,
+        accessor: ObjID,
+        prots: Protections,
+        target_priv_key: &SigningKey,
+        revocation: Revoc,
+        gates: Gates,
+        hashing_algo: HashingAlgo,
+        scope: CapScope,
+    ) -> Result<Self, SecurityError> {
+        Self::new_with_uses(
+            target,
+            accessor,
+            prots,
+            target_priv_key,
+            revocation,
+            gates,
+            hashing_algo,
+            0,
+            (scope != CapScope::WHOLE).then_some(scope),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new_with_uses(
+        target: ObjID,
+        accessor: ObjID,
+        prots: Protections,
+        target_priv_key: &SigningKey,
+        revocation: Revoc,
+        gates: Gates,
+        hashing_algo: HashingAlgo,
+        uses: u16,
+        scope: Option<CapScope>,
     ) -> Result<Self, SecurityError> {
         let flags: CapFlags = hashing_algo.clone().into();
 
@@ -79,12 +202,15 @@ impl Cap {
             flags, target
         );
 
-        let hash_arr = Cap::serialize(accessor, target, prots, flags, revocation, gates);
+        let (hash_arr, len) = Cap::serialize(
+            accessor, target, prots, flags, revocation, gates, uses, scope,
+        );
+        let hash_arr = &hash_arr[..len];
 
         let sig = match hashing_algo {
             HashingAlgo::Blake3 => {
                 // unimplemented!("running into problems with blake3 compilation on aarch64");
-                let hash = blake3::hash(&hash_arr);
+                let hash = blake3::hash(hash_arr);
                 target_priv_key.sign(hash.as_bytes())?
             }
             HashingAlgo::Sha256 => {
@@ -102,20 +228,39 @@ impl Cap {
             flags,
             revocation,
             gates,
+            uses,
+            scope,
             sig,
         })
     }
 
+    /// The number of accesses this capability is good for, if it is limited.
+    pub fn max_uses(&self) -> Option<NonZeroU16> {
+        NonZeroU16::new(self.uses)
+    }
+
+    /// The byte range of the target this capability is limited to, if it is scoped.
+    pub fn scope(&self) -> Option<CapScope> {
+        self.scope
+    }
+
+    /// checks to see if the `len` bytes at `offset` in the target fall in the capability's scope.
+    pub fn covers(&self, offset: u64, len: u64) -> bool {
+        self.scope.is_none_or(|scope| scope.contains(offset, len))
+    }
+
     /// verifies signature inside capability
 
     pub fn verify_sig(&self, verifying_key: &VerifyingKey) -> Result<(), SecurityError> {
-        let hash_arr = Self::serialize(
+        let (hash_arr, len) = Self::serialize(
             self.accessor,
             self.target,
             self.protections,
             self.flags,
             self.revocation,
             self.gates,
+            self.uses,
+            self.scope,
         );
 
         let hash_algo: HashingAlgo = self.flags.try_into()?;
@@ -125,7 +270,7 @@ impl Cap {
                 // #[cfg(feature = "log")]
                 // error!("running into problems with blake3 compilation on aarch64");
                 // unimplemented!("running into problems with blake3 compilation on aarch64");
-                let hash = blake3::hash(&hash_arr);
+                let hash = blake3::hash(&hash_arr[..len]);
                 let bind = hash.as_bytes();
                 verifying_key.verify(bind.as_slice(), &self.sig)
             }
@@ -133,13 +278,116 @@ impl Cap {
                 #[cfg(feature = "log")]
                 debug!("Hashing via Sha256");
                 let mut hasher = sha2::Sha256::new();
-                hasher.update(&hash_arr);
+                hasher.update(&hash_arr[..len]);
                 let result = hasher.finalize();
                 verifying_key.verify(result.as_slice(), &self.sig)
             }
         }
     }
 
+    /// returns the ID of this capability, used to revoke it globally
+    pub fn id(&self) -> CapId {
+        let (hash_arr, len) = Self::serialize(
+            self.accessor,
+            self.target,
+            self.protections,
+            self.flags,
+            self.revocation,
+            self.gates,
+            self.uses,
+            self.scope,
+        );
+        let mut hasher = Sha256::new();
+        hasher.update(&hash_arr[..len]);
+        CapId(hasher.finalize().into())
+    }
+
+    /// Encodes this capability, including its signature, so it can be sent to another node or
+    /// persisted. The encoding is versioned; see [`Cap::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let sig = self.sig.as_bytes();
+        let (fields, len) = Self::serialize(
+            self.accessor,
+            self.target,
+            self.protections,
+            self.flags,
+            self.revocation,
+            self.gates,
+            self.uses,
+            self.scope,
+        );
+        let mut bytes = Vec::with_capacity(CAP_WIRE_HEADER_LEN + len + sig.len());
+        bytes.push(if self.scope.is_some() {
+            CAP_WIRE_VERSION_SCOPED
+        } else {
+            CAP_WIRE_VERSION
+        });
+        bytes.extend_from_slice(&fields[..len]);
+        bytes.push(match self.sig.scheme() {
+            SigningScheme::Ecdsa => 0,
+            SigningScheme::Ed25519 => 1,
+        });
+        bytes.extend_from_slice(&(sig.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(sig);
+        bytes
+    }
+
+    /// Decodes a capability produced by [`Cap::to_bytes`]. This only checks that the encoding is
+    /// well-formed; the caller must still check the signature with [`Cap::verify_sig`] before
+    /// trusting it, which is what catches a capability whose fields were tampered with.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cap, SecurityError> {
+        let fields_len = match bytes.first() {
+            Some(&CAP_WIRE_VERSION) => CAP_SERIALIZED_LEN,
+            Some(&CAP_WIRE_VERSION_SCOPED) => CAP_SCOPED_SERIALIZED_LEN,
+            _ => return Err(SecurityError::InvalidScheme),
+        };
+        if bytes.len() < CAP_WIRE_HEADER_LEN + fields_len {
+            return Err(SecurityError::InvalidScheme);
+        }
+        let fields = &bytes[1..1 + fields_len];
+        let u128_at = |off: usize| u128::from_le_bytes(fields[off..off + 16].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(fields[off..off + 8].try_into().unwrap());
+        let u16_at = |off: usize| u16::from_le_bytes(fields[off..off + 2].try_into().unwrap());
+
+        let protections =
+            Protections::from_bits(u16_at(32)).ok_or(SecurityError::SignatureMismatch)?;
+        let flags = CapFlags::from_bits(u16_at(34)).ok_or(SecurityError::InvalidScheme)?;
+        // make sure the flags name exactly one hashing algorithm.
+        let _: HashingAlgo = flags.try_into()?;
+
+        let rest = &bytes[1 + fields_len..];
+        let scheme = match rest[0] {
+            0 => SigningScheme::Ecdsa,
+            1 => SigningScheme::Ed25519,
+            _ => return Err(SecurityError::InvalidScheme),
+        };
+        let sig_len = u16::from_le_bytes([rest[1], rest[2]]) as usize;
+        if rest.len() - 3 != sig_len {
+            return Err(SecurityError::SignatureMismatch);
+        }
+        let sig = Signature::from_parts(scheme, &rest[3..])?;
+
+        Ok(Cap {
+            accessor: ObjID::new(u128_at(0)),
+            target: ObjID::new(u128_at(16)),
+            protections,
+            flags,
+            revocation: Revoc::new(u128_at(36)),
+            gates: Gates::new(u64_at(52), u64_at(60), u64_at(68)),
+            uses: u16_at(76),
+            // A scoped encoding of the whole object would sign different bytes than the unscoped
+            // capability it decodes to, so it's rejected rather than silently failing to verify.
+            scope: match fields_len {
+                CAP_SERIALIZED_LEN => None,
+                _ => Some(CapScope::new(u64_at(78), u64_at(86)))
+                    .filter(|s| *s != CapScope::WHOLE)
+                    .map(Some)
+                    .ok_or(SecurityError::InvalidScheme)?,
+            },
+            sig,
+        })
+    }
+
     /// checks to see if the specified ptr_offset falls in the capability's gate.
     pub fn check_gate(&self, ptr_offset: u64, align: u64) -> Result<(), SecurityError> {
         // The `offset` and `length` fields specify a region within the object. When the
@@ -170,7 +418,10 @@ impl Cap {
         Ok(())
     }
 
-    /// returns all contents other than sig as a buffer ready to hash
+    /// returns all contents other than sig as a buffer ready to hash, and how many bytes of it are
+    /// used. The scope is only included for scoped capabilities, so unscoped ones sign the same
+    /// bytes they did before scopes existed.
+    #[allow(clippy::too_many_arguments)]
     fn serialize(
         accessor: ObjID,
         target: ObjID,
@@ -178,8 +429,10 @@ impl Cap {
         flags: CapFlags,
         revocation: Revoc,
         gates: Gates,
-    ) -> [u8; CAP_SERIALIZED_LEN] {
-        let mut hash_arr: [u8; CAP_SERIALIZED_LEN] = [0; CAP_SERIALIZED_LEN];
+        uses: u16,
+        scope: Option<CapScope>,
+    ) -> ([u8; CAP_SCOPED_SERIALIZED_LEN], usize) {
+        let mut hash_arr = [0; CAP_SCOPED_SERIALIZED_LEN];
         hash_arr[0..16].copy_from_slice(&accessor.raw().to_le_bytes());
         hash_arr[16..32].copy_from_slice(&target.raw().to_le_bytes());
         hash_arr[32..34].copy_from_slice(&prots.bits().to_le_bytes());
@@ -188,7 +441,13 @@ impl Cap {
         hash_arr[52..60].copy_from_slice(&gates.offset.to_le_bytes());
         hash_arr[60..68].copy_from_slice(&gates.length.to_le_bytes());
         hash_arr[68..76].copy_from_slice(&gates.align.to_le_bytes());
-        hash_arr
+        hash_arr[76..78].copy_from_slice(&uses.to_le_bytes());
+        let Some(scope) = scope else {
+            return (hash_arr, CAP_SERIALIZED_LEN);
+        };
+        hash_arr[78..86].copy_from_slice(&scope.offset.to_le_bytes());
+        hash_arr[86..94].copy_from_slice(&scope.len.to_le_bytes());
+        (hash_arr, CAP_SCOPED_SERIALIZED_LEN)
     }
 }
 
@@ -233,6 +492,184 @@ mod tests {
             .expect("capability should have been verified.")
     }
 
+    #[test]
+    fn test_capability_serialization_round_trip() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let cap = Cap::new(
+            0x123.into(),
+            0x321.into(),
+            Protections::READ | Protections::WRITE,
+            s.base(),
+            Revoc::new(1_000_000),
+            Gates::new(16, 4096, 8),
+            HashingAlgo::Sha256,
+        )
+        .expect("Capability should have been created.");
+
+        let decoded = Cap::from_bytes(&cap.to_bytes()).expect("encoding should decode");
+        assert_eq!(decoded, cap);
+        decoded
+            .verify_sig(v.base())
+            .expect("decoded capability should still verify");
+    }
+
+    #[test]
+    fn test_capability_serialization_tamper() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let cap = Cap::new(
+            0x123.into(),
+            0x321.into(),
+            Protections::READ,
+            s.base(),
+            Revoc::default(),
+            Gates::default(),
+            HashingAlgo::Sha256,
+        )
+        .expect("Capability should have been created.");
+        let bytes = cap.to_bytes();
+
+        // Granting ourselves write access still decodes, but no longer matches the signature.
+        let mut tampered = bytes.clone();
+        tampered[1 + 32] |= Protections::WRITE.bits() as u8;
+        let tampered = Cap::from_bytes(&tampered).expect("tampered fields are well-formed");
+        assert!(tampered.protections.contains(Protections::WRITE));
+        assert!(tampered.verify_sig(v.base()).is_err());
+
+        // Malformed encodings are rejected outright.
+        let mut bad_version = bytes.clone();
+        bad_version[0] = 0xff;
+        assert!(Cap::from_bytes(&bad_version).is_err());
+        assert!(Cap::from_bytes(&bytes[0..bytes.len() - 1]).is_err());
+        assert!(Cap::from_bytes(&bytes[0..10]).is_err());
+    }
+
+    #[test]
+    fn test_capability_scheme_mismatch() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let cap = default_capability(s.base());
+
+        // The same signature bytes, claimed under a different scheme.
+        let relabeled = Cap {
+            sig: Signature::from_parts(SigningScheme::Ed25519, cap.sig.as_bytes())
+                .expect("signature should fit"),
+            ..cap
+        };
+        assert_eq!(
+            relabeled.verify_sig(v.base()),
+            Err(SecurityError::InvalidScheme)
+        );
+
+        // A relabeled signature survives encoding, but still doesn't verify.
+        let decoded = Cap::from_bytes(&relabeled.to_bytes()).expect("encoding should decode");
+        assert_eq!(decoded.sig.scheme(), SigningScheme::Ed25519);
+        assert_eq!(
+            decoded.verify_sig(v.base()),
+            Err(SecurityError::InvalidScheme)
+        );
+
+        // An object whose key expects another scheme rejects the original capability too.
+        let mut other_key = *v.base();
+        other_key.scheme = SigningScheme::Ed25519;
+        assert_eq!(
+            cap.verify_sig(&other_key),
+            Err(SecurityError::InvalidScheme)
+        );
+
+        cap.verify_sig(v.base())
+            .expect("capability should verify under its own scheme");
+    }
+
+    #[test]
+    fn test_capability_limited_uses() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        assert_eq!(default_capability(s.base()).max_uses(), None);
+
+        let cap = Cap::new_limited(
+            0x123.into(),
+            0x321.into(),
+            Protections::READ,
+            s.base(),
+            Revoc::default(),
+            Gates::default(),
+            HashingAlgo::Sha256,
+            DEFAULT_CAP_USES,
+        )
+        .expect("Capability should have been created.");
+        assert_eq!(cap.max_uses().map(|n| n.get()), Some(1));
+
+        let decoded = Cap::from_bytes(&cap.to_bytes()).expect("encoding should decode");
+        assert_eq!(decoded, cap);
+
+        // The use count is covered by the signature, so it can't be raised by the holder.
+        let mut tampered = cap.to_bytes();
+        tampered[1 + 76] = 0xff;
+        let tampered = Cap::from_bytes(&tampered).expect("tampered fields are well-formed");
+        assert!(tampered.verify_sig(v.base()).is_err());
+    }
+
+    #[test]
+    fn test_capability_scope() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let unscoped = default_capability(s.base());
+        assert_eq!(unscoped.scope(), None);
+        assert!(unscoped.covers(0, u64::MAX));
+
+        let scope = CapScope::new(0x2000, 0x3000);
+        let cap = Cap::new_scoped(
+            0x123.into(),
+            0x321.into(),
+            Protections::READ,
+            s.base(),
+            Revoc::default(),
+            Gates::default(),
+            HashingAlgo::Sha256,
+            scope,
+        )
+        .expect("Capability should have been created.");
+        assert_eq!(cap.scope(), Some(scope));
+        assert!(cap.covers(0x2000, 0x1000));
+        assert!(cap.covers(0x4000, 0x1000));
+        // Pages before, after, and straddling the end of the scope aren't covered.
+        assert!(!cap.covers(0x1000, 0x1000));
+        assert!(!cap.covers(0x5000, 0x1000));
+        assert!(!cap.covers(0x4800, 0x1000));
+
+        let decoded = Cap::from_bytes(&cap.to_bytes()).expect("encoding should decode");
+        assert_eq!(decoded, cap);
+
+        // The scope is covered by the signature, so it can't be widened by the holder.
+        let mut tampered = cap.to_bytes();
+        tampered[1 + 86..1 + 94].copy_from_slice(&u64::MAX.to_le_bytes());
+        let tampered = Cap::from_bytes(&tampered).expect("tampered fields are well-formed");
+        assert!(tampered.covers(0x5000, 0x1000));
+        assert!(tampered.verify_sig(v.base()).is_err());
+
+        // Nor can it be dropped by re-encoding the capability as an unscoped one.
+        use super::{
+            CAP_SCOPED_SERIALIZED_LEN, CAP_SERIALIZED_LEN, CAP_WIRE_HEADER_LEN, CAP_WIRE_VERSION,
+        };
+        let bytes = cap.to_bytes();
+        let mut stripped = alloc::vec![CAP_WIRE_VERSION];
+        stripped.extend_from_slice(&bytes[1..1 + CAP_SERIALIZED_LEN]);
+        stripped.extend_from_slice(&bytes[1 + CAP_SCOPED_SERIALIZED_LEN..]);
+        let stripped = Cap::from_bytes(&stripped).expect("stripped fields are well-formed");
+        assert_eq!(stripped.scope(), None);
+        assert!(stripped.verify_sig(v.base()).is_err());
+
+        // Unscoped capabilities keep the encoding, and so the signatures, they had before scopes.
+        let bytes = unscoped.to_bytes();
+        assert_eq!(bytes[0], CAP_WIRE_VERSION);
+        assert_eq!(
+            bytes.len(),
+            CAP_WIRE_HEADER_LEN + CAP_SERIALIZED_LEN + unscoped.sig.as_bytes().len()
+        );
+    }
+
     #[test]
     fn test_capability_gates() {
         struct Input {
@@ -43,6 +43,9 @@ impl Debug for CapFlags {
 pub enum SigningScheme {
     #[default]
     Ecdsa,
+    /// Reserved for Ed25519. Keys and signatures can carry this tag, but none can be created or
+    /// checked under it yet.
+    Ed25519,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
@@ -0,0 +1,141 @@
+//! Objects whose contents are checked against a signature when they are mapped.
+//!
+//! An object can carry an [ObjectSeal]: a signature, made with the object's signing key, over the
+//! first `len` bytes of its base (starting right after the null page). The kernel recomputes the
+//! hash when the object is mapped and checks the signature against the object's verifying key (the
+//! object named by `kuid` in its metadata). If they don't match, the map is refused, so bytes that
+//! were changed behind the owner's back (e.g. in persistent storage) are never used.
+//!
+//! The seal is declared like other metadata extensions:
+//!
+//! 1. Write the [ObjectSeal] into the object's meta page, at [SEAL_OFFSET].
+//! 2. Add a [MetaExt] to the extension array with tag [MEXT_INTEGRITY_SEAL], whose value is the
+//!    byte offset of the seal from the start of the meta page, and count it in `extcount`.
+//!
+//! Since the seal covers the object's contents, any legitimate update to the sealed bytes must be
+//! followed by re-signing them, or the object can no longer be mapped. [reseal_object] does both
+//! steps for a mapped object.
+//!
+//! [MetaExt]: twizzler_abi::meta::MetaExt
+
+use core::mem::size_of;
+
+use sha2::{Digest, Sha256};
+use twizzler_abi::object::NULLPAGE_SIZE;
+
+use crate::{SecurityError, Signature, SigningKey, VerifyingKey};
+
+/// The meta extension tag that declares an [ObjectSeal]. The extension's value is the offset of
+/// the seal from the start of the meta page.
+pub const MEXT_INTEGRITY_SEAL: u64 = 0x7365_616c;
+
+/// Where [reseal_object] puts the seal: at the very end of the meta page, out of the way of the
+/// extension array, which grows up from the [MetaInfo](twizzler_abi::meta::MetaInfo).
+pub const SEAL_OFFSET: usize = NULLPAGE_SIZE - size_of::<ObjectSeal>();
+
+/// Hashes the sealed bytes of an object. The bytes may be fed in any number of pieces.
+#[derive(Clone, Default)]
+pub struct SealHasher(Sha256);
+
+impl SealHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// A signature over the first `len` bytes of an object's base. See the [module
+/// documentation](self).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct ObjectSeal {
+    len: u64,
+    sig: Signature,
+}
+
+impl ObjectSeal {
+    /// Seal `len` bytes whose hash (see [SealHasher]) is `digest`.
+    pub fn sign(len: usize, digest: &[u8; 32], key: &SigningKey) -> Result<Self, SecurityError> {
+        let len = len as u64;
+        let sig = key.sign(&Self::message(len, digest))?;
+        Ok(Self { len, sig })
+    }
+
+    /// The number of bytes covered by the seal.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Check that the covered bytes, whose hash is `digest`, are the ones that were sealed.
+    pub fn verify(&self, digest: &[u8; 32], key: &VerifyingKey) -> Result<(), SecurityError> {
+        key.verify(&Self::message(self.len, digest), &self.sig)
+    }
+
+    // The length is signed along with the hash, so that a seal can't be made to cover fewer bytes.
+    fn message(len: u64, digest: &[u8; 32]) -> [u8; 40] {
+        let mut msg = [0; 40];
+        msg[0..32].copy_from_slice(digest);
+        msg[32..40].copy_from_slice(&len.to_le_bytes());
+        msg
+    }
+}
+
+/// Sign the first `len` bytes of `obj`'s base with `key`, and record the seal in its metadata,
+/// replacing any previous seal. Call this after changing the sealed bytes of an object that was
+/// sealed before. The object must be mapped writable.
+#[cfg(feature = "user")]
+pub fn reseal_object(
+    obj: &impl twizzler::object::RawObject,
+    len: usize,
+    key: &SigningKey,
+) -> Result<(), twizzler_rt_abi::error::TwzError> {
+    use twizzler_abi::meta::{MetaExt, MetaInfo};
+    use twizzler_rt_abi::error::{ArgumentError, ResourceError};
+
+    if len > twizzler_abi::object::MAX_SIZE - 2 * NULLPAGE_SIZE {
+        return Err(ArgumentError::InvalidArgument.into());
+    }
+    let base = obj
+        .lea(NULLPAGE_SIZE, len)
+        .ok_or(ArgumentError::InvalidArgument)?;
+    let mut hasher = SealHasher::new();
+    // Safety: the range lies within the object, which is mapped.
+    hasher.update(unsafe { core::slice::from_raw_parts(base, len) });
+    let seal = ObjectSeal::sign(len, &hasher.finish(), key)?;
+
+    let meta = obj.meta_mut_ptr();
+    // Safety: the meta page is mapped along with the object, and the seal and extensions lie
+    // within it.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        let extcount = (*meta).extcount as usize;
+        let idx = (0..extcount)
+            .find(|i| (*exts.add(*i)).tag == MEXT_INTEGRITY_SEAL)
+            .unwrap_or(extcount);
+        if size_of::<MetaInfo>() + (idx + 1) * size_of::<MetaExt>() > SEAL_OFFSET {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        meta.cast::<u8>()
+            .add(SEAL_OFFSET)
+            .cast::<ObjectSeal>()
+            .write_unaligned(seal);
+        exts.add(idx).write(MetaExt {
+            tag: MEXT_INTEGRITY_SEAL,
+            value: SEAL_OFFSET as u64,
+        });
+        if idx == extcount {
+            (*meta).extcount += 1;
+        }
+    }
+    Ok(())
+}
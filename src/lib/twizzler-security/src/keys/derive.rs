@@ -0,0 +1,116 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+use twizzler_abi::object::ObjID;
+pub use twizzler_abi::security::GATE_KEY_LEN;
+
+use super::SigningKey;
+
+/// Length in bytes of a derived per-object encryption key.
+pub const OBJECT_KEY_LEN: usize = 32;
+
+// Domain separation for object keys, so the same master key can feed other HKDF uses later.
+const OBJECT_KEY_SALT: &[u8] = b"twizzler-lethe-object-key-v1";
+
+const GATE_KEY_SALT: &[u8] = b"twizzler-secgate-args-key-v1";
+
+/// A symmetric key for encrypting one object's data during one Lethe epoch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ObjectKey {
+    key: [u8; OBJECT_KEY_LEN],
+    /// The object this key belongs to.
+    pub id: ObjID,
+    /// The epoch this key was derived for.
+    pub epoch: u64,
+}
+
+impl ObjectKey {
+    pub fn as_bytes(&self) -> &[u8; OBJECT_KEY_LEN] {
+        &self.key
+    }
+}
+
+impl SigningKey {
+    /// Derives the encryption key for `id` during `epoch` from this key using HKDF-SHA256.
+    ///
+    /// Derivation is deterministic, so nothing but the master key needs to be stored. When the
+    /// epoch rolls over, ciphertext written under an older epoch can still be decrypted by
+    /// deriving the key for the epoch it was written in, until it is re-encrypted under the new
+    /// one. Callers must therefore record the epoch alongside the ciphertext. Destroying the
+    /// master key makes every object key unrecoverable.
+    pub fn derive_object_key(&self, id: ObjID, epoch: u64) -> ObjectKey {
+        let mut info = [0u8; 24];
+        info[0..16].copy_from_slice(&id.raw().to_le_bytes());
+        info[16..24].copy_from_slice(&epoch.to_le_bytes());
+
+        let hk = Hkdf::<Sha256>::new(Some(OBJECT_KEY_SALT), self.as_bytes());
+        let mut key = [0u8; OBJECT_KEY_LEN];
+        // Unwrap-Ok: the output is far shorter than HKDF's 255 * hash length limit.
+        hk.expand(&info, &mut key).unwrap();
+
+        ObjectKey { key, id, epoch }
+    }
+
+    /// Derives the key for encrypting the arguments of calls to the secure gate named `gate` in
+    /// `compartment`, using HKDF-SHA256. The caller's and the callee's compartments each derive it
+    /// from the same master key and set it with `secgate::set_gate_key`, so the key is never
+    /// passed between them.
+    pub fn derive_gate_key(&self, compartment: &str, gate: &str) -> [u8; GATE_KEY_LEN] {
+        // The compartment name is length-prefixed, so no two (compartment, gate) pairs share info.
+        let len = (compartment.len() as u64).to_le_bytes();
+        let info = [&len[..], compartment.as_bytes(), gate.as_bytes()];
+
+        let hk = Hkdf::<Sha256>::new(Some(GATE_KEY_SALT), self.as_bytes());
+        let mut key = [0u8; GATE_KEY_LEN];
+        // Unwrap-Ok: the output is far shorter than HKDF's 255 * hash length limit.
+        hk.expand_multi_info(&info, &mut key).unwrap();
+        key
+    }
+}
+
+#[cfg(feature = "user")]
+mod tests {
+    use twizzler_abi::object::ObjID;
+
+    use crate::{SigningKey, SigningScheme};
+
+    fn master_key(byte: u8) -> SigningKey {
+        let mut secret = [0_u8; 32];
+        secret[31] = byte;
+        SigningKey::from_slice(&secret, SigningScheme::Ecdsa).expect("secret should be valid")
+    }
+
+    #[test]
+    fn test_object_key_derivation() {
+        let master = master_key(1);
+        let id = ObjID::new(0x1234);
+
+        let k0 = master.derive_object_key(id, 0);
+        assert_eq!(k0, master.derive_object_key(id, 0));
+        assert_eq!(k0.epoch, 0);
+
+        // epoch rollover gives a fresh key, while the old one can still be re-derived.
+        let k1 = master.derive_object_key(id, 1);
+        assert_ne!(k0.as_bytes(), k1.as_bytes());
+        assert_eq!(k0, master.derive_object_key(id, 0));
+
+        let other = master.derive_object_key(ObjID::new(0x1235), 0);
+        assert_ne!(k0.as_bytes(), other.as_bytes());
+
+        let other_master = master_key(2).derive_object_key(id, 0);
+        assert_ne!(k0.as_bytes(), other_master.as_bytes());
+    }
+
+    #[test]
+    fn test_gate_key_derivation() {
+        let master = master_key(1);
+        let key = master.derive_gate_key("vault", "get_token");
+        assert_eq!(key, master.derive_gate_key("vault", "get_token"));
+        assert_ne!(key, master.derive_gate_key("vault", "put_token"));
+        // The same gate name in another compartment gets its own key.
+        assert_ne!(key, master.derive_gate_key("other", "get_token"));
+        assert_ne!(key, master.derive_gate_key("vaultget", "_token"));
+        assert_ne!(key, master_key(2).derive_gate_key("vault", "get_token"));
+        // Separate from object keys derived from the same master.
+        assert_ne!(&key, master.derive_object_key(ObjID::new(0), 0).as_bytes());
+    }
+}
@@ -1,12 +1,25 @@
+mod derive;
 mod sig;
 mod sign;
 mod verify;
+pub use derive::*;
 pub use sig::*;
 pub use sign::*;
 pub use verify::*;
 
 const MAX_KEY_SIZE: usize = 128;
 
+/// Fail an operation with `err`, because it was asked for under a scheme that keys and signatures
+/// can be tagged with but that has no implementation yet (see [SigningScheme::Ed25519]).
+///
+/// [SigningScheme::Ed25519]: crate::SigningScheme::Ed25519
+fn unsupported_scheme<T, E>(_scheme: &crate::SigningScheme, err: E) -> Result<T, E> {
+    #[cfg(feature = "log")]
+    log::error!("Unsupported signing scheme: {:?}", _scheme);
+
+    Err(err)
+}
+
 // currently these tests can only run in user space, would have to write their own
 // tests written inside kernel to run.
 #[cfg(feature = "user")]
@@ -82,4 +95,66 @@ mod tests {
             .verify(message, &sig)
             .expect("Should be verified properly");
     }
+
+    // SubjectPublicKeyInfo for the P-256 generator point, i.e. the public key for a secret
+    // scalar of 1. Matches `openssl ec -pubout -outform DER` for that key.
+    const GENERATOR_SPKI_DER: [u8; 91] = [
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08,
+        0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0x6b, 0x17, 0xd1,
+        0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40, 0xf2, 0x77, 0x03,
+        0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98, 0xc2, 0x96, 0x4f,
+        0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e, 0x16,
+        0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf, 0x51,
+        0xf5,
+    ];
+
+    fn generator_key_pair() -> (SigningKey, VerifyingKey) {
+        let mut secret = [0_u8; 32];
+        secret[31] = 1;
+
+        let skey = SigningKey::from_slice(&secret, SigningScheme::Ecdsa)
+            .expect("scalar of 1 should be a valid secret key");
+        let vkey = VerifyingKey::new(&SigningScheme::Ecdsa, &skey)
+            .expect("verifying key should be derived properly");
+
+        (skey, vkey)
+    }
+
+    #[test]
+    fn test_der_round_trip() {
+        use twizzler::object::TypedObject;
+
+        let (_, v_obj) = create_default_key_pair();
+
+        let der = v_obj.base().to_der().expect("key should encode to DER");
+        let decoded = VerifyingKey::from_der(&der).expect("DER should decode back into a key");
+
+        assert_eq!(*v_obj.base(), decoded);
+    }
+
+    #[test]
+    fn test_der_golden_bytes() {
+        let (skey, vkey) = generator_key_pair();
+
+        let der = vkey.to_der().expect("key should encode to DER");
+        assert_eq!(der.as_slice(), &GENERATOR_SPKI_DER[..]);
+
+        let message = "deadbeef".as_bytes();
+        let sig = skey.sign(message).expect("Signature should succeed");
+
+        VerifyingKey::from_der(&GENERATOR_SPKI_DER)
+            .expect("golden DER should decode")
+            .verify(message, &sig)
+            .expect("Should be verified properly");
+    }
+
+    #[test]
+    fn test_der_rejects_garbage() {
+        let mut der = GENERATOR_SPKI_DER;
+        // corrupt the algorithm identifier
+        der[8] = 0xff;
+
+        assert!(VerifyingKey::from_der(&der).is_err());
+        assert!(VerifyingKey::from_der(&[0xde, 0xad, 0xbe, 0xef]).is_err());
+    }
 }
@@ -17,9 +17,27 @@ pub struct Signature {
 }
 
 impl Signature {
-    fn as_bytes(&self) -> &[u8] {
+    pub(crate) fn as_bytes(&self) -> &[u8] {
         &self.buf[0..self.len]
     }
+
+    pub(crate) fn scheme(&self) -> SigningScheme {
+        self.scheme
+    }
+
+    /// Rebuilds a signature from its raw bytes, e.g. when decoding a serialized capability.
+    pub(crate) fn from_parts(scheme: SigningScheme, bytes: &[u8]) -> Result<Self, SecurityError> {
+        if bytes.len() > MAX_SIG_SIZE {
+            return Err(SecurityError::SignatureMismatch);
+        }
+        let mut buf = [0_u8; MAX_SIG_SIZE];
+        buf[0..bytes.len()].copy_from_slice(bytes);
+        Ok(Self {
+            buf,
+            len: bytes.len(),
+            scheme,
+        })
+    }
 }
 
 impl Display for Signature {
@@ -13,9 +13,9 @@ use {
 const ECDSA_SECRET_KEY_LENGTH: usize = 32;
 
 use p256::ecdsa::{signature::Signer, Signature as EcdsaSignature, SigningKey as EcdsaSigningKey};
-use twizzler_rt_abi::error::TwzError;
+use twizzler_rt_abi::error::{GenericError, TwzError};
 
-use super::{Signature, VerifyingKey, MAX_KEY_SIZE};
+use super::{unsupported_scheme, Signature, VerifyingKey, MAX_KEY_SIZE};
 use crate::{SecurityError, SigningScheme};
 
 /// The Objects signing key stored internally in the kernel used during the signing of capabilities.
@@ -37,7 +37,10 @@ impl SigningKey {
     ) -> Result<(Object<Self>, Object<VerifyingKey>), TwzError> {
         use alloc::borrow::ToOwned;
 
+        #[cfg(not(feature = "kernel-random"))]
         use getrandom::getrandom;
+        #[cfg(feature = "kernel-random")]
+        use monitor_api::fill_kernel_random as getrandom;
 
         #[cfg(feature = "log")]
         debug!("Creating new signing key with scheme: {:?}", scheme);
@@ -75,6 +78,9 @@ impl SigningKey {
 
                 (ecdsa_signing_key.into(), ecdsa_verifying_key.into())
             }
+            SigningScheme::Ed25519 => {
+                return unsupported_scheme(scheme, GenericError::NotSupported.into())
+            }
         };
 
         let s_object = ObjectBuilder::new(obj_create_spec.clone()).build(signing_key)?;
@@ -105,6 +111,7 @@ impl SigningKey {
 
                 Ok((ecdsa_signing_key.into(), ecdsa_verifying_key.into()))
             }
+            SigningScheme::Ed25519 => unsupported_scheme(scheme, GenericError::NotSupported.into()),
         }
     }
 
@@ -137,6 +144,7 @@ impl SigningKey {
                     scheme: SigningScheme::Ecdsa,
                 })
             }
+            SigningScheme::Ed25519 => unsupported_scheme(&scheme, SecurityError::InvalidScheme),
         }
     }
 
@@ -151,6 +159,9 @@ impl SigningKey {
                 let sig: EcdsaSignature = signing_key.sign(msg);
                 Ok(sig.into())
             }
+            SigningScheme::Ed25519 => {
+                unsupported_scheme(&self.scheme, SecurityError::InvalidScheme)
+            }
         }
     }
 }
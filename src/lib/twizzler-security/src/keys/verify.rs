@@ -1,17 +1,23 @@
 #[cfg(feature = "log")]
 use log::{debug, error};
+use alloc::vec::Vec;
+
 use p256::{
     ecdsa::{
         signature::Verifier, Signature as EcdsaSignature, SigningKey as EcdsaSigningKey,
         VerifyingKey as EcdsaVerifyingKey,
     },
-    elliptic_curve::sec1::EncodedPoint,
-    NistP256,
+    elliptic_curve::{sec1::EncodedPoint, ALGORITHM_OID as EC_PUBLIC_KEY_OID},
+    pkcs8::{
+        spki::{ObjectIdentifier, SubjectPublicKeyInfoRef},
+        DecodePublicKey, EncodePublicKey,
+    },
+    NistP256, PublicKey,
 };
 #[cfg(feature = "user")]
 use twizzler::marker::BaseType;
 
-use super::{Signature, SigningKey, MAX_KEY_SIZE};
+use super::{unsupported_scheme, Signature, SigningKey, MAX_KEY_SIZE};
 use crate::{SecurityError, SigningScheme};
 
 // making our own struct for verifying key since we need to be able to support keys with different
@@ -48,6 +54,7 @@ impl VerifyingKey {
                     scheme: SigningScheme::Ecdsa,
                 })
             }
+            SigningScheme::Ed25519 => unsupported_scheme(scheme, SecurityError::InvalidScheme),
         }
     }
 
@@ -84,6 +91,7 @@ impl VerifyingKey {
                     scheme: SigningScheme::Ecdsa,
                 })
             }
+            SigningScheme::Ed25519 => unsupported_scheme(scheme, SecurityError::InvalidScheme),
         }
     }
 
@@ -92,8 +100,71 @@ impl VerifyingKey {
         &self.key[0..self.len]
     }
 
+    /// Encodes the key as a DER SubjectPublicKeyInfo, so it can be consumed by verifiers
+    /// outside of Twizzler.
+    pub fn to_der(&self) -> Result<Vec<u8>, SecurityError> {
+        match self.scheme {
+            SigningScheme::Ecdsa => {
+                let key: EcdsaVerifyingKey = self.try_into()?;
+                let doc = PublicKey::from(key).to_public_key_der().map_err(|_e| {
+                    #[cfg(feature = "log")]
+                    error!("Failed to encode EcdsaVerifyingKey as DER due to: {:?}", _e);
+
+                    SecurityError::InvalidKey
+                })?;
+
+                Ok(doc.into_vec())
+            }
+            SigningScheme::Ed25519 => {
+                unsupported_scheme(&self.scheme, SecurityError::InvalidScheme)
+            }
+        }
+    }
+
+    /// Builds a key from a DER SubjectPublicKeyInfo, picking the scheme from the
+    /// algorithm identifier.
+    pub fn from_der(der: &[u8]) -> Result<Self, SecurityError> {
+        let spki = SubjectPublicKeyInfoRef::try_from(der).map_err(|_e| {
+            #[cfg(feature = "log")]
+            error!("Failed to parse SubjectPublicKeyInfo due to: {:?}", _e);
+
+            SecurityError::InvalidKey
+        })?;
+
+        match scheme_from_spki_oid(spki.algorithm.oid)? {
+            SigningScheme::Ecdsa => {
+                let key = EcdsaVerifyingKey::from_public_key_der(der).map_err(|_e| {
+                    #[cfg(feature = "log")]
+                    error!("Failed to decode EcdsaVerifyingKey from DER due to: {:?}", _e);
+
+                    SecurityError::InvalidKey
+                })?;
+
+                Ok(key.into())
+            }
+            scheme @ SigningScheme::Ed25519 => {
+                unsupported_scheme(&scheme, SecurityError::InvalidScheme)
+            }
+        }
+    }
+
     /// Checks whether the `sig` can be verified.
+    ///
+    /// The signature must be made under the scheme this key declares. Anything else is rejected
+    /// with [`SecurityError::InvalidScheme`] before its bytes are looked at, so a signature can
+    /// never be reinterpreted under a scheme other than the one the object's key was created for.
     pub fn verify(&self, msg: &[u8], sig: &Signature) -> Result<(), SecurityError> {
+        if sig.scheme() != self.scheme {
+            #[cfg(feature = "log")]
+            error!(
+                "Signature scheme {:?} does not match verifying key scheme {:?}",
+                sig.scheme(),
+                self.scheme
+            );
+
+            return Err(SecurityError::InvalidScheme);
+        }
+
         match self.scheme {
             SigningScheme::Ecdsa => {
                 let key: EcdsaVerifyingKey = self.try_into()?;
@@ -105,6 +176,22 @@ impl VerifyingKey {
                     SecurityError::SignatureMismatch
                 })
             }
+            SigningScheme::Ed25519 => {
+                unsupported_scheme(&self.scheme, SecurityError::InvalidScheme)
+            }
+        }
+    }
+}
+
+/// Maps the algorithm identifier of a SubjectPublicKeyInfo onto the scheme it encodes.
+fn scheme_from_spki_oid(oid: ObjectIdentifier) -> Result<SigningScheme, SecurityError> {
+    match oid {
+        EC_PUBLIC_KEY_OID => Ok(SigningScheme::Ecdsa),
+        _ => {
+            #[cfg(feature = "log")]
+            error!("Unsupported SubjectPublicKeyInfo algorithm: {}", oid);
+
+            Err(SecurityError::InvalidScheme)
         }
     }
 }
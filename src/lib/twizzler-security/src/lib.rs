@@ -17,14 +17,21 @@ mod capability;
 mod delegation;
 mod flags;
 mod gates;
+mod integrity;
 mod keys;
 mod revocation;
+mod scope;
 mod sec_ctx;
 
 pub use capability::*;
 pub use delegation::*;
 pub use flags::*;
 pub use gates::*;
+pub use integrity::*;
 pub use keys::*;
 pub use revocation::*;
+pub use scope::*;
 pub use sec_ctx::*;
+
+#[cfg(feature = "kernel-random")]
+pub use monitor_api::fill_kernel_random;
@@ -1,3 +1,10 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::num::NonZeroU16;
+
+use twizzler_rt_abi::error::{ResourceError, TwzError};
+
+use crate::CapId;
+
 /// Specifies when a Capability is invalid.
 /// Currenty is a time in ns from unix epoch but
 /// plan to change later.
@@ -17,6 +24,17 @@ impl Revoc {
     pub fn to_bytes(&self) -> [u8; 16] {
         self.inner.to_le_bytes()
     }
+
+    /// The time at which the capability stops being valid, or None if it never expires (the
+    /// default).
+    pub fn expires_at(&self) -> Option<u128> {
+        (self.inner != 0).then_some(self.inner)
+    }
+
+    /// Returns true if the capability has expired by the time `now`.
+    pub fn is_expired(&self, now: u128) -> bool {
+        self.expires_at().is_some_and(|at| at <= now)
+    }
 }
 
 impl Default for Revoc {
@@ -25,3 +43,218 @@ impl Default for Revoc {
         Self { inner: 0 }
     }
 }
+
+/// The maximum number of capability IDs a [RevocationList] will hold. Each entry costs 32 bytes
+/// of key plus tree overhead, so a full list is on the order of a few hundred KiB.
+///
+/// The list is meant for the rare case of a compromised key or leaked capability, not as the
+/// normal way to end a capability's lifetime (that's what [Revoc] expiration is for), so it is
+/// expected to stay small. Once full, further revocations fail with
+/// [ResourceError::OutOfResources] rather than growing without bound; reissue the affected
+/// objects' keys instead.
+pub const MAX_REVOKED_CAPS: usize = 8192;
+
+/// The maximum number of limited capabilities (see [crate::Cap::new_limited]) a
+/// [RevocationList] tracks at once, counting both those with uses left and those that are used
+/// up. Used-up capabilities are forgotten once they expire, since an expired capability is
+/// rejected anyway, so limited capabilities should be issued with an expiration. While the list is
+/// full of unexpired ones, new limited capabilities grant nothing. This is separate from
+/// [MAX_REVOKED_CAPS], so using up limited capabilities never stops revocation from working.
+pub const MAX_LIMITED_CAPS: usize = 8192;
+
+/// A global list of revoked capabilities, checked during verification regardless of which
+/// security context holds the capability.
+///
+/// The list also keeps the remaining use counts of limited capabilities (see
+/// [crate::Cap::new_limited]), and remembers which of them are used up until they expire. These
+/// are bounded by [MAX_LIMITED_CAPS], apart from the revoked IDs.
+///
+/// Every change to the revoked set bumps the list's epoch. Anything caching the result of a
+/// verification should record the epoch it saw, and throw the cached result away once the epoch
+/// moves on. Using up a limited capability doesn't change the epoch: accesses granted by limited
+/// capabilities must not be cached in the first place, since each one takes a use.
+#[derive(Clone, Debug, Default)]
+pub struct RevocationList {
+    revoked: BTreeSet<CapId>,
+    remaining_uses: BTreeMap<CapId, (u16, Revoc)>,
+    // Used-up limited capabilities, with the time each one expires (u128::MAX if never).
+    exhausted: BTreeMap<CapId, u128>,
+    epoch: u64,
+}
+
+impl RevocationList {
+    pub const fn new() -> Self {
+        Self {
+            revoked: BTreeSet::new(),
+            remaining_uses: BTreeMap::new(),
+            exhausted: BTreeMap::new(),
+            epoch: 0,
+        }
+    }
+
+    /// Revoke the capability with ID `id`. Revoking an already revoked ID is a no-op, and does
+    /// not change the epoch.
+    pub fn revoke(&mut self, id: CapId) -> Result<(), TwzError> {
+        if self.revoked.contains(&id) {
+            return Ok(());
+        }
+        if self.revoked.len() >= MAX_REVOKED_CAPS {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        self.revoked.insert(id);
+        self.epoch += 1;
+        Ok(())
+    }
+
+    /// Use up one access of the limited capability with ID `id`, which was issued with
+    /// `max_uses` uses and expires at `expiry`; `now` is the current time, on the same clock. Call
+    /// this once for each access the capability actually grants. Returns false, without consuming
+    /// anything, if the capability has been revoked, has expired, or has no uses left, or if the
+    /// list has no room to track it (see [MAX_LIMITED_CAPS]).
+    ///
+    /// Callers serialize on the list, so when several accesses race for the last use exactly one
+    /// of them gets it.
+    pub fn consume_use(
+        &mut self,
+        id: CapId,
+        max_uses: NonZeroU16,
+        expiry: Revoc,
+        now: u128,
+    ) -> bool {
+        if self.revoked.contains(&id) || self.exhausted.contains_key(&id) || expiry.is_expired(now)
+        {
+            return false;
+        }
+        if !self.remaining_uses.contains_key(&id) && self.limited_len() >= MAX_LIMITED_CAPS {
+            self.expire(now);
+            if self.limited_len() >= MAX_LIMITED_CAPS {
+                return false;
+            }
+        }
+        let (remaining, _) = self
+            .remaining_uses
+            .entry(id)
+            .or_insert((max_uses.get(), expiry));
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.remaining_uses.remove(&id);
+            self.exhausted
+                .insert(id, expiry.expires_at().unwrap_or(u128::MAX));
+        }
+        true
+    }
+
+    /// Returns true if the limited capability with ID `id` has no uses left.
+    pub fn is_exhausted(&self, id: &CapId) -> bool {
+        self.exhausted.contains_key(id)
+    }
+
+    /// Forget the limited capabilities that have expired by `now`. They are rejected as expired
+    /// from then on, so there's no need to remember how many uses they had left.
+    pub fn expire(&mut self, now: u128) {
+        self.exhausted.retain(|_, expiry| *expiry > now);
+        self.remaining_uses
+            .retain(|_, (_, expiry)| !expiry.is_expired(now));
+    }
+
+    fn limited_len(&self) -> usize {
+        self.remaining_uses.len() + self.exhausted.len()
+    }
+
+    /// Returns true if the capability with ID `id` has been revoked.
+    pub fn is_revoked(&self, id: &CapId) -> bool {
+        self.revoked.contains(id)
+    }
+
+    /// The current epoch, which changes every time the list does.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn len(&self) -> usize {
+        self.revoked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revoked.is_empty()
+    }
+}
+
+#[cfg(feature = "user")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revocation_list_bounds() {
+        let mut list = RevocationList::new();
+        let id = |n: usize| {
+            let mut bytes = [0; 32];
+            bytes[0..8].copy_from_slice(&(n as u64).to_le_bytes());
+            CapId::from_bytes(bytes)
+        };
+
+        list.revoke(id(0)).unwrap();
+        assert!(list.is_revoked(&id(0)));
+        assert!(!list.is_revoked(&id(1)));
+        assert_eq!(list.epoch(), 1);
+
+        // Revoking twice doesn't move the epoch, so caches aren't needlessly dropped.
+        list.revoke(id(0)).unwrap();
+        assert_eq!(list.epoch(), 1);
+
+        for n in 1..MAX_REVOKED_CAPS {
+            list.revoke(id(n)).unwrap();
+        }
+        assert_eq!(list.len(), MAX_REVOKED_CAPS);
+        assert_eq!(
+            list.revoke(id(MAX_REVOKED_CAPS)),
+            Err(TwzError::Resource(ResourceError::OutOfResources))
+        );
+        assert_eq!(list.epoch(), MAX_REVOKED_CAPS as u64);
+    }
+
+    #[test]
+    fn test_revocation_list_uses() {
+        let mut list = RevocationList::new();
+        let id = CapId::from_bytes([7; 32]);
+        let uses = NonZeroU16::new(2).unwrap();
+        let never = Revoc::default();
+
+        assert!(list.consume_use(id, uses, never, 0));
+        assert!(!list.is_exhausted(&id));
+
+        // Using up the last use neither revokes the capability nor moves the epoch.
+        assert!(list.consume_use(id, uses, never, 0));
+        assert!(list.is_exhausted(&id));
+        assert!(!list.consume_use(id, uses, never, 0));
+        assert!(!list.is_revoked(&id));
+        assert_eq!(list.epoch(), 0);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_revocation_list_exhausted_expire() {
+        let mut list = RevocationList::new();
+        let id = |n: usize| {
+            let mut bytes = [0; 32];
+            bytes[0..8].copy_from_slice(&(n as u64).to_le_bytes());
+            CapId::from_bytes(bytes)
+        };
+        let once = NonZeroU16::MIN;
+        let expiry = Revoc::new(100);
+
+        // Fill the list with used-up capabilities that expire.
+        for n in 0..MAX_LIMITED_CAPS {
+            assert!(list.consume_use(id(n), once, expiry, 10));
+        }
+        assert!(!list.consume_use(id(MAX_LIMITED_CAPS), once, expiry, 10));
+        // Revocation is unaffected.
+        list.revoke(id(0)).unwrap();
+
+        // Once they expire they're forgotten, making room for new ones, and expired capabilities
+        // can't be used again.
+        assert!(list.consume_use(id(MAX_LIMITED_CAPS), once, Revoc::new(200), 100));
+        assert!(!list.is_exhausted(&id(1)));
+        assert!(!list.consume_use(id(1), once, expiry, 100));
+    }
+}
@@ -0,0 +1,25 @@
+/// A byte range of an object that a capability's access is limited to. See
+/// [`crate::Cap::new_scoped`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CapScope {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl CapScope {
+    /// The scope of an unscoped capability, covering the whole object.
+    pub const WHOLE: CapScope = CapScope {
+        offset: 0,
+        len: u64::MAX,
+    };
+
+    pub fn new(offset: u64, len: u64) -> Self {
+        CapScope { offset, len }
+    }
+
+    /// Whether the `len` bytes starting at `offset` lie entirely inside this scope.
+    pub fn contains(&self, offset: u64, len: u64) -> bool {
+        let end = self.offset.saturating_add(self.len);
+        offset >= self.offset && offset.checked_add(len).is_some_and(|e| e <= end)
+    }
+}
@@ -1,8 +1,16 @@
 use twizzler_abi::object::{ObjID, Protections};
 
+use crate::Cap;
+
 mod base;
 pub use base::*;
 
+mod policy;
+pub use policy::*;
+
+mod quorum;
+pub use quorum::*;
+
 #[cfg(feature = "user")]
 mod user;
 
@@ -26,3 +34,14 @@ impl PermsInfo {
         }
     }
 }
+
+/// A capability stored in a security context, along with where it's stored, so that it can be
+/// found again (e.g. to re-sign it after a key rotation).
+#[derive(Clone, Copy, Debug)]
+pub struct CapRef {
+    /// The security context holding the capability.
+    pub ctx: ObjID,
+    /// The offset of the capability within the context object.
+    pub offset: usize,
+    pub cap: Cap,
+}
@@ -0,0 +1,190 @@
+//! Label-based policy for security contexts.
+//!
+//! Security contexts and objects can carry a [SecLabel]. A capability for a labeled object only
+//! grants access when it is used from a context whose label dominates the object's. Objects
+//! without a label are not restricted, and contexts without one have the lowest label.
+//!
+//! A label is recorded in an object's metadata, so that the kernel can enforce it on every
+//! lookup, as a [MetaExt] with tag [MEXT_SEC_LABEL] whose value is [SecLabel::to_ext_value]. This
+//! is the same for a security context (the label of the context) and for any other object (the
+//! label a context needs to use capabilities for it). See [set_object_label].
+//!
+//! A capability is evaluated in two steps. First its signature is checked against the target's
+//! verifying key, exactly as without labels. Only if that succeeds are the labels compared. Labels
+//! can therefore deny a validly signed capability, but never grant one that failed verification.
+//!
+//! [MetaExt]: twizzler_abi::meta::MetaExt
+
+use twizzler_abi::object::Protections;
+
+use crate::{Cap, VerifyingKey};
+
+/// The meta extension tag that records an object's [SecLabel]. The extension's value is the label
+/// itself, as given by [SecLabel::to_ext_value].
+pub const MEXT_SEC_LABEL: u64 = 0x6c61_6265_6c;
+
+/// Integrity and confidentiality labels attached to a security context or object. Labels are
+/// ordered component-wise, forming a lattice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SecLabel {
+    pub confidentiality: u8,
+    pub integrity: u8,
+}
+
+impl SecLabel {
+    pub const fn new(confidentiality: u8, integrity: u8) -> Self {
+        Self {
+            confidentiality,
+            integrity,
+        }
+    }
+
+    /// Returns true if this label is at least as high as `other` in both components.
+    pub fn dominates(&self, other: &SecLabel) -> bool {
+        self.confidentiality >= other.confidentiality && self.integrity >= other.integrity
+    }
+
+    /// Encode the label as the value of a [MEXT_SEC_LABEL] meta extension.
+    pub const fn to_ext_value(&self) -> u64 {
+        self.confidentiality as u64 | (self.integrity as u64) << 8
+    }
+
+    /// Decode the value of a [MEXT_SEC_LABEL] meta extension.
+    pub const fn from_ext_value(value: u64) -> Self {
+        Self::new(value as u8, (value >> 8) as u8)
+    }
+}
+
+/// Returns true if a context labeled `ctx_label` may use capabilities for an object labeled
+/// `target_label` (None if the object has no label).
+pub fn label_permits(ctx_label: SecLabel, target_label: Option<SecLabel>) -> bool {
+    target_label.is_none_or(|target| ctx_label.dominates(&target))
+}
+
+/// Evaluate a capability used from a context labeled `ctx_label`, for a target labeled
+/// `target_label`: the signature is checked first, then the labels. Returns the protections the
+/// capability grants, or None if it cannot be used.
+pub fn evaluate_cap(
+    cap: &Cap,
+    verifying_key: &VerifyingKey,
+    ctx_label: SecLabel,
+    target_label: Option<SecLabel>,
+) -> Option<Protections> {
+    cap.verify_sig(verifying_key).ok()?;
+    if !label_permits(ctx_label, target_label) {
+        return None;
+    }
+    Some(cap.protections)
+}
+
+/// Returns the label recorded in an object's metadata, if it has one.
+#[cfg(feature = "user")]
+pub fn object_label(obj: &impl twizzler::object::RawObject) -> Option<SecLabel> {
+    use twizzler_abi::meta::{MetaExt, MetaInfo};
+
+    let meta = obj.meta_ptr();
+    // Safety: the meta page is mapped along with the object, and the extensions lie within it.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        (0..(*meta).extcount as usize)
+            .map(|i| &*exts.add(i))
+            .find(|ext| ext.tag == MEXT_SEC_LABEL)
+            .map(|ext| SecLabel::from_ext_value(ext.value))
+    }
+}
+
+/// Record `label` as the label of `obj`, replacing any previous one. The object must be mapped
+/// writable.
+#[cfg(feature = "user")]
+pub fn set_object_label(
+    obj: &impl twizzler::object::RawObject,
+    label: SecLabel,
+) -> Result<(), twizzler_rt_abi::error::TwzError> {
+    use twizzler_abi::{
+        meta::{MetaExt, MetaInfo},
+        object::NULLPAGE_SIZE,
+    };
+    use twizzler_rt_abi::error::ResourceError;
+
+    let meta = obj.meta_mut_ptr();
+    // Safety: the meta page is mapped along with the object, and the extensions lie within it.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        let extcount = (*meta).extcount as usize;
+        let idx = (0..extcount)
+            .find(|i| (*exts.add(*i)).tag == MEXT_SEC_LABEL)
+            .unwrap_or(extcount);
+        if size_of::<MetaInfo>() + (idx + 1) * size_of::<MetaExt>() > NULLPAGE_SIZE {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        exts.add(idx).write(MetaExt {
+            tag: MEXT_SEC_LABEL,
+            value: label.to_ext_value(),
+        });
+        if idx == extcount {
+            (*meta).extcount += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "user")]
+mod tests {
+    use twizzler::object::TypedObject;
+    use twizzler_abi::syscall::ObjectCreate;
+
+    use super::*;
+    use crate::*;
+
+    extern crate test;
+
+    #[test]
+    fn test_label_mismatch_denies_valid_cap() {
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let cap = Cap::new(
+            0x123.into(),
+            0x321.into(),
+            Protections::READ,
+            s.base(),
+            Revoc::default(),
+            Gates::default(),
+            HashingAlgo::Sha256,
+        )
+        .expect("Capability should have been created.");
+
+        let target = Some(SecLabel::new(2, 1));
+        let low = SecLabel::new(1, 1);
+        let high = SecLabel::new(3, 1);
+
+        // Without a target label, the signature alone decides.
+        assert_eq!(
+            evaluate_cap(&cap, v.base(), low, None),
+            Some(Protections::READ)
+        );
+        // The signature is valid, but the context's label does not dominate the target's.
+        assert_eq!(evaluate_cap(&cap, v.base(), low, target), None);
+        assert_eq!(
+            evaluate_cap(&cap, v.base(), high, target),
+            Some(Protections::READ)
+        );
+
+        // Labels never rescue a capability whose signature does not verify.
+        let (_, other_v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        assert_eq!(evaluate_cap(&cap, other_v.base(), high, target), None);
+    }
+
+    #[test]
+    fn test_label_ext_value() {
+        let label = SecLabel::new(0xab, 0xcd);
+        assert_eq!(SecLabel::from_ext_value(label.to_ext_value()), label);
+        assert_eq!(SecLabel::from_ext_value(0), SecLabel::default());
+    }
+}
@@ -0,0 +1,57 @@
+//! Objects that require several security contexts at once (separation of duty).
+//!
+//! Normally, holding a capability in any one attached context is enough to access an object. An
+//! object can instead require that a thread has *all* of a set of contexts attached, each of which
+//! must grant the access, by declaring a [CtxQuorum] in its metadata:
+//!
+//! 1. Write a [CtxQuorum] into the object's meta page, at some offset past the [MetaInfo] and the
+//!    extension array (e.g. right after the last extension).
+//! 2. Add a [MetaExt] to the extension array with tag [MEXT_CTX_QUORUM], whose value is the byte
+//!    offset of the [CtxQuorum] from the start of the meta page, and count it in `extcount`.
+//!
+//! The kernel checks the quorum when the object is mapped. A map request fails with
+//! AccessDenied unless every listed context is attached to the calling thread (or active) and
+//! provides the requested protections on its own; default protections do not count towards a
+//! quorum.
+//!
+//! [MetaInfo]: twizzler_abi::meta::MetaInfo
+//! [MetaExt]: twizzler_abi::meta::MetaExt
+
+use twizzler_abi::object::ObjID;
+
+/// The meta extension tag that declares a [CtxQuorum]. The extension's value is the offset of the
+/// quorum from the start of the meta page.
+pub const MEXT_CTX_QUORUM: u64 = 0x7175_6f72_756d;
+
+/// The maximum number of contexts a [CtxQuorum] can list.
+pub const MAX_QUORUM_CTXS: usize = 4;
+
+/// A set of security contexts that must all be attached, and all grant access, before an object
+/// can be accessed. See the [module documentation](self) for how an object declares one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct CtxQuorum {
+    count: u32,
+    ctxs: [ObjID; MAX_QUORUM_CTXS],
+}
+
+impl CtxQuorum {
+    /// Build a quorum requiring all of `ctxs`. Returns None if there are more than
+    /// [MAX_QUORUM_CTXS].
+    pub fn new(ctxs: &[ObjID]) -> Option<Self> {
+        if ctxs.len() > MAX_QUORUM_CTXS {
+            return None;
+        }
+        let mut this = Self {
+            count: ctxs.len() as u32,
+            ctxs: [ObjID::new(0); MAX_QUORUM_CTXS],
+        };
+        this.ctxs[0..ctxs.len()].copy_from_slice(ctxs);
+        Some(this)
+    }
+
+    /// The contexts that are required. A corrupt count is clamped to [MAX_QUORUM_CTXS].
+    pub fn contexts(&self) -> &[ObjID] {
+        &self.ctxs[0..(self.count as usize).min(MAX_QUORUM_CTXS)]
+    }
+}
@@ -16,7 +16,10 @@ use twizzler_rt_abi::{
     object::MapFlags,
 };
 
-use super::{CtxMapItem, CtxMapItemType, PermsInfo, SecCtxBase, SecCtxFlags};
+use super::{
+    evaluate_cap, object_label, set_object_label, CapRef, CtxMapItem, CtxMapItemType, PermsInfo,
+    SecCtxBase, SecCtxFlags, SecLabel,
+};
 use crate::{
     sec_ctx::{MAP_ITEMS_PER_OBJ, OBJECT_ROOT_OFFSET},
     Cap, Del, VerifyingKey,
@@ -132,6 +135,55 @@ impl SecCtx {
         self.uobj.id()
     }
 
+    /// The label attached to this security context (see [SecLabel]), recorded in its metadata.
+    pub fn label(&self) -> SecLabel {
+        object_label(&self.uobj).unwrap_or_default()
+    }
+
+    /// Set the label attached to this security context. The kernel enforces it the next time it
+    /// looks up a labeled object in this context.
+    pub fn set_label(&mut self, label: SecLabel) -> Result<(), TwzError> {
+        let mut tx = self.uobj.clone().into_tx()?;
+        set_object_label(&tx, label)?;
+        tx.commit()?;
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Iterate over the capabilities in this context whose signature verifies under `vkey`, for
+    /// instance to find the ones to re-sign when the key is rotated. Capabilities are read and
+    /// checked as the iterator advances, so nothing is collected up front however many there are.
+    pub fn caps_signed_by<'a>(
+        &'a self,
+        vkey: &'a VerifyingKey,
+    ) -> impl Iterator<Item = CapRef> + 'a {
+        self.uobj
+            .base()
+            .map
+            .values()
+            .flat_map(|entries| entries.iter())
+            .filter(|entry| matches!(entry.item_type, CtxMapItemType::Cap))
+            .filter_map(move |entry| {
+                let cap = self.read_cap(entry.offset);
+                cap.verify_sig(vkey).is_ok().then_some(CapRef {
+                    ctx: self.id(),
+                    offset: entry.offset,
+                    cap,
+                })
+            })
+    }
+
+    fn read_cap(&self, offset: usize) -> Cap {
+        // pull capability out of the object
+        let ptr = self
+            .uobj
+            .lea(offset, size_of::<Cap>())
+            .expect("address should be inside of object!")
+            .cast::<Cap>();
+
+        unsafe { *ptr }
+    }
+
     pub fn remove_cap(&mut self) {
         todo!("implement later")
     }
@@ -140,7 +192,9 @@ impl SecCtx {
         todo!("implement later")
     }
 
-    /// looks up permission info for requested object
+    /// looks up permission info for requested object. Each capability's signature is verified
+    /// first, and then it may still be denied if the target is labeled with a label the context's
+    /// doesn't dominate. This mirrors the kernel's lookup.
     pub fn lookup<T: BaseType>(&mut self, target_id: ObjID) -> PermsInfo {
         // first just check cache
         if let Some(cache_entry) = self.cache.get(&target_id) {
@@ -150,9 +204,11 @@ impl SecCtx {
         let base = self.uobj.base();
 
         // fetch default protections
-        let target_object = Object::<T>::map(target_id, MapFlags::READ)
-            .expect("target object should exist!")
-            .meta_ptr();
+        let target_obj =
+            Object::<T>::map(target_id, MapFlags::READ).expect("target object should exist!");
+        let target_object = target_obj.meta_ptr();
+        let target_label = object_label(&target_obj);
+        let ctx_label = self.label();
 
         let target_obj_default_prot;
         let v_key_obj_id;
@@ -164,8 +220,9 @@ impl SecCtx {
             target_obj_default_prot = metadata.default_prot;
         }
 
-        let v_obj = Object::<VerifyingKey>::map(v_key_obj_id, MapFlags::READ | MapFlags::WRITE)
-            .expect("failed to open verifying key for this object");
+        let v_obj =
+            Object::<VerifyingKey>::map_checked(v_key_obj_id, MapFlags::READ | MapFlags::WRITE)
+                .expect("failed to open verifying key for this object");
         let v_key = v_obj.base();
 
         // step 1, add up all the permissions granted by VERIFIED capabilities and delegations
@@ -187,20 +244,9 @@ impl SecCtx {
                 }
 
                 CtxMapItemType::Cap => {
-                    // pull capability out of the object
-
-                    let ptr = self
-                        .uobj
-                        .lea(entry.offset, size_of::<Cap>())
-                        .expect("address should be inside of object!")
-                        .cast::<Cap>();
-
-                    unsafe {
-                        let cap = *ptr;
-
-                        if cap.verify_sig(v_key).is_ok() {
-                            granted_perms.provide |= cap.protections;
-                        }
+                    let cap = self.read_cap(entry.offset);
+                    if let Some(prot) = evaluate_cap(&cap, v_key, ctx_label, target_label) {
+                        granted_perms.provide |= prot;
                     }
                 }
             }
@@ -250,4 +296,53 @@ mod tests {
             SecCtx::new(Default::default(), Protections::all(), SecCtxFlags::empty())
                 .expect("new context should have been created!");
     }
+
+    #[test]
+    fn test_caps_signed_by() {
+        use crate::{Gates, HashingAlgo, Revoc, SigningKey, SigningScheme};
+
+        let (s, v) = SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+            .expect("keypair creation should not have errored!");
+        let (other_s, other_v) =
+            SigningKey::new_keypair(&SigningScheme::Ecdsa, ObjectCreate::default())
+                .expect("keypair creation should not have errored!");
+        let ctx = SecCtx::default();
+
+        let cap = |target: u128, key: &SigningKey| {
+            Cap::new(
+                target.into(),
+                ctx.id(),
+                Protections::READ,
+                key,
+                Revoc::default(),
+                Gates::default(),
+                HashingAlgo::Sha256,
+            )
+            .expect("capability should have been created")
+        };
+        // Several caps with one key, two of them for the same target, and one with another key.
+        let signed = [
+            cap(0x10, s.base()),
+            cap(0x11, s.base()),
+            cap(0x11, s.base()),
+        ];
+        for c in signed {
+            ctx.insert_cap(c).unwrap();
+        }
+        ctx.insert_cap(cap(0x12, other_s.base())).unwrap();
+
+        let found: alloc::vec::Vec<_> = ctx.caps_signed_by(v.base()).collect();
+        assert_eq!(found.len(), signed.len());
+        for c in signed {
+            assert!(found.iter().any(|r| r.cap == c));
+        }
+        for r in &found {
+            assert_eq!(r.ctx, ctx.id());
+            assert_eq!(ctx.read_cap(r.offset), r.cap);
+        }
+
+        let other: alloc::vec::Vec<_> = ctx.caps_signed_by(other_v.base()).collect();
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].cap.target, 0x12.into());
+    }
 }
@@ -2,6 +2,7 @@ use std::{
     alloc::{AllocError, Layout},
     mem::MaybeUninit,
     ops::RangeBounds,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use twizzler_abi::object::{MAX_SIZE, NULLPAGE_SIZE};
@@ -21,13 +22,33 @@ pub use vec_object::{VecIter, VecObject};
 #[cfg(test)]
 mod tests;
 
+#[cfg(test)]
+thread_local! {
+    // Counts how many times a push had to grow the backing allocation on this thread.
+    static GROWTHS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 pub struct VecInner<T: Invariant> {
-    len: usize,
+    // Atomic so that readers can load it while an appender publishes a new length.
+    len: AtomicUsize,
     cap: usize,
     start: InvPtr<T>,
 }
 
 impl<T: Invariant> VecInner<T> {
+    /// The length as last published by an appender. Every element below it has been fully
+    /// written, even if another thread is in the middle of appending more.
+    fn published_len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Make the first `len` elements visible to readers. Appenders call this only after the new
+    /// elements are written and synced, so a reader that sees the new length also sees the
+    /// elements.
+    fn publish_len(&self, len: usize) {
+        self.len.store(len, Ordering::Release);
+    }
+
     fn resolve_start(&self) -> Ref<'_, T> {
         unsafe { self.start.resolve() }
     }
@@ -60,11 +81,11 @@ impl<T: Invariant> VecInner<T> {
         let new_start = InvPtr::new(place, new_alloc.cast())?;
         self.start = new_start;
         self.cap = newcap;
-        self.len = newlen;
+        self.publish_len(newlen);
         tracing::trace!(
             "set start: {:x} len {}, cap {}",
             self.start.raw(),
-            self.len,
+            newlen,
             self.cap
         );
 
@@ -81,7 +102,7 @@ impl<T: Invariant> VecInner<T> {
         let slice = rslice.as_slice_mut();
         let byte_idx_start = (idx + 1) * size_of::<T>();
         let byte_idx = idx * size_of::<T>();
-        let byte_end = self.len * size_of::<T>();
+        let byte_end = self.published_len() * size_of::<T>();
         tracing::trace!(
             "slice byte copy: {} {} {}",
             byte_idx,
@@ -92,19 +113,19 @@ impl<T: Invariant> VecInner<T> {
         if byte_idx_start == byte_end {
             slice[byte_idx..byte_idx_start].fill(0);
         }
-        self.len -= 1;
+        self.publish_len(self.published_len() - 1);
         Ok(())
     }
 
     pub fn as_slice(&self) -> RefSlice<'_, T> {
         let r = self.resolve_start();
-        let slice = unsafe { RefSlice::from_ref(r, self.len) };
+        let slice = unsafe { RefSlice::from_ref(r, self.published_len()) };
         slice
     }
 
     fn with_slice<R>(&self, f: impl FnOnce(&[T]) -> R) -> R {
         let r = self.resolve_start();
-        let slice = unsafe { RefSlice::from_ref(r, self.len) };
+        let slice = unsafe { RefSlice::from_ref(r, self.published_len()) };
         f(slice.as_slice())
     }
 
@@ -114,13 +135,13 @@ impl<T: Invariant> VecInner<T> {
         f: impl FnOnce(&mut [T]) -> Result<R>,
     ) -> Result<R> {
         let r = self.resolve_start_tx()?;
-        let slice = unsafe { TxRefSlice::from_ref(r, self.len) };
+        let slice = unsafe { TxRefSlice::from_ref(r, self.published_len()) };
         f(slice.slice(range).as_slice_mut())
     }
 
     fn with_mut<R>(&mut self, idx: usize, f: impl FnOnce(&mut T) -> Result<R>) -> Result<R> {
         let r = self.resolve_start_tx()?;
-        let mut slice = unsafe { TxRefSlice::from_ref(r, self.len) };
+        let mut slice = unsafe { TxRefSlice::from_ref(r, self.published_len()) };
         let mut item = slice.get_mut(idx).unwrap();
         f(&mut *item)
     }
@@ -198,31 +219,34 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
         Self {
             inner: VecInner {
                 cap: 0,
-                len: 0,
+                len: AtomicUsize::new(0),
                 start: InvPtr::null(),
             },
             alloc,
         }
     }
 
+    /// Get the slot just past the end, growing the allocation if needed. The length is left alone:
+    /// the caller publishes it once the slot is written, so readers never see the slot half-made.
     fn get_slice_grow(&mut self) -> Result<TxRef<MaybeUninit<T>>> {
-        let oldlen = self.inner.len;
-        tracing::trace!("len: {}, cap: {}", self.inner.len, self.inner.cap);
-        if self.inner.len == self.inner.cap {
+        let oldlen = self.inner.published_len();
+        tracing::trace!("len: {}, cap: {}", oldlen, self.inner.cap);
+        if oldlen == self.inner.cap {
             if self.inner.start.raw() as usize + size_of::<T>() * self.inner.cap
                 >= MAX_SIZE - NULLPAGE_SIZE
             {
                 return Err(ResourceError::OutOfMemory.into());
             }
             let newcap = std::cmp::max(self.inner.cap, 1) * 2;
-            self.inner.do_realloc(newcap, oldlen + 1, &self.alloc)?;
+            self.inner.do_realloc(newcap, oldlen, &self.alloc)?;
+            #[cfg(test)]
+            GROWTHS.set(GROWTHS.get() + 1);
             let r = self.inner.resolve_start_tx()?;
             tracing::trace!("grow {:p}", r.raw());
             Ok(Self::maybe_uninit_slice(r, newcap)
                 .get_into(oldlen)
                 .unwrap())
         } else {
-            self.inner.len += 1;
             let r = self.inner.resolve_start_tx()?;
             tracing::trace!("no grow {:p} {}", r.raw(), r.is_nosync());
             Ok(Self::maybe_uninit_slice(r, self.inner.cap)
@@ -231,51 +255,69 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
         }
     }
 
+    /// Sync a newly written slot, so that it is durable before its length is published.
+    fn sync_slot<U>(mut slot: TxRef<U>) -> Result<()> {
+        slot.tx_mut().commit()?;
+        slot.nosync();
+        Ok(())
+    }
+
     fn do_push(&mut self, item: T) -> Result<()> {
         let r = self.get_slice_grow()?;
         tracing::trace!("store value: {:p}", r.raw());
         let r = r.write(item)?;
-        drop(r);
+        Self::sync_slot(r)?;
+        self.inner.publish_len(self.inner.published_len() + 1);
         Ok(())
     }
 
     pub fn len(&self) -> usize {
-        self.inner.len
+        self.inner.published_len()
     }
 
     pub fn capacity(&self) -> usize {
         self.inner.cap
     }
 
+    /// Grow the backing allocation so that at least `additional` more items can be pushed
+    /// without growing it again. Does nothing if there is already room.
     pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let needed = self
+            .inner
+            .published_len()
+            .checked_add(additional)
+            .ok_or(ResourceError::OutOfMemory)?;
+        if needed <= self.inner.cap {
+            return Ok(());
+        }
         self.inner
-            .do_realloc(self.inner.cap + additional, self.inner.len, &self.alloc)?;
+            .do_realloc(needed, self.inner.published_len(), &self.alloc)?;
         Ok(())
     }
 
     #[inline]
     pub fn as_slice(&self) -> RefSlice<'_, T> {
         let r = self.inner.resolve_start();
-        let slice = unsafe { RefSlice::from_ref(r, self.inner.len) };
+        let slice = unsafe { RefSlice::from_ref(r, self.inner.published_len()) };
         slice
     }
 
     #[inline]
     pub fn as_tx_slice(&self) -> Result<TxRefSlice<T>> {
         let r = self.inner.resolve_start_tx()?;
-        let slice = unsafe { TxRefSlice::from_ref(r, self.inner.len) };
+        let slice = unsafe { TxRefSlice::from_ref(r, self.inner.published_len()) };
         Ok(slice)
     }
 
     #[inline]
     pub unsafe fn as_mut_slice(&mut self) -> RefSliceMut<'_, T> {
         let r = unsafe { self.inner.start.resolve_mut() };
-        let slice = unsafe { RefSliceMut::from_ref(r, self.inner.len) };
+        let slice = unsafe { RefSliceMut::from_ref(r, self.inner.published_len()) };
         slice
     }
 
     pub fn remove_inplace(&mut self, idx: usize) -> Result<()> {
-        if idx >= self.inner.len {
+        if idx >= self.inner.published_len() {
             return Err(ArgumentError::InvalidArgument.into());
         }
         self.inner.with_mut(idx, |item| {
@@ -287,7 +329,7 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
     }
 
     pub fn truncate(&mut self, newlen: usize) -> Result<()> {
-        let oldlen = self.inner.len;
+        let oldlen = self.inner.published_len();
         if newlen >= oldlen {
             return Ok(());
         }
@@ -297,12 +339,12 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
             }
             Ok(())
         })?;
-        self.inner.len = newlen;
+        self.inner.publish_len(newlen);
         Ok(())
     }
 
     pub fn shrink_to_fit(&mut self) -> Result<()> {
-        self.inner.cap = self.inner.len;
+        self.inner.cap = self.inner.published_len();
         // TODO: release memory
         Ok(())
     }
@@ -343,10 +385,10 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
     }
 
     pub fn last_ref(&self) -> Option<Ref<'_, T>> {
-        if self.inner.len == 0 {
+        if self.inner.published_len() == 0 {
             None
         } else {
-            self.get_ref(self.inner.len - 1)
+            self.get_ref(self.inner.published_len() - 1)
         }
     }
 
@@ -432,35 +474,97 @@ impl<T: Invariant, Alloc: Allocator> Vec<T, Alloc> {
         })
     }
 
-    pub fn retain<F>(&mut self, mut f: F) -> Result<()>
+    /// Keep only the elements for which `f` returns true, dropping the rest. The kept elements are
+    /// moved down in place, a run of consecutive kept elements at a time.
+    pub fn retain<F>(&mut self, f: F) -> Result<()>
     where
         F: FnMut(&T) -> bool,
     {
-        let mut del = 0;
-        let len = self.len();
+        self.compact(f, |item| unsafe { core::ptr::drop_in_place(item) })
+    }
 
+    // Visit each element once, in order. Elements for which `keep` returns false are handed to
+    // `removed` (which must drop or move them out) while still in place; the kept ones are then
+    // moved down over the gaps, with one move per run of kept elements rather than per element.
+    fn compact(
+        &mut self,
+        mut keep: impl FnMut(&T) -> bool,
+        mut removed: impl FnMut(*mut T),
+    ) -> Result<()> {
+        let len = self.inner.published_len();
+        let mut slice = unsafe { TxRefSlice::from_ref(self.inner.resolve_start_tx()?, len) };
+        let mut guard = CompactGuard {
+            inner: &self.inner,
+            slice: slice.as_slice_mut(),
+            read: 0,
+            write: 0,
+        };
         for i in 0..len {
-            let should_retain = self.with_slice(|slice| f(&slice[i - del]));
-            if !should_retain {
-                self.remove_inplace(i - del)?;
-                del += 1;
+            if keep(&guard.slice[i]) {
+                continue;
             }
+            guard.close_run(i);
+            // The element is gone once it's handed over, even if `removed` panics.
+            guard.read = i + 1;
+            removed(&mut guard.slice[i] as *mut T);
         }
-
+        // Moves the last run down and publishes the new length.
+        drop(guard);
         Ok(())
     }
 }
 
+/// Move `count` elements of `slice` from `src` to `dst`, like memmove: the ranges may overlap.
+///
+/// # Safety
+/// The source elements are moved out, and must be treated as uninitialized afterwards, except
+/// where the destination range overlaps them.
+unsafe fn move_range<T>(slice: &mut [T], src: usize, dst: usize, count: usize) {
+    assert!(src.max(dst) + count <= slice.len());
+    if src != dst && count > 0 {
+        let base = slice.as_mut_ptr();
+        unsafe { core::ptr::copy(base.add(src), base.add(dst), count) };
+    }
+}
+
+// A compaction in progress. Elements below `write` are the kept ones, already in place; those from
+// `read` on haven't been moved yet, and the slots in between are free. Dropping the guard, whether
+// the compaction finished or a callback panicked, moves the unmoved elements down and publishes
+// the length, like the guard in std's `Vec::retain`, so no element is lost or seen twice.
+struct CompactGuard<'a, T: Invariant> {
+    inner: &'a VecInner<T>,
+    slice: &'a mut [T],
+    read: usize,
+    write: usize,
+}
+
+impl<T: Invariant> CompactGuard<'_, T> {
+    // Move the run of kept elements that ends at `end` down to the compacted prefix.
+    fn close_run(&mut self, end: usize) {
+        let count = end - self.read;
+        unsafe { move_range(self.slice, self.read, self.write, count) };
+        self.write += count;
+        self.read = end;
+    }
+}
+
+impl<T: Invariant> Drop for CompactGuard<'_, T> {
+    fn drop(&mut self) {
+        self.close_run(self.slice.len());
+        self.inner.publish_len(self.write);
+    }
+}
+
 impl<T: Invariant + StoreCopy, Alloc: Allocator> Vec<T, Alloc> {
     pub fn push(&mut self, item: T) -> Result<()> {
         self.do_push(item)
     }
 
     pub fn pop(&mut self) -> Result<Option<T>> {
-        if self.inner.len == 0 {
+        if self.inner.published_len() == 0 {
             return Ok(None);
         }
-        let new_len = self.inner.len - 1;
+        let new_len = self.inner.published_len() - 1;
         let val = self
             .inner
             .with_slice(|slice| unsafe { ((&slice[new_len]) as *const T).read() });
@@ -470,7 +574,7 @@ impl<T: Invariant + StoreCopy, Alloc: Allocator> Vec<T, Alloc> {
 
     pub fn remove(&mut self, idx: usize) -> Result<T> {
         //let mut inner = self.inner.get()?;
-        if idx >= self.inner.len {
+        if idx >= self.inner.published_len() {
             return Err(ArgumentError::InvalidArgument.into());
         }
         let val = self
@@ -479,6 +583,17 @@ impl<T: Invariant + StoreCopy, Alloc: Allocator> Vec<T, Alloc> {
         self.inner.do_remove(idx)?;
         Ok(val)
     }
+
+    /// Remove the elements for which `f` returns true, returning them in order. The remaining
+    /// elements are compacted in place, as with [Self::retain].
+    pub fn drain_filter<F>(&mut self, mut f: F) -> Result<std::vec::Vec<T>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut drained = std::vec::Vec::new();
+        self.compact(|item| !f(item), |item| drained.push(unsafe { item.read() }))?;
+        Ok(drained)
+    }
 }
 
 impl<T: Invariant, Alloc: Allocator + SingleObjectAllocator> Vec<T, Alloc> {
@@ -491,7 +606,9 @@ impl<T: Invariant, Alloc: Allocator + SingleObjectAllocator> Vec<T, Alloc> {
         F: FnOnce(RefMut<MaybeUninit<T>>) -> Result<RefMut<T>>,
     {
         let mut r = self.get_slice_grow()?;
-        let _val = ctor(r.as_mut())?;
+        ctor(r.as_mut())?;
+        Self::sync_slot(r)?;
+        self.inner.publish_len(self.inner.published_len() + 1);
         Ok(())
     }
 }
@@ -169,6 +169,26 @@ fn test_capacity_and_reserve() {
     assert!(vec_obj.capacity() >= initial_cap + 10);
 }
 
+#[test]
+fn test_reserve_then_append() {
+    let mut vec_obj = VecObject::<u32, VecObjectAlloc>::new(ObjectBuilder::default()).unwrap();
+    vec_obj.push(7).unwrap();
+    vec_obj.reserve(1000).unwrap();
+    let cap = vec_obj.capacity();
+    assert!(cap >= 1001);
+
+    // Reserving less than the free space left is a no-op.
+    vec_obj.reserve(10).unwrap();
+    assert_eq!(vec_obj.capacity(), cap);
+
+    let growths = GROWTHS.get();
+    vec_obj.append(0..1000).unwrap();
+    assert_eq!(GROWTHS.get(), growths);
+    assert_eq!(vec_obj.capacity(), cap);
+    assert_eq!(vec_obj.len(), 1001);
+    assert_eq!(*vec_obj.get_ref(1000).unwrap(), 999);
+}
+
 #[test]
 fn test_clear() {
     let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
@@ -366,6 +386,63 @@ fn test_retain() {
     assert_eq!(vec_obj.get_ref(4).unwrap().x, 10);
 }
 
+#[test]
+fn test_retain_large() {
+    let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
+    vec_obj.append((0..20000).map(|x| Simple { x })).unwrap();
+
+    vec_obj.retain(|item| item.x % 2 == 0).unwrap();
+    assert_eq!(vec_obj.len(), 10000);
+    for (i, item) in vec_obj.iter().enumerate() {
+        assert_eq!(item.x, i as u32 * 2);
+    }
+
+    // Removing whole runs at the ends, and nothing at all.
+    vec_obj
+        .retain(|item| item.x >= 100 && item.x < 19900)
+        .unwrap();
+    assert_eq!(vec_obj.len(), 9900);
+    assert_eq!(vec_obj.first_ref().unwrap().x, 100);
+    assert_eq!(vec_obj.last_ref().unwrap().x, 19898);
+    vec_obj.retain(|_| true).unwrap();
+    assert_eq!(vec_obj.len(), 9900);
+}
+
+#[test]
+fn test_retain_panic() {
+    let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
+    vec_obj.append((1..=10).map(|x| Simple { x })).unwrap();
+
+    // A panicking predicate leaves the elements it already removed out, and everything from the
+    // panic on in place, with a matching length.
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        vec_obj.retain(|item| {
+            assert_ne!(item.x, 7);
+            item.x % 2 == 0
+        })
+    }));
+    assert!(res.is_err());
+    assert_eq!(vec_obj.len(), 7);
+    let rest: std::vec::Vec<_> = vec_obj.iter().map(|s| s.x).collect();
+    assert_eq!(rest, [2, 4, 6, 7, 8, 9, 10]);
+}
+
+#[test]
+fn test_drain_filter() {
+    let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
+    vec_obj.append((1..=10).map(|x| Simple { x })).unwrap();
+
+    let drained = vec_obj.drain_filter(|item| item.x % 3 == 0).unwrap();
+    assert_eq!(drained, [Simple { x: 3 }, Simple { x: 6 }, Simple { x: 9 }]);
+    assert_eq!(vec_obj.len(), 7);
+    let rest: std::vec::Vec<_> = vec_obj.iter().map(|s| s.x).collect();
+    assert_eq!(rest, [1, 2, 4, 5, 7, 8, 10]);
+
+    let drained = vec_obj.drain_filter(|_| true).unwrap();
+    assert_eq!(drained.len(), 7);
+    assert!(vec_obj.is_empty());
+}
+
 #[test]
 fn test_with_slice() {
     let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
@@ -432,3 +509,53 @@ fn test_remove_inplace() {
     // Test removing from invalid index
     assert!(vec_obj.remove_inplace(10).is_err());
 }
+
+#[test]
+fn iter_matches_index_reads() {
+    let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
+    assert_eq!(vec_obj.iter().next(), None);
+
+    vec_obj
+        .append((0..10000).map(|x| Simple { x: x * 3 }))
+        .unwrap();
+
+    let iter = vec_obj.iter();
+    assert_eq!(iter.len(), vec_obj.len());
+    let mut count = 0;
+    for (i, item) in iter.enumerate() {
+        assert_eq!(*item, *vec_obj.get_ref(i).unwrap());
+        count += 1;
+    }
+    assert_eq!(count, vec_obj.len());
+
+    let sum: u32 = (&vec_obj).into_iter().copied().map(|s| s.x).sum();
+    assert_eq!(sum, (0..10000).map(|x| x * 3).sum());
+}
+
+#[test]
+fn read_during_append() {
+    const COUNT: u32 = 20000;
+    let mut vec_obj = VecObject::new(ObjectBuilder::default()).unwrap();
+    let reader_obj = vec_obj.clone();
+
+    let reader = std::thread::spawn(move || {
+        let mut last_len = 0;
+        while last_len < COUNT as usize {
+            let slice = reader_obj.slice(..);
+            let items = slice.as_slice();
+            assert!(items.len() >= last_len);
+            // Slots start out zeroed, so an unwritten slot shows up as x == 0. Slots below the last
+            // length seen were already checked, and appends don't touch them.
+            for (i, item) in items.iter().enumerate().skip(last_len) {
+                assert_eq!(item.x, i as u32 + 1);
+            }
+            last_len = items.len();
+        }
+    });
+
+    for i in 0..COUNT {
+        vec_obj.push(Simple { x: i + 1 }).unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(vec_obj.len(), COUNT as usize);
+}
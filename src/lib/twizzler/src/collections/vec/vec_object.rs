@@ -38,6 +38,8 @@ impl<T: Invariant, A: Allocator> VecObject<T, A> {
         self.obj
     }
 
+    /// Iterate over the elements in order. The backing slice is resolved once up front, so
+    /// iteration does not pay for a lookup per element.
     pub fn iter(&self) -> VecIter<'_, T> {
         if self.len() == 0 {
             return VecIter {
@@ -69,6 +71,9 @@ impl<T: Invariant, A: Allocator> VecObject<T, A> {
         self.obj.base().capacity()
     }
 
+    /// Make room for at least `additional` more items, so that pushing them does not need to grow
+    /// the backing allocation along the way. The new capacity is committed with the object, like
+    /// any other change to the vector.
     pub fn reserve(&mut self, additional: usize) -> Result<()> {
         self.obj.with_tx(|tx| {
             let mut base = tx.base_mut();
@@ -129,6 +134,8 @@ impl<T: Invariant, A: Allocator> VecObject<T, A> {
         self.obj.with_tx(|tx| tx.base_mut().clear())
     }
 
+    /// Keep only the elements for which `f` returns true. The vector is compacted in place, and
+    /// the new length and contents are committed together.
     pub fn retain<F>(&mut self, f: F) -> Result<()>
     where
         F: FnMut(&T) -> bool,
@@ -347,6 +354,15 @@ impl<T: Invariant + StoreCopy, A: Allocator> VecObject<T, A> {
         self.obj.with_tx(|tx| tx.base_mut().remove(idx))
     }
 
+    /// Remove the elements for which `f` returns true, and return them in order. Like
+    /// [Self::retain], this is a single pass that is committed as a whole.
+    pub fn drain_filter<F>(&mut self, f: F) -> Result<std::vec::Vec<T>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.obj.with_tx(|tx| tx.base_mut().drain_filter(f))
+    }
+
     pub fn split_off(&mut self, _point: usize) -> Result<Self> {
         todo!()
     }
@@ -421,12 +437,21 @@ impl<'a, T: 'a> Iterator for VecIter<'a, T> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let pos = self.pos;
+        let item = self.slice().get(self.pos)?;
         self.pos += 1;
-        self.slice().get(pos)
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.pos;
+        (remaining, Some(remaining))
     }
 }
 
+impl<'a, T: 'a> ExactSizeIterator for VecIter<'a, T> {}
+
+impl<'a, T: 'a> core::iter::FusedIterator for VecIter<'a, T> {}
+
 impl<'a, T: Invariant, A: Allocator> IntoIterator for &'a VecObject<T, A> {
     type Item = &'a T;
 
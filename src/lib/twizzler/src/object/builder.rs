@@ -8,7 +8,7 @@ use twizzler_abi::{
 };
 use twizzler_rt_abi::object::MapFlags;
 
-use super::{Object, TxObject};
+use super::{set_base_fingerprint, Object, TxObject};
 use crate::{
     marker::{BaseType, StoreCopy},
     Result,
@@ -97,6 +97,7 @@ impl<Base: BaseType> ObjectBuilder<Base> {
         }
         let mu_object = unsafe { Object::<MaybeUninit<Base>>::map_unchecked(id, flags) }?;
         let object = ctor(mu_object.into_tx()?)?;
+        set_base_fingerprint(&object, Base::fingerprint())?;
         object.into_object()
     }
 
@@ -146,8 +147,20 @@ impl<Base: BaseType> Default for ObjectBuilder<Base> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
+    use twizzler_abi::{
+        object::MAX_SIZE,
+        syscall::{sys_object_ctrl, sys_object_stat, ObjectControlCmd},
+    };
+    use twizzler_rt_abi::{error::ArgumentError, object::MapFlags};
+
     use super::ObjectBuilder;
-    use crate::{marker::BaseType, object::TypedObject, ptr::InvPtr};
+    use crate::{
+        marker::BaseType,
+        object::{base_fingerprint, Object, RawObject, TypedObject},
+        ptr::InvPtr,
+    };
 
     #[test]
     fn builder_simple() {
@@ -182,4 +195,74 @@ mod tests {
         let r = unsafe { base_foo.ptr.resolve() };
         assert_eq!(*r, 42);
     }
+
+    struct Tagged(u64);
+    impl BaseType for Tagged {
+        fn fingerprint() -> u64 {
+            0x7a66
+        }
+    }
+
+    struct OtherTagged(u64);
+    impl BaseType for OtherTagged {
+        fn fingerprint() -> u64 {
+            0x7a67
+        }
+    }
+
+    #[test]
+    fn builder_records_base_type() {
+        let tagged = ObjectBuilder::default().build(Tagged(1)).unwrap();
+        let plain = ObjectBuilder::default().build(42u64).unwrap();
+        assert_eq!(base_fingerprint(&tagged), Some(0x7a66));
+        assert_eq!(base_fingerprint(&plain), Some(0));
+
+        let map = |id| Object::<Tagged>::map_checked(id, MapFlags::READ);
+        assert_eq!(map(tagged.id()).unwrap().base().0, 1);
+        // An object built as another type can't be mapped as this one...
+        let other = ObjectBuilder::default().build(OtherTagged(2)).unwrap();
+        assert_eq!(
+            map(other.id()).err(),
+            Some(ArgumentError::InvalidArgument.into())
+        );
+        assert!(map(plain.id()).is_err());
+        // ...but types without a fingerprint aren't checked.
+        assert!(Object::<u64>::map_checked(tagged.id(), MapFlags::READ).is_ok());
+    }
+
+    const PREFETCH_PAGES: usize = 4;
+
+    fn wait_for_pages(obj: &impl RawObject, n_pages: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if sys_object_stat(obj.id()).unwrap().pages >= n_pages {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    struct Pages([u8; 0x1000 * PREFETCH_PAGES]);
+    impl BaseType for Pages {}
+
+    #[test]
+    fn map_prefetch_existing_object() {
+        // Persist the object and drop the handle it was built through, so that it is opened the way
+        // an existing object is.
+        let id = ObjectBuilder::default()
+            .persist()
+            .build(Pages([1; 0x1000 * PREFETCH_PAGES]))
+            .unwrap()
+            .id();
+        sys_object_ctrl(id, ObjectControlCmd::Sync).unwrap();
+
+        let obj = Object::<Pages>::map_prefetch(id, MapFlags::READ, PREFETCH_PAGES).unwrap();
+        assert!(wait_for_pages(&obj, PREFETCH_PAGES));
+        assert!(obj.base().0.iter().all(|b| *b == 1));
+
+        // Asking for more pages than the object has just reads the whole object.
+        let obj = Object::<Pages>::map_prefetch(id, MapFlags::READ, MAX_SIZE / 0x1000).unwrap();
+        assert!(wait_for_pages(&obj, PREFETCH_PAGES));
+    }
 }
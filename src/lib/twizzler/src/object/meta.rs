@@ -1 +1,54 @@
 pub use twizzler_abi::meta::*;
+use twizzler_abi::object::NULLPAGE_SIZE;
+use twizzler_rt_abi::error::ResourceError;
+
+use super::RawObject;
+use crate::Result;
+
+/// The meta extension tag that records an object's base type. The extension's value is the
+/// [fingerprint](crate::marker::BaseType::fingerprint) of the type the object was built with.
+pub const MEXT_BASE_FINGERPRINT: u64 = 0x6261_7365;
+
+/// Returns the base type fingerprint recorded in an object's metadata, if it has one.
+pub fn base_fingerprint(obj: &impl RawObject) -> Option<u64> {
+    let meta = obj.meta_ptr();
+    // Safety: the meta page is mapped along with the object, and the extensions lie within it.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        (0..(*meta).extcount as usize)
+            .map(|i| &*exts.add(i))
+            .find(|ext| ext.tag == MEXT_BASE_FINGERPRINT)
+            .map(|ext| ext.value)
+    }
+}
+
+/// Record `fingerprint` as the base type fingerprint of `obj`, replacing any previous one. The
+/// object must be mapped writable.
+pub(crate) fn set_base_fingerprint(obj: &impl RawObject, fingerprint: u64) -> Result<()> {
+    let meta = obj.meta_mut_ptr();
+    // Safety: the meta page is mapped along with the object, and the extensions lie within it.
+    unsafe {
+        let exts = meta
+            .cast::<u8>()
+            .add(size_of::<MetaInfo>())
+            .cast::<MetaExt>();
+        let extcount = (*meta).extcount as usize;
+        let idx = (0..extcount)
+            .find(|i| (*exts.add(*i)).tag == MEXT_BASE_FINGERPRINT)
+            .unwrap_or(extcount);
+        if size_of::<MetaInfo>() + (idx + 1) * size_of::<MetaExt>() > NULLPAGE_SIZE {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        exts.add(idx).write(MetaExt {
+            tag: MEXT_BASE_FINGERPRINT,
+            value: fingerprint,
+        });
+        if idx == extcount {
+            (*meta).extcount += 1;
+        }
+    }
+    Ok(())
+}
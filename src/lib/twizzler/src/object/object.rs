@@ -1,12 +1,13 @@
 use std::marker::PhantomData;
 
-use twizzler_abi::object::ObjID;
+use twizzler_abi::{object::ObjID, syscall::ObjectControlCmd};
 use twizzler_rt_abi::{
+    error::ArgumentError,
     object::{MapFlags, ObjectHandle},
     Result,
 };
 
-use super::{MutObject, RawObject, TxObject, TypedObject};
+use super::{base_fingerprint, MutObject, RawObject, TxObject, TypedObject};
 use crate::{marker::BaseType, ptr::Ref, util::maybe_remap};
 
 pub struct Object<Base> {
@@ -121,8 +122,7 @@ impl<Base> Object<Base> {
     /// The provided map flags must contain at least READ, and for stable
     /// read maps, INDIRECT. For writes, add WRITE and PERSIST.
     ///
-    /// This function checks the underlying fingerprint of the base type against
-    /// the stored value and fails on mismatch to ensure type safety.
+    /// This does not yet check the object's base type; use [Object::map_checked] for that.
     pub fn map(id: ObjID, flags: MapFlags) -> Result<Self> {
         // TODO: check base fingerprint
         let handle = twizzler_rt_abi::object::twz_rt_map_object(id, flags)?;
@@ -140,6 +140,27 @@ impl<Base> Object<Base> {
         unsafe { Ok(Self::from_handle_unchecked(handle)) }
     }
 
+    /// Open a new object from its ID, like [Object::map], and ask the pager to start reading in its
+    /// first `n_pages` pages in the background (see [Object::prefetch]). This smooths the first
+    /// accesses to a large pager-backed object. A failed hint is logged and otherwise ignored.
+    pub fn map_prefetch(id: ObjID, flags: MapFlags, n_pages: usize) -> Result<Self> {
+        let obj = Self::map(id, flags)?;
+        let _ = obj
+            .prefetch(n_pages)
+            .inspect_err(|e| tracing::debug!("prefetch of {} failed: {}", id, e));
+        Ok(obj)
+    }
+
+    /// Ask the pager to start reading in the first `n_pages` pages of this object's data in the
+    /// background. This is only a hint: it returns without waiting for the pages, reads no
+    /// further than the end of the object, and does nothing for objects that aren't pager-backed.
+    pub fn prefetch(&self, n_pages: usize) -> Result<()> {
+        twizzler_abi::syscall::sys_object_ctrl(
+            self.id(),
+            ObjectControlCmd::PreloadPages(n_pages as u64),
+        )
+    }
+
     /// Return the ID of the object.
     pub fn id(&self) -> ObjID {
         self.handle.id()
@@ -152,6 +173,36 @@ impl<Base> Object<Base> {
     }
 }
 
+impl<Base: BaseType> Object<Base> {
+    /// Open a new object from its ID, like [Object::map], and check its base type (see
+    /// [Object::check_base_type]).
+    pub fn map_checked(id: ObjID, flags: MapFlags) -> Result<Self> {
+        let obj = Self::map(id, flags)?;
+        obj.check_base_type()?;
+        Ok(obj)
+    }
+
+    /// Check that the object was built with base type `Base`, by comparing the fingerprint
+    /// recorded in its metadata against `Base::fingerprint()`. Fails with InvalidArgument on a
+    /// mismatch, which means that the object is being used as the wrong type. Objects that don't
+    /// record a fingerprint, and base types without one (a fingerprint of 0), pass.
+    pub fn check_base_type(&self) -> Result<()> {
+        let expected = Base::fingerprint();
+        match base_fingerprint(self) {
+            Some(found) if expected != 0 && found != expected => {
+                tracing::debug!(
+                    "{}: base type fingerprint is {}, expected {}",
+                    self.id(),
+                    found,
+                    expected
+                );
+                Err(ArgumentError::InvalidArgument.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 impl<Base> RawObject for Object<Base> {
     fn handle(&self) -> &ObjectHandle {
         &self.handle
@@ -15,8 +15,9 @@ use std::{
     ptr::NonNull,
     sync::{
         atomic::{AtomicPtr, AtomicU32, Ordering},
-        OnceLock,
+        Mutex, OnceLock,
     },
+    time::{Duration, Instant},
 };
 
 pub use dynlink::{
@@ -25,7 +26,7 @@ pub use dynlink::{
 };
 use secgate::{
     util::{Descriptor, Handle},
-    Crossing, DynamicSecGate,
+    Crossing, DynamicSecGate, GateProvider,
 };
 use twizzler_abi::object::{ObjID, MAX_SIZE, NULLPAGE_SIZE};
 
@@ -37,7 +38,7 @@ mod gates {
 pub use gates::*;
 use twizzler_rt_abi::{
     debug::{DlPhdrInfo, LinkMap, LoadedImageId},
-    error::{ArgumentError, TwzError},
+    error::{ArgumentError, GenericError, ResourceError, TwzError},
 };
 
 /// Shared data between the monitor and a compartment runtime. Written to by the monitor, and
@@ -244,6 +245,26 @@ impl LibraryHandle {
     pub fn desc(&self) -> Descriptor {
         self.desc
     }
+
+    /// Get the secure gates exported by this library. Empty if the library exports none.
+    pub fn gates(&self) -> Vec<GateDesc> {
+        (0..)
+            .map_while(|n| gates::monitor_rt_get_library_gate(self.desc, n).ok())
+            .map(|raw| GateDesc {
+                name: lazy_sb::read_string_from_sb(raw.name_len),
+                address: raw.address,
+            })
+            .collect()
+    }
+}
+
+/// A secure gate exported by a library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GateDesc {
+    /// The gate's name.
+    pub name: String,
+    /// The address of the gate's entry point.
+    pub address: usize,
 }
 
 /// A builder-type for loading libraries.
@@ -275,9 +296,22 @@ impl<'a> LibraryLoader<'a> {
 /// A compartment handle. On drop, the compartment may be unloaded.
 pub struct CompartmentHandle {
     desc: Option<Descriptor>,
+    // The compartment's flags as last fetched for [secgate::TargetLifecycle], and when.
+    lifecycle: Mutex<Option<(Instant, CompartmentFlags)>>,
 }
 
+/// How long gate calls through a [CompartmentHandle] trust the lifecycle state they last fetched
+/// from the monitor before asking again.
+const LIFECYCLE_REFRESH_INTERVAL: Duration = Duration::from_millis(10);
+
 impl CompartmentHandle {
+    fn from_desc(desc: Option<Descriptor>) -> Self {
+        Self {
+            desc,
+            lifecycle: Mutex::new(None),
+        }
+    }
+
     /// Get the compartment info.
     pub fn info(&self) -> CompartmentInfo<'_> {
         CompartmentInfo::from_raw(gates::monitor_rt_get_compartment_info(self.desc).unwrap())
@@ -294,7 +328,34 @@ impl CompartmentHandle {
     ) -> Result<DynamicSecGate<'_, A, R>, TwzError> {
         let name_len = lazy_sb::write_bytes_to_sb(name.as_bytes());
         let address = gates::monitor_rt_compartment_dynamic_gate(self.desc, name_len)?;
-        Ok(DynamicSecGate::new(address))
+        Ok(DynamicSecGate::new(address).with_lifecycle(self))
+    }
+}
+
+/// Calls through gates from [CompartmentHandle::dynamic_gate] check that the compartment hasn't
+/// exited or run its destructors. The flags are fetched from the monitor at most once every
+/// [LIFECYCLE_REFRESH_INTERVAL], so most calls don't pay for a call into the monitor. Teardown
+/// can't be undone, so once it's seen the monitor isn't asked again. If the flags can't be fetched,
+/// the call goes ahead, as it would have without the check.
+impl secgate::TargetLifecycle for CompartmentHandle {
+    fn is_tearing_down(&self) -> bool {
+        // Calls within the current compartment can't outlive it.
+        if self.desc.is_none() {
+            return false;
+        }
+        let teardown = CompartmentFlags::EXITED | CompartmentFlags::DESTRUCTED;
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if let Some((fetched, flags)) = *lifecycle {
+            if flags.intersects(teardown) || fetched.elapsed() < LIFECYCLE_REFRESH_INTERVAL {
+                return flags.intersects(teardown);
+            }
+        }
+        let Ok(raw) = gates::monitor_rt_get_compartment_flags(self.desc) else {
+            return false;
+        };
+        let flags = CompartmentFlags::from_bits_truncate(raw);
+        *lifecycle = Some((Instant::now(), flags));
+        flags.intersects(teardown)
     }
 }
 
@@ -370,7 +431,57 @@ impl CompartmentLoader {
             envs_len as u64,
             self.flags.bits(),
         )?;
-        Ok(CompartmentHandle { desc: Some(desc) })
+        Ok(CompartmentHandle::from_desc(Some(desc)))
+    }
+}
+
+/// A compartment that is looked up by name, and loaded on first use if it isn't already running.
+/// Use with [secgate::call_or_load] to call gates in lazily-activated services.
+pub struct LazyCompartment {
+    name: String,
+    loader: CompartmentLoader,
+    handle: OnceLock<CompartmentHandle>,
+}
+
+impl LazyCompartment {
+    /// Make a new lazy compartment, which will be loaded with `loader` if no compartment named
+    /// `name` exists when one of its gates is first called.
+    pub fn new(name: impl ToString, loader: CompartmentLoader) -> Self {
+        Self {
+            name: name.to_string(),
+            loader,
+            handle: OnceLock::new(),
+        }
+    }
+
+    /// Get the handle for this compartment, if it has been found or loaded.
+    pub fn handle(&self) -> Option<&CompartmentHandle> {
+        self.handle.get()
+    }
+}
+
+impl GateProvider for LazyCompartment {
+    unsafe fn lookup_gate<A: Tuple + Crossing + Copy, R: Crossing + Copy>(
+        &self,
+        name: &str,
+    ) -> Result<DynamicSecGate<'_, A, R>, TwzError> {
+        let handle = match self.handle.get() {
+            Some(handle) => handle,
+            None => {
+                let handle = CompartmentHandle::lookup(&self.name)
+                    .map_err(|_| TwzError::from(ResourceError::Unavailable))?;
+                self.handle.get_or_init(|| handle)
+            }
+        };
+        handle.dynamic_gate(name)
+    }
+
+    fn load_provider(&self) -> Result<(), TwzError> {
+        if self.handle.get().is_none() {
+            let handle = self.loader.load()?;
+            let _ = self.handle.set(handle);
+        }
+        Ok(())
     }
 }
 
@@ -384,7 +495,7 @@ impl Handle for CompartmentHandle {
         Self: Sized,
     {
         let desc = gates::monitor_rt_get_compartment_handle(info)?;
-        Ok(CompartmentHandle { desc: Some(desc) })
+        Ok(CompartmentHandle::from_desc(Some(desc)))
     }
 
     fn release(&mut self) {
@@ -456,15 +567,15 @@ impl<'a> CompartmentInfo<'a> {
 impl CompartmentHandle {
     /// Get a handle to the current compartment.
     pub fn current() -> Self {
-        Self { desc: None }
+        Self::from_desc(None)
     }
 
     /// Lookup a compartment by name.
     pub fn lookup(name: impl AsRef<str>) -> Result<Self, TwzError> {
         let name_len = lazy_sb::write_bytes_to_sb(name.as_ref().as_bytes());
-        Ok(Self {
-            desc: Some(gates::monitor_rt_lookup_compartment(name_len)?),
-        })
+        Ok(Self::from_desc(Some(gates::monitor_rt_lookup_compartment(
+            name_len,
+        )?)))
     }
 
     /// Get an iterator over this compartment's dependencies.
@@ -536,7 +647,7 @@ impl<'a> Iterator for CompartmentDepsIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let desc = gates::monitor_rt_get_compartment_deps(self.comp.desc, self.n).ok()?;
         self.n += 1;
-        Some(CompartmentHandle { desc: Some(desc) })
+        Some(CompartmentHandle::from_desc(Some(desc)))
     }
 
     fn nth(&mut self, n: usize) -> Option<Self::Item> {
@@ -592,7 +703,8 @@ bitflags::bitflags! {
 }
 
 /// Contains raw mapping addresses, for use when translating to object handles for the runtime.
-#[derive(Copy, Clone, PartialEq, PartialOrd, Ord, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, PartialOrd, Ord, Eq, Debug, secgate::StableLayout)]
+#[repr(C)]
 pub struct MappedObjectAddrs {
     pub slot: usize,
     pub start: usize,
@@ -614,6 +726,21 @@ pub fn stats() -> Option<gates::MonitorStats> {
     gates::monitor_rt_stats().ok()
 }
 
+/// Fill a buffer with random bytes drawn from the kernel's entropy pool, via the monitor. The
+/// monitor rate-limits how much randomness each compartment may request, so this may fail with
+/// [twizzler_rt_abi::error::ResourceError::Busy] if called too often.
+pub fn fill_kernel_random(buf: &mut [u8]) -> Result<(), TwzError> {
+    for chunk in buf.chunks_mut(gates::MONITOR_RANDOM_MAX_REQUEST) {
+        let len = gates::monitor_rt_get_random(chunk.len())?;
+        let bytes = lazy_sb::read_bytes_from_sb(len);
+        if bytes.len() != chunk.len() {
+            return Err(GenericError::Internal.into());
+        }
+        chunk.copy_from_slice(&bytes);
+    }
+    Ok(())
+}
+
 mod lazy_sb {
     //! A per-thread per-compartment simple buffer used for transferring strings between
     //! compartments and the monitor. This is necessary because the monitor runs at too low of a
@@ -1,7 +1,7 @@
 use std::fmt::Debug;
 
 use dynlink::context::NewCompartmentFlags;
-use secgate::{util::Descriptor, Crossing};
+use secgate::{util::Descriptor, Crossing, StableLayout};
 use twizzler_rt_abi::{
     debug::{DlPhdrInfo, LinkMap},
     error::{ArgumentError, ResourceError, TwzError},
@@ -80,7 +80,7 @@ pub fn monitor_rt_get_library_handle(
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, StableLayout)]
 pub struct LibraryInfo {
     pub name_len: usize,
     pub compartment_id: ObjID,
@@ -93,8 +93,31 @@ pub struct LibraryInfo {
     pub desc: Descriptor,
 }
 
+#[cfg_attr(feature = "secgate-impl", secgate::secure_gate(options(info)))]
+#[cfg_attr(
+    not(feature = "secgate-impl"),
+    secgate::secure_gate(options(info, api))
+)]
+pub fn monitor_rt_get_library_gate(
+    info: &secgate::GateCallInfo,
+    desc: Descriptor,
+    gate_n: usize,
+) -> Result<GateInfo, TwzError> {
+    let monitor = crate::mon::get_monitor();
+    let instance = info.source_context().unwrap_or(MONITOR_INSTANCE_ID);
+    let thread = info.thread_id();
+    monitor.get_library_gate(instance, thread, desc, gate_n)
+}
+
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, StableLayout)]
+pub struct GateInfo {
+    pub name_len: usize,
+    pub address: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, StableLayout)]
 pub struct CompartmentInfo {
     pub name_len: usize,
     pub id: ObjID,
@@ -136,6 +159,20 @@ pub fn monitor_rt_get_compartment_info(
     monitor.get_compartment_info(caller, info.thread_id(), desc)
 }
 
+#[cfg_attr(feature = "secgate-impl", secgate::secure_gate(options(info)))]
+#[cfg_attr(
+    not(feature = "secgate-impl"),
+    secgate::secure_gate(options(info, api))
+)]
+pub fn monitor_rt_get_compartment_flags(
+    info: &secgate::GateCallInfo,
+    desc: Option<Descriptor>,
+) -> Result<u64, TwzError> {
+    let monitor = crate::mon::get_monitor();
+    let caller = info.source_context().unwrap_or(MONITOR_INSTANCE_ID);
+    monitor.get_compartment_flags(caller, desc)
+}
+
 #[cfg_attr(feature = "secgate-impl", secgate::secure_gate(options(info)))]
 #[cfg_attr(
     not(feature = "secgate-impl"),
@@ -166,7 +203,7 @@ pub fn monitor_rt_get_compartment_deps(
     monitor.get_compartment_deps(caller, desc, dep_n)
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash, Copy)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash, Copy, StableLayout)]
 #[repr(C)]
 pub struct ThreadInfo {
     pub repr_id: ObjID,
@@ -392,7 +429,7 @@ pub fn monitor_rt_get_thread_simple_buffer(
     monitor.get_thread_simple_buffer(caller, info.thread_id())
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, StableLayout)]
 #[repr(C)]
 #[allow(dead_code)]
 pub enum MonitorCompControlCmd {
@@ -413,7 +450,7 @@ pub fn monitor_rt_comp_ctrl(
     Ok(monitor.compartment_ctrl(info, cmd))
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct MonitorStats {
     pub space: SpaceStats,
@@ -423,32 +460,32 @@ pub struct MonitorStats {
     pub dynlink: DynlinkStats,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct SpaceStats {
     pub mapped: usize,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct ThreadMgrStats {
     pub nr_threads: usize,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct CompartmentMgrStats {
     pub nr_compartments: usize,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct HandleStats {
     pub nr_comp_handles: usize,
     pub nr_lib_handles: usize,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, StableLayout)]
 #[repr(C)]
 pub struct DynlinkStats {
     pub nr_libs: usize,
@@ -474,3 +511,17 @@ pub fn monitor_rt_set_nameroot(info: &secgate::GateCallInfo, root: ObjID) -> Res
     let monitor = crate::mon::get_monitor();
     monitor.set_nameroot(info, root)
 }
+
+/// Maximum number of random bytes that can be requested from the monitor in one gate call.
+pub const MONITOR_RANDOM_MAX_REQUEST: usize = 256;
+
+#[cfg_attr(feature = "secgate-impl", secgate::secure_gate(options(info)))]
+#[cfg_attr(
+    not(feature = "secgate-impl"),
+    secgate::secure_gate(options(info, api))
+)]
+pub fn monitor_rt_get_random(info: &secgate::GateCallInfo, len: usize) -> Result<usize, TwzError> {
+    let monitor = crate::mon::get_monitor();
+    let caller = info.source_context().unwrap_or(MONITOR_INSTANCE_ID);
+    monitor.get_random(caller, info.thread_id(), len)
+}
@@ -337,6 +337,23 @@ impl super::Monitor {
         .ok_or(ResourceError::OutOfResources.into())
     }
 
+    /// Get a compartment's flags. Unlike [Self::get_compartment_info], this doesn't write to the
+    /// calling thread's simple buffer, so it's cheap enough to check before gate calls.
+    #[tracing::instrument(skip(self), level = tracing::Level::DEBUG)]
+    pub fn get_compartment_flags(
+        &self,
+        caller: ObjID,
+        desc: Option<Descriptor>,
+    ) -> Result<u64, TwzError> {
+        let instance = {
+            let comphandles = self._compartment_handles.write(ThreadKey::get().unwrap());
+            desc.map(|comp| comphandles.lookup(caller, comp).map(|ch| ch.instance))
+                .unwrap_or(Some(caller))
+                .ok_or(ArgumentError::InvalidArgument)?
+        };
+        Ok(self.load_compartment_flags(instance))
+    }
+
     #[tracing::instrument(skip(self), level = tracing::Level::DEBUG)]
     pub fn compartment_wait(&self, caller: ObjID, desc: Option<Descriptor>, flags: u64) -> u64 {
         let Some(instance) = ({
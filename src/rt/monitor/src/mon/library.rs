@@ -10,7 +10,7 @@ use twizzler_rt_abi::{
 };
 
 use super::Monitor;
-use crate::gates::LibraryInfo;
+use crate::gates::{GateInfo, LibraryInfo};
 
 /// A handle to a library.
 pub struct LibraryHandle {
@@ -68,6 +68,36 @@ impl Monitor {
         })
     }
 
+    /// Get the n'th secure gate exported by a library. The gate's name is written to the
+    /// compartment-thread's simple buffer. Returns an error once n is past the last gate.
+    pub fn get_library_gate(
+        &self,
+        instance: ObjID,
+        thread: ObjID,
+        desc: Descriptor,
+        gate_n: usize,
+    ) -> Result<GateInfo, TwzError> {
+        let (_, ref mut comps, ref dynlink, ref libhandles, _) =
+            *self.locks.lock(ThreadKey::get().unwrap());
+        let handle = libhandles
+            .lookup(instance, desc)
+            .ok_or(ArgumentError::InvalidArgument)?;
+        // TODO: dynlink err map
+        let lib = dynlink
+            .get_library(handle.id)
+            .map_err(|_| GenericError::Internal)?;
+        let gate = lib
+            .iter_secgates()
+            .and_then(|gates| gates.get(gate_n))
+            .ok_or(ArgumentError::InvalidArgument)?;
+        let pt = comps.get_mut(instance)?.get_per_thread(thread);
+        let name_len = pt.write_bytes(gate.name().to_bytes());
+        Ok(GateInfo {
+            name_len,
+            address: gate.imp,
+        })
+    }
+
     /// Open a handle to the n'th library for a compartment.
     pub fn get_library_handle(
         &self,
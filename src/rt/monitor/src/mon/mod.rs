@@ -34,6 +34,7 @@ use crate::{gates::MonitorCompControlCmd, init::InitDynlinkContext};
 
 pub(crate) mod compartment;
 pub mod library;
+mod random;
 pub(crate) mod space;
 pub mod stat;
 pub(crate) mod thread;
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, mem::MaybeUninit, sync::Mutex, time::Instant};
+
+use twizzler_abi::syscall::{sys_get_random, GetRandomFlags};
+use twizzler_rt_abi::{
+    error::{ArgumentError, ResourceError, TwzError},
+    object::ObjID,
+};
+
+use super::Monitor;
+use crate::gates::MONITOR_RANDOM_MAX_REQUEST;
+
+/// Number of bytes a compartment may draw in a burst before being rate-limited.
+const RANDOM_BURST_BYTES: usize = 4096;
+/// Number of bytes added back to a compartment's budget per second.
+const RANDOM_REFILL_PER_SEC: usize = 1024;
+/// Number of tracked compartments above which idle budgets are evicted.
+const RANDOM_BUDGETS_PRUNE_AT: usize = 64;
+
+/// A token bucket tracking how much kernel randomness a compartment may still request.
+struct RandomBudget {
+    available: usize,
+    last_refill: Instant,
+}
+
+impl RandomBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            available: RANDOM_BURST_BYTES,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill = (elapsed.as_millis() as usize).saturating_mul(RANDOM_REFILL_PER_SEC) / 1000;
+        if refill > 0 {
+            self.available = (self.available + refill).min(RANDOM_BURST_BYTES);
+            self.last_refill = now;
+        }
+    }
+
+    /// A full budget behaves the same as a new one, so it can be forgotten.
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.available == RANDOM_BURST_BYTES
+    }
+
+    fn try_take(&mut self, len: usize, now: Instant) -> bool {
+        self.refill(now);
+        if self.available < len {
+            return false;
+        }
+        self.available -= len;
+        true
+    }
+}
+
+static RANDOM_BUDGETS: Mutex<Option<HashMap<ObjID, RandomBudget>>> = Mutex::new(None);
+
+impl Monitor {
+    /// Fill the calling thread's simple buffer with up to `len` bytes of kernel randomness. Each
+    /// compartment is limited in how fast it can draw random data, so that no single compartment
+    /// can exhaust the kernel's entropy pool.
+    #[tracing::instrument(skip(self), level = tracing::Level::DEBUG)]
+    pub fn get_random(&self, sctx: ObjID, thread: ObjID, len: usize) -> Result<usize, TwzError> {
+        if len == 0 || len > MONITOR_RANDOM_MAX_REQUEST {
+            return Err(ArgumentError::InvalidArgument.into());
+        }
+
+        {
+            let now = Instant::now();
+            let mut budgets = RANDOM_BUDGETS.lock().unwrap();
+            let budgets = budgets.get_or_insert_with(HashMap::new);
+            // Compartments come and go, so drop the budgets of those that have not drawn
+            // recently. This keeps the map to the compartments that drew in the last few
+            // seconds.
+            if budgets.len() >= RANDOM_BUDGETS_PRUNE_AT && !budgets.contains_key(&sctx) {
+                budgets.retain(|_, budget| !budget.is_full(now));
+            }
+            let budget = budgets
+                .entry(sctx)
+                .or_insert_with(|| RandomBudget::new(now));
+            if !budget.try_take(len, now) {
+                tracing::debug!("compartment {} exceeded random budget", sctx);
+                return Err(ResourceError::Busy.into());
+            }
+        }
+
+        let mut buf = [MaybeUninit::<u8>::uninit(); MONITOR_RANDOM_MAX_REQUEST];
+        let mut filled = 0;
+        while filled < len {
+            filled += sys_get_random(&mut buf[filled..len], GetRandomFlags::empty())?;
+        }
+        // Safety: the kernel initialized the first len bytes above.
+        let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), len) };
+        self._write_thread_simple_buffer(sctx, thread, bytes)
+    }
+}
+
+const _: () = assert!(RANDOM_BURST_BYTES >= MONITOR_RANDOM_MAX_REQUEST);
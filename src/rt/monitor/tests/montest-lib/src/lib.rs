@@ -6,6 +6,7 @@ extern crate twizzler_runtime;
 
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use secgate::util::ScratchRef;
 use twizzler_rt_abi::Result;
 
 #[secgate::secure_gate]
@@ -42,6 +43,40 @@ pub fn dynamic_test(x: u32) -> Result<u32> {
     Ok(42 + x)
 }
 
+#[secgate::secure_gate(options(info))]
+pub fn test_scratch_write(
+    info: &secgate::GateCallInfo,
+    scratch: ScratchRef,
+    x: u32,
+) -> Result<usize> {
+    let mut buffer = scratch.map(info)?;
+    let data = format!("scratch {}", x);
+    Ok(buffer.write(data.as_bytes()))
+}
+
+/// Make `levels` nested gate calls back into this library, calling out to the monitor (a different
+/// security context) at each level. Returns the number of levels whose frame was correctly restored
+/// after their nested calls returned.
+#[secgate::secure_gate]
+pub fn test_nested_frames(levels: u32) -> Result<u32> {
+    let sctx = secgate::get_sctx_id();
+    let depth = secgate::frame_depth();
+
+    let _ = monitor_api::stats();
+    if secgate::get_sctx_id() != sctx || secgate::frame_depth() != depth {
+        return Ok(0);
+    }
+    if levels == 0 {
+        return Ok(1);
+    }
+
+    let restored = test_nested_frames(levels - 1)?;
+    if secgate::get_sctx_id() != sctx || secgate::frame_depth() != depth {
+        return Ok(restored);
+    }
+    Ok(restored + 1)
+}
+
 static WAS_CTOR_RUN: AtomicBool = AtomicBool::new(false);
 
 #[used]
@@ -42,6 +42,7 @@ mod tests {
     use std::sync::atomic::Ordering;
 
     use monitor_api::CompartmentHandle;
+    use twizzler_rt_abi::error::GenericError;
 
     use crate::montest_lib;
     extern crate secgate;
@@ -70,19 +71,21 @@ mod tests {
     #[test]
     fn test_uncaught_internal_panic() {
         setup_logging();
+        let sctx = secgate::get_sctx_id();
         assert_eq!(
-            secgate::SecGateReturn::CalleePanic,
+            Err(GenericError::Internal.into()),
             montest_lib::test_internal_panic(false)
         );
+        // The caller's security context must be restored even though the callee panicked.
+        assert_eq!(sctx, secgate::get_sctx_id());
+        // And the gate must still be usable afterwards.
+        assert_eq!(Ok(1), montest_lib::test_internal_panic(true));
     }
 
     #[test]
     fn test_internal_panic() {
         setup_logging();
-        assert_eq!(
-            secgate::SecGateReturn::Success(1),
-            montest_lib::test_internal_panic(true)
-        );
+        assert_eq!(Ok(1), montest_lib::test_internal_panic(true));
     }
 
     #[test]
@@ -110,6 +113,111 @@ mod tests {
         let ret = unsafe { secgate::dynamic_gate_call(gate, (3,)).ok().unwrap() };
         assert_eq!(ret, 45);
     }
+
+    #[test]
+    fn test_library_gates() {
+        let current = CompartmentHandle::current();
+        let name = format!("{}::libmontest_lib.so", current.info().name);
+        let comp = CompartmentHandle::lookup(&name)
+            .expect(&format!("failed to open compartment: {}", &name));
+        let lib = comp
+            .libs()
+            .find(|lib| lib.info().name == "libmontest_lib.so")
+            .unwrap();
+        let gates = lib.gates();
+        let dynamic = gates.iter().find(|g| g.name == "dynamic_test").unwrap();
+        assert_ne!(dynamic.address, 0);
+        assert!(gates.iter().any(|g| g.name == "test_global_call_count"));
+
+        // Libraries that export no gates report none.
+        if let Some(std) = comp
+            .libs()
+            .find(|lib| lib.info().name.starts_with("libstd"))
+        {
+            assert!(std.gates().is_empty());
+        }
+    }
+
+    extern "C" fn registered_add(
+        _info: *const secgate::GateCallInfo,
+        args: *const secgate::Arguments<(u32, u32)>,
+        ret: *mut secgate::Return<Result<u32, twizzler_rt_abi::error::TwzError>>,
+    ) {
+        let (a, b) = unsafe { *args }.into_inner();
+        unsafe { &mut *ret }.set(Ok(a + b));
+    }
+
+    #[test]
+    fn test_registered_gate() {
+        secgate::register_gate(
+            "montest_registered_add",
+            registered_add as secgate::GateEntry<(u32, u32), u32>,
+        )
+        .unwrap();
+        let gate =
+            secgate::DynamicSecGate::<(u32, u32), u32>::by_name("montest_registered_add").unwrap();
+        assert_eq!(gate(40, 2), Ok(42));
+        assert!(secgate::unregister_gate("montest_registered_add"));
+    }
+
+    #[test]
+    fn test_scratch_object() {
+        setup_logging();
+        let scratch = secgate::util::ScratchObject::new().unwrap();
+        let len = montest_lib::test_scratch_write(scratch.scratch_ref(), 42).unwrap();
+
+        let mut buf = vec![0u8; len];
+        assert_eq!(scratch.buffer().read(&mut buf), len);
+        assert_eq!(&buf, b"scratch 42");
+    }
+
+    #[test]
+    fn test_nested_gate_frames() {
+        setup_logging();
+        let sctx = secgate::get_sctx_id();
+        let depth = secgate::frame_depth();
+
+        // Each level calls back into montest-lib and out to the monitor before returning.
+        assert_eq!(montest_lib::test_nested_frames(4), Ok(5));
+        assert_eq!(secgate::get_sctx_id(), sctx);
+        assert_eq!(secgate::frame_depth(), depth);
+    }
+
+    #[test]
+    fn test_alloc_zeroed() {
+        let layout = std::alloc::Layout::from_size_align(64 * 1024, 16).unwrap();
+        unsafe {
+            // Dirty some memory and free it, so a following zeroed allocation may reuse it.
+            let dirty = std::alloc::alloc(layout);
+            dirty.write_bytes(0xaa, layout.size());
+            std::alloc::dealloc(dirty, layout);
+
+            for _ in 0..4 {
+                let ptr = std::alloc::alloc_zeroed(layout);
+                assert!(!ptr.is_null());
+                let bytes = core::slice::from_raw_parts(ptr, layout.size());
+                assert!(bytes.iter().all(|b| *b == 0));
+                ptr.write_bytes(0x55, layout.size());
+                std::alloc::dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_kernel_random() {
+        setup_logging();
+        let mut a = [0u8; 64];
+        let mut b = [0u8; 64];
+        monitor_api::fill_kernel_random(&mut a).unwrap();
+        monitor_api::fill_kernel_random(&mut b).unwrap();
+        assert!(a.iter().any(|x| *x != 0));
+        assert_ne!(a, b);
+
+        // Requests larger than a single gate call are split into chunks.
+        let mut big = vec![0u8; 1000];
+        monitor_api::fill_kernel_random(&mut big).unwrap();
+        assert!(big[900..].iter().any(|x| *x != 0));
+    }
 }
 
 static WAS_CTOR_RUN: AtomicBool = AtomicBool::new(false);
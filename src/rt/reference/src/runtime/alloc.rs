@@ -2,25 +2,38 @@
 //! to support allocation before the runtime is fully ready, so to avoid calling into std, we
 //! implement a manual spinlock around the allocator until the better Mutex is available. Once it
 //! is, we move the allocator into the mutex, and use that.
+//!
+//! Heap objects are freshly-created volatile objects, so their memory starts out zeroed. We track
+//! how much of each heap object has ever been handed out, so that zeroed allocations served from
+//! untouched memory do not need to be cleared again.
+//!
+//! Small allocations are rounded up to a power-of-two size class, and each thread keeps a cache of
+//! free blocks for each class. The cache is refilled from (and flushed to) the shared allocator in
+//! batches, so most small allocations and frees don't need to take the allocator lock. A thread's
+//! cache is flushed when the thread exits. Threads that enter this compartment through a gate don't
+//! use a cache, since the TLS they get here is abandoned when the gate returns, without any chance
+//! to flush it.
 
 use core::{
     alloc::{GlobalAlloc, Layout},
+    cell::RefCell,
+    ops::Range,
     ptr::NonNull,
     sync::atomic::Ordering,
 };
 use std::{alloc::Allocator, mem::size_of, sync::atomic::AtomicUsize};
 
-use twizzler_abi::simple_mutex::Mutex;
+use twizzler_abi::simple_mutex::{LockGuard, Mutex};
 
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 const MIN_ALIGN: usize = 16;
 
-use talc::{OomHandler, Span, Talc};
+use talc::{Counters, OomHandler, Span, Talc};
 use twizzler_abi::{
     object::{ObjID, Protections, MAX_SIZE, NULLPAGE_SIZE},
     syscall::{
-        sys_object_create, sys_object_map, BackingType, LifetimeType, ObjectCreate,
-        ObjectCreateFlags,
+        sys_object_create, sys_object_map, sys_thread_self_id, sys_thread_yield, BackingType,
+        LifetimeType, ObjectCreate, ObjectCreateFlags,
     },
 };
 use twizzler_rt_abi::object::MapFlags;
@@ -32,6 +45,8 @@ static LOCAL_ALLOCATOR: LocalAllocator = LocalAllocator {
     _runtime: &OUR_RUNTIME,
     inner: Mutex::new(LocalAllocatorInner::new()),
     bootstrap_alloc_slot: AtomicUsize::new(0),
+    zeroing_skipped: AtomicUsize::new(0),
+    lock_acquisitions: AtomicUsize::new(0),
 };
 
 unsafe impl Sync for LocalAllocator {}
@@ -52,20 +67,270 @@ pub struct LocalAllocator {
     _runtime: &'static ReferenceRuntime,
     inner: Mutex<LocalAllocatorInner>,
     bootstrap_alloc_slot: AtomicUsize,
+    zeroing_skipped: AtomicUsize,
+    lock_acquisitions: AtomicUsize,
 }
 
 impl LocalAllocator {
+    fn lock(&self) -> LockGuard<'_, LocalAllocatorInner> {
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.inner.lock()
+    }
+
     pub fn get_id_from_ptr(&self, ptr: *const u8) -> Option<ObjID> {
         let slot = ptr as usize / MAX_SIZE;
-        let inner = self.inner.lock();
+        let inner = self.lock();
         inner.talc.oom_handler.objects.iter().find_map(|info| {
-            if info.0 == slot {
-                Some(info.1)
+            if info.slot == slot {
+                Some(info.id)
             } else {
                 None
             }
         })
     }
+
+    /// Returns the total number of bytes that zeroed allocations did not need to clear, because
+    /// they were served from untouched heap memory.
+    pub fn zeroing_skipped_bytes(&self) -> usize {
+        self.zeroing_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Returns how fragmented the heap's free memory is, from 0.0 (all free memory is one
+    /// contiguous block) approaching 1.0 (free memory is split into many small blocks).
+    pub fn fragmentation(&self) -> f32 {
+        fragmentation(self.lock().talc.get_counters())
+    }
+
+    /// Returns the number of times the shared allocator lock has been taken.
+    pub fn lock_acquisitions(&self) -> usize {
+        self.lock_acquisitions.load(Ordering::Relaxed)
+    }
+
+    /// Return all blocks in the calling thread's cache to the shared allocator. Must be called
+    /// before a thread exits, or the cached blocks are leaked (see
+    /// [ReferenceRuntime::exit_thread]).
+    pub fn flush_thread_cache(&self) {
+        with_thread_cache(|cache| {
+            for class in 0..NR_SIZE_CLASSES {
+                let count = cache.bins[class].count;
+                self.flush_bin(&mut cache.bins[class], class, count);
+            }
+        });
+    }
+
+    /// Stop the calling thread from caching blocks, for threads whose TLS may go away without the
+    /// thread exiting. Its small allocations take the allocator lock from now on.
+    pub(crate) fn disable_thread_cache(&self) {
+        self.flush_thread_cache();
+        with_thread_cache(|cache| cache.disabled = true);
+    }
+
+    /// Free the top `count` blocks of a cache bin to the shared allocator, taking the lock once.
+    fn flush_bin(&self, bin: &mut CacheBin, class: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let layout = size_class_layout(class);
+        let mut inner = self.lock();
+        for _ in 0..count {
+            bin.count -= 1;
+            // Blocks were checked on their way into the cache (see [LocalAllocator::check_free]).
+            let ptr = bin.blocks[bin.count];
+            unsafe { inner.do_dealloc(ptr, layout) };
+        }
+    }
+
+    /// Panic if ptr could not have come from this heap. This runs before a free goes into a
+    /// thread's cache, since the cache would otherwise hand a bogus pointer straight back out. It
+    /// doesn't take the allocator lock unless there are more heap objects than [HEAP_SLOTS] holds.
+    fn check_free(&self, ptr: *mut u8, layout: Layout) {
+        let nr_slots = NR_HEAP_SLOTS.load(Ordering::Acquire);
+        let res = if nr_slots <= MAX_HEAP_SLOTS {
+            check_free_in(
+                HEAP_SLOTS[..nr_slots]
+                    .iter()
+                    .map(|slot| slot.load(Ordering::Relaxed)),
+                ptr,
+                layout,
+            )
+        } else {
+            check_free(&self.lock().talc.oom_handler.objects, ptr, layout)
+        };
+        if let Err(reason) = res {
+            panic!("invalid free of {:p} ({:?}): {}", ptr, layout, reason);
+        }
+    }
+
+    /// Allocate a block of the given size class from the calling thread's cache, refilling the
+    /// cache from the shared allocator if it is empty. Returns None if the cache can't be used.
+    fn cache_alloc(&self, class: usize) -> Option<*mut u8> {
+        with_thread_cache(|cache| {
+            let bin = &mut cache.bins[class];
+            if bin.count == 0 {
+                let layout = size_class_layout(class);
+                let mut inner = self.lock();
+                while bin.count < CACHE_BATCH {
+                    let (ptr, _) = unsafe { inner.do_alloc(layout) };
+                    bin.blocks[bin.count] = ptr;
+                    bin.count += 1;
+                }
+            }
+            bin.count -= 1;
+            bin.blocks[bin.count]
+        })
+    }
+
+    /// Return a block of the given size class to the calling thread's cache, flushing part of the
+    /// cache to the shared allocator if it is full. Returns false if the cache can't be used.
+    fn cache_dealloc(&self, ptr: *mut u8, class: usize) -> bool {
+        with_thread_cache(|cache| {
+            let bin = &mut cache.bins[class];
+            if bin.count == CACHE_DEPTH {
+                self.flush_bin(bin, class, CACHE_BATCH);
+            }
+            bin.blocks[bin.count] = ptr;
+            bin.count += 1;
+        })
+        .is_some()
+    }
+}
+
+/// Number of small-allocation size classes, from [MIN_ALIGN] bytes up, doubling each time.
+const NR_SIZE_CLASSES: usize = 6;
+/// Maximum number of free blocks a thread caches per size class.
+const CACHE_DEPTH: usize = 32;
+/// Number of blocks moved between a thread's cache and the shared allocator at once.
+const CACHE_BATCH: usize = CACHE_DEPTH / 2;
+
+/// Maximum number of heap objects whose slots are kept in [HEAP_SLOTS].
+const MAX_HEAP_SLOTS: usize = 64;
+
+/// The slots of the heap objects, in the order they were added, so that frees can be checked
+/// without taking the allocator lock. Entries are only written with the lock held, before
+/// [NR_HEAP_SLOTS] is bumped to cover them.
+static HEAP_SLOTS: [AtomicUsize; MAX_HEAP_SLOTS] = [const { AtomicUsize::new(0) }; MAX_HEAP_SLOTS];
+/// Number of heap objects added so far, which may exceed [MAX_HEAP_SLOTS].
+static NR_HEAP_SLOTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the size class for an (already alignment-bumped) layout, or None if the allocation is too
+/// large or too aligned to be cached.
+fn size_class(layout: Layout) -> Option<usize> {
+    if layout.align() > MIN_ALIGN {
+        return None;
+    }
+    let size = layout.size().max(MIN_ALIGN).next_power_of_two();
+    let class = (size / MIN_ALIGN).trailing_zeros() as usize;
+    (class < NR_SIZE_CLASSES).then_some(class)
+}
+
+/// The layout of the blocks in a size class. Small allocations are always allocated and freed
+/// with this layout, whether or not they went through a thread's cache.
+fn size_class_layout(class: usize) -> Layout {
+    Layout::from_size_align(MIN_ALIGN << class, MIN_ALIGN).unwrap()
+}
+
+struct CacheBin {
+    count: usize,
+    blocks: [*mut u8; CACHE_DEPTH],
+}
+
+struct ThreadCache {
+    bins: [CacheBin; NR_SIZE_CLASSES],
+    disabled: bool,
+}
+
+impl ThreadCache {
+    const fn new() -> Self {
+        const EMPTY: CacheBin = CacheBin {
+            count: 0,
+            blocks: [core::ptr::null_mut(); CACHE_DEPTH],
+        };
+        Self {
+            bins: [EMPTY; NR_SIZE_CLASSES],
+            disabled: false,
+        }
+    }
+}
+
+#[thread_local]
+static THREAD_CACHE: RefCell<ThreadCache> = RefCell::new(ThreadCache::new());
+
+/// Run a closure on the calling thread's cache, if it can be used right now.
+fn with_thread_cache<R>(f: impl FnOnce(&mut ThreadCache) -> R) -> Option<R> {
+    if !thread_cache_usable() {
+        return None;
+    }
+    // The cache may already be borrowed if the shared allocator allocates while we are refilling
+    // or flushing (e.g. to map a new heap object). Fall back to the lock, then.
+    let mut cache = THREAD_CACHE.try_borrow_mut().ok()?;
+    if cache.disabled {
+        return None;
+    }
+    Some(f(&mut cache))
+}
+
+/// Maximum number of threads that can be switching their TLS at once. Any more wait their turn.
+const MAX_TLS_SWITCHES: usize = 16;
+
+/// Number of threads currently switching their thread pointer.
+static TLS_SWITCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// The threads, by repr ID, that are currently switching their thread pointer. This can't be kept
+/// in the switching thread's own thread-local data, since that is just what it can't access
+/// meanwhile.
+static TLS_SWITCHING: Mutex<[Option<ObjID>; MAX_TLS_SWITCHES]> =
+    Mutex::new([None; MAX_TLS_SWITCHES]);
+
+/// Held while the calling thread's TLS is being replaced. Allocations the thread makes meanwhile
+/// bypass its cache and go straight to the shared allocator.
+pub(crate) struct TlsSwitchGuard {
+    slot: usize,
+}
+
+impl TlsSwitchGuard {
+    pub(crate) fn new() -> Self {
+        let me = sys_thread_self_id();
+        loop {
+            let mut switching = TLS_SWITCHING.lock();
+            if let Some(slot) = switching.iter().position(Option::is_none) {
+                switching[slot] = Some(me);
+                TLS_SWITCHES.fetch_add(1, Ordering::SeqCst);
+                return Self { slot };
+            }
+            drop(switching);
+            sys_thread_yield();
+        }
+    }
+
+    fn is_switching(thread: ObjID) -> bool {
+        TLS_SWITCHING.lock().contains(&Some(thread))
+    }
+}
+
+impl Drop for TlsSwitchGuard {
+    fn drop(&mut self) {
+        TLS_SWITCHING.lock()[self.slot] = None;
+        TLS_SWITCHES.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Thread-local data isn't available until the runtime is ready, nor while the calling thread's
+/// TLS is being switched.
+fn thread_cache_usable() -> bool {
+    if !OUR_RUNTIME.state().contains(RuntimeState::READY) {
+        return false;
+    }
+    // Finding out which thread we are takes a syscall, so only ask while some thread is switching.
+    TLS_SWITCHES.load(Ordering::SeqCst) == 0 || !TlsSwitchGuard::is_switching(sys_thread_self_id())
+}
+
+/// Talc only tracks how many free blocks there are, not their sizes, so estimate fragmentation
+/// from the count: with n free blocks, the average block is 1/n of the free memory.
+fn fragmentation(counters: &Counters) -> f32 {
+    if counters.available_bytes == 0 || counters.fragment_count <= 1 {
+        return 0.0;
+    }
+    1.0 - 1.0 / counters.fragment_count as f32
 }
 
 struct LocalAllocatorInner {
@@ -74,7 +339,56 @@ struct LocalAllocatorInner {
 
 struct RuntimeOom {
     list_obj: Option<(usize, ObjID)>,
-    objects: Vec<(usize, ObjID), FailAlloc>,
+    objects: Vec<HeapObject, FailAlloc>,
+}
+
+/// Bytes past the end of an allocation that talc may write its own metadata into (allocation tags
+/// and the header of the following free chunk).
+const TALC_METADATA_SLACK: usize = 64;
+/// Bytes at the bottom of a newly-claimed heap that talc may use for its bins and first chunk
+/// header.
+const TALC_CLAIM_RESERVE: usize = NULLPAGE_SIZE;
+
+/// Offset from the base of a heap object to the start of the heap. We reserve an additional page
+/// at the base of the object for future use. This behavior may change as the runtime is fleshed
+/// out.
+const HEAP_OFFSET: usize = NULLPAGE_SIZE * 2;
+/// Offset from the endpoint of a heap object to where the endpoint of the heap is. Reserve a page
+/// for the metadata + a few pages for any future FOT entries.
+const TOP_OFFSET: usize = NULLPAGE_SIZE * 4;
+
+/// An object backing part of the heap.
+struct HeapObject {
+    slot: usize,
+    id: ObjID,
+    // Memory in [untouched_start, untouched_end) has never been handed out or written by talc, so
+    // it is still zero from when the object was created.
+    untouched_start: usize,
+    untouched_end: usize,
+}
+
+impl HeapObject {
+    fn new(slot: usize, id: ObjID, base: usize, top: usize) -> Self {
+        Self {
+            slot,
+            id,
+            untouched_start: base + TALC_CLAIM_RESERVE,
+            untouched_end: top - TALC_METADATA_SLACK,
+        }
+    }
+
+    /// Record that [start, end) has been handed out, returning the part of it that was untouched
+    /// (and so is known to be zero). The returned range may be empty.
+    fn take_untouched(&mut self, start: usize, end: usize) -> Range<usize> {
+        let untouched = start.max(self.untouched_start)..end.min(self.untouched_end);
+        self.untouched_start = self.untouched_start.max(end + TALC_METADATA_SLACK);
+        untouched
+    }
+}
+
+/// The part of the heap object in slot that was claimed as heap.
+fn heap_span(slot: usize) -> Range<usize> {
+    (slot * MAX_SIZE + HEAP_OFFSET)..((slot + 1) * MAX_SIZE - TOP_OFFSET)
 }
 
 fn release_object(id: ObjID) {
@@ -122,12 +436,6 @@ fn create_and_map() -> Option<(usize, ObjID)> {
 impl OomHandler for RuntimeOom {
     fn handle_oom(talc: &mut Talc<Self>, _layout: Layout) -> Result<(), ()> {
         let (slot, id) = create_and_map().ok_or(())?;
-        // reserve an additional page size at the base of the object for future use. This behavior
-        // may change as the runtime is fleshed out.
-        const HEAP_OFFSET: usize = NULLPAGE_SIZE * 2;
-        // offset from the endpoint of the object to where the endpoint of the heap is. Reserve a
-        // page for the metadata + a few pages for any future FOT entries.
-        const TOP_OFFSET: usize = NULLPAGE_SIZE * 4;
         let base = slot * MAX_SIZE + HEAP_OFFSET;
         let top = (slot + 1) * MAX_SIZE - TOP_OFFSET;
 
@@ -146,13 +454,20 @@ impl OomHandler for RuntimeOom {
             let slot = talc.oom_handler.list_obj.unwrap().0;
             let list_vec_start = slot * MAX_SIZE + HEAP_OFFSET;
             let list_vec_bytes = MAX_SIZE - TOP_OFFSET;
-            let list_vec_cap = list_vec_bytes / size_of::<(usize, ObjID)>();
+            let list_vec_cap = list_vec_bytes / size_of::<HeapObject>();
             let na = FailAlloc;
             talc.oom_handler.objects =
                 unsafe { Vec::from_raw_parts_in(list_vec_start as *mut _, 0, list_vec_cap, na) };
         }
 
-        talc.oom_handler.objects.push((slot, id));
+        talc.oom_handler
+            .objects
+            .push(HeapObject::new(slot, id, base, top));
+        let nr_slots = NR_HEAP_SLOTS.load(Ordering::Relaxed);
+        if nr_slots < MAX_HEAP_SLOTS {
+            HEAP_SLOTS[nr_slots].store(slot, Ordering::Relaxed);
+        }
+        NR_HEAP_SLOTS.store(nr_slots + 1, Ordering::Release);
 
         Ok(())
     }
@@ -176,8 +491,44 @@ unsafe impl GlobalAlloc for LocalAllocator {
         let layout =
             Layout::from_size_align(layout.size(), core::cmp::max(layout.align(), MIN_ALIGN))
                 .expect("layout alignment bump failed");
-        let mut inner = self.inner.lock();
-        let ptr = inner.do_alloc(layout);
+        if let Some(class) = size_class(layout) {
+            if let Some(ptr) = self.cache_alloc(class) {
+                return ptr;
+            }
+            return self.lock().do_alloc(size_class_layout(class)).0;
+        }
+        let mut inner = self.lock();
+        let (ptr, _) = inner.do_alloc(layout);
+        ptr
+    }
+
+    #[track_caller]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let layout =
+            Layout::from_size_align(layout.size(), core::cmp::max(layout.align(), MIN_ALIGN))
+                .expect("layout alignment bump failed");
+        if let Some(class) = size_class(layout) {
+            // Cached blocks may have been used before, so always clear them.
+            let ptr = match self.cache_alloc(class) {
+                Some(ptr) => ptr,
+                None => self.lock().do_alloc(size_class_layout(class)).0,
+            };
+            ptr.write_bytes(0, layout.size());
+            return ptr;
+        }
+        let (ptr, untouched) = self.lock().do_alloc(layout);
+
+        // Only clear the parts of the allocation that may have been written before.
+        let start = ptr as usize;
+        let end = start + layout.size();
+        if untouched.is_empty() {
+            ptr.write_bytes(0, layout.size());
+        } else {
+            ptr.write_bytes(0, untouched.start - start);
+            (untouched.end as *mut u8).write_bytes(0, end - untouched.end);
+            self.zeroing_skipped
+                .fetch_add(untouched.len(), Ordering::Relaxed);
+        }
         ptr
     }
 
@@ -200,9 +551,49 @@ unsafe impl GlobalAlloc for LocalAllocator {
         {
             return;
         }
-        let mut inner = self.inner.lock();
-        inner.do_dealloc(ptr, layout)
+        let class = size_class(layout);
+        let layout = class.map_or(layout, size_class_layout);
+        // Check before caching the block, so that a bogus pointer is never handed back out. This
+        // is done without the lock held, since reporting the panic may allocate.
+        if cfg!(debug_assertions) {
+            self.check_free(ptr, layout);
+        }
+        if let Some(class) = class {
+            if self.cache_dealloc(ptr, class) {
+                return;
+            }
+        }
+        self.lock().do_dealloc(ptr, layout)
+    }
+}
+
+/// Check that ptr could have come from this heap, so that a wild or corrupted free is reported
+/// instead of corrupting talc's structures. This cannot catch every bad free (e.g. a double free
+/// of a real allocation), but it catches pointers that were never handed out by us. Must not
+/// allocate, since it runs with the allocator locked.
+fn check_free(objects: &[HeapObject], ptr: *mut u8, layout: Layout) -> Result<(), &'static str> {
+    check_free_in(objects.iter().map(|obj| obj.slot), ptr, layout)
+}
+
+/// Like [check_free], given just the slots of the heap objects.
+fn check_free_in(
+    slots: impl IntoIterator<Item = usize>,
+    ptr: *mut u8,
+    layout: Layout,
+) -> Result<(), &'static str> {
+    if ptr.is_null() {
+        return Err("null pointer");
+    }
+    if ptr as usize % layout.align() != 0 {
+        return Err("pointer is misaligned for its layout");
     }
+    if !slots
+        .into_iter()
+        .any(|slot| heap_span(slot).contains(&(ptr as usize)))
+    {
+        return Err("pointer is not within any heap object");
+    }
+    Ok(())
 }
 
 impl LocalAllocatorInner {
@@ -215,11 +606,229 @@ impl LocalAllocatorInner {
         }
     }
 
-    unsafe fn do_alloc(&mut self, layout: Layout) -> *mut u8 {
-        self.talc.malloc(layout).unwrap().as_ptr()
+    /// Allocate memory, returning the pointer and the range within the allocation that is known to
+    /// be zero.
+    unsafe fn do_alloc(&mut self, layout: Layout) -> (*mut u8, Range<usize>) {
+        let ptr = self.talc.malloc(layout).unwrap().as_ptr();
+        let start = ptr as usize;
+        let untouched = self
+            .talc
+            .oom_handler
+            .objects
+            .iter_mut()
+            .find(|obj| obj.slot == start / MAX_SIZE)
+            .map_or(start..start, |obj| {
+                obj.take_untouched(start, start + layout.size())
+            });
+        (ptr, untouched)
     }
 
     unsafe fn do_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         self.talc.free(NonNull::new(ptr).unwrap(), layout);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap_object() -> HeapObject {
+        HeapObject::new(1, ObjID::new(1), MAX_SIZE, 2 * MAX_SIZE)
+    }
+
+    #[test]
+    fn fresh_memory_is_untouched() {
+        let mut obj = heap_object();
+        let start = obj.untouched_start;
+        assert_eq!(obj.take_untouched(start, start + 128), start..start + 128);
+
+        // The next allocation starts after talc's metadata for the previous one.
+        let next = start + 128 + TALC_METADATA_SLACK;
+        assert_eq!(obj.take_untouched(next, next + 64), next..next + 64);
+    }
+
+    #[test]
+    fn reused_memory_is_not_untouched() {
+        let mut obj = heap_object();
+        let start = obj.untouched_start;
+        obj.take_untouched(start, start + 4096);
+
+        // Handing the same memory out again (after a free) must not skip zeroing.
+        assert!(obj.take_untouched(start, start + 4096).is_empty());
+
+        // A larger allocation overlapping the dirtied region is only partly untouched.
+        let untouched = obj.take_untouched(start, start + 8192);
+        assert_eq!(untouched, start + 4096 + TALC_METADATA_SLACK..start + 8192);
+    }
+
+    #[test]
+    fn fresh_zeroed_allocations_skip_zeroing() {
+        const SIZE: usize = 1024 * 1024;
+        let layout = Layout::from_size_align(SIZE, MIN_ALIGN).unwrap();
+
+        let before = LOCAL_ALLOCATOR.zeroing_skipped_bytes();
+        let first = unsafe { LOCAL_ALLOCATOR.alloc_zeroed(layout) };
+        assert!(!first.is_null());
+        let skipped = LOCAL_ALLOCATOR.zeroing_skipped_bytes() - before;
+        // Only talc's metadata at the edges of the span may have been written before.
+        assert!(
+            skipped + 2 * TALC_METADATA_SLACK >= SIZE,
+            "skipped {} of {} bytes",
+            skipped,
+            SIZE
+        );
+        unsafe {
+            first.write_bytes(0xaa, SIZE);
+            LOCAL_ALLOCATOR.dealloc(first, layout);
+        }
+
+        // Reusing the dirtied memory must clear it, so none of the overlap may be skipped.
+        let before = LOCAL_ALLOCATOR.zeroing_skipped_bytes();
+        let second = unsafe { LOCAL_ALLOCATOR.alloc_zeroed(layout) };
+        assert!(!second.is_null());
+        let skipped = LOCAL_ALLOCATOR.zeroing_skipped_bytes() - before;
+        let overlap_start = (first as usize).max(second as usize);
+        let overlap_end = (first as usize + SIZE).min(second as usize + SIZE);
+        let overlap = overlap_end.saturating_sub(overlap_start);
+        assert!(
+            skipped <= SIZE - overlap,
+            "skipped {} bytes of a reused allocation overlapping {} bytes",
+            skipped,
+            overlap
+        );
+        let bytes = unsafe { core::slice::from_raw_parts(second, SIZE) };
+        assert!(bytes.iter().all(|b| *b == 0));
+        unsafe { LOCAL_ALLOCATOR.dealloc(second, layout) };
+    }
+
+    #[test]
+    fn free_checks_heap_bounds() {
+        let objects = [heap_object()];
+        let layout = Layout::from_size_align(64, MIN_ALIGN).unwrap();
+        let heap = MAX_SIZE + HEAP_OFFSET;
+        assert!(check_free(&objects, heap as *mut u8, layout).is_ok());
+
+        // Not in any heap object, in the reserved area at the base, and misaligned.
+        assert!(check_free(&objects, (3 * MAX_SIZE + HEAP_OFFSET) as *mut u8, layout).is_err());
+        assert!(check_free(&objects, MAX_SIZE as *mut u8, layout).is_err());
+        assert!(check_free(&objects, (heap + 8) as *mut u8, layout).is_err());
+        assert!(check_free(&objects, core::ptr::null_mut(), layout).is_err());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "invalid free")]
+    fn bogus_free_panics() {
+        let mut bogus = 0u128;
+        unsafe {
+            LOCAL_ALLOCATOR.dealloc(
+                (&raw mut bogus).cast(),
+                Layout::from_size_align(16, 16).unwrap(),
+            )
+        };
+    }
+
+    #[test]
+    fn holes_raise_fragmentation() {
+        let mut arena = [0u8; 64 * 1024];
+        let mut talc = Talc::new(talc::ErrOnOom);
+        unsafe { talc.claim(Span::from_slice(arena.as_mut_slice() as *mut [u8])) }.unwrap();
+        assert_eq!(fragmentation(talc.get_counters()), 0.0);
+
+        let layout = Layout::from_size_align(256, MIN_ALIGN).unwrap();
+        let ptrs: Vec<_> = (0..16)
+            .map(|_| unsafe { talc.malloc(layout) }.unwrap())
+            .collect();
+        assert_eq!(fragmentation(talc.get_counters()), 0.0);
+
+        // Free every other allocation, leaving holes between the live ones.
+        let mut last = 0.0;
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { talc.free(*ptr, layout) };
+            let frag = fragmentation(talc.get_counters());
+            assert!(frag > last);
+            last = frag;
+        }
+        assert!(last > 0.5);
+    }
+
+    #[test]
+    fn small_sizes_map_to_classes() {
+        let class = |size, align| size_class(Layout::from_size_align(size, align).unwrap());
+        assert_eq!(class(1, MIN_ALIGN), Some(0));
+        assert_eq!(class(MIN_ALIGN, MIN_ALIGN), Some(0));
+        assert_eq!(class(MIN_ALIGN + 1, MIN_ALIGN), Some(1));
+        let largest = MIN_ALIGN << (NR_SIZE_CLASSES - 1);
+        assert_eq!(class(largest, MIN_ALIGN), Some(NR_SIZE_CLASSES - 1));
+        assert_eq!(class(largest + 1, MIN_ALIGN), None);
+        assert_eq!(class(16, MIN_ALIGN * 2), None);
+        for c in 0..NR_SIZE_CLASSES {
+            assert_eq!(size_class(size_class_layout(c)), Some(c));
+        }
+    }
+
+    #[test]
+    fn thread_cache_reduces_locking() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 2000;
+        let before = LOCAL_ALLOCATOR.lock_acquisitions();
+        let threads: Vec<_> = (0..THREADS)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    for i in 0..ROUNDS {
+                        let b = std::hint::black_box(Box::new([t as u8; 48]));
+                        assert_eq!(b[47], t as u8, "round {}", i);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let taken = LOCAL_ALLOCATOR.lock_acquisitions() - before;
+        // Without the cache, every allocation and free takes the lock. Allow plenty of slack for
+        // other threads allocating while the test runs, and for spawning the threads.
+        assert!(
+            taken < THREADS * ROUNDS / 4,
+            "took the allocator lock {} times for {} allocations",
+            taken,
+            THREADS * ROUNDS
+        );
+    }
+
+    #[test]
+    fn tls_switch_only_affects_the_switching_thread() {
+        let guard = TlsSwitchGuard::new();
+        assert!(!thread_cache_usable());
+        assert!(std::thread::spawn(thread_cache_usable).join().unwrap());
+        drop(guard);
+        assert!(thread_cache_usable());
+    }
+
+    #[test]
+    fn disabled_thread_cache_takes_lock() {
+        const ROUNDS: usize = 100;
+        std::thread::spawn(|| {
+            LOCAL_ALLOCATOR.disable_thread_cache();
+            let before = LOCAL_ALLOCATOR.lock_acquisitions();
+            for _ in 0..ROUNDS {
+                drop(std::hint::black_box(Box::new([0u8; 48])));
+            }
+            // Every allocation and free goes to the shared allocator.
+            assert!(LOCAL_ALLOCATOR.lock_acquisitions() - before >= 2 * ROUNDS);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn metadata_regions_are_not_untouched() {
+        let mut obj = heap_object();
+        let base = MAX_SIZE;
+        assert!(obj.take_untouched(base, base + 64).is_empty());
+
+        let top = 2 * MAX_SIZE;
+        let untouched = obj.take_untouched(top - 4096, top);
+        assert_eq!(untouched.end, top - TALC_METADATA_SLACK);
+    }
+}
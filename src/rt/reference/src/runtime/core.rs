@@ -44,7 +44,7 @@ impl ReferenceRuntime {
     #[track_caller]
     pub fn exit(&self, code: i32) -> ! {
         if self.state().contains(RuntimeState::READY) {
-            twizzler_abi::syscall::sys_thread_exit(code as u64);
+            self.exit_thread(code as u64);
         } else {
             preinit_println!("runtime exit before runtime ready: {}", code);
             preinit_abort();
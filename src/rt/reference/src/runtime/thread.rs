@@ -63,6 +63,13 @@ impl ReferenceRuntime {
         false
     }
 
+    /// Exit the calling thread. Every thread running in this runtime exits through here, so that
+    /// the thread's allocator cache is returned before its TLS goes away.
+    pub(crate) fn exit_thread(&self, code: u64) -> ! {
+        self.get_alloc().flush_thread_cache();
+        twizzler_abi::syscall::sys_thread_exit(code);
+    }
+
     pub fn yield_now(&self) {
         sys_thread_yield()
     }
@@ -18,6 +18,7 @@ use twizzler_rt_abi::{
 
 use super::internal::InternalThread;
 use crate::runtime::{
+    alloc::TlsSwitchGuard,
     thread::{
         tcb::{trampoline, TLS_GEN_MGR},
         MIN_STACK_ALIGN, THREAD_MGR,
@@ -129,6 +130,8 @@ impl<'a> Drop for IdDropper<'a> {
 
 impl ReferenceRuntime {
     pub fn cross_compartment_entry(&self) -> Result<()> {
+        // This thread has no usable TLS until the new TLS is set below.
+        let tls_switch = TlsSwitchGuard::new();
         twizzler_abi::syscall::sys_thread_settls(0);
         if OUR_RUNTIME.is_monitor().is_some() {
             twizzler_abi::syscall::sys_thread_set_active_sctx_id(0.into()).inspect_err(|e| {
@@ -156,6 +159,10 @@ impl ReferenceRuntime {
             .get_next_tls_info(None, || RuntimeThreadControl::new(id))
             .unwrap();
         twizzler_abi::syscall::sys_thread_settls(tls as u64);
+        drop(tls_switch);
+        // This TLS is abandoned when the gate call returns, and the thread won't exit through this
+        // runtime, so a cache here would never be flushed.
+        OUR_RUNTIME.get_alloc().disable_thread_cache();
         Ok(())
     }
 
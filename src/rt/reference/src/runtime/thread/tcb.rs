@@ -46,7 +46,7 @@ pub(super) extern "C" fn trampoline(arg: usize) -> ! {
         0
     })
     .unwrap_or(THREAD_PANIC_CODE);
-    twizzler_abi::syscall::sys_thread_exit(code);
+    OUR_RUNTIME.exit_thread(code);
 }
 
 #[derive(Default)]
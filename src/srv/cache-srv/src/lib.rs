@@ -11,11 +11,12 @@ use twizzler_rt_abi::{
     Result,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, secgate::StableLayout)]
 #[repr(C)]
 pub struct CachedStats {
     pub id: ObjID,
-    pub start: Instant,
+    /// How long the object has been held, in nanoseconds.
+    pub held_ns: u64,
     pub flags: MapFlags,
 }
 
@@ -82,7 +83,7 @@ pub fn list_nth(nth: u64) -> Result<Option<CachedStats>> {
         Ok(Some(CachedStats {
             id: v.handle.id(),
             flags: v.handle.map_flags(),
-            start: v.start,
+            held_ns: v.start.elapsed().as_nanos() as u64,
         }))
     } else {
         Ok(None)
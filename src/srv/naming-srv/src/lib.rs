@@ -190,6 +190,24 @@ pub fn put(
     client.session.put(path, id)
 }
 
+#[secure_gate(options(info))]
+pub fn put_if_absent(
+    info: &secgate::GateCallInfo,
+    desc: Descriptor,
+    name_len: usize,
+    id: ObjID,
+) -> Result<NsNode> {
+    let service = NAMINGSERVICE.get().unwrap();
+    let mut binding = service.handles.lock().unwrap();
+    let client = binding
+        .lookup_mut(info.source_context().unwrap_or(0.into()), desc)
+        .ok_or(ArgumentError::BadHandle)?;
+
+    let path = client.read_buffer(name_len)?;
+
+    client.session.put_if_absent(path, id)
+}
+
 #[secure_gate(options(info))]
 pub fn mkns(
     info: &secgate::GateCallInfo,
@@ -0,0 +1,237 @@
+//! Object aliases: which stored object backs each object ID.
+//!
+//! Swapping two objects' contents (see [crate::Store::swap_objects]) doesn't copy their data.
+//! Instead, each ID is pointed at the other's stored object, so the swap is one small, durable
+//! update to this table. An object's metadata page (page 0 of its stored object) stays with the
+//! object's ID, so the two metadata pages are exchanged as well. They're recorded in the same
+//! update, and written out again at startup if a crash got in the way.
+//!
+//! The table lives in a reserved store object, in two alternating slots. Each update is written
+//! to the slot not holding the current table, with a higher generation and a checksum, so a torn
+//! write leaves the previous table intact.
+
+use std::collections::HashMap;
+
+use object_store::PagedObjectStore;
+use twizzler_rt_abi::{
+    error::{ObjectError, ResourceError, TwzError},
+    Result,
+};
+
+use crate::helpers::PAGE;
+
+/// The store object holding the alias table.
+const ALIASES_ID: u128 = 0x616c_6961_7365_735f_7477_7a5f_7061_6765;
+const SLOT_SIZE: usize = 0x10000;
+const HEADER_SIZE: usize = 64;
+const ENTRY_SIZE: usize = 32;
+/// Where the slot keeps the metadata pages of a swap that may not have been written out yet.
+const META_OFFSET: usize = SLOT_SIZE - 2 * PAGE as usize;
+/// The most IDs that can be backed by another ID's stored object at once.
+pub const MAX_ALIASES: usize = (META_OFFSET - HEADER_SIZE) / ENTRY_SIZE;
+
+const FLAG_META_PENDING: u32 = 1;
+
+/// A pending metadata exchange: each ID, and the metadata page to write to its new backing.
+struct PendingMeta {
+    ids: [u128; 2],
+    pages: [Vec<u8>; 2],
+}
+
+#[derive(Default)]
+pub struct Aliases {
+    map: HashMap<u128, u128>,
+    generation: u64,
+    /// A committed swap whose metadata pages haven't been written out yet.
+    unfinished: Option<PendingMeta>,
+}
+
+impl Aliases {
+    /// Load the table from `store`, creating it if this store has never had one, and finish any
+    /// metadata exchange that was interrupted.
+    pub fn load(store: &dyn PagedObjectStore) -> Result<Self> {
+        match store.len(ALIASES_ID) {
+            Err(TwzError::Object(ObjectError::NoSuchObject)) => {
+                store.create_object(ALIASES_ID)?;
+                return Ok(Self::default());
+            }
+            res => res?,
+        };
+
+        let mut current = None;
+        for slot in 0..2 {
+            let mut buf = vec![0; SLOT_SIZE];
+            let len = store.read_object(ALIASES_ID, (slot * SLOT_SIZE) as u64, &mut buf)?;
+            buf[len..].fill(0);
+            if let Some(table) = decode(&buf) {
+                if current
+                    .as_ref()
+                    .is_none_or(|cur: &Self| table.generation > cur.generation)
+                {
+                    current = Some(table);
+                }
+            }
+        }
+        let Some(mut aliases) = current else {
+            // Nothing was ever committed.
+            return Ok(Self::default());
+        };
+        aliases.finish(store)?;
+        Ok(aliases)
+    }
+
+    /// The stored object backing `id`.
+    pub fn backing(&self, id: u128) -> u128 {
+        self.map.get(&id).copied().unwrap_or(id)
+    }
+
+    /// Whether `id`'s stored object backs some other ID.
+    pub fn is_borrowed(&self, id: u128) -> bool {
+        self.map.iter().any(|(k, v)| *v == id && *k != id)
+    }
+
+    /// Forget `id`, after its stored object was deleted.
+    pub fn forget(&mut self, store: &dyn PagedObjectStore, id: u128) -> Result<()> {
+        if !self.map.contains_key(&id) {
+            return Ok(());
+        }
+        self.finish(store)?;
+        let mut next = self.map.clone();
+        next.remove(&id);
+        self.commit(store, &next, None)?;
+        self.map = next;
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Exchange the stored objects backing `a` and `b`, keeping each ID's metadata page. Nothing
+    /// changes if this fails before the new table is durable; after that, the swap is done, and a
+    /// failure to write the metadata pages is logged, and retried before the next update to the
+    /// table, or at the next startup.
+    pub fn swap(&mut self, store: &dyn PagedObjectStore, a: u128, b: u128) -> Result<()> {
+        self.finish(store)?;
+        let (a_backing, b_backing) = (self.backing(a), self.backing(b));
+        store.len(a_backing)?;
+        store.len(b_backing)?;
+        let read_meta = |id| -> Result<Vec<u8>> {
+            let mut page = vec![0; PAGE as usize];
+            let len = store.read_object(id, 0, &mut page)?;
+            page[len..].fill(0);
+            Ok(page)
+        };
+        let pending = PendingMeta {
+            ids: [a, b],
+            pages: [read_meta(a_backing)?, read_meta(b_backing)?],
+        };
+
+        let mut next = self.map.clone();
+        for (id, backing) in [(a, b_backing), (b, a_backing)] {
+            if id == backing {
+                next.remove(&id);
+            } else {
+                next.insert(id, backing);
+            }
+        }
+        if next.len() > MAX_ALIASES {
+            return Err(ResourceError::OutOfResources.into());
+        }
+        self.commit(store, &next, Some(&pending))?;
+        self.map = next;
+        self.generation += 1;
+        self.unfinished = Some(pending);
+
+        if let Err(e) = self.finish(store) {
+            tracing::warn!("failed to swap metadata of {:x} and {:x}: {}", a, b, e);
+        }
+        Ok(())
+    }
+
+    /// Write out the metadata pages of the last swap, if that hasn't been done yet.
+    fn finish(&mut self, store: &dyn PagedObjectStore) -> Result<()> {
+        let Some(pending) = &self.unfinished else {
+            return Ok(());
+        };
+        for (id, page) in pending.ids.iter().zip(&pending.pages) {
+            store.write_object(self.backing(*id), 0, page)?;
+        }
+        store.flush()?;
+        self.commit(store, &self.map, None)?;
+        self.generation += 1;
+        self.unfinished = None;
+        Ok(())
+    }
+
+    /// Durably write `map` as the next generation of the table.
+    fn commit(
+        &self,
+        store: &dyn PagedObjectStore,
+        map: &HashMap<u128, u128>,
+        pending: Option<&PendingMeta>,
+    ) -> Result<()> {
+        let generation = self.generation + 1;
+        let buf = encode(generation, map, pending);
+        let slot = (generation % 2) as usize;
+        store.write_object(ALIASES_ID, (slot * SLOT_SIZE) as u64, &buf)?;
+        store.flush()
+    }
+}
+
+fn checksum(buf: &[u8]) -> u64 {
+    // FNV-1a, skipping the checksum field itself.
+    buf[..16]
+        .iter()
+        .chain(&buf[24..])
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100_0000_01b3)
+        })
+}
+
+fn encode(generation: u64, map: &HashMap<u128, u128>, pending: Option<&PendingMeta>) -> Vec<u8> {
+    let mut buf = vec![0; SLOT_SIZE];
+    buf[0..8].copy_from_slice(&generation.to_le_bytes());
+    buf[8..12].copy_from_slice(&(map.len() as u32).to_le_bytes());
+    for (i, (id, backing)) in map.iter().enumerate() {
+        let entry = &mut buf[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..16].copy_from_slice(&id.to_le_bytes());
+        entry[16..].copy_from_slice(&backing.to_le_bytes());
+    }
+    if let Some(pending) = pending {
+        buf[12..16].copy_from_slice(&FLAG_META_PENDING.to_le_bytes());
+        buf[32..48].copy_from_slice(&pending.ids[0].to_le_bytes());
+        buf[48..64].copy_from_slice(&pending.ids[1].to_le_bytes());
+        buf[META_OFFSET..][..PAGE as usize].copy_from_slice(&pending.pages[0]);
+        buf[META_OFFSET + PAGE as usize..].copy_from_slice(&pending.pages[1]);
+    }
+    let sum = checksum(&buf);
+    buf[16..24].copy_from_slice(&sum.to_le_bytes());
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<Aliases> {
+    let u32_at = |off: usize| u32::from_le_bytes(buf[off..off + 4].try_into().unwrap());
+    let u64_at = |off: usize| u64::from_le_bytes(buf[off..off + 8].try_into().unwrap());
+    let u128_at = |off: usize| u128::from_le_bytes(buf[off..off + 16].try_into().unwrap());
+    let generation = u64_at(0);
+    let count = u32_at(8) as usize;
+    if generation == 0 || count > MAX_ALIASES || u64_at(16) != checksum(buf) {
+        return None;
+    }
+    let map = (0..count)
+        .map(|i| {
+            let off = HEADER_SIZE + i * ENTRY_SIZE;
+            (u128_at(off), u128_at(off + 16))
+        })
+        .collect();
+    let unfinished = (u32_at(12) & FLAG_META_PENDING != 0).then(|| PendingMeta {
+        ids: [u128_at(32), u128_at(48)],
+        pages: [
+            buf[META_OFFSET..][..PAGE as usize].to_vec(),
+            buf[META_OFFSET + PAGE as usize..].to_vec(),
+        ],
+    });
+    Some(Aliases {
+        map,
+        generation,
+        unfinished,
+    })
+}
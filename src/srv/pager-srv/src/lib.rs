@@ -25,8 +25,9 @@ use twizzler_abi::pager::{
 use twizzler_queue::{QueueBase, QueueSender};
 use twizzler_rt_abi::{error::TwzError, object::MapFlags};
 
-use crate::{data::PagerData, request_handle::handle_kernel_request};
+use crate::{aliases::Aliases, data::PagerData, request_handle::handle_kernel_request};
 
+mod aliases;
 mod data;
 mod disk;
 mod handle;
@@ -235,9 +236,10 @@ impl Stores {
         &mut self,
         store: Arc<dyn PagedObjectStore + Send + Sync + 'static>,
         dev: Arc<dyn PagedDevice + Send + Sync + 'static>,
-    ) {
+    ) -> Result<()> {
         self.map
-            .insert(ObjID::new(0), Arc::new(Store { inner: store, dev }));
+            .insert(ObjID::new(0), Arc::new(Store::new(store, dev)?));
+        Ok(())
     }
 }
 
@@ -245,27 +247,60 @@ impl Stores {
 struct Store {
     inner: Arc<dyn PagedObjectStore + Send + Sync + 'static>,
     dev: Arc<dyn PagedDevice + Send + Sync + 'static>,
+    aliases: Mutex<Aliases>,
+}
+
+impl Store {
+    fn new(
+        inner: Arc<dyn PagedObjectStore + Send + Sync + 'static>,
+        dev: Arc<dyn PagedDevice + Send + Sync + 'static>,
+    ) -> Result<Self> {
+        let aliases = Mutex::new(Aliases::load(&*inner)?);
+        Ok(Self {
+            inner,
+            dev,
+            aliases,
+        })
+    }
+
+    /// The stored object backing `id`, which differs from `id` once it has been swapped.
+    fn backing(&self, id: object_store::ObjID) -> object_store::ObjID {
+        self.aliases.lock().unwrap().backing(id)
+    }
+
+    /// Exchange the contents of two objects, by exchanging which stored object backs each. Their
+    /// metadata pages stay with them.
+    pub fn swap_objects(&self, a: object_store::ObjID, b: object_store::ObjID) -> Result<()> {
+        self.aliases.lock().unwrap().swap(&*self.inner, a, b)
+    }
 }
 
 impl PagedObjectStore for Store {
     fn create_object(&self, id: object_store::ObjID) -> Result<()> {
+        let aliases = self.aliases.lock().unwrap();
+        // The ID's own stored object may still be in use, by the object it was swapped with.
+        if aliases.backing(id) != id || aliases.is_borrowed(id) {
+            return Err(TwzError::INVALID_ARGUMENT);
+        }
         self.inner.create_object(id)
     }
 
     fn delete_object(&self, id: object_store::ObjID) -> Result<()> {
-        self.inner.delete_object(id)
+        let mut aliases = self.aliases.lock().unwrap();
+        self.inner.delete_object(aliases.backing(id))?;
+        aliases.forget(&*self.inner, id)
     }
 
     fn len(&self, id: object_store::ObjID) -> Result<u64> {
-        self.inner.len(id)
+        self.inner.len(self.backing(id))
     }
 
     fn read_object(&self, id: object_store::ObjID, offset: u64, buf: &mut [u8]) -> Result<usize> {
-        self.inner.read_object(id, offset, buf)
+        self.inner.read_object(self.backing(id), offset, buf)
     }
 
     fn write_object(&self, id: object_store::ObjID, offset: u64, buf: &[u8]) -> Result<()> {
-        self.inner.write_object(id, offset, buf)
+        self.inner.write_object(self.backing(id), offset, buf)
     }
 
     fn get_config_id(&self) -> Result<object_store::ObjID> {
@@ -285,7 +320,7 @@ impl PagedObjectStore for Store {
         id: object_store::ObjID,
         reqs: &'a mut [object_store::PageRequest],
     ) -> Result<usize> {
-        self.inner.page_in_object(id, reqs)
+        self.inner.page_in_object(self.backing(id), reqs)
     }
 
     fn page_out_object<'a>(
@@ -293,7 +328,7 @@ impl PagedObjectStore for Store {
         id: object_store::ObjID,
         reqs: &'a mut [object_store::PageRequest],
     ) -> Result<usize> {
-        self.inner.page_out_object(id, reqs)
+        self.inner.page_out_object(self.backing(id), reqs)
     }
 
     fn enumerate_external(&self, _id: object_store::ObjID) -> Result<Vec<ExternalFile>> {
@@ -354,7 +389,8 @@ fn do_pager_start(q1: ObjID, q2: ObjID) -> ObjID {
     ctx.stores
         .lock()
         .unwrap()
-        .insert_device(Arc::new(ext4_store), Arc::new(disk));
+        .insert_device(Arc::new(ext4_store), Arc::new(disk))
+        .unwrap();
 
     spawn_queues(ctx, rq, ex);
 
@@ -41,7 +41,8 @@ async fn handle_page_data_request_task(
                 req_range.end,
                 len
             );
-            req_range.end = len.next_multiple_of(PAGE) + PAGE;
+            // Don't read past the end of the object, but also not past what was asked for.
+            req_range.end = req_range.end.min(len.next_multiple_of(PAGE) + PAGE);
         }
         PCOUNT.fetch_add(1, Ordering::SeqCst);
     } else {
@@ -260,6 +261,43 @@ pub async fn handle_kernel_request(
         KernelCommand::ObjectEvict(info) => {
             return vec![handle_sync_region(ctx, info).await];
         }
+        KernelCommand::ObjectRangeFree(obj_id, range) => {
+            unblock(move || {
+                // The object store has no way to discard part of an object, so overwrite the range
+                // with zeros, which is what it must read back as.
+                let zeros = [0; PAGE as usize];
+                let res = ctx.paged_ostore(None).and_then(|po| {
+                    for off in (range.start..range.end).step_by(zeros.len()) {
+                        let len = (range.end - off).min(PAGE) as usize;
+                        po.write_object(obj_id.raw(), off, &zeros[..len])?;
+                    }
+                    Ok(())
+                });
+                match res {
+                    Ok(()) => KernelCompletionData::Okay,
+                    Err(e) => {
+                        tracing::warn!("failed to free range {:?} of {}: {}", range, obj_id, e);
+                        KernelCompletionData::Error(e.into())
+                    }
+                }
+            })
+            .await
+        }
+        KernelCommand::ObjectSwap(obj_id, other) => {
+            unblock(move || {
+                let res = ctx
+                    .paged_ostore(None)
+                    .and_then(|po| po.swap_objects(obj_id.raw(), other.raw()));
+                match res {
+                    Ok(()) => KernelCompletionData::Okay,
+                    Err(e) => {
+                        tracing::warn!("failed to swap {} and {}: {}", obj_id, other, e);
+                        KernelCompletionData::Error(e.into())
+                    }
+                }
+            })
+            .await
+        }
     };
 
     tracing::debug!("done; sending response: {:?}", data);